@@ -0,0 +1,177 @@
+use rocket::{
+    http::Status,
+    request::{FromRequest, Outcome, Request},
+};
+use rocket_okapi::{
+    gen::OpenApiGenerator,
+    request::{OpenApiFromRequest, RequestHeaderInput},
+};
+use serde::{Deserialize, Serialize};
+use std::{env, error::Error, fs, path::Path, sync::Arc};
+
+use crate::{
+    rocket_models::ApiError, rocket_utils::header_security_input, validation::record_failure,
+};
+
+pub const API_KEY_HEADER: &str = "X-API-Key";
+pub const API_KEYS_ENV: &str = "API_KEYS";
+
+// The caller identified by a valid API key
+#[derive(Debug, Clone, PartialEq)]
+pub struct ApiKeyAuth {
+    pub name: String,
+}
+
+#[derive(Debug)]
+pub enum ApiKeyError {
+    Missing,
+    Invalid,
+}
+
+#[rocket::async_trait]
+pub trait ApiKeyValidator: Send + Sync {
+    /// Returns the name of the key owner when `key` is valid.
+    async fn validate(&self, key: &str) -> Option<String>;
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct ApiKeyEntry {
+    pub name: String,
+    pub key: String,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Default)]
+pub struct StaticApiKeys {
+    #[serde(default)]
+    pub keys: Vec<ApiKeyEntry>,
+}
+
+impl StaticApiKeys {
+    /// Reads keys from a comma separated list of `name:key` entries, e.g.
+    /// `API_KEYS="ci-bot:abc,partner:xyz"`.
+    pub fn from_env(var: &str) -> Self {
+        let value = env::var(var).unwrap_or_default();
+        let keys = value
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .enumerate()
+            .map(|(index, entry)| match entry.split_once(':') {
+                Some((name, key)) => ApiKeyEntry {
+                    name: name.trim().to_string(),
+                    key: key.trim().to_string(),
+                },
+                None => ApiKeyEntry {
+                    name: format!("api-key-{}", index),
+                    key: entry.to_string(),
+                },
+            })
+            .collect();
+        StaticApiKeys { keys }
+    }
+
+    /// Reads keys from a TOML file made of `[[keys]]` tables with a `name` and a `key`.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error>> {
+        let contents = fs::read_to_string(&path).map_err(|e| {
+            format!(
+                "Failed to read the file '{}': {}",
+                path.as_ref().display(),
+                e
+            )
+        })?;
+        Ok(toml::from_str(&contents)?)
+    }
+}
+
+#[rocket::async_trait]
+impl ApiKeyValidator for StaticApiKeys {
+    async fn validate(&self, key: &str) -> Option<String> {
+        // Compare against every key so the response time does not reveal which one matched
+        let mut owner = None;
+        for entry in &self.keys {
+            if constant_time_eq(entry.key.as_bytes(), key.as_bytes()) {
+                owner = Some(entry.name.clone());
+            }
+        }
+        owner
+    }
+}
+
+// Rocket managed state holding the validator used by the `ApiKeyAuth` guard. Without it, keys
+// are read from the `API_KEYS` environment variable.
+#[derive(Clone)]
+pub struct ApiKeyStore(Arc<dyn ApiKeyValidator>);
+
+impl ApiKeyStore {
+    pub fn new<V: ApiKeyValidator + 'static>(validator: V) -> Self {
+        ApiKeyStore(Arc::new(validator))
+    }
+
+    pub async fn validate(&self, key: &str) -> Option<String> {
+        self.0.validate(key).await
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ApiKeyAuth {
+    type Error = ApiKeyError;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let keys: Vec<_> = request.headers().get(API_KEY_HEADER).collect();
+        if keys.len() != 1 {
+            record_failure(
+                request,
+                ApiError::new(
+                    401,
+                    "api_key_missing",
+                    &format!("A single {} header is required", API_KEY_HEADER),
+                ),
+            );
+            return Outcome::Error((Status::Unauthorized, ApiKeyError::Missing));
+        }
+        let key = keys[0].trim();
+
+        let owner = match request.rocket().state::<ApiKeyStore>() {
+            Some(store) => store.validate(key).await,
+            None => StaticApiKeys::from_env(API_KEYS_ENV).validate(key).await,
+        };
+
+        match owner {
+            Some(name) => Outcome::Success(ApiKeyAuth { name }),
+            None => {
+                record_failure(
+                    request,
+                    ApiError::new(401, "api_key_invalid", "The API key is not valid"),
+                );
+                Outcome::Error((Status::Unauthorized, ApiKeyError::Invalid))
+            }
+        }
+    }
+}
+
+impl<'a> OpenApiFromRequest<'a> for ApiKeyAuth {
+    fn from_request_input(
+        _gen: &mut OpenApiGenerator,
+        _name: String,
+        _required: bool,
+    ) -> rocket_okapi::Result<RequestHeaderInput> {
+        Ok(header_security_input(
+            API_KEY_HEADER,
+            "ApiKeyAuth",
+            "Requires an API key to access",
+        ))
+    }
+
+    fn get_responses(
+        _gen: &mut rocket_okapi::gen::OpenApiGenerator,
+    ) -> rocket_okapi::Result<okapi::openapi3::Responses> {
+        Ok(okapi::openapi3::Responses::default())
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
@@ -0,0 +1,166 @@
+use chrono::{DateTime, Utc};
+use rocket::{
+    fairing::{Fairing, Info, Kind},
+    http::Method,
+    Request, Response,
+};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use crate::{rocket_errors::correlation_id, rocket_utils::authenticated_actor};
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditOutcome {
+    Success,
+    Denied,
+    Failure,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct AuditEvent {
+    pub actor: Option<String>,
+    pub org_id: Option<String>,
+    pub method: String,
+    pub route: String,
+    pub path: String,
+    pub status: u16,
+    pub outcome: AuditOutcome,
+    pub correlation_id: String,
+    pub occurred_at: DateTime<Utc>,
+}
+
+#[rocket::async_trait]
+pub trait AuditSink: Send + Sync {
+    async fn record(&self, event: &AuditEvent);
+}
+
+// Emits audit events as `tracing` events on the `audit` target
+pub struct TracingAuditSink;
+
+#[rocket::async_trait]
+impl AuditSink for TracingAuditSink {
+    async fn record(&self, event: &AuditEvent) {
+        tracing::info!(
+            target: "audit",
+            actor = event.actor.as_deref().unwrap_or("anonymous"),
+            org_id = event.org_id.as_deref().unwrap_or(""),
+            method = %event.method,
+            route = %event.route,
+            status = event.status,
+            outcome = ?event.outcome,
+            correlation_id = %event.correlation_id,
+            "audit event"
+        );
+    }
+}
+
+// POSTs every audit event as JSON to the central audit service
+#[cfg(feature = "client")]
+pub struct HttpAuditSink {
+    endpoint: String,
+    token: Option<String>,
+    http: reqwest::Client,
+}
+
+#[cfg(feature = "client")]
+impl HttpAuditSink {
+    pub fn new(endpoint: &str) -> Self {
+        HttpAuditSink {
+            endpoint: endpoint.to_string(),
+            token: None,
+            http: reqwest::Client::new(),
+        }
+    }
+
+    pub fn with_token(mut self, token: &str) -> Self {
+        self.token = Some(token.to_string());
+        self
+    }
+}
+
+#[cfg(feature = "client")]
+#[rocket::async_trait]
+impl AuditSink for HttpAuditSink {
+    async fn record(&self, event: &AuditEvent) {
+        let mut request = self.http.post(&self.endpoint).json(event);
+        if let Some(token) = &self.token {
+            request = request.header("X-ISC-Authorization", format!("Bearer {}", token));
+        }
+        match request.send().await {
+            Ok(response) if !response.status().is_success() => {
+                tracing::warn!(status = %response.status(), "audit service rejected an event")
+            }
+            Err(e) => tracing::warn!(error = %e, "failed to deliver an audit event"),
+            _ => {}
+        }
+    }
+}
+
+// Records an `AuditEvent` for every state-changing request (POST, PUT, PATCH, DELETE)
+pub struct AuditFairing {
+    sink: Arc<dyn AuditSink>,
+}
+
+impl AuditFairing {
+    pub fn new<S: AuditSink + 'static>(sink: S) -> Self {
+        AuditFairing {
+            sink: Arc::new(sink),
+        }
+    }
+}
+
+fn is_state_changing(method: Method) -> bool {
+    matches!(
+        method,
+        Method::Post | Method::Put | Method::Patch | Method::Delete
+    )
+}
+
+#[rocket::async_trait]
+impl Fairing for AuditFairing {
+    fn info(&self) -> Info {
+        Info {
+            name: "Audit logging",
+            kind: Kind::Response,
+        }
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        if !is_state_changing(request.method()) {
+            return;
+        }
+
+        let status = response.status().code;
+        let outcome = match status {
+            200..=399 => AuditOutcome::Success,
+            401 | 403 => AuditOutcome::Denied,
+            _ => AuditOutcome::Failure,
+        };
+        // As recorded by the claims guards of the handler, requests none of them authenticated
+        // are anonymous
+        let (actor, org_id) = match authenticated_actor(request) {
+            Some(actor) => (Some(actor.sub), actor.org_id),
+            None => (None, None),
+        };
+
+        let event = AuditEvent {
+            actor,
+            org_id,
+            method: request.method().to_string(),
+            route: request
+                .route()
+                .map(|route| route.uri.to_string())
+                .unwrap_or_else(|| request.uri().path().to_string()),
+            path: request.uri().path().to_string(),
+            status,
+            outcome,
+            correlation_id: correlation_id(request),
+            occurred_at: Utc::now(),
+        };
+
+        // Deliver in the background so slow sinks do not delay the response
+        let sink = self.sink.clone();
+        rocket::tokio::spawn(async move { sink.record(&event).await });
+    }
+}
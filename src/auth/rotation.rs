@@ -0,0 +1,474 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use uuid::Uuid;
+
+use crate::{
+    claims::{issued_now, Claims},
+    jwt::{JwtConfig, TokenErrorKind},
+    revocation::{issued_at, token_id, Revocation},
+    rocket_models::ApiError,
+};
+
+pub type StoreError = Box<dyn Error + Send + Sync>;
+
+pub const ACCESS_TOKEN_TYPE: &str = "access";
+pub const REFRESH_TOKEN_TYPE: &str = "refresh";
+/// Claim holding the family of a refresh token. Access tokens carry it as `sid`, so a family is
+/// also the session of `sessions::Session`.
+pub const FAMILY_CLAIM: &str = "fid";
+
+#[derive(Debug, Deserialize, Serialize, JsonSchema, Clone, PartialEq)]
+pub struct TokenPair {
+    pub access_token: String,
+    pub refresh_token: String,
+    pub access_expires_at: usize,
+    pub refresh_expires_at: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RotationPolicy {
+    pub access_ttl: Duration,
+    pub refresh_ttl: Duration, // Sliding, each rotation issues a refresh token valid this long
+}
+
+impl Default for RotationPolicy {
+    fn default() -> Self {
+        RotationPolicy {
+            access_ttl: Duration::from_secs(15 * 60),
+            refresh_ttl: Duration::from_secs(30 * 24 * 60 * 60),
+        }
+    }
+}
+
+// The refresh tokens issued from one login. Only the latest one, `current_jti`, may be used: a
+// rotated token coming back means it leaked, and the whole family is revoked.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct TokenFamily {
+    pub family_id: String,
+    pub user_id: String,
+    pub current_jti: String,
+    pub revoked: bool,
+    pub created_at: DateTime<Utc>,
+    pub rotated_at: DateTime<Utc>,
+}
+
+#[async_trait]
+pub trait RefreshTokenStore: Send + Sync {
+    async fn create(&self, family: &TokenFamily, ttl: Duration) -> Result<(), StoreError>;
+
+    async fn load(&self, family_id: &str) -> Result<Option<TokenFamily>, StoreError>;
+
+    /// Atomically replaces the current token of the family by `to_jti` if it is `from_jti` and
+    /// the family is not revoked, returning whether it did.
+    async fn rotate(
+        &self,
+        family_id: &str,
+        from_jti: &str,
+        to_jti: &str,
+        ttl: Duration,
+    ) -> Result<bool, StoreError>;
+
+    async fn revoke(&self, family_id: &str) -> Result<(), StoreError>;
+}
+
+#[derive(Default)]
+pub struct MemoryRefreshTokenStore {
+    families: Mutex<HashMap<String, (TokenFamily, Instant)>>,
+}
+
+impl MemoryRefreshTokenStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl RefreshTokenStore for MemoryRefreshTokenStore {
+    async fn create(&self, family: &TokenFamily, ttl: Duration) -> Result<(), StoreError> {
+        self.families.lock().unwrap().insert(
+            family.family_id.clone(),
+            (family.clone(), Instant::now() + ttl),
+        );
+        Ok(())
+    }
+
+    async fn load(&self, family_id: &str) -> Result<Option<TokenFamily>, StoreError> {
+        let mut families = self.families.lock().unwrap();
+        let now = Instant::now();
+        families.retain(|_, (_, expires_at)| *expires_at > now);
+        Ok(families.get(family_id).map(|(family, _)| family.clone()))
+    }
+
+    async fn rotate(
+        &self,
+        family_id: &str,
+        from_jti: &str,
+        to_jti: &str,
+        ttl: Duration,
+    ) -> Result<bool, StoreError> {
+        let mut families = self.families.lock().unwrap();
+        match families.get_mut(family_id) {
+            Some((family, expires_at))
+                if !family.revoked
+                    && family.current_jti == from_jti
+                    && *expires_at > Instant::now() =>
+            {
+                family.current_jti = to_jti.to_string();
+                family.rotated_at = Utc::now();
+                *expires_at = Instant::now() + ttl;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    async fn revoke(&self, family_id: &str) -> Result<(), StoreError> {
+        if let Some((family, _)) = self.families.lock().unwrap().get_mut(family_id) {
+            family.revoked = true;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "redis")]
+pub use self::redis_store::RedisRefreshTokenStore;
+
+#[cfg(feature = "redis")]
+mod redis_store {
+    use async_trait::async_trait;
+    use std::time::Duration;
+
+    use super::{RefreshTokenStore, StoreError, TokenFamily};
+    use crate::redis_utils::{RedisPool, TypedRedis};
+
+    // Swaps the current token only if it is the expected one, keeping the record in step
+    const ROTATE_SCRIPT: &str = r#"
+local raw = redis.call('GET', KEYS[1])
+if not raw then return 0 end
+local family = cjson.decode(raw)
+if family.revoked or family.current_jti ~= ARGV[1] then return 0 end
+family.current_jti = ARGV[2]
+family.rotated_at = ARGV[3]
+redis.call('SET', KEYS[1], cjson.encode(family), 'EX', ARGV[4])
+return 1
+"#;
+
+    const REVOKE_SCRIPT: &str = r#"
+local raw = redis.call('GET', KEYS[1])
+if not raw then return 0 end
+local family = cjson.decode(raw)
+family.revoked = true
+redis.call('SET', KEYS[1], cjson.encode(family), 'KEEPTTL')
+return 1
+"#;
+
+    // Families shared between replicas under `{namespace}:{family_id}`
+    pub struct RedisRefreshTokenStore {
+        pool: RedisPool,
+        families: TypedRedis<TokenFamily>,
+    }
+
+    impl RedisRefreshTokenStore {
+        pub fn new(pool: RedisPool, namespace: &str) -> Self {
+            RedisRefreshTokenStore {
+                pool,
+                families: TypedRedis::new(namespace),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl RefreshTokenStore for RedisRefreshTokenStore {
+        async fn create(&self, family: &TokenFamily, ttl: Duration) -> Result<(), StoreError> {
+            self.families
+                .set_ex(&self.pool, &family.family_id, family, ttl)
+                .await
+        }
+
+        async fn load(&self, family_id: &str) -> Result<Option<TokenFamily>, StoreError> {
+            self.families.get(&self.pool, family_id).await
+        }
+
+        async fn rotate(
+            &self,
+            family_id: &str,
+            from_jti: &str,
+            to_jti: &str,
+            ttl: Duration,
+        ) -> Result<bool, StoreError> {
+            let rotated: i64 = redis::cmd("EVAL")
+                .arg(ROTATE_SCRIPT)
+                .arg(1)
+                .arg(self.families.key(family_id))
+                .arg(from_jti)
+                .arg(to_jti)
+                .arg(chrono::Utc::now().to_rfc3339())
+                .arg(ttl.as_secs().max(1))
+                .query_async(&mut self.pool.connection())
+                .await?;
+            Ok(rotated == 1)
+        }
+
+        async fn revoke(&self, family_id: &str) -> Result<(), StoreError> {
+            let _: i64 = redis::cmd("EVAL")
+                .arg(REVOKE_SCRIPT)
+                .arg(1)
+                .arg(self.families.key(family_id))
+                .query_async(&mut self.pool.connection())
+                .await?;
+            Ok(())
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum RotationError {
+    Token(TokenErrorKind),
+    NotRefreshToken,
+    UnknownFamily,
+    FamilyRevoked,
+    Reused, // A rotated refresh token was presented again, its family is now revoked
+    Store(StoreError),
+    Encode(String),
+}
+
+impl fmt::Display for RotationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RotationError::Token(kind) => write!(f, "{}", kind.message()),
+            RotationError::NotRefreshToken => write!(f, "The token is not a refresh token"),
+            RotationError::UnknownFamily => write!(f, "The refresh token is unknown or expired"),
+            RotationError::FamilyRevoked => write!(f, "The refresh token has been revoked"),
+            RotationError::Reused => write!(
+                f,
+                "The refresh token was already used, every token of the login is revoked"
+            ),
+            RotationError::Store(e) => write!(f, "The token store is unavailable: {}", e),
+            RotationError::Encode(e) => write!(f, "Failed to issue the tokens: {}", e),
+        }
+    }
+}
+
+impl Error for RotationError {}
+
+impl From<RotationError> for ApiError {
+    fn from(error: RotationError) -> Self {
+        let message = error.to_string();
+        match error {
+            RotationError::Token(kind) => ApiError::new(401, kind.error_code(), &message),
+            RotationError::NotRefreshToken => ApiError::new(401, "token_invalid", &message),
+            RotationError::UnknownFamily | RotationError::FamilyRevoked => {
+                ApiError::new(401, "token_revoked", &message)
+            }
+            RotationError::Reused => ApiError::new(401, "token_reused", &message),
+            // The details of the store and the signing stay in the logs
+            RotationError::Store(_) => {
+                tracing::error!("{}", message);
+                ApiError::new(
+                    503,
+                    "token_store_unavailable",
+                    "The token store is unavailable",
+                )
+            }
+            RotationError::Encode(_) => {
+                tracing::error!("{}", message);
+                ApiError::internal("Failed to issue the tokens")
+            }
+        }
+    }
+}
+
+fn expires_in(valid_for: Duration) -> usize {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    (now + valid_for).as_secs() as usize
+}
+
+fn string_claim<'a>(claims: &'a Claims, name: &str) -> Option<&'a str> {
+    claims.extra.get(name).and_then(|value| value.as_str())
+}
+
+// Issues access / refresh token pairs and rotates the refresh tokens, e.g. in the identity
+// service:
+//
+// let pair = rotation.issue(Claims::new(&email, &user_id, "access", Duration::ZERO)).await?;
+// let next = rotation.refresh(&pair.refresh_token).await?;
+#[derive(Clone)]
+pub struct TokenRotation {
+    jwt: JwtConfig,
+    store: Arc<dyn RefreshTokenStore>,
+    policy: RotationPolicy,
+    revocation: Option<Revocation>,
+}
+
+impl TokenRotation {
+    pub fn new<S: RefreshTokenStore + 'static>(
+        jwt: JwtConfig,
+        store: S,
+        policy: RotationPolicy,
+    ) -> Self {
+        TokenRotation {
+            jwt,
+            store: Arc::new(store),
+            policy,
+            revocation: None,
+        }
+    }
+
+    /// Refuses to rotate refresh tokens revoked by id or subject, as the claims guards refuse
+    /// the access tokens. Pass the `Revocation` Rocket manages.
+    pub fn with_revocation(mut self, revocation: Revocation) -> Self {
+        self.revocation = Some(revocation);
+        self
+    }
+
+    /// Starts a family for a login, from claims whose `exp`, `token_type` and token ids are
+    /// overwritten.
+    pub async fn issue(&self, claims: Claims) -> Result<TokenPair, RotationError> {
+        let family_id = Uuid::new_v4().to_string();
+        let jti = Uuid::new_v4().to_string();
+        let now = Utc::now();
+        let family = TokenFamily {
+            family_id: family_id.clone(),
+            user_id: claims.user_id.clone(),
+            current_jti: jti.clone(),
+            revoked: false,
+            created_at: now,
+            rotated_at: now,
+        };
+        self.store
+            .create(&family, self.policy.refresh_ttl)
+            .await
+            .map_err(RotationError::Store)?;
+        self.pair(claims, &family_id, &jti)
+    }
+
+    /// Exchanges a refresh token for a new pair. A refresh token is only accepted once: using
+    /// it again revokes its family, logging out both the thief and the legitimate user.
+    pub async fn refresh(&self, refresh_token: &str) -> Result<TokenPair, RotationError> {
+        let claims: Claims = self
+            .jwt
+            .decode(refresh_token)
+            .map_err(|e| RotationError::Token(TokenErrorKind::from(&e)))?;
+        if claims.token_type != REFRESH_TOKEN_TYPE {
+            return Err(RotationError::NotRefreshToken);
+        }
+        let (family_id, jti) = match (
+            string_claim(&claims, FAMILY_CLAIM),
+            string_claim(&claims, "jti"),
+        ) {
+            (Some(family_id), Some(jti)) => (family_id.to_string(), jti.to_string()),
+            _ => return Err(RotationError::Token(TokenErrorKind::Malformed)),
+        };
+        if let Some(revocation) = &self.revocation {
+            if revocation
+                .is_revoked(
+                    token_id(&claims.extra),
+                    &claims.sub,
+                    issued_at(&claims.extra),
+                )
+                .await
+            {
+                return Err(RotationError::Token(TokenErrorKind::Revoked));
+            }
+        }
+
+        let next_jti = Uuid::new_v4().to_string();
+        let rotated = self
+            .store
+            .rotate(&family_id, &jti, &next_jti, self.policy.refresh_ttl)
+            .await
+            .map_err(RotationError::Store)?;
+        if rotated {
+            return self.pair(claims, &family_id, &next_jti);
+        }
+
+        match self
+            .store
+            .load(&family_id)
+            .await
+            .map_err(RotationError::Store)?
+        {
+            None => Err(RotationError::UnknownFamily),
+            Some(family) if family.revoked => Err(RotationError::FamilyRevoked),
+            Some(_) => {
+                tracing::warn!(
+                    family = %family_id,
+                    user = %claims.user_id,
+                    "refresh token reused, revoking its family"
+                );
+                self.store
+                    .revoke(&family_id)
+                    .await
+                    .map_err(RotationError::Store)?;
+                Err(RotationError::Reused)
+            }
+        }
+    }
+
+    /// Revokes every refresh token of a login, e.g. on logout. Its access tokens stay valid
+    /// until they expire unless they are also revoked, see `revocation`.
+    pub async fn revoke(&self, family_id: &str) -> Result<(), RotationError> {
+        self.store
+            .revoke(family_id)
+            .await
+            .map_err(RotationError::Store)
+    }
+
+    fn pair(
+        &self,
+        mut claims: Claims,
+        family_id: &str,
+        refresh_jti: &str,
+    ) -> Result<TokenPair, RotationError> {
+        for claim in ["jti", "sid", FAMILY_CLAIM] {
+            claims.extra.remove(claim);
+        }
+        // A fresh `iat`, so subject revocations only have to outlive the tokens issued since
+        claims.extra.extend(issued_now());
+
+        let mut access = claims.clone();
+        access.token_type = ACCESS_TOKEN_TYPE.to_string();
+        access.exp = expires_in(self.policy.access_ttl);
+        access
+            .extra
+            .insert("jti".to_string(), Value::String(Uuid::new_v4().to_string()));
+        access
+            .extra
+            .insert("sid".to_string(), Value::String(family_id.to_string()));
+
+        let mut refresh = claims;
+        refresh.token_type = REFRESH_TOKEN_TYPE.to_string();
+        refresh.exp = expires_in(self.policy.refresh_ttl);
+        refresh
+            .extra
+            .insert("jti".to_string(), Value::String(refresh_jti.to_string()));
+        refresh.extra.insert(
+            FAMILY_CLAIM.to_string(),
+            Value::String(family_id.to_string()),
+        );
+
+        let encode = |claims: &Claims| {
+            self.jwt
+                .encode(claims)
+                .map_err(|e| RotationError::Encode(e.to_string()))
+        };
+        Ok(TokenPair {
+            access_token: encode(&access)?,
+            refresh_token: encode(&refresh)?,
+            access_expires_at: access.exp,
+            refresh_expires_at: refresh.exp,
+        })
+    }
+}
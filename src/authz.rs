@@ -0,0 +1,287 @@
+use rocket::{
+    fairing::{Fairing, Info, Kind},
+    Build, Data, Request, Rocket,
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{error::Error, fs, marker::PhantomData, path::Path};
+
+use crate::{
+    claims::{APIClaims, ISCClaims},
+    rocket_errors::{deny, mount_denied},
+    rocket_models::ApiError,
+    rocket_utils::{authenticate, RevocableClaims},
+};
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct AuthzRule {
+    /// Route pattern, `*` matches one path segment and `**` any number of segments.
+    pub path: String,
+    /// Methods the rule applies to, all methods when empty.
+    #[serde(default)]
+    pub methods: Vec<String>,
+    /// Every scope listed here is required.
+    #[serde(default)]
+    pub scopes: Vec<String>,
+    /// Membership of any of these groups is required.
+    #[serde(default)]
+    pub groups: Vec<i64>,
+}
+
+impl AuthzRule {
+    pub fn matches(&self, method: &str, path: &str) -> bool {
+        self.matches_segments(method, &split_path(path))
+    }
+
+    // Matches the decoded segments of a request, `%2F` in a segment does not split it
+    fn matches_segments(&self, method: &str, segments: &[&str]) -> bool {
+        let method = routed_method(method);
+        let method_matches = self.methods.is_empty()
+            || self
+                .methods
+                .iter()
+                .any(|m| routed_method(m).eq_ignore_ascii_case(method));
+        method_matches && segments_match(&split_path(&self.path), segments)
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Default)]
+pub struct AuthzPolicy {
+    #[serde(default)]
+    pub rules: Vec<AuthzRule>,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Principal {
+    pub scopes: Vec<String>,
+    pub group_id: Option<i64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AuthzDecision {
+    Allow,
+    Unauthenticated,
+    Forbidden,
+}
+
+impl AuthzPolicy {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error>> {
+        let contents = fs::read_to_string(&path).map_err(|e| {
+            format!(
+                "Failed to read the file '{}': {}",
+                path.as_ref().display(),
+                e
+            )
+        })?;
+        toml::from_str(&contents).map_err(|e| {
+            format!(
+                "Failed to parse TOML from file '{}': {}",
+                path.as_ref().display(),
+                e
+            )
+            .into()
+        })
+    }
+
+    pub fn matching_rules(&self, method: &str, path: &str) -> Vec<&AuthzRule> {
+        self.matching_segments(method, &split_path(path))
+    }
+
+    fn matching_segments(&self, method: &str, segments: &[&str]) -> Vec<&AuthzRule> {
+        self.rules
+            .iter()
+            .filter(|rule| rule.matches_segments(method, segments))
+            .collect()
+    }
+
+    /// Every matching rule has to be satisfied. Requests no rule matches are allowed.
+    pub fn decide(&self, method: &str, path: &str, principal: Option<&Principal>) -> AuthzDecision {
+        self.decide_segments(method, &split_path(path), principal)
+    }
+
+    fn decide_segments(
+        &self,
+        method: &str,
+        segments: &[&str],
+        principal: Option<&Principal>,
+    ) -> AuthzDecision {
+        let rules = self.matching_segments(method, segments);
+        if rules.is_empty() {
+            return AuthzDecision::Allow;
+        }
+
+        let principal = match principal {
+            Some(principal) => principal,
+            None => return AuthzDecision::Unauthenticated,
+        };
+
+        let satisfied = rules.iter().all(|rule| {
+            let has_scopes = rule
+                .scopes
+                .iter()
+                .all(|scope| principal.scopes.contains(scope));
+            let in_group = rule.groups.is_empty()
+                || principal
+                    .group_id
+                    .map(|group| rule.groups.contains(&group))
+                    .unwrap_or(false);
+            has_scopes && in_group
+        });
+
+        if satisfied {
+            AuthzDecision::Allow
+        } else {
+            AuthzDecision::Forbidden
+        }
+    }
+}
+
+// Rocket answers HEAD requests with the GET handler, so rules for one cover the other
+fn routed_method(method: &str) -> &str {
+    match method.eq_ignore_ascii_case("HEAD") {
+        true => "GET",
+        false => method,
+    }
+}
+
+fn split_path(path: &str) -> Vec<&str> {
+    path.split('/').filter(|s| !s.is_empty()).collect()
+}
+
+fn segments_match(pattern: &[&str], path: &[&str]) -> bool {
+    match (pattern.first(), path.first()) {
+        (None, None) => true,
+        (Some(&"**"), _) => {
+            segments_match(&pattern[1..], path)
+                || (!path.is_empty() && segments_match(pattern, &path[1..]))
+        }
+        (Some(p), Some(s)) if *p == "*" || p == s => segments_match(&pattern[1..], &path[1..]),
+        _ => false,
+    }
+}
+
+// Claims types the principal of a request is read from, `APIClaims` and `ISCClaims` out of the
+// box. Services with their own claims type add it with `AuthzFairing::claims`.
+pub trait PolicyClaims: RevocableClaims + DeserializeOwned + Send + 'static {
+    /// The header carrying the bearer token, e.g. `Authorization`.
+    const HEADER: &'static str;
+
+    fn principal(self) -> Principal;
+}
+
+impl PolicyClaims for APIClaims {
+    const HEADER: &'static str = "X-API-Authorization";
+
+    fn principal(self) -> Principal {
+        Principal {
+            scopes: self.scopes,
+            group_id: Some(self.group_id),
+        }
+    }
+}
+
+impl PolicyClaims for ISCClaims {
+    const HEADER: &'static str = "X-ISC-Authorization";
+
+    fn principal(self) -> Principal {
+        Principal {
+            scopes: self.scopes,
+            group_id: None,
+        }
+    }
+}
+
+#[rocket::async_trait]
+trait PrincipalSource: Send + Sync {
+    async fn principal(&self, request: &Request<'_>) -> Option<Principal>;
+}
+
+struct ClaimsSource<T>(PhantomData<fn() -> T>);
+
+#[rocket::async_trait]
+impl<T: PolicyClaims> PrincipalSource for ClaimsSource<T> {
+    async fn principal(&self, request: &Request<'_>) -> Option<Principal> {
+        // Decoded without the guards, which would record their failure for the 401 catcher
+        let claims = authenticate::<T>(request, T::HEADER).await.ok()?;
+        Some(claims.principal())
+    }
+}
+
+// Enforces an `AuthzPolicy` before the handlers run. Denied requests are answered with the
+// standard `ApiError` body, see `rocket_errors::deny`.
+pub struct AuthzFairing {
+    policy: AuthzPolicy,
+    sources: Vec<Box<dyn PrincipalSource>>,
+}
+
+impl AuthzFairing {
+    pub fn new(policy: AuthzPolicy) -> Self {
+        AuthzFairing {
+            policy,
+            sources: vec![],
+        }
+        .claims::<APIClaims>()
+        .claims::<ISCClaims>()
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error>> {
+        Ok(Self::new(AuthzPolicy::from_file(path)?))
+    }
+
+    /// Also reads the principal from the claims type `T`, the scopes of every valid token of
+    /// the request adding up.
+    pub fn claims<T: PolicyClaims>(mut self) -> Self {
+        self.sources.push(Box::new(ClaimsSource::<T>(PhantomData)));
+        self
+    }
+
+    async fn principal_of(&self, request: &Request<'_>) -> Option<Principal> {
+        let mut principal: Option<Principal> = None;
+        for source in &self.sources {
+            if let Some(found) = source.principal(request).await {
+                let p = principal.get_or_insert_with(Principal::default);
+                p.scopes.extend(found.scopes);
+                p.group_id = p.group_id.or(found.group_id);
+            }
+        }
+        principal
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for AuthzFairing {
+    fn info(&self) -> Info {
+        Info {
+            name: "Policy based authorization",
+            kind: Kind::Ignite | Kind::Request,
+        }
+    }
+
+    async fn on_ignite(&self, rocket: Rocket<Build>) -> rocket::fairing::Result {
+        Ok(mount_denied(rocket))
+    }
+
+    async fn on_request(&self, request: &mut Request<'_>, _: &mut Data<'_>) {
+        let method = request.method().as_str();
+        // Rocket routes on the percent-decoded segments, so the rules are matched against them
+        // rather than the raw path, where `/%61dmin` would not match an `/admin` rule
+        let segments: Vec<&str> = request.uri().path().segments().collect();
+        if self.policy.matching_segments(method, &segments).is_empty() {
+            return;
+        }
+
+        let principal = self.principal_of(request).await;
+        let error = match self
+            .policy
+            .decide_segments(method, &segments, principal.as_ref())
+        {
+            AuthzDecision::Allow => return,
+            AuthzDecision::Unauthenticated => {
+                ApiError::unauthorized("Authentication is required to access this resource")
+            }
+            AuthzDecision::Forbidden => {
+                ApiError::forbidden("You are not allowed to access this resource")
+            }
+        };
+        deny(request, error);
+    }
+}
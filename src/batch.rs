@@ -0,0 +1,173 @@
+use rocket::futures::{future::BoxFuture, stream, FutureExt, StreamExt};
+use schemars::JsonSchema;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
+use std::{collections::HashMap, future::Future, panic::AssertUnwindSafe, sync::Arc};
+
+use crate::rocket_models::ApiError;
+
+const DEFAULT_MAX_ITEMS: usize = 50;
+const DEFAULT_CONCURRENCY: usize = 8;
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, JsonSchema)]
+pub struct BatchItem {
+    /// Echoed in the matching result, for clients to pair them.
+    #[serde(default)]
+    pub id: Option<String>,
+    /// Name of the registered operation, e.g. `get_user`.
+    pub op: String,
+    #[serde(default)]
+    pub body: Value,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, JsonSchema)]
+pub struct BatchRequest {
+    pub requests: Vec<BatchItem>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, JsonSchema)]
+pub struct BatchItemResult {
+    pub id: Option<String>,
+    pub status: u16,
+    /// The result of the operation, or an `ApiError` when `status` is not 200.
+    pub body: Value,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, JsonSchema)]
+pub struct BatchResponse {
+    /// One result per request, in the same order.
+    pub responses: Vec<BatchItemResult>,
+}
+
+type Handler<C> =
+    Arc<dyn Fn(C, Value) -> BoxFuture<'static, Result<Value, ApiError>> + Send + Sync>;
+
+// Runs many operations in one HTTP request, each failing on its own: a bad item gets its own
+// error status while the others succeed. Operations are registered by name with a context `C`
+// passed to each, typically the caller's claims, e.g.
+//
+// let router = BatchRouter::new()
+//     .register("get_user", |claims: Claims, input: UserId| async move { .. });
+//
+// #[post("/batch", data = "<batch>")]
+// async fn batch(router: &State<BatchRouter<Claims>>, claims: Claims, batch: Json<BatchRequest>)
+//     -> Result<Json<BatchResponse>, ApiError> {
+//     Ok(Json(router.dispatch(claims, batch.into_inner()).await?))
+// }
+pub struct BatchRouter<C> {
+    handlers: HashMap<String, Handler<C>>,
+    max_items: usize,
+    concurrency: usize,
+}
+
+impl<C> Default for BatchRouter<C> {
+    fn default() -> Self {
+        BatchRouter {
+            handlers: HashMap::new(),
+            max_items: DEFAULT_MAX_ITEMS,
+            concurrency: DEFAULT_CONCURRENCY,
+        }
+    }
+}
+
+impl<C: Clone + Send + 'static> BatchRouter<C> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers `handler` for the items of operation `op`, their `body` deserialized as `I`.
+    pub fn register<I, O, F, Fut>(mut self, op: &str, handler: F) -> Self
+    where
+        I: DeserializeOwned + Send + 'static,
+        O: Serialize + 'static,
+        F: Fn(C, I) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<O, ApiError>> + Send + 'static,
+    {
+        let handler = Arc::new(handler);
+        let boxed: Handler<C> = Arc::new(move |context, body| {
+            let handler = handler.clone();
+            async move {
+                let input: I = serde_json::from_value(body).map_err(|e| {
+                    ApiError::bad_request(&format!("Invalid body for this operation: {}", e))
+                })?;
+                let output = handler(context, input).await?;
+                serde_json::to_value(output).map_err(|e| ApiError::internal(&e.to_string()))
+            }
+            .boxed()
+        });
+        self.handlers.insert(op.to_string(), boxed);
+        self
+    }
+
+    /// Items accepted in one batch, larger batches are refused as a whole.
+    pub fn max_items(mut self, max_items: usize) -> Self {
+        self.max_items = max_items;
+        self
+    }
+
+    /// Items running at the same time.
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    pub fn operations(&self) -> Vec<&str> {
+        let mut operations: Vec<&str> = self.handlers.keys().map(String::as_str).collect();
+        operations.sort();
+        operations
+    }
+
+    async fn run(&self, context: C, item: BatchItem) -> BatchItemResult {
+        let result = match self.handlers.get(&item.op) {
+            Some(handler) => AssertUnwindSafe(handler(context, item.body))
+                .catch_unwind()
+                .await
+                .unwrap_or_else(|_| {
+                    tracing::error!(op = %item.op, "batch operation panicked");
+                    Err(ApiError::internal("The operation failed unexpectedly"))
+                }),
+            None => Err(ApiError::not_found(&format!(
+                "Unknown operation '{}'",
+                item.op
+            ))),
+        };
+        match result {
+            Ok(body) => BatchItemResult {
+                id: item.id,
+                status: 200,
+                body,
+            },
+            Err(error) => BatchItemResult {
+                id: item.id,
+                status: error.status,
+                body: serde_json::to_value(&error).unwrap_or(Value::Null),
+            },
+        }
+    }
+
+    /// Runs every item, `concurrency` at a time, and returns their results in order. Only a
+    /// batch over `max_items` fails as a whole, with a 413.
+    pub async fn dispatch(
+        &self,
+        context: C,
+        batch: BatchRequest,
+    ) -> Result<BatchResponse, ApiError> {
+        if batch.requests.len() > self.max_items {
+            return Err(ApiError::new(
+                413,
+                "batch_too_large",
+                &format!(
+                    "A batch holds at most {} requests, got {}",
+                    self.max_items,
+                    batch.requests.len()
+                ),
+            ));
+        }
+        let responses = stream::iter(batch.requests)
+            .map(|item| self.run(context.clone(), item))
+            .buffered(self.concurrency)
+            .collect()
+            .await;
+        Ok(BatchResponse { responses })
+    }
+}
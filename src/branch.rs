@@ -0,0 +1,72 @@
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+
+use crate::{git, Channel, ConsumerDBSchema, GingerDBConfig};
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum BranchSource {
+    Override,
+    Config,
+    Git,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct ResolvedBranch {
+    pub name: String,
+    pub channel: Option<Channel>,
+    pub source: BranchSource,
+}
+
+impl Channel {
+    pub fn from_branch(branch: &str) -> Option<Channel> {
+        match branch {
+            "main" | "master" => Some(Channel::Final),
+            "develop" | "dev" | "nightly" => Some(Channel::Nightly),
+            "alpha" => Some(Channel::Alpha),
+            "beta" => Some(Channel::Beta),
+            _ => None,
+        }
+    }
+}
+
+/// Determines the branch a tool operates on. An explicit override wins over the branch set in
+/// the config file, which wins over the branch currently checked out in the working directory.
+pub fn resolve_branch(
+    override_branch: Option<&str>,
+    config_branch: Option<&str>,
+) -> Result<ResolvedBranch, Box<dyn Error>> {
+    let (name, source) = match (non_empty(override_branch), non_empty(config_branch)) {
+        (Some(branch), _) => (branch.to_string(), BranchSource::Override),
+        (None, Some(branch)) => (branch.to_string(), BranchSource::Config),
+        (None, None) => (git::current_branch()?, BranchSource::Git),
+    };
+
+    Ok(ResolvedBranch {
+        channel: Channel::from_branch(&name),
+        name,
+        source,
+    })
+}
+
+impl ConsumerDBSchema {
+    pub fn resolve_branch(
+        &self,
+        override_branch: Option<&str>,
+    ) -> Result<ResolvedBranch, Box<dyn Error>> {
+        resolve_branch(override_branch, self.branch.as_deref())
+    }
+}
+
+impl GingerDBConfig {
+    pub fn resolve_branch(
+        &self,
+        override_branch: Option<&str>,
+    ) -> Result<ResolvedBranch, Box<dyn Error>> {
+        resolve_branch(override_branch, Some(&self.branch))
+    }
+}
+
+fn non_empty(branch: Option<&str>) -> Option<&str> {
+    branch.map(str::trim).filter(|b| !b.is_empty())
+}
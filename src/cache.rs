@@ -0,0 +1,308 @@
+use async_trait::async_trait;
+use std::{
+    collections::{BTreeMap, HashMap},
+    future::Future,
+    hash::Hash,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+#[async_trait]
+pub trait Cache<K, V>: Send + Sync
+where
+    K: Send + Sync + 'static,
+    V: Send + Sync + 'static,
+{
+    async fn get(&self, key: &K) -> Option<V>;
+
+    async fn set(&self, key: K, value: V, ttl: Duration);
+
+    async fn remove(&self, key: &K);
+
+    async fn clear(&self);
+
+    /// Returns the cached value for `key`, or runs `compute` and caches its result for `ttl`.
+    /// Errors are returned as is and never cached.
+    async fn get_or_compute<F, Fut, E>(&self, key: K, ttl: Duration, compute: F) -> Result<V, E>
+    where
+        K: Clone,
+        V: Clone,
+        F: FnOnce() -> Fut + Send,
+        Fut: Future<Output = Result<V, E>> + Send,
+        E: Send,
+    {
+        if let Some(value) = self.get(&key).await {
+            return Ok(value);
+        }
+        let value = compute().await?;
+        self.set(key, value.clone(), ttl).await;
+        Ok(value)
+    }
+}
+
+struct LruEntry<V> {
+    value: V,
+    expires_at: Instant,
+    last_used: u64,
+}
+
+struct LruState<K, V> {
+    entries: HashMap<K, LruEntry<V>>,
+    // Keys ordered by their last use, the first one is evicted when the cache is full
+    recency: BTreeMap<u64, K>,
+    tick: u64,
+}
+
+impl<K: Hash + Eq + Clone, V> LruState<K, V> {
+    fn next_tick(&mut self) -> u64 {
+        self.tick += 1;
+        self.tick
+    }
+
+    fn remove(&mut self, key: &K) -> Option<LruEntry<V>> {
+        let entry = self.entries.remove(key)?;
+        self.recency.remove(&entry.last_used);
+        Some(entry)
+    }
+}
+
+// In-process cache evicting the least recently used entry once `capacity` is reached
+pub struct LruCache<K, V> {
+    capacity: usize,
+    state: Mutex<LruState<K, V>>,
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> LruCache<K, V> {
+    pub fn new(capacity: usize) -> Self {
+        LruCache {
+            capacity: capacity.max(1),
+            state: Mutex::new(LruState {
+                entries: HashMap::new(),
+                recency: BTreeMap::new(),
+                tick: 0,
+            }),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.state.lock().unwrap().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn get_entry(&self, key: &K) -> Option<V> {
+        let mut state = self.state.lock().unwrap();
+        let tick = state.next_tick();
+        let entry = state.entries.get_mut(key)?;
+
+        if entry.expires_at <= Instant::now() {
+            state.remove(key);
+            return None;
+        }
+
+        let previous = entry.last_used;
+        entry.last_used = tick;
+        let value = entry.value.clone();
+        state.recency.remove(&previous);
+        state.recency.insert(tick, key.clone());
+        Some(value)
+    }
+
+    fn set_entry(&self, key: K, value: V, ttl: Duration) {
+        let mut state = self.state.lock().unwrap();
+        state.remove(&key);
+
+        if state.entries.len() >= self.capacity {
+            let oldest = state.recency.first_key_value().map(|(_, key)| key.clone());
+            if let Some(oldest) = oldest {
+                state.remove(&oldest);
+            }
+        }
+
+        let tick = state.next_tick();
+        state.recency.insert(tick, key.clone());
+        state.entries.insert(
+            key,
+            LruEntry {
+                value,
+                expires_at: Instant::now() + ttl,
+                last_used: tick,
+            },
+        );
+    }
+}
+
+#[async_trait]
+impl<K, V> Cache<K, V> for LruCache<K, V>
+where
+    K: Hash + Eq + Clone + Send + Sync + 'static,
+    V: Clone + Send + Sync + 'static,
+{
+    async fn get(&self, key: &K) -> Option<V> {
+        self.get_entry(key)
+    }
+
+    async fn set(&self, key: K, value: V, ttl: Duration) {
+        self.set_entry(key, value, ttl)
+    }
+
+    async fn remove(&self, key: &K) {
+        self.state.lock().unwrap().remove(key);
+    }
+
+    async fn clear(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.entries.clear();
+        state.recency.clear();
+    }
+}
+
+// A cache shared by the values built per request, e.g. dataloaders
+#[async_trait]
+impl<K, V, C> Cache<K, V> for Arc<C>
+where
+    K: Send + Sync + 'static,
+    V: Send + Sync + 'static,
+    C: Cache<K, V> + ?Sized,
+{
+    async fn get(&self, key: &K) -> Option<V> {
+        (**self).get(key).await
+    }
+
+    async fn set(&self, key: K, value: V, ttl: Duration) {
+        (**self).set(key, value, ttl).await
+    }
+
+    async fn remove(&self, key: &K) {
+        (**self).remove(key).await
+    }
+
+    async fn clear(&self) {
+        (**self).clear().await
+    }
+}
+
+#[cfg(feature = "redis")]
+pub use self::redis_cache::RedisCache;
+
+#[cfg(feature = "redis")]
+mod redis_cache {
+    use async_trait::async_trait;
+    use redis::{aio::ConnectionManager, AsyncCommands};
+    use serde::{de::DeserializeOwned, Serialize};
+    use std::{error::Error, fmt::Display, marker::PhantomData, time::Duration};
+
+    use super::Cache;
+
+    // Keys looked at per SCAN call when clearing
+    const SCAN_BATCH: usize = 500;
+
+    // Cache shared between replicas. Values are stored as JSON under `{prefix}:{key}`.
+    pub struct RedisCache<V> {
+        connection: ConnectionManager,
+        prefix: String,
+        _value: PhantomData<fn() -> V>,
+    }
+
+    impl<V> RedisCache<V> {
+        pub async fn new(url: &str, prefix: &str) -> Result<Self, Box<dyn Error>> {
+            let client = redis::Client::open(url)
+                .map_err(|e| format!("Invalid Redis URL '{}': {}", url, e))?;
+            let connection = ConnectionManager::new(client)
+                .await
+                .map_err(|e| format!("Failed to connect to Redis at '{}': {}", url, e))?;
+            Ok(RedisCache {
+                connection,
+                prefix: prefix.to_string(),
+                _value: PhantomData,
+            })
+        }
+
+        fn key<K: Display>(&self, key: &K) -> String {
+            format!("{}:{}", self.prefix, key)
+        }
+    }
+
+    #[async_trait]
+    impl<K, V> Cache<K, V> for RedisCache<V>
+    where
+        K: Display + Send + Sync + 'static,
+        V: Serialize + DeserializeOwned + Send + Sync + 'static,
+    {
+        async fn get(&self, key: &K) -> Option<V> {
+            let mut connection = self.connection.clone();
+            let raw: Option<String> = match connection.get(self.key(key)).await {
+                Ok(raw) => raw,
+                Err(e) => {
+                    tracing::warn!(error = %e, "failed to read from the Redis cache");
+                    None
+                }
+            };
+            raw.and_then(|raw| serde_json::from_str(&raw).ok())
+        }
+
+        async fn set(&self, key: K, value: V, ttl: Duration) {
+            let raw = match serde_json::to_string(&value) {
+                Ok(raw) => raw,
+                Err(e) => {
+                    tracing::warn!(error = %e, "failed to serialize a cache entry");
+                    return;
+                }
+            };
+            let mut connection = self.connection.clone();
+            let seconds = ttl.as_secs().max(1);
+            let result: redis::RedisResult<()> =
+                connection.set_ex(self.key(&key), raw, seconds).await;
+            if let Err(e) = result {
+                tracing::warn!(error = %e, "failed to write to the Redis cache");
+            }
+        }
+
+        async fn remove(&self, key: &K) {
+            let mut connection = self.connection.clone();
+            let result: redis::RedisResult<()> = connection.del(self.key(key)).await;
+            if let Err(e) = result {
+                tracing::warn!(error = %e, "failed to remove from the Redis cache");
+            }
+        }
+
+        // Walks the prefix with SCAN and deletes a batch at a time, KEYS would block Redis
+        async fn clear(&self) {
+            let mut connection = self.connection.clone();
+            let pattern = format!("{}:*", self.prefix);
+            let mut cursor: u64 = 0;
+            loop {
+                let scanned: redis::RedisResult<(u64, Vec<String>)> = redis::cmd("SCAN")
+                    .arg(cursor)
+                    .arg("MATCH")
+                    .arg(&pattern)
+                    .arg("COUNT")
+                    .arg(SCAN_BATCH)
+                    .query_async(&mut connection)
+                    .await;
+                let keys = match scanned {
+                    Ok((next, keys)) => {
+                        cursor = next;
+                        keys
+                    }
+                    Err(e) => {
+                        tracing::warn!(error = %e, "failed to list the Redis cache keys");
+                        return;
+                    }
+                };
+                if !keys.is_empty() {
+                    let result: redis::RedisResult<()> = connection.del(keys).await;
+                    if let Err(e) = result {
+                        tracing::warn!(error = %e, "failed to clear the Redis cache");
+                        return;
+                    }
+                }
+                if cursor == 0 {
+                    return;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,457 @@
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, error::Error, fs, path::Path, time::Duration};
+
+#[cfg(feature = "codegen")]
+use crate::{codegen::templates::field_name, LANG};
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CacheValueType {
+    String,
+    Integer,
+    Json,
+    Hash,
+    List,
+    Set,
+    SortedSet,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(tag = "policy", rename_all = "snake_case")]
+pub enum TtlPolicy {
+    #[default]
+    None,
+    Fixed {
+        seconds: u64,
+    },
+    Sliding {
+        seconds: u64, // Extended on every read
+    },
+}
+
+impl TtlPolicy {
+    pub fn duration(&self) -> Option<Duration> {
+        match self {
+            TtlPolicy::None => None,
+            TtlPolicy::Fixed { seconds } | TtlPolicy::Sliding { seconds } => {
+                Some(Duration::from_secs(*seconds))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyParamType {
+    #[default]
+    String,
+    Integer,
+    Uuid,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct KeyParam {
+    pub name: String,
+    #[serde(default)]
+    pub param_type: KeyParamType,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum KeyPart {
+    Literal(String),
+    Placeholder(String),
+}
+
+// A named key pattern, e.g. `user:{user_id}:sessions`, always prefixed with the schema namespace
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct CacheKeySchema {
+    pub name: String,
+    pub pattern: String,
+    #[serde(default)]
+    pub params: Vec<KeyParam>, // One per placeholder, untyped placeholders are strings
+    pub value_type: CacheValueType,
+    #[serde(default)]
+    pub ttl: TtlPolicy,
+}
+
+impl CacheKeySchema {
+    /// Splits the pattern into literal text and `{placeholder}`s.
+    pub fn parts(&self) -> Result<Vec<KeyPart>, String> {
+        let mut parts = vec![];
+        let mut rest = self.pattern.as_str();
+        while !rest.is_empty() {
+            match rest.find(['{', '}']) {
+                Some(start) if rest[start..].starts_with('{') => {
+                    if start > 0 {
+                        parts.push(KeyPart::Literal(rest[..start].to_string()));
+                    }
+                    let end = rest[start..].find('}').ok_or_else(|| {
+                        format!("The key pattern '{}' has an unclosed '{{'", self.pattern)
+                    })?;
+                    parts.push(KeyPart::Placeholder(
+                        rest[start + 1..start + end].to_string(),
+                    ));
+                    rest = &rest[start + end + 1..];
+                }
+                Some(_) => {
+                    return Err(format!(
+                        "The key pattern '{}' has an unmatched '}}'",
+                        self.pattern
+                    ))
+                }
+                None => {
+                    parts.push(KeyPart::Literal(rest.to_string()));
+                    rest = "";
+                }
+            }
+        }
+        Ok(parts)
+    }
+
+    pub fn placeholders(&self) -> Vec<String> {
+        self.parts()
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|part| match part {
+                KeyPart::Placeholder(name) => Some(name),
+                KeyPart::Literal(_) => None,
+            })
+            .collect()
+    }
+
+    pub fn param_type(&self, placeholder: &str) -> KeyParamType {
+        self.params
+            .iter()
+            .find(|p| p.name == placeholder)
+            .map(|p| p.param_type)
+            .unwrap_or_default()
+    }
+
+    // The pattern with every placeholder as `*`, equal shapes build the same keys
+    fn shape(&self) -> String {
+        self.parts()
+            .unwrap_or_default()
+            .into_iter()
+            .map(|part| match part {
+                KeyPart::Literal(text) => text,
+                KeyPart::Placeholder(_) => "*".to_string(),
+            })
+            .collect()
+    }
+}
+
+fn is_identifier(name: &str) -> bool {
+    name.chars().next().is_some_and(|c| c.is_ascii_alphabetic())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn is_key_text(text: &str) -> bool {
+    text.chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, ':' | '_' | '-' | '.'))
+}
+
+// The schema behind `ConsumerDBSchema::cache_schema_id`: the Redis keys a service owns, all
+// under its `namespace`
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct CacheSchema {
+    pub schema_id: String,
+    pub branch: String,
+    pub version: Option<String>,
+    pub namespace: String,
+    #[serde(default)]
+    pub keys: Vec<CacheKeySchema>,
+}
+
+impl CacheSchema {
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error>> {
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path).map_err(|e| {
+            format!(
+                "Failed to read the cache schema '{}': {}",
+                path.display(),
+                e
+            )
+        })?;
+        Ok(serde_json::from_str(&contents)?)
+    }
+
+    pub fn key(&self, name: &str) -> Option<&CacheKeySchema> {
+        self.keys.iter().find(|k| k.name == name)
+    }
+
+    /// The full key `name` builds for `args`, e.g. `iam:user:42:sessions`.
+    pub fn build_key(&self, name: &str, args: &[(&str, &str)]) -> Result<String, Box<dyn Error>> {
+        let key = self.key(name).ok_or_else(|| {
+            format!(
+                "The key '{}' is not part of the cache schema '{}'",
+                name, self.schema_id
+            )
+        })?;
+        let args: HashMap<&str, &str> = args.iter().copied().collect();
+        let mut built = format!("{}:", self.namespace);
+        for part in key.parts()? {
+            match part {
+                KeyPart::Literal(text) => built.push_str(&text),
+                KeyPart::Placeholder(placeholder) => {
+                    let value = args.get(placeholder.as_str()).ok_or_else(|| {
+                        format!("The key '{}' needs a value for '{}'", name, placeholder)
+                    })?;
+                    if value.is_empty() || !is_key_text(value) {
+                        return Err(format!(
+                            "'{}' is not a valid value for '{}' of the key '{}'",
+                            value, placeholder, name
+                        )
+                        .into());
+                    }
+                    built.push_str(value);
+                }
+            }
+        }
+        Ok(built)
+    }
+
+    /// Problems with the schema: invalid namespace, duplicate names, malformed patterns or
+    /// placeholders, parameters without a placeholder and patterns building the same keys.
+    pub fn validate(&self) -> Vec<String> {
+        let mut problems = vec![];
+        if self.namespace.is_empty()
+            || self.namespace.contains(':')
+            || !is_key_text(&self.namespace)
+        {
+            problems.push(format!(
+                "The namespace '{}' must be non-empty without ':' or special characters",
+                self.namespace
+            ));
+        }
+
+        for (index, key) in self.keys.iter().enumerate() {
+            if !is_identifier(&key.name) {
+                problems.push(format!("The key name '{}' is not an identifier", key.name));
+            }
+            if self.keys[..index].iter().any(|k| k.name == key.name) {
+                problems.push(format!("The key '{}' is defined more than once", key.name));
+            }
+            let parts = match key.parts() {
+                Ok(parts) => parts,
+                Err(problem) => {
+                    problems.push(problem);
+                    continue;
+                }
+            };
+            let mut placeholders = vec![];
+            for part in &parts {
+                match part {
+                    KeyPart::Literal(text) if !is_key_text(text) => problems.push(format!(
+                        "The key pattern '{}' has characters outside [A-Za-z0-9:_-.]",
+                        key.pattern
+                    )),
+                    KeyPart::Literal(_) => {}
+                    KeyPart::Placeholder(name) => {
+                        if !is_identifier(name) {
+                            problems.push(format!(
+                                "The placeholder '{{{}}}' of '{}' is not an identifier",
+                                name, key.name
+                            ));
+                        } else if placeholders.contains(name) {
+                            problems.push(format!(
+                                "The placeholder '{{{}}}' is used twice in '{}'",
+                                name, key.name
+                            ));
+                        }
+                        placeholders.push(name.clone());
+                    }
+                }
+            }
+            if parts
+                .windows(2)
+                .any(|w| matches!(w, [KeyPart::Placeholder(_), KeyPart::Placeholder(_)]))
+            {
+                problems.push(format!(
+                    "The key pattern '{}' has adjacent placeholders",
+                    key.pattern
+                ));
+            }
+            for param in &key.params {
+                if !placeholders.contains(&param.name) {
+                    problems.push(format!(
+                        "The parameter '{}' of '{}' is not used in its pattern",
+                        param.name, key.name
+                    ));
+                }
+            }
+            if let TtlPolicy::Fixed { seconds: 0 } | TtlPolicy::Sliding { seconds: 0 } = key.ttl {
+                problems.push(format!("The key '{}' has a TTL of 0 seconds", key.name));
+            }
+            if let Some(other) = self.keys[..index]
+                .iter()
+                .find(|k| k.name != key.name && k.shape() == key.shape())
+            {
+                problems.push(format!(
+                    "The keys '{}' and '{}' build the same keys",
+                    other.name, key.name
+                ));
+            }
+        }
+        problems
+    }
+
+    /// Key builder functions for `lang`, one per key, to include in generated code.
+    #[cfg(feature = "codegen")]
+    pub fn render_key_builders(&self, lang: &LANG) -> Result<String, Box<dyn Error>> {
+        use std::fmt::Write;
+
+        let problems = self.validate();
+        if !problems.is_empty() {
+            return Err(format!(
+                "The cache schema '{}' is invalid: {}",
+                self.schema_id,
+                problems.join("; ")
+            )
+            .into());
+        }
+
+        let mut rendered = String::new();
+        for key in &self.keys {
+            let function = field_name(lang, &format!("{}_key", key.name));
+            let parts = key.parts()?;
+            let ttl = match key.ttl.duration() {
+                Some(ttl) => format!(", TTL {}s", ttl.as_secs()),
+                None => String::new(),
+            };
+            let comment = format!("`{}:{}`{}", self.namespace, key.pattern, ttl);
+            let params: Vec<(String, KeyParamType)> = key
+                .placeholders()
+                .into_iter()
+                .map(|p| (field_name(lang, &p), key.param_type(&p)))
+                .collect();
+            let template = |placeholder: &dyn Fn(usize, &str) -> String| -> String {
+                let mut index = 0;
+                let mut text = format!("{}:", self.namespace);
+                for part in &parts {
+                    match part {
+                        KeyPart::Literal(literal) => text.push_str(literal),
+                        KeyPart::Placeholder(name) => {
+                            text.push_str(&placeholder(index, &field_name(lang, name)));
+                            index += 1;
+                        }
+                    }
+                }
+                text
+            };
+
+            match lang {
+                LANG::Rust => {
+                    let args: Vec<String> = params
+                        .iter()
+                        .map(|(name, param_type)| {
+                            let rust_type = match param_type {
+                                KeyParamType::Integer => "i64",
+                                KeyParamType::String | KeyParamType::Uuid => "&str",
+                            };
+                            format!("{}: {}", name, rust_type)
+                        })
+                        .collect();
+                    let values: Vec<&str> = params.iter().map(|(name, _)| name.as_str()).collect();
+                    writeln!(rendered, "/// {}", comment)?;
+                    writeln!(
+                        rendered,
+                        "pub fn {}({}) -> String {{",
+                        function,
+                        args.join(", ")
+                    )?;
+                    if values.is_empty() {
+                        writeln!(
+                            rendered,
+                            "    \"{}\".to_string()",
+                            template(&|_, _| String::new())
+                        )?;
+                    } else {
+                        writeln!(
+                            rendered,
+                            "    format!(\"{}\", {})",
+                            template(&|_, _| "{}".to_string()),
+                            values.join(", ")
+                        )?;
+                    }
+                    writeln!(rendered, "}}\n")?;
+                }
+                LANG::TS => {
+                    let args: Vec<String> = params
+                        .iter()
+                        .map(|(name, param_type)| {
+                            let ts_type = match param_type {
+                                KeyParamType::Integer => "number",
+                                KeyParamType::String | KeyParamType::Uuid => "string",
+                            };
+                            format!("{}: {}", name, ts_type)
+                        })
+                        .collect();
+                    writeln!(rendered, "/** {} */", comment)?;
+                    writeln!(
+                        rendered,
+                        "export const {} = ({}): string => `{}`;\n",
+                        function,
+                        args.join(", "),
+                        template(&|_, name| format!("${{{}}}", name))
+                    )?;
+                }
+                LANG::Python => {
+                    let args: Vec<String> = params
+                        .iter()
+                        .map(|(name, param_type)| {
+                            let py_type = match param_type {
+                                KeyParamType::Integer => "int",
+                                KeyParamType::String | KeyParamType::Uuid => "str",
+                            };
+                            format!("{}: {}", name, py_type)
+                        })
+                        .collect();
+                    writeln!(rendered, "def {}({}) -> str:", function, args.join(", "))?;
+                    writeln!(rendered, "    \"\"\"{}\"\"\"", comment)?;
+                    let prefix = if params.is_empty() { "" } else { "f" };
+                    writeln!(
+                        rendered,
+                        "    return {}\"{}\"\n\n",
+                        prefix,
+                        template(&|_, name| format!("{{{}}}", name))
+                    )?;
+                }
+                LANG::Shell => {
+                    writeln!(rendered, "# {}", comment)?;
+                    writeln!(
+                        rendered,
+                        "{}() {{\n    echo \"{}\"\n}}\n",
+                        function,
+                        template(&|index, _| format!("${}", index + 1))
+                    )?;
+                }
+            }
+        }
+        Ok(format!("{}\n", rendered.trim_end()))
+    }
+}
+
+/// Namespaces claimed by more than one schema, the collisions teams run into when nothing
+/// defines the keys.
+pub fn namespace_conflicts(schemas: &[CacheSchema]) -> Vec<String> {
+    let mut claimed: HashMap<&str, Vec<&str>> = HashMap::new();
+    for schema in schemas {
+        claimed
+            .entry(schema.namespace.as_str())
+            .or_default()
+            .push(schema.schema_id.as_str());
+    }
+    let mut conflicts: Vec<String> = claimed
+        .into_iter()
+        .filter(|(_, ids)| ids.len() > 1)
+        .map(|(namespace, ids)| {
+            format!(
+                "The namespace '{}' is used by {}",
+                namespace,
+                ids.join(", ")
+            )
+        })
+        .collect();
+    conflicts.sort();
+    conflicts
+}
@@ -0,0 +1,213 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    collections::HashMap,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+// The `aud` claim may either be a single string or a list of strings
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum Audience {
+    Single(String),
+    Multiple(Vec<String>),
+}
+
+impl Audience {
+    pub fn contains(&self, audience: &str) -> bool {
+        match self {
+            Audience::Single(aud) => aud == audience,
+            Audience::Multiple(auds) => auds.iter().any(|aud| aud == audience),
+        }
+    }
+}
+
+// Claims of the user access / refresh tokens sent in the `Authorization` header
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Claims {
+    pub sub: String,
+    pub exp: usize,
+    pub user_id: String,
+    pub token_type: String, // Add token_type to distinguish between access and refresh tokens
+    pub first_name: Option<String>,
+    pub last_name: Option<String>,
+    pub middle_name: Option<String>,
+    pub client_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub iss: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub aud: Option<Audience>,
+    #[serde(flatten)]
+    pub extra: HashMap<String, Value>, // Service specific claims, e.g. tenant or feature flags
+}
+
+impl Claims {
+    pub fn new(sub: &str, user_id: &str, token_type: &str, valid_for: Duration) -> Self {
+        Claims {
+            sub: sub.to_string(),
+            exp: expires_in(valid_for),
+            user_id: user_id.to_string(),
+            token_type: token_type.to_string(),
+            first_name: None,
+            last_name: None,
+            middle_name: None,
+            client_id: None,
+            iss: None,
+            aud: None,
+            extra: issued_now(),
+        }
+    }
+
+    pub fn with_name(
+        mut self,
+        first_name: &str,
+        middle_name: Option<&str>,
+        last_name: &str,
+    ) -> Self {
+        self.first_name = Some(first_name.to_string());
+        self.middle_name = middle_name.map(str::to_string);
+        self.last_name = Some(last_name.to_string());
+        self
+    }
+
+    pub fn with_client_id(mut self, client_id: &str) -> Self {
+        self.client_id = Some(client_id.to_string());
+        self
+    }
+
+    pub fn with_issuer(mut self, issuer: &str) -> Self {
+        self.iss = Some(issuer.to_string());
+        self
+    }
+
+    pub fn with_audience(mut self, audience: &str) -> Self {
+        self.aud = Some(Audience::Single(audience.to_string()));
+        self
+    }
+
+    pub fn with_extra(mut self, key: &str, value: Value) -> Self {
+        self.extra.insert(key.to_string(), value);
+        self
+    }
+}
+
+// Claims of the API tokens sent in the `X-API-Authorization` header
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct APIClaims {
+    pub sub: String,
+    pub exp: usize,
+    pub group_id: i64,
+    pub scopes: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub iss: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub aud: Option<Audience>,
+    #[serde(flatten)]
+    pub extra: HashMap<String, Value>, // Service specific claims, e.g. tenant or feature flags
+}
+
+impl APIClaims {
+    pub fn new(sub: &str, group_id: i64, valid_for: Duration) -> Self {
+        APIClaims {
+            sub: sub.to_string(),
+            exp: expires_in(valid_for),
+            group_id,
+            scopes: vec![],
+            iss: None,
+            aud: None,
+            extra: issued_now(),
+        }
+    }
+
+    pub fn with_scopes(mut self, scopes: &[&str]) -> Self {
+        self.scopes = scopes.iter().map(|s| s.to_string()).collect();
+        self
+    }
+
+    pub fn has_scope(&self, scope: &str) -> bool {
+        self.scopes.iter().any(|s| s == scope)
+    }
+
+    pub fn with_issuer(mut self, issuer: &str) -> Self {
+        self.iss = Some(issuer.to_string());
+        self
+    }
+
+    pub fn with_audience(mut self, audience: &str) -> Self {
+        self.aud = Some(Audience::Single(audience.to_string()));
+        self
+    }
+
+    pub fn with_extra(mut self, key: &str, value: Value) -> Self {
+        self.extra.insert(key.to_string(), value);
+        self
+    }
+}
+
+// Claims of the inter service communication tokens sent in the `X-ISC-Authorization` header
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ISCClaims {
+    pub sub: String,
+    pub exp: usize,
+    pub org_id: String,
+    pub scopes: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub iss: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub aud: Option<Audience>,
+    #[serde(flatten)]
+    pub extra: HashMap<String, Value>, // Service specific claims, e.g. tenant or feature flags
+}
+
+impl ISCClaims {
+    pub fn new(sub: &str, org_id: &str, valid_for: Duration) -> Self {
+        ISCClaims {
+            sub: sub.to_string(),
+            exp: expires_in(valid_for),
+            org_id: org_id.to_string(),
+            scopes: vec![],
+            iss: None,
+            aud: None,
+            extra: issued_now(),
+        }
+    }
+
+    pub fn with_scopes(mut self, scopes: &[&str]) -> Self {
+        self.scopes = scopes.iter().map(|s| s.to_string()).collect();
+        self
+    }
+
+    pub fn has_scope(&self, scope: &str) -> bool {
+        self.scopes.iter().any(|s| s == scope)
+    }
+
+    pub fn with_issuer(mut self, issuer: &str) -> Self {
+        self.iss = Some(issuer.to_string());
+        self
+    }
+
+    pub fn with_audience(mut self, audience: &str) -> Self {
+        self.aud = Some(Audience::Single(audience.to_string()));
+        self
+    }
+
+    pub fn with_extra(mut self, key: &str, value: Value) -> Self {
+        self.extra.insert(key.to_string(), value);
+        self
+    }
+}
+
+// The extra claims of a new token: its `iat`, which subject revocations are compared with
+pub(crate) fn issued_now() -> HashMap<String, Value> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    HashMap::from([("iat".to_string(), Value::from(now.as_secs()))])
+}
+
+fn expires_in(valid_for: Duration) -> usize {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    (now + valid_for).as_secs() as usize
+}
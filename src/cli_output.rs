@@ -0,0 +1,383 @@
+use clap::{Args, ValueEnum};
+use colored::Colorize;
+use indicatif::{ProgressBar, ProgressStyle};
+use serde::{Deserialize, Serialize};
+use std::{
+    env,
+    error::Error,
+    fmt,
+    io::{self, IsTerminal},
+};
+
+use crate::cli_result::CliResult;
+
+#[derive(ValueEnum, Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    #[default]
+    Table,
+    Json,
+    Yaml,
+}
+
+impl fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OutputFormat::Table => write!(f, "table"),
+            OutputFormat::Json => write!(f, "json"),
+            OutputFormat::Yaml => write!(f, "yaml"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum Verbosity {
+    Quiet, // Results and errors only
+    #[default]
+    Normal,
+    Verbose, // Also the details of each step
+}
+
+impl Verbosity {
+    pub fn from_flags(quiet: bool, verbose: bool) -> Self {
+        match (quiet, verbose) {
+            (true, _) => Verbosity::Quiet,
+            (false, true) => Verbosity::Verbose,
+            (false, false) => Verbosity::Normal,
+        }
+    }
+}
+
+// The output flags every ginger tool accepts, to `#[command(flatten)]` into its CLI
+#[derive(Args, Debug, Clone, Default, PartialEq)]
+pub struct OutputArgs {
+    /// Output format of the results
+    #[arg(long, value_enum, default_value_t, global = true)]
+    pub output: OutputFormat,
+    /// Shorthand for `--output json`
+    #[arg(long, global = true)]
+    pub json: bool,
+    /// Only print results and errors
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    pub quiet: bool,
+    /// Print the details of each step
+    #[arg(short, long, global = true)]
+    pub verbose: bool,
+    /// Disable colors, also disabled by the NO_COLOR variable and when not writing to a terminal
+    #[arg(long, global = true)]
+    pub no_color: bool,
+}
+
+impl OutputArgs {
+    pub fn printer(&self) -> Printer {
+        let format = if self.json {
+            OutputFormat::Json
+        } else {
+            self.output
+        };
+        let printer = Printer::new(format, Verbosity::from_flags(self.quiet, self.verbose));
+        if self.no_color {
+            printer.color(false)
+        } else {
+            printer
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Success,
+    Info,
+    Warning,
+    Error,
+    Skipped,
+}
+
+impl Status {
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            Status::Success => "✔",
+            Status::Info => "ℹ",
+            Status::Warning => "⚠",
+            Status::Error => "✖",
+            Status::Skipped => "-",
+        }
+    }
+
+    fn colored_symbol(&self) -> String {
+        let symbol = self.symbol();
+        match self {
+            Status::Success => symbol.green(),
+            Status::Info => symbol.blue(),
+            Status::Warning => symbol.yellow(),
+            Status::Error => symbol.red(),
+            Status::Skipped => symbol.dimmed(),
+        }
+        .to_string()
+    }
+}
+
+// A plain text table with columns padded to their widest cell
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Table {
+    pub headers: Vec<String>,
+    pub rows: Vec<Vec<String>>,
+}
+
+impl Table {
+    pub fn new(headers: &[&str]) -> Self {
+        Table {
+            headers: headers.iter().map(|h| h.to_string()).collect(),
+            rows: vec![],
+        }
+    }
+
+    pub fn row<I, S>(mut self, cells: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: ToString,
+    {
+        self.add_row(cells);
+        self
+    }
+
+    pub fn add_row<I, S>(&mut self, cells: I)
+    where
+        I: IntoIterator<Item = S>,
+        S: ToString,
+    {
+        self.rows
+            .push(cells.into_iter().map(|c| c.to_string()).collect());
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    fn widths(&self) -> Vec<usize> {
+        let columns = self
+            .rows
+            .iter()
+            .map(Vec::len)
+            .chain([self.headers.len()])
+            .max()
+            .unwrap_or(0);
+        (0..columns)
+            .map(|column| {
+                self.rows
+                    .iter()
+                    .chain([&self.headers])
+                    .filter_map(|row| row.get(column))
+                    .map(|cell| cell.chars().count())
+                    .max()
+                    .unwrap_or(0)
+            })
+            .collect()
+    }
+
+    fn render_row(row: &[String], widths: &[usize]) -> String {
+        let cells: Vec<String> = widths
+            .iter()
+            .enumerate()
+            .map(|(column, width)| {
+                format!(
+                    "{:<width$}",
+                    row.get(column).map(String::as_str).unwrap_or(""),
+                    width = width
+                )
+            })
+            .collect();
+        cells.join("  ").trim_end().to_string()
+    }
+
+    pub fn render(&self, color: bool) -> String {
+        let widths = self.widths();
+        let mut lines = vec![];
+        if !self.headers.is_empty() {
+            let header = Self::render_row(&self.headers, &widths);
+            lines.push(if color {
+                header.bold().to_string()
+            } else {
+                header
+            });
+        }
+        lines.extend(self.rows.iter().map(|row| Self::render_row(row, &widths)));
+        lines.join("\n")
+    }
+}
+
+impl fmt::Display for Table {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.render(false))
+    }
+}
+
+fn color_supported() -> bool {
+    env::var_os("NO_COLOR").is_none() && io::stdout().is_terminal()
+}
+
+// Prints results and progress of a CLI command. Results go to stdout in the chosen format,
+// messages go to stdout for tables and to stderr for JSON and YAML so the output stays parseable.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Printer {
+    pub format: OutputFormat,
+    pub verbosity: Verbosity,
+    color: bool,
+}
+
+impl Default for Printer {
+    fn default() -> Self {
+        Printer::new(OutputFormat::Table, Verbosity::Normal)
+    }
+}
+
+impl Printer {
+    pub fn new(format: OutputFormat, verbosity: Verbosity) -> Self {
+        Printer {
+            format,
+            verbosity,
+            color: color_supported(),
+        }
+    }
+
+    pub fn color(mut self, color: bool) -> Self {
+        self.color = color;
+        self
+    }
+
+    pub fn is_quiet(&self) -> bool {
+        self.verbosity == Verbosity::Quiet
+    }
+
+    pub fn is_verbose(&self) -> bool {
+        self.verbosity == Verbosity::Verbose
+    }
+
+    fn message(&self, line: &str) {
+        match self.format {
+            OutputFormat::Table => println!("{}", line),
+            OutputFormat::Json | OutputFormat::Yaml => eprintln!("{}", line),
+        }
+    }
+
+    /// `message` prefixed with the symbol of `status`. Errors are always printed (to stderr),
+    /// other statuses are hidden by `--quiet`.
+    pub fn status(&self, status: Status, message: &str) {
+        let symbol = if self.color {
+            status.colored_symbol()
+        } else {
+            status.symbol().to_string()
+        };
+        let line = format!("{} {}", symbol, message);
+        if status == Status::Error {
+            eprintln!("{}", line);
+        } else if !self.is_quiet() {
+            self.message(&line);
+        }
+    }
+
+    pub fn success(&self, message: &str) {
+        self.status(Status::Success, message)
+    }
+
+    pub fn info(&self, message: &str) {
+        self.status(Status::Info, message)
+    }
+
+    pub fn warn(&self, message: &str) {
+        self.status(Status::Warning, message)
+    }
+
+    pub fn error(&self, message: &str) {
+        self.status(Status::Error, message)
+    }
+
+    /// A message only printed with `--verbose`.
+    pub fn detail(&self, message: &str) {
+        if self.is_verbose() {
+            let line = if self.color {
+                message.dimmed().to_string()
+            } else {
+                message.to_string()
+            };
+            self.message(&line);
+        }
+    }
+
+    /// Renders `value` in the output format, `table` being its table form.
+    pub fn render<T: Serialize>(&self, value: &T, table: &Table) -> Result<String, Box<dyn Error>> {
+        Ok(match self.format {
+            OutputFormat::Table => table.render(self.color),
+            OutputFormat::Json => serde_json::to_string_pretty(value)?,
+            OutputFormat::Yaml => serde_yaml::to_string(value)?.trim_end().to_string(),
+        })
+    }
+
+    /// Prints the result of the command, whatever the verbosity.
+    pub fn print<T: Serialize>(&self, value: &T, table: &Table) -> Result<(), Box<dyn Error>> {
+        println!("{}", self.render(value, table)?);
+        Ok(())
+    }
+
+    /// Prints the outcome of the command and returns its exit code. JSON and YAML get the whole
+    /// `CliResult`, tables get the warnings and errors as status lines followed by the data.
+    pub fn finish<T, F>(&self, result: &CliResult<T>, table: F) -> i32
+    where
+        T: Serialize,
+        F: FnOnce(&T) -> Table,
+    {
+        let rendered = match self.format {
+            OutputFormat::Table => {
+                for warning in &result.warnings {
+                    self.warn(&warning.message);
+                }
+                for error in &result.errors {
+                    self.error(&error.message);
+                }
+                result
+                    .data
+                    .as_ref()
+                    .map(|data| Ok(table(data).render(self.color)))
+            }
+            OutputFormat::Json => Some(Ok(result.to_json())),
+            OutputFormat::Yaml => Some(
+                serde_yaml::to_string(result)
+                    .map(|yaml| yaml.trim_end().to_string())
+                    .map_err(|e| e.to_string()),
+            ),
+        };
+        match rendered {
+            Some(Ok(output)) if !output.is_empty() => println!("{}", output),
+            Some(Err(e)) => {
+                self.error(&format!("Failed to render the result: {}", e));
+                return 1;
+            }
+            _ => {}
+        }
+        result.exit_code()
+    }
+
+    /// A progress bar of `len` steps, hidden with `--quiet`, JSON or YAML output and when stderr is
+    /// not a terminal.
+    pub fn progress(&self, len: u64, message: &str) -> ProgressBar {
+        if self.is_quiet() || self.format != OutputFormat::Table || !io::stderr().is_terminal() {
+            return ProgressBar::hidden();
+        }
+        let style = ProgressStyle::with_template("{msg} [{bar:30}] {pos}/{len} ({eta})")
+            .unwrap_or_else(|_| ProgressStyle::default_bar())
+            .progress_chars("=> ");
+        ProgressBar::new(len)
+            .with_style(style)
+            .with_message(message.to_string())
+    }
+
+    /// A spinner for steps of unknown length, hidden like `progress`.
+    pub fn spinner(&self, message: &str) -> ProgressBar {
+        if self.is_quiet() || self.format != OutputFormat::Table || !io::stderr().is_terminal() {
+            return ProgressBar::hidden();
+        }
+        let spinner = ProgressBar::new_spinner().with_message(message.to_string());
+        spinner.enable_steady_tick(std::time::Duration::from_millis(100));
+        spinner
+    }
+}
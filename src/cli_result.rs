@@ -0,0 +1,140 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CliStatus {
+    Ok,
+    Warning, // Done, with warnings
+    Error,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct CliMessage {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub code: Option<String>, // Stable identifier scripts can match on, e.g. `config_not_found`
+    pub message: String,
+}
+
+impl CliMessage {
+    pub fn new(message: &str) -> Self {
+        CliMessage {
+            code: None,
+            message: message.to_string(),
+        }
+    }
+
+    pub fn with_code(code: &str, message: &str) -> Self {
+        CliMessage {
+            code: Some(code.to_string()),
+            message: message.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for CliMessage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+// What a ginger CLI prints with `--json`, the same shape for every command:
+// `{"status": "ok", "data": ..., "warnings": [], "errors": []}`
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct CliResult<T> {
+    pub status: CliStatus,
+    pub data: Option<T>,
+    #[serde(default)]
+    pub warnings: Vec<CliMessage>,
+    #[serde(default)]
+    pub errors: Vec<CliMessage>,
+}
+
+impl<T> CliResult<T> {
+    pub fn ok(data: T) -> Self {
+        CliResult {
+            status: CliStatus::Ok,
+            data: Some(data),
+            warnings: vec![],
+            errors: vec![],
+        }
+    }
+
+    pub fn failure(message: &str) -> Self {
+        CliResult {
+            status: CliStatus::Error,
+            data: None,
+            warnings: vec![],
+            errors: vec![CliMessage::new(message)],
+        }
+    }
+
+    pub fn from_result<E: fmt::Display>(result: Result<T, E>) -> Self {
+        match result {
+            Ok(data) => CliResult::ok(data),
+            Err(e) => CliResult::failure(&e.to_string()),
+        }
+    }
+
+    fn update_status(&mut self) {
+        self.status = if !self.errors.is_empty() {
+            CliStatus::Error
+        } else if !self.warnings.is_empty() {
+            CliStatus::Warning
+        } else {
+            CliStatus::Ok
+        };
+    }
+
+    pub fn warning(mut self, warning: CliMessage) -> Self {
+        self.warnings.push(warning);
+        self.update_status();
+        self
+    }
+
+    pub fn warnings<I: IntoIterator<Item = String>>(mut self, warnings: I) -> Self {
+        self.warnings
+            .extend(warnings.into_iter().map(|w| CliMessage::new(&w)));
+        self.update_status();
+        self
+    }
+
+    pub fn error(mut self, error: CliMessage) -> Self {
+        self.errors.push(error);
+        self.update_status();
+        self
+    }
+
+    pub fn is_success(&self) -> bool {
+        self.status != CliStatus::Error
+    }
+
+    /// 0 unless the command failed.
+    pub fn exit_code(&self) -> i32 {
+        if self.is_success() {
+            0
+        } else {
+            1
+        }
+    }
+
+    pub fn map<U, F: FnOnce(T) -> U>(self, f: F) -> CliResult<U> {
+        CliResult {
+            status: self.status,
+            data: self.data.map(f),
+            warnings: self.warnings,
+            errors: self.errors,
+        }
+    }
+}
+
+impl<T: Serialize> CliResult<T> {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap_or_else(|e| {
+            format!(
+                "{{\"status\":\"error\",\"data\":null,\"warnings\":[],\"errors\":[{{\"message\":{}}}]}}",
+                serde_json::Value::String(format!("Failed to serialize the result: {}", e))
+            )
+        })
+    }
+}
@@ -0,0 +1,220 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    fmt, fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{config_io::write_atomic, provenance::sha256_file};
+
+pub const CODEGEN_LOCK_FILE: &str = ".ginger-society/codegen.lock";
+
+/// Hash of whatever a file is generated from, e.g. a `TableSchema`. Object keys are sorted
+/// before hashing so map ordering cannot change the hash.
+pub fn source_hash<S: Serialize>(source: &S) -> Result<String, Box<dyn Error>> {
+    let canonical = serde_json::to_vec(&sorted_keys(serde_json::to_value(source)?))?;
+    Ok(Sha256::digest(&canonical)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect())
+}
+
+// `serde_json::Map` keeps insertion order with `preserve_order`, so objects are rebuilt from
+// their entries sorted by key
+fn sorted_keys(value: serde_json::Value) -> serde_json::Value {
+    match value {
+        serde_json::Value::Object(map) => {
+            let mut entries: Vec<_> = map.into_iter().collect();
+            entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+            serde_json::Value::Object(
+                entries
+                    .into_iter()
+                    .map(|(key, value)| (key, sorted_keys(value)))
+                    .collect(),
+            )
+        }
+        serde_json::Value::Array(items) => {
+            serde_json::Value::Array(items.into_iter().map(sorted_keys).collect())
+        }
+        other => other,
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct LockEntry {
+    pub source_hash: String,
+    pub output_hash: String, // Of the file as written, to notice edits and deletions
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RegenerationReason {
+    New,
+    SourceChanged,
+    OutputChanged, // Edited or deleted since it was generated
+    GeneratorChanged,
+}
+
+impl fmt::Display for RegenerationReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RegenerationReason::New => write!(f, "new"),
+            RegenerationReason::SourceChanged => write!(f, "source changed"),
+            RegenerationReason::OutputChanged => write!(f, "output changed on disk"),
+            RegenerationReason::GeneratorChanged => write!(f, "generator changed"),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Default)]
+pub struct RegenerationPlan {
+    pub rewrite: Vec<(String, RegenerationReason)>,
+    pub skip: Vec<String>,
+    pub stale: Vec<String>, // Recorded in the lock but no longer generated
+}
+
+impl RegenerationPlan {
+    pub fn needs_rewrite(&self, path: &str) -> bool {
+        self.rewrite.iter().any(|(p, _)| p == path)
+    }
+
+    pub fn is_up_to_date(&self) -> bool {
+        self.rewrite.is_empty() && self.stale.is_empty()
+    }
+}
+
+impl fmt::Display for RegenerationPlan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (path, reason) in &self.rewrite {
+            writeln!(f, "rewrite {} ({})", path, reason)?;
+        }
+        for path in &self.stale {
+            writeln!(f, "stale   {}", path)?;
+        }
+        write!(f, "{} file(s) up to date", self.skip.len())
+    }
+}
+
+// `.ginger-society/codegen.lock`: which source every generated file was last produced from.
+// Regeneration renders and writes only the files whose source, output or generator changed,
+// so untouched files keep their mtimes.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Default)]
+pub struct CodegenLock {
+    pub generator: String, // Name and version of the generator, changing it rewrites everything
+    #[serde(default)]
+    pub files: BTreeMap<String, LockEntry>,
+}
+
+impl CodegenLock {
+    pub fn new(generator: &str) -> Self {
+        CodegenLock {
+            generator: generator.to_string(),
+            files: BTreeMap::new(),
+        }
+    }
+
+    pub fn path<P: AsRef<Path>>(root: P) -> PathBuf {
+        root.as_ref().join(CODEGEN_LOCK_FILE)
+    }
+
+    /// The lock of the project at `root`, an empty one for `generator` if there is none yet.
+    pub fn load<P: AsRef<Path>>(root: P, generator: &str) -> Result<Self, Box<dyn Error>> {
+        let path = Self::path(root);
+        if !path.exists() {
+            return Ok(Self::new(generator));
+        }
+        let contents = fs::read_to_string(&path)
+            .map_err(|e| format!("Failed to read '{}': {}", path.display(), e))?;
+        let mut lock: CodegenLock = toml::from_str(&contents)
+            .map_err(|e| format!("Failed to parse '{}': {}", path.display(), e))?;
+        if lock.generator != generator {
+            // Keep the entries so `plan` reports `GeneratorChanged` for them
+            lock.files
+                .values_mut()
+                .for_each(|entry| entry.source_hash.clear());
+            lock.generator = generator.to_string();
+        }
+        Ok(lock)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, root: P) -> Result<(), Box<dyn Error>> {
+        let path = Self::path(root);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        write_atomic(&path, &toml::to_string(self)?, false)
+    }
+
+    /// Why the file at `path` (relative to `root`) must be regenerated from a source hashing
+    /// to `source_hash`, `None` when it is up to date.
+    pub fn check<P: AsRef<Path>>(
+        &self,
+        root: P,
+        path: &str,
+        source_hash: &str,
+    ) -> Result<Option<RegenerationReason>, Box<dyn Error>> {
+        let entry = match self.files.get(path) {
+            Some(entry) => entry,
+            None => return Ok(Some(RegenerationReason::New)),
+        };
+        if entry.source_hash.is_empty() {
+            return Ok(Some(RegenerationReason::GeneratorChanged));
+        }
+        if entry.source_hash != source_hash {
+            return Ok(Some(RegenerationReason::SourceChanged));
+        }
+        let output = root.as_ref().join(path);
+        if !output.exists() || sha256_file(&output)?.0 != entry.output_hash {
+            return Ok(Some(RegenerationReason::OutputChanged));
+        }
+        Ok(None)
+    }
+
+    /// Checks every `(path, source hash)` the generator would produce.
+    pub fn plan<P: AsRef<Path>>(
+        &self,
+        root: P,
+        outputs: &[(String, String)],
+    ) -> Result<RegenerationPlan, Box<dyn Error>> {
+        let root = root.as_ref();
+        let mut plan = RegenerationPlan::default();
+        for (path, hash) in outputs {
+            match self.check(root, path, hash)? {
+                Some(reason) => plan.rewrite.push((path.clone(), reason)),
+                None => plan.skip.push(path.clone()),
+            }
+        }
+        plan.stale = self
+            .files
+            .keys()
+            .filter(|path| !outputs.iter().any(|(p, _)| p == *path))
+            .cloned()
+            .collect();
+        Ok(plan)
+    }
+
+    /// Records the file at `path` as just written from a source hashing to `source_hash`.
+    pub fn record<P: AsRef<Path>>(
+        &mut self,
+        root: P,
+        path: &str,
+        source_hash: &str,
+    ) -> Result<(), Box<dyn Error>> {
+        let (output_hash, _) = sha256_file(root.as_ref().join(path))?;
+        self.files.insert(
+            path.to_string(),
+            LockEntry {
+                source_hash: source_hash.to_string(),
+                output_hash,
+            },
+        );
+        Ok(())
+    }
+
+    /// Forgets files that are no longer generated.
+    pub fn remove(&mut self, path: &str) {
+        self.files.remove(path);
+    }
+}
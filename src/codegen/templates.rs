@@ -0,0 +1,186 @@
+use heck::{ToKebabCase, ToLowerCamelCase, ToShoutySnakeCase, ToSnakeCase, ToUpperCamelCase};
+use minijinja::{Environment, UndefinedBehavior};
+use serde::Serialize;
+use std::{
+    error::Error,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use crate::{type_map::TypeMap, LANG, ORM};
+
+type TypeMapper = Arc<dyn Fn(&str) -> String + Send + Sync>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RenderedFile {
+    pub path: PathBuf,
+    pub contents: String,
+}
+
+/// Field names as the language writes them: `camelCase` for TypeScript, `snake_case` otherwise.
+pub fn field_name(lang: &LANG, name: &str) -> String {
+    match lang {
+        LANG::TS => name.to_lower_camel_case(),
+        LANG::Rust | LANG::Python | LANG::Shell => name.to_snake_case(),
+    }
+}
+
+/// Type names as the language writes them, `PascalCase` everywhere but shell scripts.
+pub fn type_name(lang: &LANG, name: &str) -> String {
+    match lang {
+        LANG::Shell => name.to_shouty_snake_case(),
+        LANG::Rust | LANG::TS | LANG::Python => name.to_upper_camel_case(),
+    }
+}
+
+// The rendering stack shared by the generators. Templates see these filters on top of the
+// minijinja builtins:
+//
+// snake_case, camel_case, pascal_case, kebab_case, shouty_case: plain naming conversions
+// field_name, type_name: the naming convention of the target `LANG`
+// map_type: the target type of a schema type, see `with_type_map` and `with_type_mapper`
+//
+// Undefined variables are errors so typos in templates do not silently render empty strings.
+pub struct TemplateRenderer {
+    env: Environment<'static>,
+    lang: LANG,
+}
+
+impl TemplateRenderer {
+    pub fn new(lang: LANG) -> Self {
+        let mut env = Environment::new();
+        env.set_undefined_behavior(UndefinedBehavior::Strict);
+        env.set_keep_trailing_newline(true);
+        env.add_filter("snake_case", |value: String| value.to_snake_case());
+        env.add_filter("camel_case", |value: String| value.to_lower_camel_case());
+        env.add_filter("pascal_case", |value: String| value.to_upper_camel_case());
+        env.add_filter("kebab_case", |value: String| value.to_kebab_case());
+        env.add_filter("shouty_case", |value: String| value.to_shouty_snake_case());
+        env.add_filter("field_name", move |value: String| field_name(&lang, &value));
+        env.add_filter("type_name", move |value: String| type_name(&lang, &value));
+
+        let mut renderer = TemplateRenderer { env, lang };
+        renderer.set_type_mapper(Arc::new(|schema_type: &str| schema_type.to_string()));
+        renderer
+    }
+
+    pub fn lang(&self) -> LANG {
+        self.lang
+    }
+
+    /// Sets what the `map_type` filter turns a schema type into. Defaults to the schema type
+    /// itself.
+    pub fn with_type_mapper<F>(mut self, mapper: F) -> Self
+    where
+        F: Fn(&str) -> String + Send + Sync + 'static,
+    {
+        self.set_type_mapper(Arc::new(mapper));
+        self
+    }
+
+    /// Makes `map_type` use the language types of `orm` from `type_map`, unknown schema types
+    /// render unchanged.
+    pub fn with_type_map(self, orm: ORM, type_map: TypeMap) -> Self {
+        let lang = self.lang;
+        self.with_type_mapper(move |schema_type| {
+            type_map
+                .lookup(lang, orm, schema_type)
+                .map(|mapped| mapped.language_type)
+                .unwrap_or_else(|| schema_type.to_string())
+        })
+    }
+
+    fn set_type_mapper(&mut self, mapper: TypeMapper) {
+        self.env
+            .add_filter("map_type", move |schema_type: String| mapper(&schema_type));
+    }
+
+    pub fn add_template(&mut self, name: &str, source: &str) -> Result<(), Box<dyn Error>> {
+        self.env
+            .add_template_owned(name.to_string(), source.to_string())
+            .map_err(|e| format!("Invalid template '{}': {}", name, e).into())
+    }
+
+    /// Adds every `*.j2` / `*.jinja` file of `dir` (not recursive), named after the file name
+    /// without the extension.
+    pub fn add_templates_from_dir<P: AsRef<Path>>(&mut self, dir: P) -> Result<(), Box<dyn Error>> {
+        let dir = dir.as_ref();
+        let mut paths: Vec<PathBuf> = std::fs::read_dir(dir)
+            .map_err(|e| format!("Failed to read the templates in '{}': {}", dir.display(), e))?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| {
+                path.extension()
+                    .is_some_and(|ext| ext == "j2" || ext == "jinja")
+            })
+            .collect();
+        paths.sort();
+
+        for path in paths {
+            let name = path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .ok_or_else(|| format!("'{}' is not a valid template name", path.display()))?
+                .to_string();
+            let source = std::fs::read_to_string(&path)?;
+            self.add_template(&name, &source)?;
+        }
+        Ok(())
+    }
+
+    pub fn render<S: Serialize>(&self, name: &str, context: &S) -> Result<String, Box<dyn Error>> {
+        let template = self
+            .env
+            .get_template(name)
+            .map_err(|e| format!("Unknown template '{}': {}", name, e))?;
+        let rendered = template
+            .render(context)
+            .map_err(|e| format!("Failed to render '{}': {:#}", name, e))?;
+        Ok(normalize_output(&rendered))
+    }
+
+    pub fn render_str<S: Serialize>(
+        &self,
+        source: &str,
+        context: &S,
+    ) -> Result<String, Box<dyn Error>> {
+        let rendered = self
+            .env
+            .render_str(source, context)
+            .map_err(|e| format!("Failed to render template: {:#}", e))?;
+        Ok(normalize_output(&rendered))
+    }
+
+    /// Renders each `(output path, template name)` pair with the same context. Files come back
+    /// sorted by path, so writing them is deterministic whatever order generators list them in.
+    pub fn render_outputs<S: Serialize>(
+        &self,
+        outputs: &[(&str, &str)],
+        context: &S,
+    ) -> Result<Vec<RenderedFile>, Box<dyn Error>> {
+        let mut files = outputs
+            .iter()
+            .map(|(path, name)| {
+                Ok(RenderedFile {
+                    path: PathBuf::from(path),
+                    contents: self.render(name, context)?,
+                })
+            })
+            .collect::<Result<Vec<_>, Box<dyn Error>>>()?;
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(files)
+    }
+}
+
+// Unix line endings, no trailing whitespace and exactly one final newline, so output does not
+// depend on how templates were edited
+fn normalize_output(rendered: &str) -> String {
+    let mut output: String = rendered
+        .lines()
+        .map(str::trim_end)
+        .collect::<Vec<_>>()
+        .join("\n");
+    let trimmed = output.trim_end_matches('\n').len();
+    output.truncate(trimmed);
+    output.push('\n');
+    output
+}
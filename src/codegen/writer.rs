@@ -0,0 +1,374 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    error::Error,
+    fmt, fs,
+    path::{Path, PathBuf},
+};
+
+use super::{lock::CodegenLock, templates::RenderedFile};
+use crate::config_io::{write_file, WriteMode};
+
+const MARKER: &str = "ginger:generated";
+const DEFAULT_REGION: &str = "main";
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CommentStyle {
+    Slash, // `//`
+    Hash,  // `#`
+    Dash,  // `--`
+}
+
+impl CommentStyle {
+    /// Picked from the extension, `#` for anything unknown.
+    pub fn for_path<P: AsRef<Path>>(path: P) -> Self {
+        match path.as_ref().extension().and_then(|ext| ext.to_str()) {
+            Some("rs" | "ts" | "tsx" | "js" | "jsx" | "go" | "java" | "kt" | "swift") => {
+                CommentStyle::Slash
+            }
+            Some("sql") => CommentStyle::Dash,
+            _ => CommentStyle::Hash,
+        }
+    }
+
+    fn prefix(&self) -> &'static str {
+        match self {
+            CommentStyle::Slash => "//",
+            CommentStyle::Hash => "#",
+            CommentStyle::Dash => "--",
+        }
+    }
+}
+
+fn region_hash(content: &str) -> String {
+    Sha256::digest(content.as_bytes())
+        .iter()
+        .take(6)
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Wraps `content` in the BEGIN/END markers of the managed region `name`. Generators emit
+/// their output through this; text outside the markers belongs to the user.
+pub fn managed_region(style: CommentStyle, name: &str, content: &str) -> String {
+    let mut content = content.to_string();
+    if !content.is_empty() && !content.ends_with('\n') {
+        content.push('\n');
+    }
+    format!(
+        "{prefix} BEGIN {marker} {name} {hash}\n{content}{prefix} END {marker} {name}\n",
+        prefix = style.prefix(),
+        marker = MARKER,
+        name = name,
+        hash = region_hash(&content),
+        content = content,
+    )
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    Text(String),
+    Region {
+        name: String,
+        hash: String,
+        content: String,
+    },
+}
+
+fn marker_of<'a>(line: &'a str, keyword: &str) -> Option<Vec<&'a str>> {
+    let mut words = line.split_whitespace().skip(1);
+    if words.next()? != keyword || words.next()? != MARKER {
+        return None;
+    }
+    Some(words.collect())
+}
+
+fn parse_segments(path: &Path, contents: &str) -> Result<Vec<Segment>, Box<dyn Error>> {
+    let mut segments = vec![];
+    let mut text = String::new();
+    let mut region: Option<(String, String, String)> = None;
+
+    for (index, line) in contents.split_inclusive('\n').enumerate() {
+        match &mut region {
+            None => match marker_of(line, "BEGIN") {
+                Some(words) => {
+                    let (name, hash) = match words[..] {
+                        [name, hash] => (name, hash),
+                        _ => {
+                            return Err(format!(
+                                "{}:{}: malformed BEGIN marker",
+                                path.display(),
+                                index + 1
+                            )
+                            .into())
+                        }
+                    };
+                    if !text.is_empty() {
+                        segments.push(Segment::Text(std::mem::take(&mut text)));
+                    }
+                    region = Some((name.to_string(), hash.to_string(), String::new()));
+                }
+                None => text.push_str(line),
+            },
+            Some((name, hash, content)) => match marker_of(line, "END") {
+                Some(words) if words.first() == Some(&name.as_str()) => {
+                    segments.push(Segment::Region {
+                        name: std::mem::take(name),
+                        hash: std::mem::take(hash),
+                        content: std::mem::take(content),
+                    });
+                    region = None;
+                }
+                Some(_) => {
+                    return Err(format!(
+                        "{}:{}: END marker does not match the region '{}'",
+                        path.display(),
+                        index + 1,
+                        name
+                    )
+                    .into())
+                }
+                None => content.push_str(line),
+            },
+        }
+    }
+
+    if let Some((name, _, _)) = region {
+        return Err(format!("{}: region '{}' is never closed", path.display(), name).into());
+    }
+    if !text.is_empty() {
+        segments.push(Segment::Text(text));
+    }
+    Ok(segments)
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum FileStatus {
+    Created,
+    Updated,
+    Unchanged,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct WrittenFile {
+    pub path: PathBuf,
+    pub status: FileStatus,
+    pub diff: String,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Default)]
+pub struct WriteReport {
+    pub files: Vec<WrittenFile>,
+}
+
+impl WriteReport {
+    pub fn changed(&self) -> Vec<&WrittenFile> {
+        self.files
+            .iter()
+            .filter(|file| file.status != FileStatus::Unchanged)
+            .collect()
+    }
+
+    /// For `--check` runs: fails listing the files regeneration would change.
+    pub fn ensure_unchanged(&self) -> Result<(), Box<dyn Error>> {
+        let changed = self.changed();
+        if changed.is_empty() {
+            return Ok(());
+        }
+        let paths: Vec<String> = changed
+            .iter()
+            .map(|file| file.path.display().to_string())
+            .collect();
+        Err(format!(
+            "Generated code is out of date, regenerate: {}",
+            paths.join(", ")
+        )
+        .into())
+    }
+}
+
+impl fmt::Display for WriteReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for file in &self.files {
+            let status = match file.status {
+                FileStatus::Created => "created",
+                FileStatus::Updated => "updated",
+                FileStatus::Unchanged => "unchanged",
+            };
+            writeln!(f, "{:>9} {}", status, file.path.display())?;
+        }
+        Ok(())
+    }
+}
+
+// Writes generated files, replacing only their managed regions.
+//
+// Regions are matched by name: the new content replaces the old one, regions the generator no
+// longer emits are removed and new ones are appended. A region whose content no longer matches
+// the hash in its BEGIN marker was edited by hand, regeneration then fails unless `force` is set.
+#[derive(Debug, Clone, Default)]
+pub struct CodeWriter {
+    root: PathBuf,
+    check: bool,
+    force: bool,
+}
+
+impl CodeWriter {
+    /// Output paths are relative to `root`.
+    pub fn new<P: AsRef<Path>>(root: P) -> Self {
+        CodeWriter {
+            root: root.as_ref().to_path_buf(),
+            ..Default::default()
+        }
+    }
+
+    /// Only computes what would change, nothing is written. Pair with
+    /// `WriteReport::ensure_unchanged` in CI.
+    pub fn check(mut self, check: bool) -> Self {
+        self.check = check;
+        self
+    }
+
+    /// Overwrites managed regions even when they were edited by hand.
+    pub fn force(mut self, force: bool) -> Self {
+        self.force = force;
+        self
+    }
+
+    /// Merges `generated` into the file at `path`. Output without markers is managed as a
+    /// single region.
+    pub fn write(&self, path: &Path, generated: &str) -> Result<WrittenFile, Box<dyn Error>> {
+        let full_path = self.root.join(path);
+        let style = CommentStyle::for_path(path);
+        let generated = if generated.contains(MARKER) {
+            generated.to_string()
+        } else {
+            managed_region(style, DEFAULT_REGION, generated)
+        };
+
+        let existing =
+            if full_path.exists() {
+                Some(fs::read_to_string(&full_path).map_err(|e| {
+                    format!("Failed to read the file '{}': {}", full_path.display(), e)
+                })?)
+            } else {
+                None
+            };
+
+        let contents = match &existing {
+            None => generated,
+            Some(existing) => self.merge(&full_path, existing, &generated, style)?,
+        };
+
+        let mode = if self.check {
+            WriteMode::DryRun
+        } else {
+            WriteMode::Apply
+        };
+        if !self.check {
+            if let Some(parent) = full_path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+        }
+        let preview = write_file(&full_path, &contents, mode)?;
+
+        let status = match (&existing, preview.changed) {
+            (None, _) => FileStatus::Created,
+            (Some(_), true) => FileStatus::Updated,
+            (Some(_), false) => FileStatus::Unchanged,
+        };
+        Ok(WrittenFile {
+            path: path.to_path_buf(),
+            status,
+            diff: preview.diff,
+        })
+    }
+
+    /// Writes every rendered file, in order.
+    pub fn write_all(&self, files: &[RenderedFile]) -> Result<WriteReport, Box<dyn Error>> {
+        let mut report = WriteReport::default();
+        for file in files {
+            report.files.push(self.write(&file.path, &file.contents)?);
+        }
+        Ok(report)
+    }
+
+    /// Like `write_all`, for `(file, source hash)` pairs: files `lock` records as generated from
+    /// the same source are left untouched, the others are written and recorded. Render only the
+    /// files `CodegenLock::plan` asks for to save the rendering time as well.
+    pub fn write_incremental(
+        &self,
+        files: &[(RenderedFile, String)],
+        lock: &mut CodegenLock,
+    ) -> Result<WriteReport, Box<dyn Error>> {
+        let mut report = WriteReport::default();
+        for (file, source_hash) in files {
+            let key = file.path.to_string_lossy();
+            if lock.check(&self.root, &key, source_hash)?.is_none() {
+                report.files.push(WrittenFile {
+                    path: file.path.clone(),
+                    status: FileStatus::Unchanged,
+                    diff: String::new(),
+                });
+                continue;
+            }
+            let written = self.write(&file.path, &file.contents)?;
+            if !self.check {
+                lock.record(&self.root, &key, source_hash)?;
+            }
+            report.files.push(written);
+        }
+        Ok(report)
+    }
+
+    fn merge(
+        &self,
+        path: &Path,
+        existing: &str,
+        generated: &str,
+        style: CommentStyle,
+    ) -> Result<String, Box<dyn Error>> {
+        let mut new_regions: Vec<(String, String)> = parse_segments(path, generated)?
+            .into_iter()
+            .filter_map(|segment| match segment {
+                Segment::Region { name, content, .. } => Some((name, content)),
+                Segment::Text(_) => None,
+            })
+            .collect();
+
+        let mut merged = String::new();
+        for segment in parse_segments(path, existing)? {
+            match segment {
+                Segment::Text(text) => merged.push_str(&text),
+                Segment::Region {
+                    name,
+                    hash,
+                    content,
+                } => {
+                    if !self.force && region_hash(&content) != hash {
+                        return Err(format!(
+                            "The managed region '{}' of '{}' was edited by hand, move the \
+                             changes outside the markers or regenerate with force",
+                            name,
+                            path.display()
+                        )
+                        .into());
+                    }
+                    if let Some(index) = new_regions.iter().position(|(n, _)| *n == name) {
+                        let (name, content) = new_regions.remove(index);
+                        merged.push_str(&managed_region(style, &name, &content));
+                    }
+                }
+            }
+        }
+        for (name, content) in new_regions {
+            if !merged.is_empty() && !merged.ends_with('\n') {
+                merged.push('\n');
+            }
+            merged.push_str(&managed_region(style, &name, &content));
+        }
+        Ok(merged)
+    }
+}
@@ -0,0 +1,143 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::{git::Commit, release_plan::BumpKind};
+
+// A commit message following https://www.conventionalcommits.org, e.g.
+// `feat(api)!: drop the v1 routes`
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct ConventionalCommit {
+    #[serde(rename = "type")]
+    pub commit_type: String,
+    pub scope: Option<String>,
+    pub breaking: bool,
+    pub description: String,
+    pub body: String,
+}
+
+const BREAKING_FOOTERS: [&str; 2] = ["BREAKING CHANGE:", "BREAKING-CHANGE:"];
+
+impl ConventionalCommit {
+    /// Parses a full commit message, `None` when the subject does not follow the convention.
+    pub fn parse(message: &str) -> Option<Self> {
+        let (subject, body) = match message.split_once('\n') {
+            Some((subject, body)) => (subject, body.trim()),
+            None => (message, ""),
+        };
+        Self::parse_parts(subject, body)
+    }
+
+    pub fn from_commit(commit: &Commit) -> Option<Self> {
+        Self::parse_parts(&commit.subject, commit.body.trim())
+    }
+
+    fn parse_parts(subject: &str, body: &str) -> Option<Self> {
+        let (header, description) = subject.trim().split_once(':')?;
+        let description = description.trim();
+        if description.is_empty() {
+            return None;
+        }
+
+        let (header, bang) = match header.strip_suffix('!') {
+            Some(header) => (header, true),
+            None => (header, false),
+        };
+        let (commit_type, scope) = match header.split_once('(') {
+            Some((commit_type, rest)) => {
+                let scope = rest.strip_suffix(')')?.trim();
+                (commit_type, (!scope.is_empty()).then(|| scope.to_string()))
+            }
+            None => (header, None),
+        };
+        if commit_type.is_empty() || !commit_type.chars().all(|c| c.is_ascii_alphabetic()) {
+            return None;
+        }
+
+        let breaking = bang
+            || body.lines().any(|line| {
+                BREAKING_FOOTERS
+                    .iter()
+                    .any(|footer| line.trim_start().starts_with(footer))
+            });
+
+        Some(ConventionalCommit {
+            commit_type: commit_type.to_lowercase(),
+            scope,
+            breaking,
+            description: description.to_string(),
+            body: body.to_string(),
+        })
+    }
+
+    /// The bump the commit requires on its own, `None` for commits that do not change the
+    /// released code (`docs`, `chore`, `ci`, ...).
+    pub fn bump(&self) -> Option<BumpKind> {
+        if self.breaking {
+            return Some(BumpKind::Major);
+        }
+        match self.commit_type.as_str() {
+            "feat" => Some(BumpKind::Minor),
+            "fix" | "perf" | "revert" => Some(BumpKind::Patch),
+            _ => None,
+        }
+    }
+
+    /// Heading the commit is listed under in a changelog, `None` for commits left out of it.
+    pub fn changelog_section(&self) -> Option<&'static str> {
+        if self.breaking {
+            return Some("Breaking Changes");
+        }
+        match self.commit_type.as_str() {
+            "feat" => Some("Features"),
+            "fix" => Some("Bug Fixes"),
+            "perf" => Some("Performance"),
+            "revert" => Some("Reverts"),
+            "docs" => Some("Documentation"),
+            _ => None,
+        }
+    }
+
+    /// Whether the commit is about `package`: its scope is the package name or the last segment
+    /// of it (`api` for `@ginger-society/api`). Unscoped commits apply to every package.
+    pub fn applies_to(&self, package: &str) -> bool {
+        match &self.scope {
+            None => true,
+            Some(scope) => {
+                let short = package.rsplit('/').next().unwrap_or(package);
+                scope.eq_ignore_ascii_case(package) || scope.eq_ignore_ascii_case(short)
+            }
+        }
+    }
+}
+
+/// The highest bump required by `commits`, commits that do not follow the convention are
+/// ignored.
+pub fn infer_bump(commits: &[Commit]) -> Option<BumpKind> {
+    commits
+        .iter()
+        .filter_map(ConventionalCommit::from_commit)
+        .filter_map(|commit| commit.bump())
+        .max()
+}
+
+/// The bump each of `packages` requires, see `ConventionalCommit::applies_to`. Packages
+/// without a relevant commit are left out. Feed the result to
+/// `release_plan::release_plan_with_bumps`.
+pub fn infer_bumps(commits: &[Commit], packages: &[&str]) -> HashMap<String, BumpKind> {
+    let parsed: Vec<ConventionalCommit> = commits
+        .iter()
+        .filter_map(ConventionalCommit::from_commit)
+        .collect();
+
+    packages
+        .iter()
+        .filter_map(|package| {
+            parsed
+                .iter()
+                .filter(|commit| commit.applies_to(package))
+                .filter_map(|commit| commit.bump())
+                .max()
+                .map(|bump| (package.to_string(), bump))
+        })
+        .collect()
+}
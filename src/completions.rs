@@ -0,0 +1,67 @@
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::{Channel, DbType, Environment, LANG};
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct CompletionHint {
+    pub value: String,
+    pub help: Option<String>,
+    #[serde(default)]
+    pub aliases: Vec<String>, // Also accepted on the command line, not offered for completion
+}
+
+/// The values of a `ValueEnum` with their descriptions and aliases.
+pub fn completion_hints<T: ValueEnum>() -> Vec<CompletionHint> {
+    T::value_variants()
+        .iter()
+        .filter_map(T::to_possible_value)
+        .filter(|value| !value.is_hide_set())
+        .map(|value| {
+            let mut names = value.get_name_and_aliases().map(str::to_string);
+            CompletionHint {
+                value: names.next().unwrap_or_default(),
+                help: value.get_help().map(|help| help.to_string()),
+                aliases: names.collect(),
+            }
+        })
+        .collect()
+}
+
+/// Hints for the shared enums, keyed by the argument name downstream CLIs use for them.
+pub fn shared_completion_hints() -> BTreeMap<&'static str, Vec<CompletionHint>> {
+    BTreeMap::from([
+        ("channel", completion_hints::<Channel>()),
+        ("db-type", completion_hints::<DbType>()),
+        ("env", completion_hints::<Environment>()),
+        ("lang", completion_hints::<LANG>()),
+    ])
+}
+
+/// Parses `value` like clap does for a `ValueEnum` argument: case insensitive, aliases included.
+pub fn parse_value<T: ValueEnum>(value: &str) -> Result<T, String> {
+    T::from_str(value.trim(), true).map_err(|_| {
+        let expected: Vec<String> = completion_hints::<T>()
+            .into_iter()
+            .map(|hint| hint.value)
+            .collect();
+        format!(
+            "'{}' is not a valid value, expected one of: {}",
+            value,
+            expected.join(", ")
+        )
+    })
+}
+
+/// Writes the completion script of `command` for `shell`, e.g. behind a `completions`
+/// subcommand: `generate(shell, &mut Cli::command(), "ginger-db", &mut io::stdout())`.
+#[cfg(feature = "cli")]
+pub fn generate(
+    shell: clap_complete::Shell,
+    command: &mut clap::Command,
+    bin_name: &str,
+    out: &mut dyn std::io::Write,
+) {
+    clap_complete::generate(shell, command, bin_name, out)
+}
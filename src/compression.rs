@@ -0,0 +1,229 @@
+use flate2::write::GzEncoder;
+use rocket::{
+    fairing::{Fairing, Info, Kind},
+    http::{Header, Method},
+    Request, Response,
+};
+use std::io::{self, Cursor, Write};
+
+use crate::upload::content_type_allowed;
+
+const DEFAULT_MIN_SIZE: usize = 1024; // Smaller bodies barely shrink, the headers dominate
+const DEFAULT_CONTENT_TYPES: [&str; 7] = [
+    "text/*",
+    "application/json",
+    "application/javascript",
+    "application/xml",
+    "application/wasm",
+    "application/problem+json",
+    "image/svg+xml",
+];
+const GZIP_LEVEL: u32 = 6;
+// Quality 11 is meant for build time compression, 5 is as fast as gzip and still smaller
+const BROTLI_QUALITY: u32 = 5;
+const BROTLI_WINDOW: u32 = 22;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Brotli,
+    Gzip,
+}
+
+impl Encoding {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Encoding::Brotli => "br",
+            Encoding::Gzip => "gzip",
+        }
+    }
+
+    pub fn compress(&self, body: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            Encoding::Brotli => {
+                let mut writer =
+                    brotli::CompressorWriter::new(vec![], 4096, BROTLI_QUALITY, BROTLI_WINDOW);
+                writer.write_all(body)?;
+                Ok(writer.into_inner())
+            }
+            Encoding::Gzip => {
+                let mut encoder = GzEncoder::new(vec![], flate2::Compression::new(GZIP_LEVEL));
+                encoder.write_all(body)?;
+                encoder.finish()
+            }
+        }
+    }
+}
+
+/// The encoding to answer a request sending `accept_encoding` with, brotli winning ties.
+/// Encodings refused with `q=0` or not listed (unless `*` is) are never picked.
+pub fn negotiate(accept_encoding: &str, brotli: bool) -> Option<Encoding> {
+    let entries: Vec<(String, f32)> = accept_encoding
+        .split(',')
+        .filter_map(|entry| {
+            let mut parts = entry.split(';');
+            let name = parts.next()?.trim().to_lowercase();
+            let quality = parts
+                .filter_map(|param| param.trim().strip_prefix("q="))
+                .find_map(|q| q.trim().parse::<f32>().ok())
+                .unwrap_or(1.0);
+            (!name.is_empty()).then_some((name, quality))
+        })
+        .collect();
+    let quality = |name: &str| {
+        entries
+            .iter()
+            .find(|(entry, _)| entry == name)
+            .or_else(|| entries.iter().find(|(entry, _)| entry == "*"))
+            .map(|(_, q)| *q)
+            .unwrap_or(0.0)
+    };
+    let mut candidates = vec![(Encoding::Gzip, quality("gzip"))];
+    if brotli {
+        candidates.insert(0, (Encoding::Brotli, quality("br")));
+    }
+    candidates
+        .into_iter()
+        .filter(|(_, q)| *q > 0.0)
+        .fold(
+            None,
+            |best: Option<(Encoding, f32)>, (encoding, q)| match best {
+                Some((_, best_q)) if best_q >= q => best,
+                _ => Some((encoding, q)),
+            },
+        )
+        .map(|(encoding, _)| encoding)
+}
+
+// Compresses responses with brotli or gzip, as negotiated with `Accept-Encoding`, for the
+// environments without nginx in front of the service. Only bodies of a known size of at least
+// `min_size` bytes and of a compressible content type are touched: streams stay streams, and
+// responses already carrying a `Content-Encoding`, e.g. the pre-compressed files of `Spa`, are
+// left as they are.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Compression {
+    min_size: usize,
+    content_types: Vec<String>,
+    brotli: bool,
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Compression {
+            min_size: DEFAULT_MIN_SIZE,
+            content_types: DEFAULT_CONTENT_TYPES.map(String::from).to_vec(),
+            brotli: true,
+        }
+    }
+}
+
+impl Compression {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn min_size(mut self, bytes: usize) -> Self {
+        self.min_size = bytes;
+        self
+    }
+
+    /// Replaces the compressed content types, `type/*` wildcards included.
+    pub fn content_types(mut self, content_types: &[&str]) -> Self {
+        self.content_types = content_types.iter().map(|t| t.to_string()).collect();
+        self
+    }
+
+    /// Only offers gzip, e.g. when a proxy in front mishandles brotli.
+    pub fn without_brotli(mut self) -> Self {
+        self.brotli = false;
+        self
+    }
+
+    fn is_compressible(&self, response: &Response<'_>) -> bool {
+        let allowed: Vec<&str> = self.content_types.iter().map(String::as_str).collect();
+        ![204, 206, 304].contains(&response.status().code)
+            && !response.headers().contains("Content-Encoding")
+            && response
+                .body()
+                .preset_size()
+                .is_some_and(|size| size >= self.min_size)
+            && response
+                .headers()
+                .get_one("Content-Type")
+                .is_some_and(|content_type| content_type_allowed(content_type, &allowed))
+    }
+}
+
+fn add_vary(response: &mut Response<'_>) {
+    let varies = response
+        .headers()
+        .get("Vary")
+        .flat_map(|vary| vary.split(','))
+        .any(|name| {
+            let name = name.trim();
+            name == "*" || name.eq_ignore_ascii_case("Accept-Encoding")
+        });
+    if !varies {
+        response.adjoin_header(Header::new("Vary", "Accept-Encoding"));
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for Compression {
+    fn info(&self) -> Info {
+        Info {
+            name: "Response compression",
+            kind: Kind::Response,
+        }
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        if request.method() == Method::Head || !self.is_compressible(response) {
+            return;
+        }
+        // Caches must keep one copy per encoding even when this client got the plain body
+        add_vary(response);
+        let encoding = match request
+            .headers()
+            .get_one("Accept-Encoding")
+            .and_then(|accept| negotiate(accept, self.brotli))
+        {
+            Some(encoding) => encoding,
+            None => return,
+        };
+
+        let body = match response.body_mut().to_bytes().await {
+            Ok(body) => body,
+            Err(e) => {
+                tracing::warn!(error = %e, "failed to read the response body to compress");
+                return;
+            }
+        };
+        let compressed = match rocket::tokio::task::spawn_blocking({
+            let body = body.clone();
+            move || encoding.compress(&body)
+        })
+        .await
+        {
+            Ok(Ok(compressed)) if compressed.len() < body.len() => compressed,
+            Ok(Err(e)) => {
+                tracing::warn!(error = %e, encoding = encoding.name(), "failed to compress the response");
+                response.set_sized_body(body.len(), Cursor::new(body));
+                return;
+            }
+            _ => {
+                response.set_sized_body(body.len(), Cursor::new(body));
+                return;
+            }
+        };
+
+        // The representation changed, a strong validator would no longer be byte exact
+        if let Some(etag) = response.headers().get_one("ETag") {
+            if !etag.starts_with("W/") {
+                let weak = format!("W/{}", etag);
+                response.set_header(Header::new("ETag", weak));
+            }
+        }
+        response.set_header(Header::new("Content-Encoding", encoding.name()));
+        response.set_sized_body(compressed.len(), Cursor::new(compressed));
+    }
+}
@@ -0,0 +1,283 @@
+use aes_gcm::{
+    aead::{Aead, KeyInit},
+    Aes256Gcm, Nonce,
+};
+use data_encoding::{BASE64, HEXLOWER_PERMISSIVE};
+use rand::RngCore;
+use std::{
+    collections::{HashMap, HashSet},
+    error::Error,
+    fmt, fs,
+    path::Path,
+    sync::RwLock,
+};
+use toml_edit::{DocumentMut, Item, Value};
+
+use crate::secrets::{SecretProvider, CONFIG_KEY};
+
+/// Prefix of the encrypted string values of config files, followed by the base64 of the nonce
+/// and the AES-256-GCM ciphertext.
+pub const ENCRYPTED_PREFIX: &str = "enc:AES256:";
+
+const NONCE_LEN: usize = 12;
+
+static INSTALLED: RwLock<Option<ConfigCipher>> = RwLock::new(None);
+
+pub fn is_encrypted(value: &str) -> bool {
+    value.starts_with(ENCRYPTED_PREFIX)
+}
+
+// Encrypts and decrypts the values of config files, e.g. `url = "enc:AES256:..."` for a database
+// URL carrying credentials that has to be committed
+#[derive(Clone)]
+pub struct ConfigCipher {
+    key: [u8; 32],
+}
+
+impl fmt::Debug for ConfigCipher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ConfigCipher(***)")
+    }
+}
+
+impl ConfigCipher {
+    pub fn new(key: [u8; 32]) -> Self {
+        ConfigCipher { key }
+    }
+
+    /// A new random key, to be stored with `encoded_key` in the secrets of the service.
+    pub fn generate() -> Self {
+        let mut key = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut key);
+        Self::new(key)
+    }
+
+    /// A key given as 64 hex characters or as base64.
+    pub fn from_encoded(key: &str) -> Result<Self, Box<dyn Error>> {
+        let key = key.trim();
+        let bytes = match key.len() {
+            64 => HEXLOWER_PERMISSIVE.decode(key.as_bytes()),
+            _ => BASE64.decode(key.as_bytes()),
+        }
+        .map_err(|_| "The config key is neither hex nor base64")?;
+        let key: [u8; 32] = bytes.try_into().map_err(|bytes: Vec<u8>| {
+            format!("The config key has {} bytes, not 32", bytes.len())
+        })?;
+        Ok(Self::new(key))
+    }
+
+    /// The key held by `secrets` under `config/key`.
+    pub async fn from_secrets(secrets: &dyn SecretProvider) -> Result<Self, Box<dyn Error>> {
+        let key = secrets
+            .require(CONFIG_KEY)
+            .await
+            .map_err(|e| e.to_string())?;
+        Self::from_encoded(key.expose())
+    }
+
+    pub fn encoded_key(&self) -> String {
+        BASE64.encode(&self.key)
+    }
+
+    /// Makes the `read_*` / `write_*` config functions of the crate decrypt and re-encrypt
+    /// values with this key, replacing the previously installed one.
+    pub fn install(self) {
+        *INSTALLED.write().unwrap() = Some(self);
+    }
+
+    /// `enc:AES256:` followed by the encrypted `plaintext`, a new nonce being used every time.
+    pub fn encrypt(&self, plaintext: &str) -> String {
+        let mut nonce = [0u8; NONCE_LEN];
+        rand::thread_rng().fill_bytes(&mut nonce);
+        let ciphertext = Aes256Gcm::new(&self.key.into())
+            .encrypt(Nonce::from_slice(&nonce), plaintext.as_bytes())
+            .expect("AES-GCM encryption of a string cannot fail");
+        let mut payload = nonce.to_vec();
+        payload.extend(ciphertext);
+        format!("{}{}", ENCRYPTED_PREFIX, BASE64.encode(&payload))
+    }
+
+    pub fn decrypt(&self, value: &str) -> Result<String, Box<dyn Error>> {
+        let encoded = value
+            .strip_prefix(ENCRYPTED_PREFIX)
+            .ok_or_else(|| format!("The value does not start with '{}'", ENCRYPTED_PREFIX))?;
+        let payload = BASE64
+            .decode(encoded.as_bytes())
+            .map_err(|_| "The encrypted value is not valid base64")?;
+        if payload.len() < NONCE_LEN {
+            return Err("The encrypted value is truncated".into());
+        }
+        let (nonce, ciphertext) = payload.split_at(NONCE_LEN);
+        let plaintext = Aes256Gcm::new(&self.key.into())
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| "The value was encrypted with another key or was tampered with")?;
+        Ok(String::from_utf8(plaintext)?)
+    }
+}
+
+/// The TOML document `contents` with its encrypted values decrypted by the installed cipher.
+/// Documents without encrypted values are returned as they are, with or without a cipher.
+pub fn decrypt_toml(contents: &str) -> Result<String, Box<dyn Error>> {
+    Ok(decrypt_toml_values(contents)?.0)
+}
+
+/// Like `decrypt_toml`, also returning the decrypted values so errors about the document can
+/// mask them.
+pub(crate) fn decrypt_toml_values(contents: &str) -> Result<(String, Vec<String>), Box<dyn Error>> {
+    if !contents.contains(ENCRYPTED_PREFIX) {
+        return Ok((contents.to_string(), vec![]));
+    }
+    let installed = INSTALLED.read().unwrap();
+    let cipher = installed
+        .as_ref()
+        .ok_or("The config has encrypted values but no config key is installed")?;
+    let mut document: DocumentMut = contents.parse()?;
+    let mut result = Ok(());
+    let mut plaintexts = vec![];
+    visit_strings(
+        document.as_item_mut(),
+        &mut String::new(),
+        &mut |path, value| {
+            if result.is_ok() && is_encrypted(value) {
+                match cipher.decrypt(value) {
+                    Ok(plaintext) => {
+                        plaintexts.push(plaintext.clone());
+                        return Some(plaintext);
+                    }
+                    Err(e) => result = Err(format!("Failed to decrypt '{}': {}", path, e)),
+                }
+            }
+            None
+        },
+    );
+    result?;
+    Ok((document.to_string(), plaintexts))
+}
+
+/// `contents` with the values encrypted in the TOML document `previous` encrypted again, so a
+/// config read with `decrypt_toml` and written back keeps its secrets encrypted. Secrets are
+/// recognized by value, wherever they moved to, e.g. in a reordered array, and new values written
+/// where a secret was are encrypted too. Unchanged secrets keep their previous ciphertext, so
+/// rewriting a config does not churn it.
+pub fn encrypt_toml_like(contents: &str, previous: &str) -> Result<String, Box<dyn Error>> {
+    if !previous.contains(ENCRYPTED_PREFIX) {
+        return Ok(contents.to_string());
+    }
+    let installed = INSTALLED.read().unwrap();
+    let cipher = installed
+        .as_ref()
+        .ok_or("The config has encrypted values but no config key is installed")?;
+
+    // Ciphertexts by plaintext, the paths of the encrypted values and the values kept in clear
+    let mut secrets = HashMap::new();
+    let mut paths = HashSet::new();
+    let mut clear = HashSet::new();
+    let mut previous: DocumentMut = previous.parse()?;
+    visit_strings(
+        previous.as_item_mut(),
+        &mut String::new(),
+        &mut |path, value| {
+            if is_encrypted(value) {
+                if let Ok(plaintext) = cipher.decrypt(value) {
+                    secrets.insert(plaintext, value.to_string());
+                }
+                paths.insert(path.to_string());
+            } else {
+                clear.insert(value.to_string());
+            }
+            None
+        },
+    );
+    secrets.remove("");
+
+    let mut document: DocumentMut = contents.parse()?;
+    visit_strings(
+        document.as_item_mut(),
+        &mut String::new(),
+        &mut |path, value| match secrets.get(value) {
+            _ if is_encrypted(value) => None,
+            Some(ciphertext) => Some(ciphertext.clone()),
+            None if paths.contains(path) && !clear.contains(value) => Some(cipher.encrypt(value)),
+            None => None,
+        },
+    );
+    Ok(document.to_string())
+}
+
+/// `encrypt_toml_like` against the current contents of `path`, if any.
+pub(crate) fn encrypt_toml_like_file<P: AsRef<Path>>(
+    contents: &str,
+    path: P,
+) -> Result<String, Box<dyn Error>> {
+    match fs::read_to_string(path) {
+        Ok(previous) => encrypt_toml_like(contents, &previous),
+        Err(_) => Ok(contents.to_string()),
+    }
+}
+
+// Calls `f` with the dotted path (`database[0].url`) of every string value under `item`,
+// replacing the values it returns a new string for
+fn visit_strings<F>(item: &mut Item, path: &mut String, f: &mut F)
+where
+    F: FnMut(&str, &str) -> Option<String>,
+{
+    match item {
+        Item::Table(table) => {
+            for (key, item) in table.iter_mut() {
+                with_segment(path, &format!(".{}", key.get()), |path| {
+                    visit_strings(item, path, f)
+                });
+            }
+        }
+        Item::ArrayOfTables(tables) => {
+            for (index, table) in tables.iter_mut().enumerate() {
+                with_segment(path, &format!("[{}]", index), |path| {
+                    for (key, item) in table.iter_mut() {
+                        with_segment(path, &format!(".{}", key.get()), |path| {
+                            visit_strings(item, path, f)
+                        });
+                    }
+                });
+            }
+        }
+        Item::Value(value) => visit_value(value, path, f),
+        Item::None => {}
+    }
+}
+
+fn visit_value<F>(value: &mut Value, path: &mut String, f: &mut F)
+where
+    F: FnMut(&str, &str) -> Option<String>,
+{
+    match value {
+        Value::String(string) => {
+            if let Some(replacement) = f(path.trim_start_matches('.'), string.value()) {
+                let decor = string.decor().clone();
+                *value = Value::from(replacement);
+                *value.decor_mut() = decor;
+            }
+        }
+        Value::Array(array) => {
+            for (index, value) in array.iter_mut().enumerate() {
+                with_segment(path, &format!("[{}]", index), |path| {
+                    visit_value(value, path, f)
+                });
+            }
+        }
+        Value::InlineTable(table) => {
+            for (key, value) in table.iter_mut() {
+                with_segment(path, &format!(".{}", key.get()), |path| {
+                    visit_value(value, path, f)
+                });
+            }
+        }
+        _ => {}
+    }
+}
+
+fn with_segment(path: &mut String, segment: &str, f: impl FnOnce(&mut String)) {
+    let len = path.len();
+    path.push_str(segment);
+    f(path);
+    path.truncate(len);
+}
@@ -0,0 +1,131 @@
+use std::{error::Error, fmt, ops::Range};
+
+// One problem of a config file, located when the TOML parser knows where it is
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigIssue {
+    pub message: String,          // e.g. "missing field `lang`"
+    pub key_path: Option<String>, // e.g. `database[1].port`, `None` for the document itself
+    pub line: Option<usize>,      // 1-based
+    pub column: Option<usize>,    // 1-based, in characters
+    pub snippet: Option<String>,  // The offending line with the span underlined
+}
+
+impl ConfigIssue {
+    pub fn new(message: &str) -> Self {
+        ConfigIssue {
+            message: message.to_string(),
+            key_path: None,
+            line: None,
+            column: None,
+            snippet: None,
+        }
+    }
+
+    pub fn at_key(mut self, key_path: &str) -> Self {
+        if !key_path.is_empty() && key_path != "." {
+            self.key_path = Some(key_path.to_string());
+        }
+        self
+    }
+
+    /// Locates the issue at the byte range `span` of `contents`.
+    pub fn at_span(mut self, contents: &str, span: Range<usize>) -> Self {
+        let start = span.start.min(contents.len());
+        let line_start = contents[..start].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = contents[start..]
+            .find('\n')
+            .map_or(contents.len(), |i| start + i);
+        let line = contents[..start].matches('\n').count() + 1;
+        let column = contents[line_start..start].chars().count() + 1;
+
+        let text = contents[line_start..line_end].trim_end_matches('\r');
+        let end = span.end.clamp(start, line_end);
+        let width = contents[start..end].chars().count().max(1);
+        let gutter = " ".repeat(line.to_string().len());
+        self.snippet = Some(format!(
+            "{} |\n{} | {}\n{} | {}{}",
+            gutter,
+            line,
+            text,
+            gutter,
+            " ".repeat(column - 1),
+            "^".repeat(width)
+        ));
+        self.line = Some(line);
+        self.column = Some(column);
+        self
+    }
+}
+
+// Why a config file could not be read, with every issue found in it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigError {
+    pub config: String, // Path of the config file
+    pub issues: Vec<ConfigIssue>,
+}
+
+impl ConfigError {
+    pub fn new(config: &str, issues: Vec<ConfigIssue>) -> Self {
+        ConfigError {
+            config: config.to_string(),
+            issues,
+        }
+    }
+
+    /// The error with every occurrence of the `secrets` masked, e.g. the decrypted values of
+    /// the config. Masks have the length of the secret, so snippets stay underlined right.
+    pub fn masked(mut self, secrets: &[String]) -> Self {
+        let mask = |text: &mut String| {
+            for secret in secrets.iter().filter(|secret| !secret.is_empty()) {
+                *text = text.replace(secret.as_str(), &"*".repeat(secret.chars().count()));
+            }
+        };
+        for issue in &mut self.issues {
+            mask(&mut issue.message);
+            if let Some(snippet) = &mut issue.snippet {
+                mask(snippet);
+            }
+        }
+        self
+    }
+
+    /// The error of the TOML parser, with the key path tracked while deserializing.
+    pub fn from_toml(
+        config: &str,
+        contents: &str,
+        error: &toml::de::Error,
+        key_path: Option<&str>,
+    ) -> Self {
+        let mut issue = ConfigIssue::new(error.message().trim_end());
+        if let Some(key_path) = key_path {
+            issue = issue.at_key(key_path);
+        }
+        if let Some(span) = error.span() {
+            issue = issue.at_span(contents, span);
+        }
+        Self::new(config, vec![issue])
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (index, issue) in self.issues.iter().enumerate() {
+            if index > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "error: {}\n  --> {}", issue.message, self.config)?;
+            if let (Some(line), Some(column)) = (issue.line, issue.column) {
+                write!(f, ":{}:{}", line, column)?;
+            }
+            if let Some(key_path) = &issue.key_path {
+                write!(f, " (at `{}`)", key_path)?;
+            }
+            if let Some(snippet) = &issue.snippet {
+                write!(f, "\n{}", snippet)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Error for ConfigError {}
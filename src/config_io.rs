@@ -0,0 +1,156 @@
+use fs2::FileExt;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use similar::TextDiff;
+use std::{
+    error::Error,
+    fs::{self, File},
+    io::Write,
+    path::{Path, PathBuf},
+    process,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{config_crypto::encrypt_toml_like, config_strict::parse_unchecked};
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+pub enum WriteMode {
+    Apply,
+    ApplyWithBackup, // Keeps the previous contents in `<file>.bak`
+    DryRun,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct WritePreview {
+    pub path: PathBuf,
+    pub contents: String,
+    pub diff: String, // Unified diff against the current file, empty when nothing changes
+    pub changed: bool,
+}
+
+/// Writes `contents` to `path`, or only computes what would be written in `DryRun` mode.
+/// Either way the would-be contents and a unified diff against the current file are returned.
+pub fn write_file<P: AsRef<Path>>(
+    path: P,
+    contents: &str,
+    mode: WriteMode,
+) -> Result<WritePreview, Box<dyn Error>> {
+    let path = path.as_ref();
+    let current = if path.exists() {
+        fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read the file '{}': {}", path.display(), e))?
+    } else {
+        String::new()
+    };
+
+    let changed = current != contents;
+    let diff = if changed {
+        let name = path.display().to_string();
+        TextDiff::from_lines(current.as_str(), contents)
+            .unified_diff()
+            .context_radius(3)
+            .header(&name, &name)
+            .to_string()
+    } else {
+        String::new()
+    };
+
+    match mode {
+        WriteMode::Apply => write_atomic(path, contents, false)?,
+        WriteMode::ApplyWithBackup => write_atomic(path, contents, true)?,
+        WriteMode::DryRun => {}
+    }
+
+    Ok(WritePreview {
+        path: path.to_path_buf(),
+        contents: contents.to_string(),
+        diff,
+        changed,
+    })
+}
+
+/// Writes `contents` to a temporary file next to `path` and renames it over `path`, so readers
+/// never observe a partially written file. With `backup`, the previous contents are kept in
+/// `<path>.bak`.
+pub fn write_atomic<P: AsRef<Path>>(
+    path: P,
+    contents: &str,
+    backup: bool,
+) -> Result<(), Box<dyn Error>> {
+    let path = path.as_ref();
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| format!("'{}' is not a file path", path.display()))?
+        .to_string_lossy();
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH)?.subsec_nanos();
+    let temp_path = path.with_file_name(format!(".{}.{}-{}.tmp", file_name, process::id(), nanos));
+
+    let result = write_and_rename(path, &temp_path, contents, backup);
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result.map_err(|e| format!("Failed to write the file '{}': {}", path.display(), e).into())
+}
+
+fn write_and_rename(
+    path: &Path,
+    temp_path: &Path,
+    contents: &str,
+    backup: bool,
+) -> std::io::Result<()> {
+    let mut file = File::create(temp_path)?;
+    file.write_all(contents.as_bytes())?;
+    file.sync_all()?;
+
+    if let Ok(metadata) = fs::metadata(path) {
+        fs::set_permissions(temp_path, metadata.permissions())?;
+        if backup {
+            let mut backup_path = path.as_os_str().to_owned();
+            backup_path.push(".bak");
+            fs::copy(path, backup_path)?;
+        }
+    }
+
+    fs::rename(temp_path, path)
+}
+
+/// Runs a read-modify-write cycle on a TOML config file while holding an exclusive advisory
+/// lock, so concurrent processes (e.g. two CI jobs bumping `releaser.toml`) are serialized.
+/// The file is only written back when `f` succeeds.
+///
+/// The lock is taken on a `<path>.lock` sidecar because the atomic write replaces the config
+/// file itself.
+pub fn with_locked_config<T, P, F, R>(path: P, f: F) -> Result<R, Box<dyn Error>>
+where
+    T: Serialize + DeserializeOwned,
+    P: AsRef<Path>,
+    F: FnOnce(&mut T) -> Result<R, Box<dyn Error>>,
+{
+    let path = path.as_ref();
+    let mut lock_path = path.as_os_str().to_owned();
+    lock_path.push(".lock");
+
+    let lock_file = File::create(&lock_path).map_err(|e| {
+        format!(
+            "Failed to create the lock file for '{}': {}",
+            path.display(),
+            e
+        )
+    })?;
+    lock_file
+        .lock_exclusive()
+        .map_err(|e| format!("Failed to lock '{}': {}", path.display(), e))?;
+
+    let result = (|| {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read the file '{}': {}", path.display(), e))?;
+        let mut config: T = parse_unchecked(&path.display().to_string(), &contents)?;
+
+        let value = f(&mut config)?;
+        let updated = encrypt_toml_like(&toml::to_string(&config)?, &contents)?;
+        write_atomic(path, &updated, false)?;
+        Ok(value)
+    })();
+
+    let _ = FileExt::unlock(&lock_file);
+    result
+}
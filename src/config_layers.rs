@@ -0,0 +1,205 @@
+use schemars::JsonSchema;
+use serde::{de::DeserializeOwned, Serialize};
+use std::{
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    config_strict, config_warnings::Deprecations, ConsumerDBConfig, GingerDBConfig,
+    PackageMetadata, ReleaserConfig, ServiceConfig,
+};
+
+// How an array of the overriding config is combined with the one of the base config
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ListMerge {
+    Replace,             // The override replaces the whole list, the default
+    Append,              // Items of the override that the base lacks are appended
+    ByKey(&'static str), // Tables with the same value at the key are merged, others are appended
+}
+
+// Configs that can be layered, e.g. `services.toml` overridden by an uncommitted
+// `services.local.toml`. Precedence, from lowest to highest: the base file, then the override.
+//
+// Tables are merged key by key, so an override only has to set what differs: `urls.dev` in the
+// override replaces the dev URL and keeps the others. Scalars of the override replace those of
+// the base. Arrays follow the `ListMerge` of their key path.
+pub trait Layered: Serialize + DeserializeOwned + JsonSchema {
+    /// How the arrays at `path` (dotted keys without indices, e.g. `database.links`) are merged.
+    fn list_merge(_path: &str) -> ListMerge {
+        ListMerge::Replace
+    }
+
+    /// Rewrites the merged table before it is parsed, e.g. to move deprecated keys.
+    fn migrate(_raw: &mut toml::Table) {}
+
+    /// What the `read_*` function of the config does after parsing, `raw` being the merged table.
+    fn loaded(&mut self, _raw: &toml::Table) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+
+    /// `base` overridden by `overlay`. Options that are `None` in `overlay` keep the value of
+    /// `base`, every other field of `overlay` wins.
+    fn merge(base: Self, overlay: Self) -> Result<Self, Box<dyn Error>> {
+        let mut table = toml::Table::try_from(&base)?;
+        merge_tables::<Self>(&mut table, toml::Table::try_from(&overlay)?, "");
+        Ok(table.try_into()?)
+    }
+
+    /// Reads the config at `path` and, when it exists, the override next to it (see
+    /// `local_path`). Both files may have encrypted values and unknown keys are treated
+    /// according to the installed `ParseMode`.
+    fn read_layered<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error>> {
+        let path = path.as_ref();
+        let mut raw = read_table(path)?;
+        let local = local_path(path);
+        let config = match local.exists() {
+            true => {
+                merge_tables::<Self>(&mut raw, read_table(&local)?, "");
+                format!("{} + {}", path.display(), local.display())
+            }
+            false => path.display().to_string(),
+        };
+        let mut parsed = raw.clone();
+        Self::migrate(&mut parsed);
+        let mut layered: Self = config_strict::parse_table(&config, parsed)?;
+        layered.loaded(&raw)?;
+        Ok(layered)
+    }
+}
+
+/// The local override of the config at `path`, `services.toml` being overridden by
+/// `services.local.toml`.
+pub fn local_path<P: AsRef<Path>>(path: P) -> PathBuf {
+    let path = path.as_ref();
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match path.extension() {
+        Some(extension) => format!("{}.local.{}", stem, extension.to_string_lossy()),
+        None => format!("{}.local", stem),
+    };
+    path.with_file_name(name)
+}
+
+fn read_table(path: &Path) -> Result<toml::Table, Box<dyn Error>> {
+    let contents = fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read the file '{}': {}", path.display(), e))?;
+    config_strict::from_str(&path.display().to_string(), &contents)
+}
+
+/// Merges `overlay` into `base` with the precedence and list strategies of `T`.
+pub fn merge_tables<T: Layered>(base: &mut toml::Table, overlay: toml::Table, path: &str) {
+    for (key, value) in overlay {
+        let path = match path {
+            "" => key.clone(),
+            _ => format!("{}.{}", path, key),
+        };
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(base)), toml::Value::Table(overlay)) => {
+                merge_tables::<T>(base, overlay, &path)
+            }
+            (Some(toml::Value::Array(base)), toml::Value::Array(overlay)) => {
+                merge_arrays::<T>(base, overlay, &path)
+            }
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+fn merge_arrays<T: Layered>(base: &mut Vec<toml::Value>, overlay: Vec<toml::Value>, path: &str) {
+    match T::list_merge(path) {
+        ListMerge::Replace => *base = overlay,
+        ListMerge::Append => {
+            for value in overlay {
+                if !base.contains(&value) {
+                    base.push(value);
+                }
+            }
+        }
+        ListMerge::ByKey(key) => {
+            for value in overlay {
+                let existing = value
+                    .get(key)
+                    .and_then(|id| base.iter_mut().find(|item| item.get(key) == Some(id)));
+                match (existing, value) {
+                    (Some(toml::Value::Table(item)), toml::Value::Table(overlay)) => {
+                        merge_tables::<T>(item, overlay, path)
+                    }
+                    (_, value) => base.push(value),
+                }
+            }
+        }
+    }
+}
+
+impl Layered for ServiceConfig {
+    fn migrate(raw: &mut toml::Table) {
+        ServiceConfig::replace_deprecated(raw);
+    }
+
+    fn loaded(&mut self, raw: &toml::Table) -> Result<(), Box<dyn Error>> {
+        ServiceConfig::deprecation_warnings(raw).record();
+        self.normalize().log("service config");
+        Ok(self.validate_urls()?)
+    }
+}
+
+// Databases are matched by name, so an override can change the port of one of them
+impl Layered for GingerDBConfig {
+    fn list_merge(path: &str) -> ListMerge {
+        match path {
+            "database" => ListMerge::ByKey("name"),
+            "database.links" => ListMerge::ByKey("label"),
+            _ => ListMerge::Replace,
+        }
+    }
+
+    fn loaded(&mut self, _raw: &toml::Table) -> Result<(), Box<dyn Error>> {
+        self.normalize().log("db config");
+        Ok(())
+    }
+}
+
+impl Layered for ConsumerDBConfig {
+    fn list_merge(path: &str) -> ListMerge {
+        match path {
+            "tables.names" => ListMerge::Append,
+            _ => ListMerge::Replace,
+        }
+    }
+
+    fn loaded(&mut self, _raw: &toml::Table) -> Result<(), Box<dyn Error>> {
+        self.normalize().log("consumer db config");
+        Ok(())
+    }
+}
+
+impl Layered for ReleaserConfig {
+    fn list_merge(path: &str) -> ListMerge {
+        match path {
+            "references" => ListMerge::ByKey("file_name"),
+            _ => ListMerge::Replace,
+        }
+    }
+
+    fn loaded(&mut self, _raw: &toml::Table) -> Result<(), Box<dyn Error>> {
+        self.normalize().log("releaser config");
+        Ok(())
+    }
+}
+
+impl Layered for PackageMetadata {
+    fn list_merge(path: &str) -> ListMerge {
+        match path {
+            "links" => ListMerge::ByKey("label"),
+            _ => ListMerge::Replace,
+        }
+    }
+
+    fn loaded(&mut self, _raw: &toml::Table) -> Result<(), Box<dyn Error>> {
+        self.normalize().log("package metadata");
+        Ok(())
+    }
+}
@@ -0,0 +1,62 @@
+use schemars::{schema::RootSchema, schema_for};
+use std::{
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    config_io::write_atomic,
+    storage::{BlobStore, StorageError},
+    ConsumerDBConfig, GingerDBConfig, PackageMetadata, ReleaserConfig, ServiceConfig,
+};
+
+/// JSON Schemas of every config file, keyed by the file name they are exported under.
+pub fn config_schemas() -> Vec<(&'static str, RootSchema)> {
+    vec![
+        ("service-config.schema.json", schema_for!(ServiceConfig)),
+        ("ginger-db-config.schema.json", schema_for!(GingerDBConfig)),
+        (
+            "consumer-db-config.schema.json",
+            schema_for!(ConsumerDBConfig),
+        ),
+        ("releaser-config.schema.json", schema_for!(ReleaserConfig)),
+        ("package-metadata.schema.json", schema_for!(PackageMetadata)),
+    ]
+}
+
+/// Writes the schemas of `config_schemas` to `dir`, creating it if needed, so editors can
+/// offer completion (e.g. taplo's `#:schema` directive) and other tooling can validate
+/// configs. Returns the written paths.
+pub fn export_schemas<P: AsRef<Path>>(dir: P) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let dir = dir.as_ref();
+    fs::create_dir_all(dir)
+        .map_err(|e| format!("Failed to create the directory '{}': {}", dir.display(), e))?;
+
+    let mut written = vec![];
+    for (file_name, schema) in config_schemas() {
+        let path = dir.join(file_name);
+        let contents = serde_json::to_string_pretty(&schema)?;
+        write_atomic(&path, &(contents + "\n"), false)?;
+        written.push(path);
+    }
+    Ok(written)
+}
+
+/// Like `export_schemas`, uploading to `store` under `prefix`, e.g. `schemas/` of the bucket
+/// the docs site is served from. Returns the written keys.
+pub async fn export_schemas_to(
+    store: &dyn BlobStore,
+    prefix: &str,
+) -> Result<Vec<String>, StorageError> {
+    let mut written = vec![];
+    for (file_name, schema) in config_schemas() {
+        let key = format!("{}{}", prefix, file_name);
+        let contents = serde_json::to_string_pretty(&schema)? + "\n";
+        store
+            .put(&key, contents.into_bytes(), Some("application/schema+json"))
+            .await?;
+        written.push(key);
+    }
+    Ok(written)
+}
@@ -0,0 +1,288 @@
+use schemars::{schema_for, JsonSchema};
+use serde::{de::DeserializeOwned, Deserializer};
+use serde_json::Value;
+use std::{
+    error::Error,
+    ops::Range,
+    sync::atomic::{AtomicBool, Ordering},
+};
+use toml_edit::{ImDocument, Item, Table};
+
+use crate::{
+    config_crypto::decrypt_toml_values,
+    config_error::{ConfigError, ConfigIssue},
+    lint::closest,
+};
+
+static STRICT: AtomicBool = AtomicBool::new(false);
+
+// How the `read_*` config functions treat keys that no field reads
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ParseMode {
+    #[default]
+    Lenient, // Unknown keys are ignored, as serde does by default
+    Strict, // Unknown keys fail the read, with the closest known key as a suggestion
+}
+
+impl ParseMode {
+    /// Makes the `read_*` config functions of the crate parse in this mode, e.g. behind a
+    /// `--strict` flag of a CLI.
+    pub fn install(self) {
+        STRICT.store(self == ParseMode::Strict, Ordering::Relaxed);
+    }
+
+    pub fn current() -> Self {
+        match STRICT.load(Ordering::Relaxed) {
+            true => ParseMode::Strict,
+            false => ParseMode::Lenient,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Key(String),
+    Index(usize),
+}
+
+/// Parses the TOML `contents` of the config `config` (its path, for errors) like
+/// `toml::from_str`, failing with a `ConfigError` locating the problem. Encrypted values are
+/// decrypted by the installed cipher and masked in the errors.
+pub fn from_str<T: DeserializeOwned>(config: &str, contents: &str) -> Result<T, Box<dyn Error>> {
+    deserialize(config, contents, false, None)
+}
+
+/// Like `from_str`, also failing on any key that no field of `T` reads.
+pub fn from_str_strict<T>(config: &str, contents: &str) -> Result<T, Box<dyn Error>>
+where
+    T: DeserializeOwned + JsonSchema,
+{
+    let schema = serde_json::to_value(schema_for!(T)).ok();
+    deserialize(config, contents, true, schema.as_ref())
+}
+
+/// Parses `contents` in the installed `ParseMode`, used by the `read_*` functions.
+pub(crate) fn parse<T>(config: &str, contents: &str) -> Result<T, Box<dyn Error>>
+where
+    T: DeserializeOwned + JsonSchema,
+{
+    match ParseMode::current() {
+        ParseMode::Strict => from_str_strict(config, contents),
+        ParseMode::Lenient => from_str(config, contents),
+    }
+}
+
+/// Like `parse` for configs without a schema, unknown keys are reported without suggestions.
+pub(crate) fn parse_unchecked<T: DeserializeOwned>(
+    config: &str,
+    contents: &str,
+) -> Result<T, Box<dyn Error>> {
+    let strict = ParseMode::current() == ParseMode::Strict;
+    deserialize(config, contents, strict, None)
+}
+
+/// Deserializes a table merged from several files (see `config_layers`) in the installed
+/// `ParseMode`, errors having a key path but no position.
+pub(crate) fn parse_table<T>(config: &str, table: toml::Table) -> Result<T, Box<dyn Error>>
+where
+    T: DeserializeOwned + JsonSchema,
+{
+    let strict = ParseMode::current() == ParseMode::Strict;
+    let schema = match strict {
+        true => serde_json::to_value(schema_for!(T)).ok(),
+        false => None,
+    };
+    let value = toml::Value::Table(table);
+    Ok(deserialize_from(
+        config,
+        "",
+        value,
+        strict,
+        schema.as_ref(),
+    )?)
+}
+
+fn deserialize<T: DeserializeOwned>(
+    config: &str,
+    contents: &str,
+    strict: bool,
+    schema: Option<&Value>,
+) -> Result<T, Box<dyn Error>> {
+    // Errors are located in the decrypted document, its secrets must not end up in them
+    let (contents, secrets) = decrypt_toml_values(contents)?;
+    let deserializer = toml::Deserializer::new(&contents);
+    deserialize_from(config, &contents, deserializer, strict, schema)
+        .map_err(|e| e.masked(&secrets).into())
+}
+
+// `contents` is only used to locate the errors, empty when deserializing a value
+fn deserialize_from<'de, T, D>(
+    config: &str,
+    contents: &str,
+    deserializer: D,
+    strict: bool,
+    schema: Option<&Value>,
+) -> Result<T, ConfigError>
+where
+    T: DeserializeOwned,
+    D: Deserializer<'de, Error = toml::de::Error>,
+{
+    let mut ignored: Vec<Vec<Segment>> = vec![];
+    let mut track = |path: serde_ignored::Path| {
+        let mut segments = vec![];
+        collect_segments(&path, &mut segments);
+        ignored.push(segments);
+    };
+    let deserializer = serde_ignored::Deserializer::new(deserializer, &mut track);
+    let value: T = serde_path_to_error::deserialize(deserializer).map_err(|e| {
+        ConfigError::from_toml(config, contents, e.inner(), Some(&e.path().to_string()))
+    })?;
+    if !strict || ignored.is_empty() {
+        return Ok(value);
+    }
+
+    let document = ImDocument::parse(contents).ok();
+    let issues = ignored
+        .iter()
+        .map(|segments| {
+            let (key, parent) = segments.split_last().expect("ignored keys have a name");
+            let mut message = match key {
+                Segment::Key(key) => format!("unknown key `{}`", key),
+                Segment::Index(index) => format!("unexpected item {}", index),
+            };
+            if let (Segment::Key(key), Some(schema)) = (key, schema) {
+                let known = known_keys(schema, schema, parent);
+                if let Some(suggestion) = closest(key, &known) {
+                    message.push_str(&format!(", did you mean `{}`?", suggestion));
+                }
+            }
+            let issue = ConfigIssue::new(&message).at_key(&display_path(segments));
+            match document
+                .as_ref()
+                .and_then(|document| key_span(document, segments))
+            {
+                Some(span) => issue.at_span(contents, span),
+                None => issue,
+            }
+        })
+        .collect();
+    Err(ConfigError::new(config, issues))
+}
+
+enum Node<'a> {
+    Item(&'a Item),
+    Table(&'a Table),
+    Value(&'a toml_edit::Value),
+}
+
+// Where the key at `path` is written in the document
+fn key_span(document: &ImDocument<&str>, path: &[Segment]) -> Option<Range<usize>> {
+    let mut node = Node::Table(document.as_table());
+    let mut span = None;
+    for segment in path {
+        node = match (segment, node) {
+            (Segment::Key(key), Node::Table(table) | Node::Item(Item::Table(table))) => {
+                let (key, item) = table.get_key_value(key)?;
+                span = key.span();
+                Node::Item(item)
+            }
+            (
+                Segment::Key(key),
+                Node::Item(Item::Value(toml_edit::Value::InlineTable(table)))
+                | Node::Value(toml_edit::Value::InlineTable(table)),
+            ) => {
+                let (key, item) = table.get_key_value(key)?;
+                span = key.span();
+                Node::Item(item)
+            }
+            (Segment::Index(index), Node::Item(Item::ArrayOfTables(tables))) => {
+                Node::Table(tables.get(*index)?)
+            }
+            (
+                Segment::Index(index),
+                Node::Item(Item::Value(toml_edit::Value::Array(array)))
+                | Node::Value(toml_edit::Value::Array(array)),
+            ) => Node::Value(array.get(*index)?),
+            _ => return None,
+        };
+    }
+    span
+}
+
+fn collect_segments(path: &serde_ignored::Path, segments: &mut Vec<Segment>) {
+    match path {
+        serde_ignored::Path::Root => {}
+        serde_ignored::Path::Seq { parent, index } => {
+            collect_segments(parent, segments);
+            segments.push(Segment::Index(*index));
+        }
+        serde_ignored::Path::Map { parent, key } => {
+            collect_segments(parent, segments);
+            segments.push(Segment::Key(key.clone()));
+        }
+        serde_ignored::Path::Some { parent }
+        | serde_ignored::Path::NewtypeStruct { parent }
+        | serde_ignored::Path::NewtypeVariant { parent } => collect_segments(parent, segments),
+    }
+}
+
+fn display_path(segments: &[Segment]) -> String {
+    let mut path = String::new();
+    for segment in segments {
+        match segment {
+            Segment::Key(key) if path.is_empty() => path.push_str(key),
+            Segment::Key(key) => path.push_str(&format!(".{}", key)),
+            Segment::Index(index) => path.push_str(&format!("[{}]", index)),
+        }
+    }
+    path
+}
+
+// The properties of the object the schema describes at `path`, through references and the
+// `anyOf` of optional fields
+fn known_keys(root: &Value, schema: &Value, path: &[Segment]) -> Vec<String> {
+    let mut keys = vec![];
+    for schema in alternatives(root, schema) {
+        match path.split_first() {
+            None => {
+                if let Some(properties) = schema["properties"].as_object() {
+                    keys.extend(properties.keys().cloned());
+                }
+            }
+            Some((Segment::Key(key), rest)) => {
+                let next = match schema["properties"].get(key) {
+                    Some(property) => property,
+                    None => &schema["additionalProperties"],
+                };
+                if next.is_object() {
+                    keys.extend(known_keys(root, next, rest));
+                }
+            }
+            Some((Segment::Index(_), rest)) => {
+                if schema["items"].is_object() {
+                    keys.extend(known_keys(root, &schema["items"], rest));
+                }
+            }
+        }
+    }
+    keys.sort();
+    keys.dedup();
+    keys
+}
+
+fn alternatives<'a>(root: &'a Value, schema: &'a Value) -> Vec<&'a Value> {
+    let schema = match schema["$ref"].as_str() {
+        Some(reference) => reference
+            .strip_prefix("#/definitions/")
+            .and_then(|name| root["definitions"].get(name))
+            .unwrap_or(schema),
+        None => schema,
+    };
+    let mut schemas = vec![schema];
+    for combinator in ["anyOf", "oneOf", "allOf"] {
+        for alternative in schema[combinator].as_array().into_iter().flatten() {
+            schemas.extend(alternatives(root, alternative));
+        }
+    }
+    schemas
+}
@@ -0,0 +1,212 @@
+use serde::{Deserialize, Serialize};
+use std::{fmt, fmt::Write, sync::Mutex};
+
+use crate::ServiceConfig;
+
+static RECORDED: Mutex<Vec<ConfigWarning>> = Mutex::new(vec![]);
+
+// A config key that is still read but is going away
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeprecatedField {
+    pub key: &'static str, // Dotted for nested keys, e.g. `portal_config.tnc_url`
+    pub replacement: Option<&'static str>,
+    pub hint: &'static str,
+}
+
+// Configs with deprecated fields, checked by the `read_*` functions and the
+// `deprecated-fields` lint rule
+pub trait Deprecations {
+    /// The file name of the config, e.g. `services.toml`.
+    const CONFIG: &'static str;
+
+    fn deprecated_fields() -> &'static [DeprecatedField];
+
+    /// The warnings of the deprecated fields set in the raw config.
+    fn deprecation_warnings(raw: &toml::Table) -> ConfigWarnings {
+        let mut warnings = ConfigWarnings::default();
+        for field in Self::deprecated_fields() {
+            if lookup(raw, field.key).is_some() {
+                warnings.push(ConfigWarning::deprecated(Self::CONFIG, field));
+            }
+        }
+        warnings
+    }
+
+    /// Moves the deprecated keys set in the raw config to their replacement, which wins when
+    /// both are set. Returns whether `raw` changed.
+    fn replace_deprecated(raw: &mut toml::Table) -> bool {
+        let mut changed = false;
+        for field in Self::deprecated_fields() {
+            let Some(replacement) = field.replacement else {
+                continue;
+            };
+            let (parent, key) = split_parent(field.key);
+            let (replacement_parent, replacement_key) = split_parent(replacement);
+            let table = match parent {
+                _ if parent != replacement_parent => continue,
+                Some(parent) => lookup_mut(raw, parent).and_then(toml::Value::as_table_mut),
+                None => Some(&mut *raw),
+            };
+            let Some(table) = table else {
+                continue;
+            };
+            let Some(value) = table.remove(key) else {
+                continue;
+            };
+            changed = true;
+            if table.contains_key(replacement_key) {
+                tracing::warn!(
+                    config = Self::CONFIG,
+                    key = field.key,
+                    replacement,
+                    "both the deprecated key and its replacement are set, the replacement is used"
+                );
+            } else {
+                table.insert(replacement_key.to_string(), value);
+            }
+        }
+        changed
+    }
+}
+
+impl Deprecations for ServiceConfig {
+    const CONFIG: &'static str = "services.toml";
+
+    fn deprecated_fields() -> &'static [DeprecatedField] {
+        &[
+            DeprecatedField {
+                key: "portal_refs_file",
+                replacement: Some("refs_file"),
+                hint: "Rename `portal_refs_file` to `refs_file`",
+            },
+            DeprecatedField {
+                key: "override_name",
+                replacement: None,
+                hint: "Remove `override_name`, the service is named after its package",
+            },
+        ]
+    }
+}
+
+// The dotted path of the table holding `key`, if any, and the key within it
+fn split_parent(key: &str) -> (Option<&str>, &str) {
+    match key.rsplit_once('.') {
+        Some((parent, key)) => (Some(parent), key),
+        None => (None, key),
+    }
+}
+
+fn lookup_mut<'a>(table: &'a mut toml::Table, key: &str) -> Option<&'a mut toml::Value> {
+    let (first, rest) = match key.split_once('.') {
+        Some((first, rest)) => (first, Some(rest)),
+        None => (key, None),
+    };
+    let value = table.get_mut(first)?;
+    match rest {
+        Some(rest) => lookup_mut(value.as_table_mut()?, rest),
+        None => Some(value),
+    }
+}
+
+fn lookup<'a>(table: &'a toml::Table, key: &str) -> Option<&'a toml::Value> {
+    let (first, rest) = match key.split_once('.') {
+        Some((first, rest)) => (first, Some(rest)),
+        None => (key, None),
+    };
+    let value = table.get(first)?;
+    match rest {
+        Some(rest) => lookup(value.as_table()?, rest),
+        None => Some(value),
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+pub struct ConfigWarning {
+    pub config: String,
+    pub key: String,
+    pub message: String,
+    pub replacement: Option<String>, // The key to use instead, when there is one
+    pub hint: Option<String>,
+}
+
+impl ConfigWarning {
+    pub fn deprecated(config: &str, field: &DeprecatedField) -> Self {
+        ConfigWarning {
+            config: config.to_string(),
+            key: field.key.to_string(),
+            message: format!("`{}` is deprecated", field.key),
+            replacement: field.replacement.map(str::to_string),
+            hint: Some(field.hint.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for ConfigWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "warning: {} [{}]: {}",
+            self.config, self.key, self.message
+        )?;
+        if let Some(hint) = &self.hint {
+            write!(f, "\n  help: {}", hint)?;
+        }
+        Ok(())
+    }
+}
+
+// The warnings of the configs read, without duplicates
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Default)]
+pub struct ConfigWarnings {
+    pub warnings: Vec<ConfigWarning>,
+}
+
+impl ConfigWarnings {
+    /// Drains the warnings recorded by the `read_*` functions since the last call, for a CLI to
+    /// print them once however many times the configs were read.
+    pub fn take() -> Self {
+        ConfigWarnings {
+            warnings: std::mem::take(&mut *RECORDED.lock().unwrap()),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.warnings.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.warnings.len()
+    }
+
+    pub fn push(&mut self, warning: ConfigWarning) {
+        if !self.warnings.contains(&warning) {
+            self.warnings.push(warning);
+        }
+    }
+
+    pub fn extend(&mut self, other: ConfigWarnings) {
+        for warning in other.warnings {
+            self.push(warning);
+        }
+    }
+
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        for warning in &self.warnings {
+            writeln!(out, "{}", warning).unwrap();
+        }
+        out
+    }
+
+    /// Logs the warnings at debug level and keeps them for `take`, used by the `read_*`
+    /// functions.
+    pub(crate) fn record(self) {
+        let mut recorded = RECORDED.lock().unwrap();
+        for warning in self.warnings {
+            tracing::debug!(config = %warning.config, key = %warning.key, "{}", warning.message);
+            if !recorded.contains(&warning) {
+                recorded.push(warning);
+            }
+        }
+    }
+}
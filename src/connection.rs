@@ -0,0 +1,164 @@
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use std::error::Error;
+
+use crate::{
+    secrets::{db_secret, SecretError, SecretProvider},
+    DatabaseConfig, DbEngine, Environment,
+};
+
+// Characters that are allowed unescaped in the userinfo part of a URL
+const USERINFO: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+const DEFAULT_K8_NAMESPACE: &str = "default";
+
+#[derive(Debug, Clone, Default)]
+pub struct DbCredentials {
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub database: Option<String>, // Defaults to the name of the database entry
+    pub namespace: Option<String>, // Kubernetes namespace, only used in the K8 environments
+}
+
+impl DbCredentials {
+    /// The credentials of `database` held by `secrets`: `db/{name}/username`,
+    /// `db/{name}/password` and `db/{name}/database`, each of them optional.
+    pub async fn from_secrets(
+        secrets: &dyn SecretProvider,
+        database: &DatabaseConfig,
+    ) -> Result<Self, SecretError> {
+        let read = |field: &str| {
+            let name = db_secret(&database.name, field);
+            async move {
+                Ok::<_, SecretError>(
+                    secrets
+                        .get(&name)
+                        .await?
+                        .map(|secret| secret.expose().to_string()),
+                )
+            }
+        };
+        Ok(DbCredentials {
+            username: read("username").await?,
+            password: read("password").await?,
+            database: read("database").await?,
+            namespace: None,
+        })
+    }
+}
+
+impl DatabaseConfig {
+    pub fn effective_engine(&self) -> DbEngine {
+        self.engine
+            .unwrap_or_else(|| DbEngine::default_for(&self.db_type))
+    }
+
+    /// Builds the connection URL for this database as seen from the given environment.
+    ///
+    /// In `Dev` the database is reached on localhost through the configured `port`. In the
+    /// other environments it is reached through its service name on the engine's default
+    /// port, using the cluster DNS name in the K8 environments.
+    pub fn connection_url(
+        &self,
+        env: &Environment,
+        credentials: &DbCredentials,
+    ) -> Result<String, Box<dyn Error>> {
+        let engine = self.effective_engine();
+
+        let (host, port) = match env {
+            Environment::Dev => {
+                let port: u16 = self.port.trim().parse().map_err(|_| {
+                    format!(
+                        "Database '{}' has an invalid port '{}'",
+                        self.name, self.port
+                    )
+                })?;
+                ("localhost".to_string(), port)
+            }
+            Environment::Stage | Environment::Prod => (self.name.clone(), engine.default_port()),
+            Environment::StageK8 | Environment::ProdK8 => {
+                let namespace = credentials
+                    .namespace
+                    .as_deref()
+                    .unwrap_or(DEFAULT_K8_NAMESPACE);
+                (
+                    format!("{}.{}.svc.cluster.local", self.name, namespace),
+                    engine.default_port(),
+                )
+            }
+        };
+
+        let userinfo = userinfo(credentials);
+        let database = credentials.database.as_deref().unwrap_or(&self.name);
+
+        let url = match engine {
+            DbEngine::Postgres | DbEngine::Mysql => {
+                if credentials.username.is_none() {
+                    return Err(format!(
+                        "A username is required to connect to the {} database '{}'",
+                        engine, self.name
+                    )
+                    .into());
+                }
+                format!(
+                    "{}://{}{}:{}/{}",
+                    engine,
+                    userinfo,
+                    host,
+                    port,
+                    encode(database)
+                )
+            }
+            DbEngine::Mongodb => format!(
+                "mongodb://{}{}:{}/{}",
+                userinfo,
+                host,
+                port,
+                encode(database)
+            ),
+            DbEngine::Redis => {
+                // Redis databases are numbered, fall back to 0 unless one is given explicitly
+                let db_index = match &credentials.database {
+                    Some(index) => index
+                        .parse::<u32>()
+                        .map_err(|_| format!("Redis database index '{}' is not a number", index))?,
+                    None => 0,
+                };
+                format!("redis://{}{}:{}/{}", userinfo, host, port, db_index)
+            }
+            DbEngine::Rabbitmq => {
+                // The default vhost is "/", which has to be encoded in the path
+                let vhost = credentials.database.as_deref().unwrap_or("/");
+                format!("amqp://{}{}:{}/{}", userinfo, host, port, encode(vhost))
+            }
+            // Brokers are only reached without TLS on the local machine
+            DbEngine::Kafka => {
+                let scheme = match env {
+                    Environment::Dev => "kafka",
+                    _ => "kafkas",
+                };
+                format!("{}://{}{}:{}", scheme, userinfo, host, port)
+            }
+        };
+
+        Ok(url)
+    }
+}
+
+fn encode(value: &str) -> String {
+    utf8_percent_encode(value, USERINFO).to_string()
+}
+
+fn userinfo(credentials: &DbCredentials) -> String {
+    match (&credentials.username, &credentials.password) {
+        (Some(username), Some(password)) => {
+            format!("{}:{}@", encode(username), encode(password))
+        }
+        (Some(username), None) => format!("{}@", encode(username)),
+        (None, Some(password)) => format!(":{}@", encode(password)),
+        (None, None) => String::new(),
+    }
+}
@@ -0,0 +1,241 @@
+use hmac::{Hmac, Mac};
+use okapi::openapi3::Responses;
+use rand::Rng;
+use rocket::{
+    fairing::{Fairing, Info, Kind},
+    http::{ContentType, Cookie, Method, SameSite, Status},
+    request::{FromRequest, Outcome},
+    Build, Data, Request, Response, Rocket,
+};
+use rocket_okapi::{
+    gen::OpenApiGenerator,
+    request::{OpenApiFromRequest, RequestHeaderInput},
+};
+use sha2::Sha256;
+
+use crate::{
+    claims::Claims,
+    rocket_errors::{deny, mount_denied},
+    rocket_models::ApiError,
+    rocket_utils::authenticate,
+    sessions::session_id,
+    validation::record_failure,
+};
+
+pub const CSRF_COOKIE: &str = "csrf_token";
+pub const CSRF_HEADER: &str = "X-CSRF-Token";
+/// Form field checked when the header is missing. It has to be the first field of the form, the
+/// fairing only looks at the start of the body.
+pub const CSRF_FIELD: &str = "_csrf";
+
+fn mac(secret: &[u8], binding: &str, nonce: &str) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC accepts keys of any size");
+    mac.update(format!("{}.{}", binding, nonce).as_bytes());
+    mac
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+// The token of the current request, valid or freshly issued, for `CsrfToken`
+struct RequestToken(Option<String>);
+
+// Double submit cookie protection for browser-facing endpoints. Every response carries a
+// `csrf_token` cookie readable by the page's scripts, and unsafe requests (POST, PUT, PATCH,
+// DELETE) sending cookies must echo it in the `X-CSRF-Token` header or the `_csrf` form field.
+// Tokens are signed and bound to the session of the `Authorization` token, see
+// `sessions::session_id`, so logging in issues a new one and a token planted before is useless.
+#[derive(Clone)]
+pub struct Csrf {
+    secret: Vec<u8>,
+    exempt: Vec<String>, // Path prefixes not checked, e.g. webhooks receivers
+    secure: bool,
+}
+
+impl Csrf {
+    /// `secret` has to be shared by every replica of the service.
+    pub fn new(secret: &str) -> Self {
+        Csrf {
+            secret: secret.as_bytes().to_vec(),
+            exempt: vec![],
+            secure: true,
+        }
+    }
+
+    pub fn exempt(mut self, prefix: &str) -> Self {
+        self.exempt.push(prefix.to_string());
+        self
+    }
+
+    /// Lets the cookie be sent over plain HTTP, for local development only.
+    pub fn insecure(mut self) -> Self {
+        self.secure = false;
+        self
+    }
+
+    pub fn issue(&self, binding: &str) -> String {
+        let nonce = hex(&rand::thread_rng().gen::<[u8; 16]>());
+        let signature = hex(&mac(&self.secret, binding, &nonce).finalize().into_bytes());
+        format!("{}.{}", nonce, signature)
+    }
+
+    pub fn verify(&self, token: &str, binding: &str) -> bool {
+        let Some((nonce, signature)) = token.split_once('.') else {
+            return false;
+        };
+        match decode_hex(signature) {
+            Some(signature) => mac(&self.secret, binding, nonce)
+                .verify_slice(&signature)
+                .is_ok(),
+            None => false,
+        }
+    }
+
+    fn is_exempt(&self, path: &str) -> bool {
+        self.exempt
+            .iter()
+            .any(|prefix| path.starts_with(prefix.as_str()))
+    }
+
+    // Decoded without the `Claims` guard so its failure or actor is not recorded for a route
+    // authenticated otherwise
+    async fn binding(request: &Request<'_>) -> String {
+        match authenticate::<Claims>(request, "Authorization").await {
+            Ok(claims) => session_id(&claims),
+            Err(_) => String::new(),
+        }
+    }
+
+    async fn submitted_token(request: &Request<'_>, data: &mut Data<'_>) -> Option<String> {
+        if let Some(token) = request.headers().get_one(CSRF_HEADER) {
+            return Some(token.trim().to_string());
+        }
+        if request.content_type() != Some(&ContentType::Form) {
+            return None;
+        }
+        let start = String::from_utf8_lossy(data.peek(512).await).to_string();
+        url::form_urlencoded::parse(start.as_bytes())
+            .find(|(name, _)| name == CSRF_FIELD)
+            .map(|(_, value)| value.to_string())
+    }
+}
+
+fn is_safe(method: Method) -> bool {
+    matches!(
+        method,
+        Method::Get | Method::Head | Method::Options | Method::Trace
+    )
+}
+
+#[rocket::async_trait]
+impl Fairing for Csrf {
+    fn info(&self) -> Info {
+        Info {
+            name: "CSRF protection",
+            kind: Kind::Ignite | Kind::Request | Kind::Response,
+        }
+    }
+
+    async fn on_ignite(&self, rocket: Rocket<Build>) -> rocket::fairing::Result {
+        Ok(mount_denied(rocket))
+    }
+
+    async fn on_request(&self, request: &mut Request<'_>, data: &mut Data<'_>) {
+        let binding = Self::binding(request).await;
+        let cookie = request
+            .cookies()
+            .get(CSRF_COOKIE)
+            .map(|cookie| cookie.value().to_string())
+            .filter(|token| self.verify(token, &binding));
+
+        // Requests without cookies carry no ambient credentials to abuse
+        let checked = !is_safe(request.method())
+            && !self.is_exempt(request.uri().path().as_str())
+            && request.cookies().iter().next().is_some();
+        if checked {
+            let submitted = Self::submitted_token(request, data).await;
+            let valid = matches!(
+                (&cookie, submitted),
+                (Some(cookie), Some(submitted)) if *cookie == submitted
+            );
+            if !valid {
+                deny(
+                    request,
+                    ApiError::new(
+                        403,
+                        "csrf_failed",
+                        "The request is missing a valid CSRF token, reload the page and try again",
+                    ),
+                );
+            }
+        }
+
+        let issued = cookie.is_none().then(|| self.issue(&binding));
+        request.local_cache(|| RequestToken(issued.or(cookie)));
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        let token = &request.local_cache(|| RequestToken(None)).0;
+        let cookie = request.cookies().get(CSRF_COOKIE);
+        if let Some(token) = token
+            .as_ref()
+            .filter(|token| cookie.is_none_or(|cookie| cookie.value() != token.as_str()))
+        {
+            // Not HttpOnly: the page's scripts read it to fill the header
+            let cookie = Cookie::build((CSRF_COOKIE, token.clone()))
+                .path("/")
+                .same_site(SameSite::Lax)
+                .secure(self.secure)
+                .http_only(false);
+            response.adjoin_header(cookie.build());
+        }
+    }
+}
+
+// The CSRF token of the current request, to embed in server rendered forms. Requires the `Csrf`
+// fairing.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CsrfToken(pub String);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for CsrfToken {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        match &request.local_cache(|| RequestToken(None)).0 {
+            Some(token) => Outcome::Success(CsrfToken(token.clone())),
+            None => {
+                record_failure(
+                    request,
+                    ApiError::internal("The Csrf fairing is not attached"),
+                );
+                Outcome::Error((Status::InternalServerError, ()))
+            }
+        }
+    }
+}
+
+impl<'a> OpenApiFromRequest<'a> for CsrfToken {
+    fn from_request_input(
+        _gen: &mut OpenApiGenerator,
+        _name: String,
+        _required: bool,
+    ) -> rocket_okapi::Result<RequestHeaderInput> {
+        Ok(RequestHeaderInput::None)
+    }
+
+    fn get_responses(_gen: &mut OpenApiGenerator) -> rocket_okapi::Result<Responses> {
+        Ok(Responses::default())
+    }
+}
@@ -0,0 +1,355 @@
+use diesel::{
+    r2d2::{ConnectionManager, Pool, R2D2Connection},
+    result::{DatabaseErrorKind, Error as DieselError},
+};
+use std::{env, error::Error, time::Duration};
+
+use crate::{rocket_models::ApiError, Environment};
+
+pub const DATABASE_URL_ENV: &str = "DATABASE_URL";
+/// Overrides the pool size of the environment.
+pub const POOL_SIZE_ENV: &str = "DB_POOL_SIZE";
+/// Overrides how long to wait for a connection, in seconds.
+pub const POOL_TIMEOUT_ENV: &str = "DB_POOL_TIMEOUT_SECS";
+
+pub type DbPool<C> = Pool<ConnectionManager<C>>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PoolSettings {
+    pub max_size: u32,
+    pub min_idle: Option<u32>, // Connections kept open, `None` for as many as `max_size`
+    pub connection_timeout: Duration,
+    pub idle_timeout: Option<Duration>,
+    pub max_lifetime: Option<Duration>,
+    pub test_on_check_out: bool,
+}
+
+impl PoolSettings {
+    /// A few connections in `Dev`, more in the shared environments where several replicas and
+    /// proxies recycle idle connections.
+    pub fn for_environment(env: &Environment) -> Self {
+        match env {
+            Environment::Dev => PoolSettings {
+                max_size: 5,
+                min_idle: Some(1),
+                connection_timeout: Duration::from_secs(5),
+                idle_timeout: Some(Duration::from_secs(5 * 60)),
+                max_lifetime: None,
+                test_on_check_out: true,
+            },
+            Environment::Stage | Environment::StageK8 => PoolSettings {
+                max_size: 10,
+                min_idle: Some(2),
+                connection_timeout: Duration::from_secs(10),
+                idle_timeout: Some(Duration::from_secs(10 * 60)),
+                max_lifetime: Some(Duration::from_secs(30 * 60)),
+                test_on_check_out: true,
+            },
+            Environment::Prod | Environment::ProdK8 => PoolSettings {
+                max_size: 20,
+                min_idle: Some(5),
+                connection_timeout: Duration::from_secs(10),
+                idle_timeout: Some(Duration::from_secs(10 * 60)),
+                max_lifetime: Some(Duration::from_secs(30 * 60)),
+                test_on_check_out: true,
+            },
+        }
+    }
+
+    /// The settings of `env` with `DB_POOL_SIZE` and `DB_POOL_TIMEOUT_SECS` applied.
+    pub fn from_env(env: &Environment) -> Result<Self, Box<dyn Error>> {
+        let mut settings = Self::for_environment(env);
+        if let Ok(size) = env::var(POOL_SIZE_ENV) {
+            settings.max_size = size
+                .trim()
+                .parse()
+                .ok()
+                .filter(|size| *size > 0)
+                .ok_or_else(|| {
+                    format!(
+                        "{} must be a positive number, got '{}'",
+                        POOL_SIZE_ENV, size
+                    )
+                })?;
+            settings.min_idle = settings.min_idle.map(|idle| idle.min(settings.max_size));
+        }
+        if let Ok(timeout) = env::var(POOL_TIMEOUT_ENV) {
+            let secs: u64 = timeout
+                .trim()
+                .parse()
+                .map_err(|_| format!("{} must be a number, got '{}'", POOL_TIMEOUT_ENV, timeout))?;
+            settings.connection_timeout = Duration::from_secs(secs);
+        }
+        Ok(settings)
+    }
+}
+
+/// A pool tuned for `env`, e.g. `build_pool::<PgConnection>(&url, &Environment::detect()?)`.
+/// Fails when the first connections cannot be opened within the connection timeout.
+pub fn build_pool<C: R2D2Connection + 'static>(
+    database_url: &str,
+    env: &Environment,
+) -> Result<DbPool<C>, Box<dyn Error>> {
+    build_pool_with(database_url, &PoolSettings::from_env(env)?)
+}
+
+pub fn build_pool_with<C: R2D2Connection + 'static>(
+    database_url: &str,
+    settings: &PoolSettings,
+) -> Result<DbPool<C>, Box<dyn Error>> {
+    Pool::builder()
+        .max_size(settings.max_size)
+        .min_idle(settings.min_idle)
+        .connection_timeout(settings.connection_timeout)
+        .idle_timeout(settings.idle_timeout)
+        .max_lifetime(settings.max_lifetime)
+        .test_on_check_out(settings.test_on_check_out)
+        .build(ConnectionManager::<C>::new(database_url))
+        // The URL holds the credentials, only the pool's error is reported
+        .map_err(|e| format!("Failed to open the database pool: {}", e).into())
+}
+
+// Lets handlers and transactions use `?` on queries. Constraint violations are the client's
+// fault, anything else is logged and hidden behind a 500.
+impl From<DieselError> for ApiError {
+    fn from(error: DieselError) -> Self {
+        match &error {
+            DieselError::NotFound => ApiError::not_found("The resource does not exist"),
+            DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, info) => {
+                ApiError::conflict(&format!("The resource already exists: {}", info.message()))
+            }
+            DieselError::DatabaseError(DatabaseErrorKind::ForeignKeyViolation, info) => {
+                ApiError::conflict(&format!(
+                    "The resource is referenced or references a missing one: {}",
+                    info.message()
+                ))
+            }
+            DieselError::DatabaseError(
+                DatabaseErrorKind::NotNullViolation | DatabaseErrorKind::CheckViolation,
+                info,
+            ) => ApiError::unprocessable(info.message()),
+            _ => {
+                tracing::error!(error = %error, "database error");
+                ApiError::internal("An unexpected error occurred")
+            }
+        }
+    }
+}
+
+#[cfg(feature = "rocket")]
+pub use self::rocket_pool::{fairing, fairing_from_env, DbConn, Tx};
+
+#[cfg(feature = "rocket")]
+mod rocket_pool {
+    use diesel::{
+        r2d2::{ConnectionManager, PooledConnection, R2D2Connection},
+        result::Error as DieselError,
+    };
+    use okapi::openapi3::Responses;
+    use rocket::{
+        fairing::AdHoc,
+        http::Status,
+        request::{FromRequest, Outcome, Request},
+    };
+    use rocket_okapi::{
+        gen::OpenApiGenerator,
+        request::{OpenApiFromRequest, RequestHeaderInput},
+    };
+    use std::{
+        env,
+        ops::{Deref, DerefMut},
+    };
+
+    use super::{build_pool, DbPool, DATABASE_URL_ENV};
+    use crate::{rocket_models::ApiError, validation::record_failure, Environment};
+
+    /// Builds the pool when Rocket ignites and manages it, failing the launch when the database
+    /// cannot be reached.
+    pub fn fairing<C: R2D2Connection + 'static>(database_url: &str, env: Environment) -> AdHoc {
+        let database_url = database_url.to_string();
+        AdHoc::try_on_ignite("Database pool", move |rocket| async move {
+            let pool = rocket::tokio::task::spawn_blocking(move || {
+                build_pool::<C>(&database_url, &env).map_err(|e| e.to_string())
+            })
+            .await;
+            match pool {
+                Ok(Ok(pool)) => Ok(rocket.manage(pool)),
+                Ok(Err(e)) => {
+                    tracing::error!(error = %e, env = %env, "failed to open the database pool");
+                    Err(rocket)
+                }
+                Err(e) => {
+                    tracing::error!(error = %e, "failed to open the database pool");
+                    Err(rocket)
+                }
+            }
+        })
+    }
+
+    /// Like `fairing`, with the URL from `DATABASE_URL` and the detected environment.
+    pub fn fairing_from_env<C: R2D2Connection + 'static>() -> AdHoc {
+        let config = env::var(DATABASE_URL_ENV)
+            .map_err(|_| format!("{} is not set", DATABASE_URL_ENV))
+            .and_then(|url| {
+                Environment::detect()
+                    .map(|env| (url, env))
+                    .map_err(|e| e.to_string())
+            });
+        match config {
+            Ok((url, env)) => fairing::<C>(&url, env),
+            Err(e) => AdHoc::try_on_ignite("Database pool", move |rocket| async move {
+                tracing::error!(error = %e, "failed to configure the database pool");
+                Err(rocket)
+            }),
+        }
+    }
+
+    // A connection checked out of the managed pool for the duration of the request. Diesel is
+    // blocking, `run` moves the queries off the async workers.
+    pub struct DbConn<C: R2D2Connection + 'static>(PooledConnection<ConnectionManager<C>>);
+
+    impl<C: R2D2Connection + 'static> DbConn<C> {
+        pub async fn run<F, R>(mut self, f: F) -> R
+        where
+            F: FnOnce(&mut C) -> R + Send + 'static,
+            R: Send + 'static,
+        {
+            rocket::tokio::task::spawn_blocking(move || f(&mut self.0))
+                .await
+                .unwrap_or_else(|e| std::panic::resume_unwind(e.into_panic()))
+        }
+
+        /// Runs `f` in a transaction, committed when it returns `Ok` and rolled back when it
+        /// returns an error or panics.
+        pub async fn transaction<F, T>(self, f: F) -> Result<T, ApiError>
+        where
+            F: FnOnce(&mut C) -> Result<T, ApiError> + Send + 'static,
+            T: Send + 'static,
+        {
+            self.run(|conn| {
+                let mut failure = None;
+                let result = conn.transaction(|conn| {
+                    f(conn).map_err(|error| {
+                        failure = Some(error);
+                        DieselError::RollbackTransaction
+                    })
+                });
+                match (result, failure) {
+                    (Ok(value), _) => Ok(value),
+                    (Err(_), Some(error)) => Err(error),
+                    // The commit or the rollback itself failed
+                    (Err(error), None) => Err(ApiError::from(error)),
+                }
+            })
+            .await
+        }
+
+        pub fn into_inner(self) -> PooledConnection<ConnectionManager<C>> {
+            self.0
+        }
+    }
+
+    impl<C: R2D2Connection + 'static> Deref for DbConn<C> {
+        type Target = C;
+
+        fn deref(&self) -> &Self::Target {
+            &self.0
+        }
+    }
+
+    impl<C: R2D2Connection + 'static> DerefMut for DbConn<C> {
+        fn deref_mut(&mut self) -> &mut Self::Target {
+            &mut self.0
+        }
+    }
+
+    #[rocket::async_trait]
+    impl<'r, C: R2D2Connection + 'static> FromRequest<'r> for DbConn<C> {
+        type Error = ApiError;
+
+        async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+            let pool = match request.rocket().state::<DbPool<C>>() {
+                Some(pool) => pool.clone(),
+                None => {
+                    tracing::error!("no database pool is managed, attach `db_pool::fairing`");
+                    let error = ApiError::internal("The database is not configured");
+                    return Outcome::Error((Status::InternalServerError, error));
+                }
+            };
+            match rocket::tokio::task::spawn_blocking(move || pool.get()).await {
+                Ok(Ok(conn)) => Outcome::Success(DbConn(conn)),
+                Ok(Err(e)) => {
+                    tracing::error!(error = %e, "no database connection available");
+                    let error = record_failure(
+                        request,
+                        ApiError::new(
+                            503,
+                            "database_unavailable",
+                            "The database is not available, try again later",
+                        ),
+                    );
+                    Outcome::Error((Status::ServiceUnavailable, error))
+                }
+                Err(e) => {
+                    tracing::error!(error = %e, "failed to check out a database connection");
+                    Outcome::Error((
+                        Status::InternalServerError,
+                        ApiError::internal("An unexpected error occurred"),
+                    ))
+                }
+            }
+        }
+    }
+
+    impl<'a, C: R2D2Connection + 'static> OpenApiFromRequest<'a> for DbConn<C> {
+        fn from_request_input(
+            _gen: &mut OpenApiGenerator,
+            _name: String,
+            _required: bool,
+        ) -> rocket_okapi::Result<RequestHeaderInput> {
+            Ok(RequestHeaderInput::None)
+        }
+
+        fn get_responses(_gen: &mut OpenApiGenerator) -> rocket_okapi::Result<Responses> {
+            Ok(Responses::default())
+        }
+    }
+
+    // A connection whose work runs in one transaction per request: handlers return the result
+    // of `Tx::run`, so a failed request leaves no partial writes behind.
+    pub struct Tx<C: R2D2Connection + 'static>(DbConn<C>);
+
+    impl<C: R2D2Connection + 'static> Tx<C> {
+        /// Commits when `f` returns `Ok`, rolls back when it returns an `ApiError` or panics.
+        pub async fn run<F, T>(self, f: F) -> Result<T, ApiError>
+        where
+            F: FnOnce(&mut C) -> Result<T, ApiError> + Send + 'static,
+            T: Send + 'static,
+        {
+            self.0.transaction(f).await
+        }
+    }
+
+    #[rocket::async_trait]
+    impl<'r, C: R2D2Connection + 'static> FromRequest<'r> for Tx<C> {
+        type Error = ApiError;
+
+        async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+            DbConn::<C>::from_request(request).await.map(Tx)
+        }
+    }
+
+    impl<'a, C: R2D2Connection + 'static> OpenApiFromRequest<'a> for Tx<C> {
+        fn from_request_input(
+            gen: &mut OpenApiGenerator,
+            name: String,
+            required: bool,
+        ) -> rocket_okapi::Result<RequestHeaderInput> {
+            DbConn::<C>::from_request_input(gen, name, required)
+        }
+
+        fn get_responses(_gen: &mut OpenApiGenerator) -> rocket_okapi::Result<Responses> {
+            Ok(Responses::default())
+        }
+    }
+}
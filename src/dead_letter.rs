@@ -0,0 +1,231 @@
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{fmt, future::Future, pin::Pin, sync::Arc};
+use uuid::Uuid;
+
+use crate::{
+    events::{EventDecoder, EventEnvelope, PublishError, RealtimePublisher},
+    rocket_models::RealtimeMessage,
+    webhooks::RetryPolicy,
+};
+
+const DEAD_LETTER_SUFFIX: &str = ".dead_letter";
+
+/// Where the messages of `topic` go once their consumer gives up on them.
+pub fn dead_letter_topic(topic: &str) -> String {
+    format!("{}{}", topic, DEAD_LETTER_SUFFIX)
+}
+
+// Why a handler failed. Poison messages, e.g. ones that do not decode, fail the same way every
+// time and are dead-lettered without being retried.
+#[derive(Debug, Clone, PartialEq)]
+pub enum HandlerError {
+    Retry(String),
+    Poison(String),
+}
+
+impl HandlerError {
+    pub fn retry<E: fmt::Display>(error: E) -> Self {
+        HandlerError::Retry(error.to_string())
+    }
+
+    pub fn poison<E: fmt::Display>(error: E) -> Self {
+        HandlerError::Poison(error.to_string())
+    }
+
+    pub fn message(&self) -> &str {
+        match self {
+            HandlerError::Retry(message) | HandlerError::Poison(message) => message,
+        }
+    }
+}
+
+impl fmt::Display for HandlerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message())
+    }
+}
+
+impl std::error::Error for HandlerError {}
+
+// A message a consumer gave up on, with what is needed to look into it and replay it
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, JsonSchema)]
+pub struct DeadLetter {
+    pub id: Uuid,
+    pub consumer: String,
+    pub topic: String,
+    pub payload: String,
+    pub error: String,
+    pub poison: bool,
+    pub attempts: u32,
+    pub first_failed_at: DateTime<Utc>,
+    pub dead_lettered_at: DateTime<Utc>,
+}
+
+impl DeadLetter {
+    pub fn to_message(&self) -> Result<RealtimeMessage, serde_json::Error> {
+        Ok(RealtimeMessage {
+            topic: dead_letter_topic(&self.topic),
+            payload: serde_json::to_string(self)?,
+        })
+    }
+
+    /// Reads a message of a dead-letter topic.
+    pub fn from_message(message: &RealtimeMessage) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(&message.payload)
+    }
+
+    /// The original message, to publish on its topic again once its consumer is fixed.
+    pub fn original(&self) -> RealtimeMessage {
+        RealtimeMessage {
+            topic: self.topic.clone(),
+            payload: self.payload.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Delivery {
+    Handled { attempts: u32 },
+    DeadLettered(DeadLetter),
+}
+
+type Handler = Arc<dyn Fn(RealtimeMessage) -> HandlerFuture + Send + Sync>;
+type HandlerFuture = Pin<Box<dyn Future<Output = Result<(), HandlerError>> + Send>>;
+
+// Runs a handler over each received message, retrying failures with backoff, then publishing the
+// message with its failure on the dead-letter topic instead of dropping it, e.g.
+//
+// let consumer = DeadLetterConsumer::new("billing", publisher, |message| async move { .. })
+//     .retry(RetryPolicy { max_attempts: 3, ..Default::default() });
+// match consumer.consume(message).await { Ok(_) => ack, Err(_) => requeue }
+//
+// A message is only acknowledged once handled or dead-lettered, so a broker outage during
+// dead-lettering requeues it rather than losing it.
+pub struct DeadLetterConsumer {
+    name: String,
+    publisher: Arc<dyn RealtimePublisher>,
+    handler: Handler,
+    retry: RetryPolicy,
+}
+
+impl DeadLetterConsumer {
+    pub fn new<F, Fut>(name: &str, publisher: Arc<dyn RealtimePublisher>, handler: F) -> Self
+    where
+        F: Fn(RealtimeMessage) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), HandlerError>> + Send + 'static,
+    {
+        DeadLetterConsumer {
+            name: name.to_string(),
+            publisher,
+            handler: Arc::new(move |message| Box::pin(handler(message))),
+            retry: RetryPolicy::default(),
+        }
+    }
+
+    /// A consumer of event envelopes decoded by `decoder`. Messages that do not decode are
+    /// poison.
+    pub fn for_events<T, F, Fut>(
+        name: &str,
+        publisher: Arc<dyn RealtimePublisher>,
+        decoder: EventDecoder<T>,
+        handler: F,
+    ) -> Self
+    where
+        T: DeserializeOwned + Send + 'static,
+        F: Fn(EventEnvelope<T>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), HandlerError>> + Send + 'static,
+    {
+        let decoder = Arc::new(decoder);
+        let handler = Arc::new(handler);
+        Self::new(name, publisher, move |message: RealtimeMessage| {
+            let decoded = decoder
+                .decode(&message.payload)
+                .map_err(HandlerError::poison);
+            let handler = handler.clone();
+            async move { handler(decoded?).await }
+        })
+    }
+
+    pub fn retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Handles `message`, retrying per the policy. Fails only when the message could neither be
+    /// handled nor dead-lettered, it should then be requeued.
+    pub async fn consume(&self, message: RealtimeMessage) -> Result<Delivery, PublishError> {
+        let max_attempts = self.retry.max_attempts.max(1);
+        let mut first_failed_at = None;
+        for attempt in 1..=max_attempts {
+            let error = match (self.handler)(message.clone()).await {
+                Ok(()) => return Ok(Delivery::Handled { attempts: attempt }),
+                Err(error) => error,
+            };
+            let first_failed_at = *first_failed_at.get_or_insert_with(Utc::now);
+            let poison = matches!(error, HandlerError::Poison(_));
+            if poison || attempt == max_attempts {
+                let dead_letter = DeadLetter {
+                    id: Uuid::new_v4(),
+                    consumer: self.name.clone(),
+                    topic: message.topic.clone(),
+                    payload: message.payload.clone(),
+                    error: error.to_string(),
+                    poison,
+                    attempts: attempt,
+                    first_failed_at,
+                    dead_lettered_at: Utc::now(),
+                };
+                tracing::warn!(
+                    consumer = %self.name,
+                    topic = %message.topic,
+                    attempts = attempt,
+                    poison,
+                    error = %error,
+                    "dead-lettering a message"
+                );
+                self.publisher.publish(dead_letter.to_message()?).await?;
+                return Ok(Delivery::DeadLettered(dead_letter));
+            }
+            tracing::debug!(consumer = %self.name, topic = %message.topic, attempt, error = %error, "retrying a message");
+            tokio::time::sleep(self.retry.delay(attempt)).await;
+        }
+        unreachable!("the last attempt always returns")
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ReplayReport {
+    pub replayed: usize,
+    pub skipped: usize,
+    pub failed: Vec<(Uuid, String)>,
+}
+
+/// Publishes the original message of each dead letter `selected` picks on its topic again, e.g.
+/// those of one consumer after a fix was deployed. Messages of the dead-letter topic that are
+/// not dead letters are skipped.
+pub async fn replay<F>(
+    messages: &[RealtimeMessage],
+    publisher: &dyn RealtimePublisher,
+    selected: F,
+) -> ReplayReport
+where
+    F: Fn(&DeadLetter) -> bool,
+{
+    let mut report = ReplayReport::default();
+    for message in messages {
+        let dead_letter = match DeadLetter::from_message(message) {
+            Ok(dead_letter) if selected(&dead_letter) => dead_letter,
+            _ => {
+                report.skipped += 1;
+                continue;
+            }
+        };
+        match publisher.publish(dead_letter.original()).await {
+            Ok(()) => report.replayed += 1,
+            Err(e) => report.failed.push((dead_letter.id, e.to_string())),
+        }
+    }
+    report
+}
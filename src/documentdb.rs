@@ -0,0 +1,257 @@
+use futures_util::TryStreamExt;
+use mongodb::{
+    bson::{doc, to_document, Document},
+    options::ClientOptions,
+    Client, Collection, Database,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use std::{error::Error, time::Duration};
+
+use crate::{
+    connection::DbCredentials, DatabaseConfig, DbEngine, DbType, Environment, GingerDBConfig,
+};
+
+pub type MongoError = Box<dyn Error + Send + Sync>;
+
+/// Field naming the organization of the documents of tenant collections.
+pub const ORG_FIELD: &str = "org_id";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MongoSettings {
+    pub connect_timeout: Duration,
+    pub server_selection_timeout: Duration,
+    pub max_pool_size: u32,
+}
+
+impl MongoSettings {
+    pub fn for_environment(env: &Environment) -> Self {
+        match env {
+            Environment::Dev => MongoSettings {
+                connect_timeout: Duration::from_secs(2),
+                server_selection_timeout: Duration::from_secs(2),
+                max_pool_size: 5,
+            },
+            _ => MongoSettings {
+                connect_timeout: Duration::from_secs(5),
+                server_selection_timeout: Duration::from_secs(10),
+                max_pool_size: 20,
+            },
+        }
+    }
+}
+
+// The client of a document database and the database named in its URL, cheap to clone
+#[derive(Clone)]
+pub struct MongoPool {
+    client: Client,
+    database: Database,
+}
+
+impl MongoPool {
+    pub async fn connect(url: &str, settings: &MongoSettings) -> Result<Self, MongoError> {
+        let mut options = ClientOptions::parse(url)
+            .await
+            .map_err(|e| format!("Invalid MongoDB URL: {}", e))?;
+        options.connect_timeout = Some(settings.connect_timeout);
+        options.server_selection_timeout = Some(settings.server_selection_timeout);
+        options.max_pool_size = Some(settings.max_pool_size);
+        let client = Client::with_options(options)?;
+        let database = client
+            .default_database()
+            .ok_or("The MongoDB URL names no database")?;
+        let pool = MongoPool { client, database };
+        pool.ping()
+            .await
+            .map_err(|e| format!("Failed to connect to MongoDB: {}", e))?;
+        Ok(pool)
+    }
+
+    /// Connects to a document database entry of the db config as seen from `env`.
+    pub async fn for_database(
+        database: &DatabaseConfig,
+        env: &Environment,
+        credentials: &DbCredentials,
+    ) -> Result<Self, MongoError> {
+        if database.effective_engine() != DbEngine::Mongodb {
+            return Err(
+                format!("The database '{}' is not a MongoDB database", database.name).into(),
+            );
+        }
+        let url = database
+            .connection_url(env, credentials)
+            .map_err(|e| e.to_string())?;
+        Self::connect(&url, &MongoSettings::for_environment(env)).await
+    }
+
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+
+    pub fn database(&self) -> &Database {
+        &self.database
+    }
+
+    /// Every document of the collection, whatever its organization. Tenant data is read through
+    /// `TenantScope::collection` instead.
+    pub fn collection<T: Send + Sync>(&self, name: &str) -> Collection<T> {
+        self.database.collection(name)
+    }
+
+    /// The health check of the database.
+    pub async fn ping(&self) -> Result<(), MongoError> {
+        self.database.run_command(doc! { "ping": 1 }).await?;
+        Ok(())
+    }
+}
+
+/// The enabled document database named `name`, or the only one when no name is given.
+pub fn document_database<'a>(
+    config: &'a GingerDBConfig,
+    name: Option<&str>,
+) -> Result<&'a DatabaseConfig, Box<dyn Error>> {
+    config.enabled_database(&DbType::DocumentDb, name)
+}
+
+// Whether the update path `path` is the organization field or a field nested in it
+fn is_org_path(path: &str) -> bool {
+    path == ORG_FIELD
+        || path
+            .strip_prefix(ORG_FIELD)
+            .is_some_and(|rest| rest.starts_with('.'))
+}
+
+// A collection as seen from one organization: every filter is restricted to the `org_id` of the
+// scope and inserted documents have to carry it. Obtained with `TenantScope::collection`.
+pub struct ScopedCollection<T: Send + Sync> {
+    collection: Collection<T>,
+    org_id: String,
+}
+
+impl<T> ScopedCollection<T>
+where
+    T: Serialize + DeserializeOwned + Send + Sync,
+{
+    pub(crate) fn new(collection: Collection<T>, org_id: &str) -> Self {
+        ScopedCollection {
+            collection,
+            org_id: org_id.to_string(),
+        }
+    }
+
+    pub fn org_id(&self) -> &str {
+        &self.org_id
+    }
+
+    /// `filter` restricted to the organization, overriding any `org_id` it has.
+    pub fn filter(&self, mut filter: Document) -> Document {
+        filter.insert(ORG_FIELD, self.org_id.as_str());
+        filter
+    }
+
+    pub async fn find(&self, filter: Document) -> Result<Vec<T>, MongoError> {
+        let cursor = self.collection.find(self.filter(filter)).await?;
+        Ok(cursor.try_collect().await?)
+    }
+
+    pub async fn find_one(&self, filter: Document) -> Result<Option<T>, MongoError> {
+        Ok(self.collection.find_one(self.filter(filter)).await?)
+    }
+
+    pub async fn count(&self, filter: Document) -> Result<u64, MongoError> {
+        Ok(self.collection.count_documents(self.filter(filter)).await?)
+    }
+
+    /// Inserts `document`, failing when its `org_id` is not the one of the scope.
+    pub async fn insert_one(&self, document: &T) -> Result<(), MongoError> {
+        let org_id = to_document(document)?
+            .get_str(ORG_FIELD)
+            .map(str::to_string)
+            .map_err(|_| format!("The document has no '{}' string field", ORG_FIELD))?;
+        if org_id != self.org_id {
+            return Err(format!(
+                "The document belongs to '{}', not to the organization of the scope '{}'",
+                org_id, self.org_id
+            )
+            .into());
+        }
+        self.collection.insert_one(document).await?;
+        Ok(())
+    }
+
+    /// Applies `update` to the documents of the organization matching `filter`, returns how
+    /// many were modified. Updates touching the `org_id` of the documents are refused, whether
+    /// they set, unset or rename it or rename another field to it.
+    pub async fn update_many(&self, filter: Document, update: Document) -> Result<u64, MongoError> {
+        for (operator, fields) in &update {
+            let fields = fields
+                .as_document()
+                .ok_or_else(|| format!("The update operator '{}' expects a document", operator))?;
+            let renamed_to = fields
+                .values()
+                .filter(|_| operator == "$rename")
+                .filter_map(|target| target.as_str());
+            if fields
+                .keys()
+                .map(String::as_str)
+                .chain(renamed_to)
+                .any(is_org_path)
+            {
+                return Err(
+                    format!("Updates cannot change the '{}' of documents", ORG_FIELD).into(),
+                );
+            }
+        }
+        let result = self
+            .collection
+            .update_many(self.filter(filter), update)
+            .await?;
+        Ok(result.modified_count)
+    }
+
+    pub async fn delete_many(&self, filter: Document) -> Result<u64, MongoError> {
+        let result = self.collection.delete_many(self.filter(filter)).await?;
+        Ok(result.deleted_count)
+    }
+}
+
+#[cfg(feature = "rocket")]
+pub use self::fairings::{fairing, fairing_for_database};
+
+#[cfg(feature = "rocket")]
+mod fairings {
+    use rocket::fairing::AdHoc;
+
+    use super::{MongoPool, MongoSettings};
+    use crate::{connection::DbCredentials, DatabaseConfig, Environment};
+
+    /// Connects when Rocket ignites and manages the `MongoPool`, failing the launch when MongoDB
+    /// cannot be reached.
+    pub fn fairing(url: &str, env: Environment) -> AdHoc {
+        let url = url.to_string();
+        AdHoc::try_on_ignite("MongoDB", move |rocket| async move {
+            match MongoPool::connect(&url, &MongoSettings::for_environment(&env)).await {
+                Ok(pool) => Ok(rocket.manage(pool)),
+                Err(e) => {
+                    tracing::error!(error = %e, "failed to connect to MongoDB");
+                    Err(rocket)
+                }
+            }
+        })
+    }
+
+    pub fn fairing_for_database(
+        database: DatabaseConfig,
+        env: Environment,
+        credentials: DbCredentials,
+    ) -> AdHoc {
+        AdHoc::try_on_ignite("MongoDB", move |rocket| async move {
+            match MongoPool::for_database(&database, &env, &credentials).await {
+                Ok(pool) => Ok(rocket.manage(pool)),
+                Err(e) => {
+                    tracing::error!(error = %e, database = %database.name, "failed to connect to MongoDB");
+                    Err(rocket)
+                }
+            }
+        })
+    }
+}
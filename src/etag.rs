@@ -0,0 +1,188 @@
+use chrono::{DateTime, SubsecRound, Utc};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+const HTTP_DATE_FORMAT: &str = "%a, %d %b %Y %H:%M:%S GMT";
+
+/// Strong validator of `bytes`, the quoted first 128 bits of their SHA-256, e.g.
+/// `"5d41402abc4b2a76b9719d911017c592"`.
+pub fn etag_for(bytes: &[u8]) -> String {
+    let hash: String = Sha256::digest(bytes)[..16]
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    format!("\"{}\"", hash)
+}
+
+/// Weak validator of `bytes`, for representations that are equivalent but not byte exact, e.g.
+/// compressed on the way out.
+pub fn weak_etag_for(bytes: &[u8]) -> String {
+    format!("W/{}", etag_for(bytes))
+}
+
+/// Strong validator of the JSON serialization of `value`.
+pub fn json_etag<T: Serialize>(value: &T) -> Result<String, serde_json::Error> {
+    Ok(etag_for(&serde_json::to_vec(value)?))
+}
+
+fn opaque_tag(etag: &str) -> &str {
+    etag.trim().trim_start_matches("W/")
+}
+
+/// Whether an `If-None-Match` header lists `etag`, compared weakly as RFC 9110 asks for this
+/// header: `W/"a"` matches `"a"`.
+pub fn etag_matches(if_none_match: &str, etag: &str) -> bool {
+    if_none_match.trim() == "*"
+        || if_none_match
+            .split(',')
+            .any(|candidate| opaque_tag(candidate) == opaque_tag(etag))
+}
+
+/// `time` as an HTTP date, e.g. `Sun, 06 Nov 1994 08:49:37 GMT`.
+pub fn http_date(time: &DateTime<Utc>) -> String {
+    time.format(HTTP_DATE_FORMAT).to_string()
+}
+
+pub fn parse_http_date(value: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc2822(value.trim())
+        .ok()
+        .map(|time| time.with_timezone(&Utc))
+}
+
+/// Whether a request with these conditional headers already holds the current representation.
+/// `If-None-Match` takes precedence, `If-Modified-Since` is only looked at without it.
+pub fn is_not_modified(
+    if_none_match: Option<&str>,
+    if_modified_since: Option<&str>,
+    etag: Option<&str>,
+    last_modified: Option<&DateTime<Utc>>,
+) -> bool {
+    if let Some(if_none_match) = if_none_match {
+        return etag.is_some_and(|etag| etag_matches(if_none_match, etag));
+    }
+    match (if_modified_since.and_then(parse_http_date), last_modified) {
+        // HTTP dates have no fractions of seconds
+        (Some(since), Some(last_modified)) => last_modified.trunc_subsecs(0) <= since,
+        _ => false,
+    }
+}
+
+#[cfg(feature = "rocket")]
+pub use self::responders::Conditional;
+
+#[cfg(feature = "rocket")]
+mod responders {
+    use chrono::{DateTime, Utc};
+    use okapi::openapi3::Responses;
+    use rocket::{
+        http::{Header, Method, Status},
+        response::{self, Responder},
+        serde::json::Json,
+        Request, Response,
+    };
+    use rocket_okapi::{
+        gen::OpenApiGenerator, response::OpenApiResponderInner, util::ensure_status_code_exists,
+    };
+    use serde::Serialize;
+
+    use super::{http_date, is_not_modified, json_etag};
+
+    // Headers a 304 repeats from the response it stands for
+    const KEPT_ON_304: [&str; 3] = ["Cache-Control", "Expires", "Vary"];
+
+    // Wraps a responder with `ETag` and `Last-Modified` headers, answering GET and HEAD requests
+    // whose `If-None-Match` or `If-Modified-Since` show the client is up to date with an empty
+    // 304 instead, e.g.
+    //
+    // Conditional::json(spec).last_modified(published_at)
+    #[derive(Debug, Clone)]
+    pub struct Conditional<R> {
+        inner: R,
+        etag: Option<String>,
+        last_modified: Option<DateTime<Utc>>,
+    }
+
+    impl<R> Conditional<R> {
+        pub fn new(inner: R) -> Self {
+            Conditional {
+                inner,
+                etag: None,
+                last_modified: None,
+            }
+        }
+
+        /// A quoted validator, e.g. from `etag_for` or a version stored with the resource.
+        pub fn etag(mut self, etag: &str) -> Self {
+            self.etag = Some(etag.to_string());
+            self
+        }
+
+        pub fn last_modified(mut self, time: DateTime<Utc>) -> Self {
+            self.last_modified = Some(time);
+            self
+        }
+    }
+
+    impl<T: Serialize> Conditional<Json<T>> {
+        /// `value` as JSON, its `ETag` hashed from the serialized body.
+        pub fn json(value: T) -> Self {
+            let etag = json_etag(&value).ok();
+            Conditional {
+                inner: Json(value),
+                etag,
+                last_modified: None,
+            }
+        }
+    }
+
+    impl<'r, 'o: 'r, R: Responder<'r, 'o>> Responder<'r, 'o> for Conditional<R> {
+        fn respond_to(self, request: &'r Request<'_>) -> response::Result<'o> {
+            let mut validators = vec![];
+            if let Some(etag) = &self.etag {
+                validators.push(Header::new("ETag", etag.clone()));
+            }
+            if let Some(last_modified) = &self.last_modified {
+                validators.push(Header::new("Last-Modified", http_date(last_modified)));
+            }
+
+            // The inner response is needed even for a 304, `*` only matches existing resources
+            let mut response = self.inner.respond_to(request)?;
+            if !response.status().class().is_success() {
+                return Ok(response);
+            }
+            let not_modified = matches!(request.method(), Method::Get | Method::Head)
+                && is_not_modified(
+                    request.headers().get_one("If-None-Match"),
+                    request.headers().get_one("If-Modified-Since"),
+                    self.etag.as_deref(),
+                    self.last_modified.as_ref(),
+                );
+            if !not_modified {
+                for header in validators {
+                    response.set_header(header);
+                }
+                return Ok(response);
+            }
+
+            let mut not_modified = Response::build();
+            not_modified.status(Status::NotModified);
+            for name in KEPT_ON_304 {
+                for value in response.headers().get(name) {
+                    not_modified.header_adjoin(Header::new(name, value.to_string()));
+                }
+            }
+            for header in validators {
+                not_modified.header(header);
+            }
+            not_modified.ok()
+        }
+    }
+
+    impl<R: OpenApiResponderInner> OpenApiResponderInner for Conditional<R> {
+        fn responses(gen: &mut OpenApiGenerator) -> rocket_okapi::Result<Responses> {
+            let mut responses = R::responses(gen)?;
+            ensure_status_code_exists(&mut responses, 304);
+            Ok(responses)
+        }
+    }
+}
@@ -0,0 +1,255 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
+use std::{collections::BTreeMap, error::Error, sync::Mutex};
+use uuid::Uuid;
+
+use crate::{claims::Claims, rocket_models::RealtimeMessage, ISCClaims};
+
+pub type PublishError = Box<dyn Error + Send + Sync>;
+
+fn default_schema_version() -> u32 {
+    1
+}
+
+// The envelope every service wraps its events in, whatever transport carries them
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, JsonSchema)]
+pub struct EventEnvelope<T> {
+    pub id: Uuid,
+    pub topic: String,
+    pub occurred_at: DateTime<Utc>,
+    pub org_id: Option<String>,
+    pub actor: Option<String>, // `sub` of the user or service the event originates from
+    #[serde(default = "default_schema_version")]
+    pub schema_version: u32, // Version of the payload shape
+    pub payload: T,
+}
+
+impl<T> EventEnvelope<T> {
+    pub fn new(topic: &str, payload: T) -> Self {
+        EventEnvelope {
+            id: Uuid::new_v4(),
+            topic: topic.to_string(),
+            occurred_at: Utc::now(),
+            org_id: None,
+            actor: None,
+            schema_version: default_schema_version(),
+            payload,
+        }
+    }
+
+    pub fn org_id(mut self, org_id: &str) -> Self {
+        self.org_id = Some(org_id.to_string());
+        self
+    }
+
+    pub fn actor(mut self, actor: &str) -> Self {
+        self.actor = Some(actor.to_string());
+        self
+    }
+
+    pub fn schema_version(mut self, schema_version: u32) -> Self {
+        self.schema_version = schema_version;
+        self
+    }
+
+    /// The user of the request as the actor.
+    pub fn from_user(self, claims: &Claims) -> Self {
+        self.actor(&claims.sub)
+    }
+
+    /// The calling service as the actor, in its organization.
+    pub fn from_service(self, claims: &ISCClaims) -> Self {
+        self.actor(&claims.sub).org_id(&claims.org_id)
+    }
+
+    pub fn map<U, F: FnOnce(T) -> U>(self, f: F) -> EventEnvelope<U> {
+        EventEnvelope {
+            id: self.id,
+            topic: self.topic,
+            occurred_at: self.occurred_at,
+            org_id: self.org_id,
+            actor: self.actor,
+            schema_version: self.schema_version,
+            payload: f(self.payload),
+        }
+    }
+}
+
+impl<T: Serialize> EventEnvelope<T> {
+    pub fn to_realtime_message(&self) -> Result<RealtimeMessage, serde_json::Error> {
+        Ok(RealtimeMessage {
+            topic: self.topic.clone(),
+            payload: serde_json::to_string(self)?,
+        })
+    }
+
+    pub async fn publish(&self, publisher: &dyn RealtimePublisher) -> Result<(), PublishError> {
+        publisher.publish(self.to_realtime_message()?).await
+    }
+}
+
+type Upgrade = Box<dyn Fn(Value) -> Result<Value, String> + Send + Sync>;
+
+// Reads envelopes of one event type, whatever payload version the producer sent. Older payloads
+// go through the registered upgrades one version at a time, payloads newer than `current` are
+// rejected so consumers are deployed before producers start sending them.
+pub struct EventDecoder<T> {
+    current: u32,
+    upgrades: BTreeMap<u32, Upgrade>, // Keyed by the version they upgrade from
+    _payload: std::marker::PhantomData<fn() -> T>,
+}
+
+impl<T: DeserializeOwned> EventDecoder<T> {
+    pub fn new(current: u32) -> Self {
+        EventDecoder {
+            current,
+            upgrades: BTreeMap::new(),
+            _payload: std::marker::PhantomData,
+        }
+    }
+
+    /// Registers how a version `from` payload becomes a version `from + 1` one.
+    pub fn upgrade<F>(mut self, from: u32, upgrade: F) -> Self
+    where
+        F: Fn(Value) -> Result<Value, String> + Send + Sync + 'static,
+    {
+        self.upgrades.insert(from, Box::new(upgrade));
+        self
+    }
+
+    /// The oldest payload version the decoder accepts.
+    pub fn oldest_supported(&self) -> u32 {
+        let mut oldest = self.current;
+        while oldest > 1 && self.upgrades.contains_key(&(oldest - 1)) {
+            oldest -= 1;
+        }
+        oldest
+    }
+
+    pub fn decode(&self, raw: &str) -> Result<EventEnvelope<T>, Box<dyn Error>> {
+        let envelope: EventEnvelope<Value> = serde_json::from_str(raw)
+            .map_err(|e| format!("The event is not a valid envelope: {}", e))?;
+        self.decode_envelope(envelope)
+    }
+
+    pub fn decode_envelope(
+        &self,
+        envelope: EventEnvelope<Value>,
+    ) -> Result<EventEnvelope<T>, Box<dyn Error>> {
+        if envelope.schema_version > self.current {
+            return Err(format!(
+                "The event {} on '{}' has payload version {}, this consumer supports up to {}",
+                envelope.id, envelope.topic, envelope.schema_version, self.current
+            )
+            .into());
+        }
+        if envelope.schema_version < self.oldest_supported() {
+            return Err(format!(
+                "The event {} on '{}' has payload version {}, this consumer supports {} and later",
+                envelope.id,
+                envelope.topic,
+                envelope.schema_version,
+                self.oldest_supported()
+            )
+            .into());
+        }
+
+        let mut payload = envelope.payload;
+        for version in envelope.schema_version..self.current {
+            payload = self.upgrades[&version](payload).map_err(|e| {
+                format!(
+                    "Failed to upgrade the event {} from payload version {}: {}",
+                    envelope.id, version, e
+                )
+            })?;
+        }
+        let payload: T = serde_json::from_value(payload).map_err(|e| {
+            format!(
+                "The payload of the event {} on '{}' is invalid: {}",
+                envelope.id, envelope.topic, e
+            )
+        })?;
+        Ok(EventEnvelope {
+            id: envelope.id,
+            topic: envelope.topic,
+            occurred_at: envelope.occurred_at,
+            org_id: envelope.org_id,
+            actor: envelope.actor,
+            schema_version: self.current,
+            payload,
+        })
+    }
+}
+
+#[async_trait]
+pub trait RealtimePublisher: Send + Sync {
+    async fn publish(&self, message: RealtimeMessage) -> Result<(), PublishError>;
+}
+
+// Keeps published messages in memory, for tests and single process setups
+#[derive(Default)]
+pub struct MemoryPublisher {
+    messages: Mutex<Vec<RealtimeMessage>>,
+}
+
+impl MemoryPublisher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn messages(&self) -> Vec<RealtimeMessage> {
+        self.messages.lock().unwrap().clone()
+    }
+}
+
+#[async_trait]
+impl RealtimePublisher for MemoryPublisher {
+    async fn publish(&self, message: RealtimeMessage) -> Result<(), PublishError> {
+        self.messages.lock().unwrap().push(message);
+        Ok(())
+    }
+}
+
+#[cfg(feature = "redis")]
+pub use self::redis_publisher::RedisPublisher;
+
+#[cfg(feature = "redis")]
+mod redis_publisher {
+    use async_trait::async_trait;
+    use redis::{aio::ConnectionManager, AsyncCommands};
+    use std::error::Error;
+
+    use super::{PublishError, RealtimePublisher};
+    use crate::rocket_models::RealtimeMessage;
+
+    // Publishes every message on the Redis channel named after its topic
+    pub struct RedisPublisher {
+        connection: ConnectionManager,
+    }
+
+    impl RedisPublisher {
+        pub async fn new(url: &str) -> Result<Self, Box<dyn Error>> {
+            let client = redis::Client::open(url)
+                .map_err(|e| format!("Invalid Redis URL '{}': {}", url, e))?;
+            let connection = ConnectionManager::new(client)
+                .await
+                .map_err(|e| format!("Failed to connect to Redis at '{}': {}", url, e))?;
+            Ok(RedisPublisher { connection })
+        }
+    }
+
+    #[async_trait]
+    impl RealtimePublisher for RedisPublisher {
+        async fn publish(&self, message: RealtimeMessage) -> Result<(), PublishError> {
+            let mut connection = self.connection.clone();
+            let _: i64 = connection
+                .publish(&message.topic, &message.payload)
+                .await
+                .map_err(|e| format!("Failed to publish on '{}': {}", message.topic, e))?;
+            Ok(())
+        }
+    }
+}
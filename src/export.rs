@@ -0,0 +1,336 @@
+use serde::Serialize;
+use std::{error::Error, str::FromStr};
+
+use crate::lint::closest;
+
+// Leading characters spreadsheet apps evaluate as a formula when opening a CSV
+const FORMULA_PREFIXES: [char; 6] = ['=', '+', '-', '@', '\t', '\r'];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    Csv,
+    #[cfg(feature = "xlsx")]
+    Xlsx,
+}
+
+impl ExportFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "text/csv; charset=utf-8",
+            #[cfg(feature = "xlsx")]
+            ExportFormat::Xlsx => {
+                "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet"
+            }
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            #[cfg(feature = "xlsx")]
+            ExportFormat::Xlsx => "xlsx",
+        }
+    }
+}
+
+impl FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "csv" => Ok(ExportFormat::Csv),
+            #[cfg(feature = "xlsx")]
+            "xlsx" | "excel" => Ok(ExportFormat::Xlsx),
+            other => Err(format!("Unsupported export format '{}'", other)),
+        }
+    }
+}
+
+/// `created_at` and `createdAt` as `Created at`.
+pub fn humanize(key: &str) -> String {
+    let mut words = String::new();
+    let mut previous: Option<char> = None;
+    for c in key.chars() {
+        if c == '_' || c == '-' {
+            words.push(' ');
+        } else {
+            if c.is_uppercase() && previous.is_some_and(|p| p.is_lowercase() || p.is_ascii_digit())
+            {
+                words.push(' ');
+            }
+            words.extend(c.to_lowercase());
+        }
+        previous = Some(c);
+    }
+    let words = words.split_whitespace().collect::<Vec<_>>().join(" ");
+    let mut chars = words.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => words,
+    }
+}
+
+pub(crate) fn guard_formula(cell: &str) -> String {
+    if cell.starts_with(FORMULA_PREFIXES) && cell.parse::<f64>().is_err() {
+        format!("'{}", cell)
+    } else {
+        cell.to_string()
+    }
+}
+
+// Rows of a serializable type as a table, its columns named after the serialized fields so
+// `#[serde(rename)]` and `#[serde(skip)]` apply, e.g.
+//
+// Export::from_rows(&users)?
+//     .select(&["email", "created_at"])?
+//     .humanize_headers()
+//     .label("email", "Email address")
+//     .to_csv()?
+//
+// Rows must be flat, as for any CSV. Without rows there are no columns either.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Export {
+    keys: Vec<String>,
+    rows: Vec<Vec<String>>,
+    columns: Vec<(usize, String)>, // Exported columns, as indexes into `keys`, and their headers
+}
+
+impl Export {
+    pub fn from_rows<T: Serialize>(rows: &[T]) -> Result<Self, Box<dyn Error>> {
+        // Going through the csv crate yields the serde field names in declaration order
+        let mut writer = csv::Writer::from_writer(vec![]);
+        for row in rows {
+            writer
+                .serialize(row)
+                .map_err(|e| format!("Failed to export a row: {}", e))?;
+        }
+        let bytes = writer.into_inner().map_err(|e| e.to_string())?;
+        let mut reader = csv::Reader::from_reader(&bytes[..]);
+        let keys: Vec<String> = reader.headers()?.iter().map(String::from).collect();
+        let rows = reader
+            .records()
+            .map(|record| record.map(|record| record.iter().map(String::from).collect()))
+            .collect::<Result<Vec<Vec<String>>, _>>()?;
+        let columns = keys.iter().cloned().enumerate().collect();
+        Ok(Export {
+            keys,
+            rows,
+            columns,
+        })
+    }
+
+    /// Keeps only `keys`, in that order, e.g. from a `?columns=email,name` query parameter.
+    pub fn select(mut self, keys: &[&str]) -> Result<Self, Box<dyn Error>> {
+        let mut columns = vec![];
+        for key in keys {
+            let index = match self.keys.iter().position(|k| k == key) {
+                Some(index) => index,
+                None => {
+                    return Err(match closest(key, &self.keys) {
+                        Some(candidate) => {
+                            format!("Unknown column '{}', did you mean '{}'?", key, candidate)
+                        }
+                        None => format!("Unknown column '{}'", key),
+                    }
+                    .into())
+                }
+            };
+            let header = self
+                .columns
+                .iter()
+                .find(|(i, _)| *i == index)
+                .map(|(_, header)| header.clone())
+                .unwrap_or_else(|| key.to_string());
+            columns.push((index, header));
+        }
+        self.columns = columns;
+        Ok(self)
+    }
+
+    /// Headers of the columns not given a `label`, as `humanize` words.
+    pub fn humanize_headers(mut self) -> Self {
+        for (index, header) in self.columns.iter_mut() {
+            if *header == self.keys[*index] {
+                *header = humanize(header);
+            }
+        }
+        self
+    }
+
+    pub fn label(mut self, key: &str, header: &str) -> Self {
+        for (index, existing) in self.columns.iter_mut() {
+            if self.keys[*index] == key {
+                *existing = header.to_string();
+            }
+        }
+        self
+    }
+
+    pub fn headers(&self) -> Vec<&str> {
+        self.columns
+            .iter()
+            .map(|(_, header)| header.as_str())
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.rows.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    fn cells<'a>(&'a self, row: &'a [String]) -> impl Iterator<Item = &'a str> {
+        self.columns.iter().map(|(index, _)| row[*index].as_str())
+    }
+
+    /// Cells starting like a formula (`=`, `+`, `@`, ...) get a leading `'` so spreadsheet apps
+    /// show them as text instead of evaluating them, negative numbers are left alone.
+    pub fn to_csv(&self) -> Result<Vec<u8>, Box<dyn Error>> {
+        let mut writer = csv::Writer::from_writer(vec![]);
+        if !self.columns.is_empty() {
+            writer.write_record(self.headers())?;
+        }
+        for row in &self.rows {
+            writer.write_record(self.cells(row).map(guard_formula))?;
+        }
+        Ok(writer.into_inner().map_err(|e| e.to_string())?)
+    }
+
+    /// A workbook with a single sheet, numbers written as numbers and the header row frozen.
+    #[cfg(feature = "xlsx")]
+    pub fn to_xlsx(&self, sheet_name: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+        use rust_xlsxwriter::{Format, Workbook};
+
+        let mut workbook = Workbook::new();
+        let sheet = workbook.add_worksheet();
+        sheet.set_name(sheet_name)?;
+        let bold = Format::new().set_bold();
+        for (column, header) in self.headers().into_iter().enumerate() {
+            sheet.write_string_with_format(0, column as u16, header, &bold)?;
+        }
+        for (row_index, row) in self.rows.iter().enumerate() {
+            let row_number = row_index as u32 + 1;
+            for (column, cell) in self.cells(row).enumerate() {
+                // Leading zeros mark identifiers, e.g. postal codes, that must stay text
+                let is_identifier = cell.len() > 1 && cell.starts_with('0') && !cell.contains('.');
+                match cell.parse::<f64>() {
+                    Ok(number) if number.is_finite() && !is_identifier => {
+                        sheet.write_number(row_number, column as u16, number)?
+                    }
+                    _ => sheet.write_string(row_number, column as u16, cell)?,
+                };
+            }
+        }
+        sheet.set_freeze_panes(1, 0)?;
+        sheet.autofit();
+        Ok(workbook.save_to_buffer()?)
+    }
+
+    pub fn to_bytes(&self, format: ExportFormat) -> Result<Vec<u8>, Box<dyn Error>> {
+        match format {
+            ExportFormat::Csv => self.to_csv(),
+            #[cfg(feature = "xlsx")]
+            ExportFormat::Xlsx => self.to_xlsx("Export"),
+        }
+    }
+}
+
+#[cfg(feature = "rocket")]
+pub use self::responders::ExportFile;
+
+#[cfg(feature = "rocket")]
+mod responders {
+    use okapi::openapi3::{MediaType, Responses};
+    use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+    use rocket::{
+        http::{ContentType, Header},
+        response::{self, Responder},
+        Request, Response,
+    };
+    use rocket_okapi::{
+        gen::OpenApiGenerator, response::OpenApiResponderInner, util::add_content_response,
+    };
+    use std::{error::Error, io::Cursor};
+
+    use super::{Export, ExportFormat};
+    use crate::rocket_models::ApiError;
+
+    // An export sent as a download, `users.csv` for a `file_stem` of `users`
+    #[derive(Debug, Clone)]
+    pub struct ExportFile {
+        bytes: Vec<u8>,
+        format: ExportFormat,
+        file_name: String,
+    }
+
+    impl ExportFile {
+        pub fn new(
+            export: &Export,
+            format: ExportFormat,
+            file_stem: &str,
+        ) -> Result<Self, Box<dyn Error>> {
+            Ok(ExportFile {
+                bytes: export.to_bytes(format)?,
+                format,
+                file_name: format!("{}.{}", file_stem, format.extension()),
+            })
+        }
+
+        /// `ExportFile::new` for handlers, failing with a 400 for unknown columns.
+        pub fn from_rows<T: serde::Serialize>(
+            rows: &[T],
+            columns: Option<&[&str]>,
+            format: ExportFormat,
+            file_stem: &str,
+        ) -> Result<Self, ApiError> {
+            let mut export =
+                Export::from_rows(rows).map_err(|e| ApiError::internal(&e.to_string()))?;
+            if let Some(columns) = columns {
+                export = export
+                    .select(columns)
+                    .map_err(|e| ApiError::bad_request(&e.to_string()))?;
+            }
+            Self::new(&export.humanize_headers(), format, file_stem)
+                .map_err(|e| ApiError::internal(&e.to_string()))
+        }
+    }
+
+    impl<'r> Responder<'r, 'static> for ExportFile {
+        fn respond_to(self, _request: &'r Request<'_>) -> response::Result<'static> {
+            let content_type = ContentType::parse_flexible(self.format.content_type())
+                .unwrap_or(ContentType::Binary);
+            // The quoted name for old clients, the encoded one for names outside of ASCII
+            let fallback: String = self
+                .file_name
+                .chars()
+                .map(|c| {
+                    if c.is_ascii_graphic() && c != '"' && c != '\\' {
+                        c
+                    } else {
+                        '_'
+                    }
+                })
+                .collect();
+            let disposition = format!(
+                "attachment; filename=\"{}\"; filename*=UTF-8''{}",
+                fallback,
+                utf8_percent_encode(&self.file_name, NON_ALPHANUMERIC)
+            );
+            Response::build()
+                .header(content_type)
+                .header(Header::new("Content-Disposition", disposition))
+                .sized_body(self.bytes.len(), Cursor::new(self.bytes))
+                .ok()
+        }
+    }
+
+    impl OpenApiResponderInner for ExportFile {
+        fn responses(_gen: &mut OpenApiGenerator) -> rocket_okapi::Result<Responses> {
+            let mut responses = Responses::default();
+            add_content_response(&mut responses, 200, "text/csv", MediaType::default())?;
+            Ok(responses)
+        }
+    }
+}
@@ -0,0 +1,221 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+use tokio::task::JoinHandle;
+
+use crate::{
+    claims::{APIClaims, Claims, ISCClaims},
+    Environment,
+};
+
+pub const DEFAULT_REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Default)]
+pub struct FeatureFlag {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Environments the flag is on in (e.g. `stage`, `prod_k8`), all environments when empty.
+    #[serde(default)]
+    pub environments: Vec<String>,
+    /// Orgs the flag is on for, all orgs when empty.
+    #[serde(default)]
+    pub orgs: Vec<String>,
+}
+
+impl FeatureFlag {
+    pub fn applies_to(&self, environment: &Environment, org_id: Option<&str>) -> bool {
+        let env = environment.to_string();
+        self.enabled
+            && (self.environments.is_empty() || self.environments.contains(&env))
+            && (self.orgs.is_empty()
+                || org_id
+                    .map(|org| self.orgs.iter().any(|o| o == org))
+                    .unwrap_or(false))
+    }
+}
+
+// The org a flag is evaluated for, taken from the caller's claims
+pub trait FlagContext {
+    fn org_id(&self) -> Option<&str>;
+}
+
+fn org_from_extra(extra: &HashMap<String, serde_json::Value>) -> Option<&str> {
+    extra.get("org_id").and_then(|org| org.as_str())
+}
+
+impl FlagContext for Claims {
+    fn org_id(&self) -> Option<&str> {
+        org_from_extra(&self.extra)
+    }
+}
+
+impl FlagContext for APIClaims {
+    fn org_id(&self) -> Option<&str> {
+        org_from_extra(&self.extra)
+    }
+}
+
+impl FlagContext for ISCClaims {
+    fn org_id(&self) -> Option<&str> {
+        Some(&self.org_id)
+    }
+}
+
+impl FlagContext for str {
+    fn org_id(&self) -> Option<&str> {
+        Some(self)
+    }
+}
+
+enum FlagSource {
+    Static,
+    File(PathBuf),
+    #[cfg(feature = "client")]
+    Remote {
+        url: String,
+        http: reqwest::Client,
+    },
+}
+
+// Flags evaluated against the current environment and the caller's org. Flags loaded from a
+// file or the flags service are kept in memory and reloaded by `refresh`.
+pub struct FeatureFlags {
+    environment: Environment,
+    source: FlagSource,
+    refresh_interval: Duration,
+    flags: RwLock<HashMap<String, FeatureFlag>>,
+}
+
+impl FeatureFlags {
+    pub fn new(environment: Environment, flags: HashMap<String, FeatureFlag>) -> Self {
+        FeatureFlags {
+            environment,
+            source: FlagSource::Static,
+            refresh_interval: DEFAULT_REFRESH_INTERVAL,
+            flags: RwLock::new(flags),
+        }
+    }
+
+    /// Loads the `[feature_flags]` section of a TOML file, e.g.
+    ///
+    /// ```toml
+    /// [feature_flags.new-billing]
+    /// enabled = true
+    /// environments = ["stage", "prod"]
+    /// orgs = ["ginger-society"]
+    /// ```
+    pub fn from_file<P: AsRef<Path>>(
+        environment: Environment,
+        path: P,
+    ) -> Result<Self, Box<dyn Error>> {
+        let flags = read_flags_file(path.as_ref())?;
+        let mut feature_flags = Self::new(environment, flags);
+        feature_flags.source = FlagSource::File(path.as_ref().to_path_buf());
+        Ok(feature_flags)
+    }
+
+    /// Loads the flags from the flags service, which answers `GET url` with a JSON object of
+    /// flags keyed by name.
+    #[cfg(feature = "client")]
+    pub async fn from_remote(environment: Environment, url: &str) -> Result<Self, Box<dyn Error>> {
+        let mut feature_flags = Self::new(environment, HashMap::new());
+        feature_flags.source = FlagSource::Remote {
+            url: url.to_string(),
+            http: reqwest::Client::new(),
+        };
+        feature_flags.refresh().await?;
+        Ok(feature_flags)
+    }
+
+    pub fn with_refresh_interval(mut self, interval: Duration) -> Self {
+        self.refresh_interval = interval;
+        self
+    }
+
+    pub fn environment(&self) -> &Environment {
+        &self.environment
+    }
+
+    pub fn is_enabled<C: FlagContext + ?Sized>(&self, name: &str, context: &C) -> bool {
+        self.flags
+            .read()
+            .unwrap()
+            .get(name)
+            .map(|flag| flag.applies_to(&self.environment, context.org_id()))
+            .unwrap_or(false)
+    }
+
+    /// Evaluates a flag without an org, flags targeting specific orgs are off.
+    pub fn is_enabled_globally(&self, name: &str) -> bool {
+        self.flags
+            .read()
+            .unwrap()
+            .get(name)
+            .map(|flag| flag.applies_to(&self.environment, None))
+            .unwrap_or(false)
+    }
+
+    pub fn flag_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.flags.read().unwrap().keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    /// Reloads the flags from their source. On failure the previously loaded flags are kept.
+    pub async fn refresh(&self) -> Result<(), Box<dyn Error>> {
+        let flags = match &self.source {
+            FlagSource::Static => return Ok(()),
+            FlagSource::File(path) => read_flags_file(path)?,
+            #[cfg(feature = "client")]
+            FlagSource::Remote { url, http } => {
+                let response = http.get(url).send().await?;
+                if !response.status().is_success() {
+                    return Err(format!(
+                        "Flags service at {} answered with {}",
+                        url,
+                        response.status()
+                    )
+                    .into());
+                }
+                response.json().await?
+            }
+        };
+        *self.flags.write().unwrap() = flags;
+        Ok(())
+    }
+
+    /// Refreshes the flags every `refresh_interval` in the background.
+    pub fn spawn_refresh(self: &Arc<Self>) -> JoinHandle<()> {
+        let flags = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(flags.refresh_interval);
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                if let Err(e) = flags.refresh().await.map_err(|e| e.to_string()) {
+                    tracing::warn!(error = %e, "failed to refresh the feature flags");
+                }
+            }
+        })
+    }
+}
+
+fn read_flags_file(path: &Path) -> Result<HashMap<String, FeatureFlag>, Box<dyn Error>> {
+    #[derive(Deserialize)]
+    struct FlagsFile {
+        #[serde(default)]
+        feature_flags: HashMap<String, FeatureFlag>,
+    }
+
+    let contents = fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read the file '{}': {}", path.display(), e))?;
+    let file: FlagsFile = toml::from_str(&contents)
+        .map_err(|e| format!("Failed to parse TOML from file '{}': {}", path.display(), e))?;
+    Ok(file.feature_flags)
+}
@@ -0,0 +1,146 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    error::Error,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+// Field and record separators used to split `git log` output safely
+const FIELD_SEP: char = '\u{1f}';
+const RECORD_SEP: char = '\u{1e}';
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct Commit {
+    pub sha: String,
+    pub author: String,
+    pub subject: String,
+    pub body: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct GitRepo {
+    dir: PathBuf,
+}
+
+impl GitRepo {
+    pub fn open<P: AsRef<Path>>(dir: P) -> Self {
+        GitRepo {
+            dir: dir.as_ref().to_path_buf(),
+        }
+    }
+
+    pub fn current() -> Self {
+        Self::open(".")
+    }
+
+    pub fn current_branch(&self) -> Result<String, Box<dyn Error>> {
+        self.run(&["rev-parse", "--abbrev-ref", "HEAD"])
+    }
+
+    pub fn head_sha(&self) -> Result<String, Box<dyn Error>> {
+        self.run(&["rev-parse", "HEAD"])
+    }
+
+    pub fn is_dirty(&self) -> Result<bool, Box<dyn Error>> {
+        Ok(!self.run(&["status", "--porcelain"])?.is_empty())
+    }
+
+    /// Returns the highest (version-sorted) tag reachable from HEAD that starts with `prefix`.
+    pub fn latest_tag_matching(&self, prefix: &str) -> Result<Option<String>, Box<dyn Error>> {
+        let pattern = format!("{}*", prefix);
+        let tags = self.run(&[
+            "tag",
+            "--merged",
+            "HEAD",
+            "--list",
+            &pattern,
+            "--sort=-v:refname",
+        ])?;
+        Ok(tags.lines().next().map(|tag| tag.trim().to_string()))
+    }
+
+    /// Lists the commits after `tag` up to HEAD (newest first), or the whole history when no
+    /// tag is given.
+    pub fn commits_since(&self, tag: Option<&str>) -> Result<Vec<Commit>, Box<dyn Error>> {
+        let format = format!("--format=%H{0}%an{0}%s{0}%b{1}", FIELD_SEP, RECORD_SEP);
+        let range = match tag {
+            Some(tag) => format!("{}..HEAD", tag),
+            None => "HEAD".to_string(),
+        };
+        let log = self.run(&["log", &format, &range])?;
+
+        let commits = log
+            .split(RECORD_SEP)
+            .map(str::trim)
+            .filter(|record| !record.is_empty())
+            .map(|record| {
+                let mut fields = record.splitn(4, FIELD_SEP);
+                Commit {
+                    sha: fields.next().unwrap_or_default().to_string(),
+                    author: fields.next().unwrap_or_default().to_string(),
+                    subject: fields.next().unwrap_or_default().to_string(),
+                    body: fields.next().unwrap_or_default().trim().to_string(),
+                }
+            })
+            .collect();
+
+        Ok(commits)
+    }
+
+    /// Paths (relative to the repository root) changed between `tag` and HEAD, or every
+    /// tracked file when no tag is given.
+    pub fn changed_files_since(&self, tag: Option<&str>) -> Result<Vec<String>, Box<dyn Error>> {
+        let output = match tag {
+            Some(tag) => self.run(&["diff", "--name-only", &format!("{}..HEAD", tag)])?,
+            None => self.run(&["ls-files"])?,
+        };
+        Ok(output
+            .lines()
+            .filter(|line| !line.is_empty())
+            .map(str::to_string)
+            .collect())
+    }
+
+    fn run(&self, args: &[&str]) -> Result<String, Box<dyn Error>> {
+        let output = Command::new("git")
+            .args(args)
+            .current_dir(&self.dir)
+            .output()
+            .map_err(|e| format!("Failed to run git: {}", e))?;
+
+        if !output.status.success() {
+            return Err(format!(
+                "git {} failed: {}",
+                args.join(" "),
+                String::from_utf8_lossy(&output.stderr).trim()
+            )
+            .into());
+        }
+
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    }
+}
+
+pub fn current_branch() -> Result<String, Box<dyn Error>> {
+    GitRepo::current().current_branch()
+}
+
+pub fn head_sha() -> Result<String, Box<dyn Error>> {
+    GitRepo::current().head_sha()
+}
+
+pub fn is_dirty() -> Result<bool, Box<dyn Error>> {
+    GitRepo::current().is_dirty()
+}
+
+pub fn latest_tag_matching(prefix: &str) -> Result<Option<String>, Box<dyn Error>> {
+    GitRepo::current().latest_tag_matching(prefix)
+}
+
+pub fn commits_since(tag: Option<&str>) -> Result<Vec<Commit>, Box<dyn Error>> {
+    GitRepo::current().commits_since(tag)
+}
+
+pub fn changed_files_since(tag: Option<&str>) -> Result<Vec<String>, Box<dyn Error>> {
+    GitRepo::current().changed_files_since(tag)
+}
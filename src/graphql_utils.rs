@@ -0,0 +1,203 @@
+use async_graphql::{
+    dataloader::{DataLoader, Loader},
+    Context, ErrorExtensions, Request as GraphQLRequest,
+};
+use okapi::openapi3::Responses;
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket_okapi::{
+    gen::OpenApiGenerator,
+    request::{OpenApiFromRequest, RequestHeaderInput},
+};
+use std::{any::Any, collections::HashMap, hash::Hash, time::Duration};
+
+use crate::{
+    cache::Cache,
+    claims::{APIClaims, Claims, ISCClaims},
+    jwt::TokenErrorKind,
+    rocket_models::ApiError,
+    rocket_utils::auth_failure,
+};
+
+// Errors of resolvers carry the `ApiError` fields as extensions, so clients branch on the same
+// `code` values as with the REST endpoints:
+// `{"message": "...", "extensions": {"code": "not_found", "status": 404}}`
+impl ErrorExtensions for ApiError {
+    fn extend(&self) -> async_graphql::Error {
+        async_graphql::Error::new(self.message.clone()).extend_with(|_, extensions| {
+            extensions.set("code", self.error_code.clone());
+            extensions.set("status", self.status);
+            if let Some(correlation_id) = &self.correlation_id {
+                extensions.set("correlationId", correlation_id.clone());
+            }
+            if !self.details.is_empty() {
+                let details =
+                    serde_json::to_value(&self.details).and_then(async_graphql::Value::from_json);
+                if let Ok(details) = details {
+                    extensions.set("details", details);
+                }
+            }
+        })
+    }
+}
+
+// The claims of every token the request carries, for GraphQL endpoints where each field decides
+// whether authentication is needed. Never fails: a rejected token is remembered so `require`
+// reports why, e.g. `token_expired`, instead of a generic error.
+//
+// #[post("/graphql", data = "<request>")]
+// async fn graphql(schema: &State<PortalSchema>, auth: GraphQLAuth, request: Json<Request>)
+//     -> Json<Response> {
+//     Json(schema.execute(auth.attach(request.into_inner())).await)
+// }
+#[derive(Debug, Clone, Default)]
+pub struct GraphQLAuth {
+    pub claims: Option<Claims>,
+    pub api_claims: Option<APIClaims>,
+    pub isc_claims: Option<ISCClaims>,
+    failure: Option<TokenErrorKind>,
+}
+
+impl GraphQLAuth {
+    pub fn failure(&self) -> Option<TokenErrorKind> {
+        self.failure
+    }
+
+    /// Adds the claims to the data of `request`, for resolvers to read with `require`.
+    pub fn attach(self, request: GraphQLRequest) -> GraphQLRequest {
+        let mut request = request;
+        if let Some(claims) = self.claims.clone() {
+            request = request.data(claims);
+        }
+        if let Some(claims) = self.api_claims.clone() {
+            request = request.data(claims);
+        }
+        if let Some(claims) = self.isc_claims.clone() {
+            request = request.data(claims);
+        }
+        request.data(self)
+    }
+}
+
+async fn claims_of<'r, T>(request: &'r Request<'_>, header: &str) -> (Option<T>, bool)
+where
+    T: FromRequest<'r>,
+{
+    if !request.headers().contains(header) {
+        return (None, false);
+    }
+    match request.guard::<T>().await {
+        Outcome::Success(claims) => (Some(claims), false),
+        _ => (None, true),
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for GraphQLAuth {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let (claims, claims_failed) = claims_of::<Claims>(request, "Authorization").await;
+        let (api_claims, api_failed) = claims_of::<APIClaims>(request, "X-API-Authorization").await;
+        let (isc_claims, isc_failed) = claims_of::<ISCClaims>(request, "X-ISC-Authorization").await;
+        let failure = (claims_failed || api_failed || isc_failed)
+            .then(|| auth_failure(request).unwrap_or(TokenErrorKind::Invalid));
+        Outcome::Success(GraphQLAuth {
+            claims,
+            api_claims,
+            isc_claims,
+            failure,
+        })
+    }
+}
+
+impl<'a> OpenApiFromRequest<'a> for GraphQLAuth {
+    fn from_request_input(
+        gen: &mut OpenApiGenerator,
+        name: String,
+        _required: bool,
+    ) -> rocket_okapi::Result<RequestHeaderInput> {
+        Claims::from_request_input(gen, name, false)
+    }
+
+    fn get_responses(_gen: &mut OpenApiGenerator) -> rocket_okapi::Result<Responses> {
+        Ok(Responses::default())
+    }
+}
+
+/// The claims of type `T` attached by `GraphQLAuth::attach`, or an `unauthenticated` style
+/// error for resolvers to return, e.g. `let claims = require::<Claims>(ctx)?;`.
+pub fn require<'a, T: Any + Send + Sync>(ctx: &Context<'a>) -> async_graphql::Result<&'a T> {
+    ctx.data_opt::<T>().ok_or_else(|| {
+        let failure = ctx.data_opt::<GraphQLAuth>().and_then(|auth| auth.failure);
+        let error = match failure {
+            Some(kind) => ApiError::new(401, kind.error_code(), kind.message()),
+            None => ApiError::new(
+                401,
+                TokenErrorKind::Missing.error_code(),
+                "Authentication is required to access this field",
+            ),
+        };
+        error.extend()
+    })
+}
+
+// Answers from a `Cache` shared across requests and only asks the wrapped loader for the keys it
+// misses. `DataLoader` on its own batches and caches within one request only.
+pub struct CachedLoader<L, C> {
+    inner: L,
+    cache: C,
+    ttl: Duration,
+}
+
+impl<L, C> CachedLoader<L, C> {
+    pub fn new(inner: L, cache: C, ttl: Duration) -> Self {
+        CachedLoader { inner, cache, ttl }
+    }
+}
+
+impl<K, L, C> Loader<K> for CachedLoader<L, C>
+where
+    K: Send + Sync + Hash + Eq + Clone + 'static,
+    L: Loader<K>,
+    C: Cache<K, L::Value> + 'static,
+{
+    type Value = L::Value;
+    type Error = L::Error;
+
+    async fn load(&self, keys: &[K]) -> Result<HashMap<K, Self::Value>, Self::Error> {
+        let mut found = HashMap::new();
+        let mut missing = vec![];
+        for key in keys {
+            match self.cache.get(key).await {
+                Some(value) => {
+                    found.insert(key.clone(), value);
+                }
+                None => missing.push(key.clone()),
+            }
+        }
+        if !missing.is_empty() {
+            // Keys the loader does not return are not cached, a later request retries them
+            for (key, value) in self.inner.load(&missing).await? {
+                self.cache.set(key.clone(), value.clone(), self.ttl).await;
+                found.insert(key, value);
+            }
+        }
+        Ok(found)
+    }
+}
+
+/// A `DataLoader` over `loader`, backed by `cache` for `ttl`, spawning its batches on the
+/// Rocket runtime. The cache is shared by every caller, so only use it for data that does not
+/// depend on who asks.
+pub fn cached_dataloader<K, L, C>(
+    loader: L,
+    cache: C,
+    ttl: Duration,
+) -> DataLoader<CachedLoader<L, C>>
+where
+    K: Send + Sync + Hash + Eq + Clone + 'static,
+    L: Loader<K>,
+    C: Cache<K, L::Value> + 'static,
+{
+    DataLoader::new(CachedLoader::new(loader, cache, ttl), rocket::tokio::spawn)
+}
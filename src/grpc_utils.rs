@@ -0,0 +1,231 @@
+// `Status` is the error type tonic services return, boxing it would only get in the way
+#![allow(clippy::result_large_err)]
+
+use std::{error::Error, sync::Arc};
+use tonic::{
+    metadata::{MetadataMap, MetadataValue},
+    service::Interceptor,
+    Code, Request, Status,
+};
+
+use crate::{
+    claims::ISCClaims,
+    jwt::{JwtConfig, TokenErrorKind},
+    revocation::{issued_at, token_id, Revocation},
+    rocket_models::ApiError,
+    secrets::{SecretProvider, ISC_TOKEN},
+};
+
+/// Metadata carrying the ISC token, the gRPC counterpart of the `X-ISC-Authorization` header.
+pub const ISC_METADATA_KEY: &str = "x-isc-authorization";
+const ERROR_CODE_METADATA_KEY: &str = "x-error-code";
+const CORRELATION_ID_METADATA_KEY: &str = "x-correlation-id";
+
+pub fn code_for_status(status: u16) -> Code {
+    match status {
+        400 | 422 => Code::InvalidArgument,
+        401 => Code::Unauthenticated,
+        403 => Code::PermissionDenied,
+        404 => Code::NotFound,
+        409 => Code::AlreadyExists,
+        412 => Code::FailedPrecondition,
+        413 | 429 => Code::ResourceExhausted,
+        499 => Code::Cancelled,
+        501 => Code::Unimplemented,
+        503 => Code::Unavailable,
+        504 => Code::DeadlineExceeded,
+        400..=499 => Code::FailedPrecondition,
+        _ => Code::Internal,
+    }
+}
+
+pub fn status_for_code(code: Code) -> u16 {
+    match code {
+        Code::Ok => 200,
+        Code::InvalidArgument | Code::OutOfRange => 400,
+        Code::Unauthenticated => 401,
+        Code::PermissionDenied => 403,
+        Code::NotFound => 404,
+        Code::AlreadyExists | Code::Aborted => 409,
+        Code::FailedPrecondition => 412,
+        Code::ResourceExhausted => 429,
+        Code::Cancelled => 499,
+        Code::Unimplemented => 501,
+        Code::Unavailable => 503,
+        Code::DeadlineExceeded => 504,
+        _ => 500,
+    }
+}
+
+// The error travels whole as JSON in the status details, and its code and correlation id as
+// metadata for clients that do not read the details
+impl From<ApiError> for Status {
+    fn from(error: ApiError) -> Self {
+        let details = serde_json::to_vec(&error).unwrap_or_default();
+        let mut metadata = MetadataMap::new();
+        if let Ok(code) = MetadataValue::try_from(error.error_code.as_str()) {
+            metadata.insert(ERROR_CODE_METADATA_KEY, code);
+        }
+        if let Some(correlation_id) = &error.correlation_id {
+            if let Ok(correlation_id) = MetadataValue::try_from(correlation_id.as_str()) {
+                metadata.insert(CORRELATION_ID_METADATA_KEY, correlation_id);
+            }
+        }
+        Status::with_details_and_metadata(
+            code_for_status(error.status),
+            error.message,
+            details.into(),
+            metadata,
+        )
+    }
+}
+
+// Statuses of our services give back the original error, others are mapped from their code
+impl From<Status> for ApiError {
+    fn from(status: Status) -> Self {
+        if let Ok(error) = serde_json::from_slice::<ApiError>(status.details()) {
+            return error;
+        }
+        let status_code = status_for_code(status.code());
+        let error_code = status
+            .metadata()
+            .get(ERROR_CODE_METADATA_KEY)
+            .and_then(|code| code.to_str().ok())
+            .map(String::from)
+            .unwrap_or_else(|| format!("grpc_{:?}", status.code()).to_lowercase());
+        let mut error = ApiError::new(status_code, &error_code, status.message());
+        error.correlation_id = status
+            .metadata()
+            .get(CORRELATION_ID_METADATA_KEY)
+            .and_then(|id| id.to_str().ok())
+            .map(String::from);
+        error
+    }
+}
+
+fn unauthenticated(kind: TokenErrorKind) -> Status {
+    ApiError::new(401, kind.error_code(), kind.message()).into()
+}
+
+// Validates the ISC token of every call and adds its claims to the request extensions, where
+// handlers read them with `isc_claims`. Interceptors cannot wait, so the revocation list is
+// checked by handlers through `ensure_not_revoked`, e.g.
+//
+// let auth = IscAuthInterceptor::from_env()?.require_scopes(&["ledger.write"]);
+// Server::builder()
+//     .add_service(LedgerServer::with_interceptor(LedgerService::default(), auth))
+#[derive(Debug, Clone)]
+pub struct IscAuthInterceptor {
+    config: Arc<JwtConfig>,
+    scopes: Vec<String>,
+}
+
+impl IscAuthInterceptor {
+    pub fn new(config: JwtConfig) -> Self {
+        IscAuthInterceptor {
+            config: Arc::new(config),
+            scopes: vec![],
+        }
+    }
+
+    /// Uses the `JwtConfig` described by the environment, see `JwtConfig::from_env`.
+    pub fn from_env() -> Result<Self, Box<dyn Error>> {
+        Ok(Self::new(JwtConfig::from_env()?))
+    }
+
+    /// Scopes every token must hold, calls missing one fail with `PERMISSION_DENIED`.
+    pub fn require_scopes(mut self, scopes: &[&str]) -> Self {
+        self.scopes = scopes.iter().map(|s| s.to_string()).collect();
+        self
+    }
+
+    pub fn authenticate(&self, metadata: &MetadataMap) -> Result<ISCClaims, Status> {
+        let values: Vec<_> = metadata.get_all(ISC_METADATA_KEY).iter().collect();
+        if values.len() != 1 {
+            return Err(unauthenticated(TokenErrorKind::Missing));
+        }
+        let token = values[0]
+            .to_str()
+            .map_err(|_| unauthenticated(TokenErrorKind::Malformed))?;
+        let token = token.trim_start_matches("Bearer ").trim();
+        let claims: ISCClaims = self
+            .config
+            .decode(token)
+            .map_err(|e| unauthenticated(TokenErrorKind::from(&e)))?;
+        if let Some(scope) = self.scopes.iter().find(|scope| !claims.has_scope(scope)) {
+            return Err(ApiError::new(
+                403,
+                "missing_scope",
+                &format!("The token lacks the '{}' scope", scope),
+            )
+            .into());
+        }
+        Ok(claims)
+    }
+}
+
+impl Interceptor for IscAuthInterceptor {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        let claims = self.authenticate(request.metadata())?;
+        request.extensions_mut().insert(claims);
+        Ok(request)
+    }
+}
+
+/// The claims added by `IscAuthInterceptor`, `UNAUTHENTICATED` when the service was mounted
+/// without it.
+pub fn isc_claims<T>(request: &Request<T>) -> Result<&ISCClaims, Status> {
+    request
+        .extensions()
+        .get::<ISCClaims>()
+        .ok_or_else(|| unauthenticated(TokenErrorKind::Missing))
+}
+
+/// Fails with `UNAUTHENTICATED` when the token of `claims` has been revoked.
+pub async fn ensure_not_revoked(revocation: &Revocation, claims: &ISCClaims) -> Result<(), Status> {
+    if revocation
+        .is_revoked(
+            token_id(&claims.extra),
+            &claims.sub,
+            issued_at(&claims.extra),
+        )
+        .await
+    {
+        return Err(unauthenticated(TokenErrorKind::Revoked));
+    }
+    Ok(())
+}
+
+// Adds an ISC token to the outgoing calls of a client, e.g.
+// `LedgerClient::with_interceptor(channel, IscToken::new(&token)?)`
+#[derive(Debug, Clone)]
+pub struct IscToken {
+    value: MetadataValue<tonic::metadata::Ascii>,
+}
+
+impl IscToken {
+    pub fn new(token: &str) -> Result<Self, Box<dyn Error>> {
+        let value = format!("Bearer {}", token)
+            .parse()
+            .map_err(|_| "The ISC token is not valid metadata")?;
+        Ok(IscToken { value })
+    }
+
+    /// The token held by `secrets` under `isc/token`.
+    pub async fn from_secrets(secrets: &dyn SecretProvider) -> Result<Self, Box<dyn Error>> {
+        let token = secrets
+            .require(ISC_TOKEN)
+            .await
+            .map_err(|e| e.to_string())?;
+        Self::new(token.expose())
+    }
+}
+
+impl Interceptor for IscToken {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        request
+            .metadata_mut()
+            .insert(ISC_METADATA_KEY, self.value.clone());
+        Ok(request)
+    }
+}
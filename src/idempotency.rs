@@ -0,0 +1,529 @@
+use okapi::openapi3::{Object, Parameter, ParameterValue};
+use rocket::{
+    http::{ContentType, Header, Status},
+    request::{FromRequest, Outcome, Request},
+    response::{self, Responder, Response},
+};
+use rocket_okapi::{
+    gen::OpenApiGenerator,
+    request::{OpenApiFromRequest, RequestHeaderInput},
+    response::OpenApiResponderInner,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    error::Error,
+    future::Future,
+    io::Cursor,
+    sync::{Arc, Mutex, OnceLock},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    claims::{APIClaims, Claims, ISCClaims},
+    rocket_models::ApiError,
+    rocket_utils::authenticate,
+    validation::record_failure,
+};
+
+pub type StoreError = Box<dyn Error + Send + Sync>;
+
+pub const IDEMPOTENCY_KEY_HEADER: &str = "Idempotency-Key";
+pub const IDEMPOTENT_REPLAYED_HEADER: &str = "Idempotent-Replayed";
+const MAX_KEY_LENGTH: usize = 255;
+// How long a running request holds its key, renewed while it runs so a crashed replica does not
+// block retries for the whole replay period
+const LEASE: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct StoredResponse {
+    pub status: u16,
+    pub body: String, // JSON
+    #[serde(default)]
+    pub fingerprint: String, // SHA-256 of the payload the response was computed for
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum IdempotencyState {
+    New,        // The caller now owns the key and must `complete` or `release` it
+    InProgress, // Another request with the same key is running
+    Completed(StoredResponse),
+}
+
+#[rocket::async_trait]
+pub trait IdempotencyStore: Send + Sync {
+    /// Claims `key` for `ttl` unless it is taken already. Errors when the store cannot tell,
+    /// the request must then not run.
+    async fn begin(&self, key: &str, ttl: Duration) -> Result<IdempotencyState, StoreError>;
+
+    /// Extends the claim on `key` to `ttl` from now, unless it was completed or released.
+    async fn renew(&self, key: &str, ttl: Duration);
+
+    /// Stores the response to replay for `key` during `ttl`.
+    async fn complete(&self, key: &str, response: StoredResponse, ttl: Duration);
+
+    /// Frees `key` so the request can be retried, e.g. after an error.
+    async fn release(&self, key: &str);
+}
+
+#[derive(Default)]
+pub struct MemoryIdempotencyStore {
+    entries: Mutex<HashMap<String, (Option<StoredResponse>, Instant)>>,
+}
+
+impl MemoryIdempotencyStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[rocket::async_trait]
+impl IdempotencyStore for MemoryIdempotencyStore {
+    async fn begin(&self, key: &str, ttl: Duration) -> Result<IdempotencyState, StoreError> {
+        let mut entries = self.entries.lock().unwrap();
+        let now = Instant::now();
+        entries.retain(|_, (_, expires_at)| *expires_at > now);
+        Ok(match entries.get(key) {
+            Some((Some(response), _)) => IdempotencyState::Completed(response.clone()),
+            Some((None, _)) => IdempotencyState::InProgress,
+            None => {
+                entries.insert(key.to_string(), (None, now + ttl));
+                IdempotencyState::New
+            }
+        })
+    }
+
+    async fn renew(&self, key: &str, ttl: Duration) {
+        if let Some((None, expires_at)) = self.entries.lock().unwrap().get_mut(key) {
+            *expires_at = Instant::now() + ttl;
+        }
+    }
+
+    async fn complete(&self, key: &str, response: StoredResponse, ttl: Duration) {
+        self.entries
+            .lock()
+            .unwrap()
+            .insert(key.to_string(), (Some(response), Instant::now() + ttl));
+    }
+
+    async fn release(&self, key: &str) {
+        self.entries.lock().unwrap().remove(key);
+    }
+}
+
+#[cfg(feature = "redis")]
+pub use self::redis_store::RedisIdempotencyStore;
+
+#[cfg(feature = "redis")]
+mod redis_store {
+    use redis::aio::ConnectionManager;
+    use std::{error::Error, time::Duration};
+
+    use super::{IdempotencyState, IdempotencyStore, StoreError, StoredResponse};
+
+    const IN_PROGRESS: &str = "in_progress";
+    // Extends the key only while it is still in progress
+    const RENEW: &str = r#"
+if redis.call("GET", KEYS[1]) == ARGV[1] then
+    return redis.call("EXPIRE", KEYS[1], ARGV[2])
+end
+return 0
+"#;
+
+    // Shares keys between replicas, under `{prefix}:{key}`
+    pub struct RedisIdempotencyStore {
+        connection: ConnectionManager,
+        prefix: String,
+    }
+
+    impl RedisIdempotencyStore {
+        pub async fn new(url: &str, prefix: &str) -> Result<Self, Box<dyn Error>> {
+            let client = redis::Client::open(url)
+                .map_err(|e| format!("Invalid Redis URL '{}': {}", url, e))?;
+            let connection = ConnectionManager::new(client)
+                .await
+                .map_err(|e| format!("Failed to connect to Redis at '{}': {}", url, e))?;
+            Ok(RedisIdempotencyStore {
+                connection,
+                prefix: prefix.to_string(),
+            })
+        }
+
+        fn key(&self, key: &str) -> String {
+            format!("{}:{}", self.prefix, key)
+        }
+    }
+
+    #[rocket::async_trait]
+    impl IdempotencyStore for RedisIdempotencyStore {
+        async fn begin(&self, key: &str, ttl: Duration) -> Result<IdempotencyState, StoreError> {
+            let mut connection = self.connection.clone();
+            let claimed: Option<String> = redis::cmd("SET")
+                .arg(self.key(key))
+                .arg(IN_PROGRESS)
+                .arg("NX")
+                .arg("EX")
+                .arg(ttl.as_secs().max(1))
+                .query_async(&mut connection)
+                .await?;
+            if claimed.is_some() {
+                return Ok(IdempotencyState::New);
+            }
+            let existing: Option<String> = redis::cmd("GET")
+                .arg(self.key(key))
+                .query_async(&mut connection)
+                .await?;
+            Ok(match existing {
+                Some(raw) if raw != IN_PROGRESS => match serde_json::from_str(&raw) {
+                    Ok(response) => IdempotencyState::Completed(response),
+                    Err(_) => IdempotencyState::InProgress,
+                },
+                _ => IdempotencyState::InProgress,
+            })
+        }
+
+        async fn renew(&self, key: &str, ttl: Duration) {
+            let mut connection = self.connection.clone();
+            let result: redis::RedisResult<i64> = redis::cmd("EVAL")
+                .arg(RENEW)
+                .arg(1)
+                .arg(self.key(key))
+                .arg(IN_PROGRESS)
+                .arg(ttl.as_secs().max(1))
+                .query_async(&mut connection)
+                .await;
+            if let Err(e) = result {
+                tracing::warn!(error = %e, "failed to renew an idempotency key");
+            }
+        }
+
+        async fn complete(&self, key: &str, response: StoredResponse, ttl: Duration) {
+            let raw = match serde_json::to_string(&response) {
+                Ok(raw) => raw,
+                Err(e) => {
+                    tracing::warn!(error = %e, "failed to serialize an idempotent response");
+                    return;
+                }
+            };
+            let mut connection = self.connection.clone();
+            let result: redis::RedisResult<()> = redis::cmd("SET")
+                .arg(self.key(key))
+                .arg(raw)
+                .arg("EX")
+                .arg(ttl.as_secs().max(1))
+                .query_async(&mut connection)
+                .await;
+            if let Err(e) = result {
+                tracing::warn!(error = %e, "failed to store an idempotent response");
+            }
+        }
+
+        async fn release(&self, key: &str) {
+            let mut connection = self.connection.clone();
+            let result: redis::RedisResult<()> = redis::cmd("DEL")
+                .arg(self.key(key))
+                .query_async(&mut connection)
+                .await;
+            if let Err(e) = result {
+                tracing::warn!(error = %e, "failed to release an idempotency key");
+            }
+        }
+    }
+}
+
+// Rocket managed state holding the store used by the `IdempotencyKey` guard, with how long
+// responses are replayed. Without it, keys are kept in memory for 24 hours.
+#[derive(Clone)]
+pub struct Idempotency {
+    store: Arc<dyn IdempotencyStore>,
+    ttl: Duration,
+}
+
+impl Idempotency {
+    pub fn new<S: IdempotencyStore + 'static>(store: S, ttl: Duration) -> Self {
+        Idempotency {
+            store: Arc::new(store),
+            ttl,
+        }
+    }
+}
+
+fn default_idempotency() -> &'static Idempotency {
+    static DEFAULT: OnceLock<Idempotency> = OnceLock::new();
+    DEFAULT.get_or_init(|| {
+        Idempotency::new(
+            MemoryIdempotencyStore::new(),
+            Duration::from_secs(24 * 60 * 60),
+        )
+    })
+}
+
+// The `Idempotency-Key` header of a request, scoped to its caller, method and path so the same
+// key on two endpoints or from two callers does not collide. Use `Option<IdempotencyKey>` where
+// the header is optional.
+#[derive(Clone)]
+pub struct IdempotencyKey {
+    pub key: String,
+    pub scope: String,     // e.g. `POST /payments`
+    pub principal: String, // e.g. `user:jane@example.com`, `anonymous` without a valid token
+    idempotency: Idempotency,
+}
+
+// Releases the key of a run that did not finish, e.g. dropped when the client went away
+struct Claim {
+    store: Arc<dyn IdempotencyStore>,
+    key: String,
+    finished: bool,
+}
+
+impl Drop for Claim {
+    fn drop(&mut self) {
+        if self.finished {
+            return;
+        }
+        if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+            let store = self.store.clone();
+            let key = std::mem::take(&mut self.key);
+            runtime.spawn(async move { store.release(&key).await });
+        }
+    }
+}
+
+// Who sent the request, from the first valid token it carries. Decoded without the guards so
+// their failures are not recorded for the 401 catcher.
+async fn principal_of(request: &Request<'_>) -> String {
+    if let Ok(claims) = authenticate::<Claims>(request, "Authorization").await {
+        return format!("user:{}", claims.sub);
+    }
+    if let Ok(claims) = authenticate::<APIClaims>(request, "X-API-Authorization").await {
+        return format!("api:{}", claims.sub);
+    }
+    if let Ok(claims) = authenticate::<ISCClaims>(request, "X-ISC-Authorization").await {
+        return format!("isc:{}:{}", claims.org_id, claims.sub);
+    }
+    "anonymous".to_string()
+}
+
+#[derive(Debug)]
+pub enum IdempotencyKeyError {
+    Missing,
+    Invalid,
+}
+
+// A response computed once per key, replayed as is for retries
+#[derive(Debug, Clone, PartialEq)]
+pub struct IdempotentResponse {
+    pub response: StoredResponse,
+    pub replayed: bool,
+}
+
+impl IdempotencyKey {
+    fn storage_key(&self) -> String {
+        format!("{} {} {}", self.principal, self.scope, self.key)
+    }
+
+    /// Runs `handler` the first time the key is seen and stores its response, later requests
+    /// with the key and the same `payload` get the stored response back, a different payload
+    /// gets a 422. Errors are not stored so the client can retry, a request arriving while the
+    /// first one is still running gets a 409. The key is held by a lease renewed while `handler`
+    /// runs, and released if the run is dropped.
+    pub async fn run<P, T, F, Fut>(
+        &self,
+        payload: &P,
+        handler: F,
+    ) -> Result<IdempotentResponse, ApiError>
+    where
+        P: Serialize + ?Sized,
+        T: Serialize,
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<(Status, T), ApiError>>,
+    {
+        let fingerprint = fingerprint(payload)?;
+        let key = self.storage_key();
+        let store = &self.idempotency.store;
+        match store.begin(&key, LEASE).await {
+            Ok(IdempotencyState::Completed(response)) if response.fingerprint == fingerprint => {
+                return Ok(IdempotentResponse {
+                    response,
+                    replayed: true,
+                })
+            }
+            Ok(IdempotencyState::Completed(_)) => {
+                return Err(ApiError::unprocessable(
+                    "This idempotency key was already used with a different payload",
+                ))
+            }
+            Ok(IdempotencyState::InProgress) => {
+                return Err(ApiError::conflict(
+                    "A request with this idempotency key is already in progress",
+                ))
+            }
+            Ok(IdempotencyState::New) => {}
+            // Running without the key could repeat the operation, the details stay in the logs
+            Err(e) => {
+                tracing::error!(error = %e, "failed to claim an idempotency key");
+                return Err(ApiError::new(
+                    503,
+                    "idempotency_store_unavailable",
+                    "The idempotency store is unavailable",
+                ));
+            }
+        }
+        let mut claim = Claim {
+            store: store.clone(),
+            key: key.clone(),
+            finished: false,
+        };
+
+        let handler = handler();
+        tokio::pin!(handler);
+        let mut renewal = tokio::time::interval(LEASE / 3);
+        renewal.tick().await;
+        let result = loop {
+            tokio::select! {
+                result = &mut handler => break result,
+                _ = renewal.tick() => store.renew(&key, LEASE).await,
+            }
+        };
+        claim.finished = true;
+
+        let result = match result {
+            Ok((status, body)) => serde_json::to_string(&body)
+                .map(|body| StoredResponse {
+                    status: status.code,
+                    body,
+                    fingerprint,
+                })
+                .map_err(|e| {
+                    ApiError::internal(&format!("Failed to serialize the response: {}", e))
+                }),
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(response) => {
+                store
+                    .complete(&key, response.clone(), self.idempotency.ttl)
+                    .await;
+                Ok(IdempotentResponse {
+                    response,
+                    replayed: false,
+                })
+            }
+            Err(e) => {
+                store.release(&key).await;
+                Err(e)
+            }
+        }
+    }
+}
+
+fn fingerprint<P: Serialize + ?Sized>(payload: &P) -> Result<String, ApiError> {
+    let payload = serde_json::to_vec(payload)
+        .map_err(|e| ApiError::internal(&format!("Failed to serialize the payload: {}", e)))?;
+    Ok(Sha256::digest(&payload)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect())
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for IdempotencyKey {
+    type Error = IdempotencyKeyError;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let key = match request.headers().get_one(IDEMPOTENCY_KEY_HEADER) {
+            Some(key) => key.trim(),
+            None => {
+                record_failure(
+                    request,
+                    ApiError::new(
+                        400,
+                        "idempotency_key_missing",
+                        &format!("The {} header is required", IDEMPOTENCY_KEY_HEADER),
+                    ),
+                );
+                return Outcome::Error((Status::BadRequest, IdempotencyKeyError::Missing));
+            }
+        };
+        if key.is_empty()
+            || key.len() > MAX_KEY_LENGTH
+            || !key.chars().all(|c| c.is_ascii_graphic())
+        {
+            record_failure(
+                request,
+                ApiError::new(
+                    400,
+                    "idempotency_key_invalid",
+                    &format!(
+                        "The {} header must be 1 to {} printable ASCII characters",
+                        IDEMPOTENCY_KEY_HEADER, MAX_KEY_LENGTH
+                    ),
+                ),
+            );
+            return Outcome::Error((Status::BadRequest, IdempotencyKeyError::Invalid));
+        }
+        let idempotency = request
+            .rocket()
+            .state::<Idempotency>()
+            .unwrap_or_else(|| default_idempotency())
+            .clone();
+        Outcome::Success(IdempotencyKey {
+            key: key.to_string(),
+            scope: format!("{} {}", request.method(), request.uri().path()),
+            principal: principal_of(request).await,
+            idempotency,
+        })
+    }
+}
+
+impl<'a> OpenApiFromRequest<'a> for IdempotencyKey {
+    fn from_request_input(
+        gen: &mut OpenApiGenerator,
+        _name: String,
+        required: bool,
+    ) -> rocket_okapi::Result<RequestHeaderInput> {
+        let schema = gen.json_schema::<String>();
+        Ok(RequestHeaderInput::Parameter(Parameter {
+            name: IDEMPOTENCY_KEY_HEADER.to_owned(),
+            location: "header".to_owned(),
+            description: Some(
+                "Unique key of the operation, retries with the same key get the first response"
+                    .to_owned(),
+            ),
+            required,
+            deprecated: false,
+            allow_empty_value: false,
+            value: ParameterValue::Schema {
+                style: None,
+                explode: None,
+                allow_reserved: false,
+                schema,
+                example: None,
+                examples: None,
+            },
+            extensions: Object::default(),
+        }))
+    }
+}
+
+impl<'r> Responder<'r, 'static> for IdempotentResponse {
+    fn respond_to(self, _request: &'r Request<'_>) -> response::Result<'static> {
+        let status = Status::from_code(self.response.status).unwrap_or(Status::Ok);
+        let mut response = Response::build();
+        response
+            .status(status)
+            .header(ContentType::JSON)
+            .sized_body(self.response.body.len(), Cursor::new(self.response.body));
+        if self.replayed {
+            response.header(Header::new(IDEMPOTENT_REPLAYED_HEADER, "true"));
+        }
+        response.ok()
+    }
+}
+
+impl OpenApiResponderInner for IdempotentResponse {
+    fn responses(_gen: &mut OpenApiGenerator) -> rocket_okapi::Result<okapi::openapi3::Responses> {
+        Ok(okapi::openapi3::Responses::default())
+    }
+}
@@ -0,0 +1,221 @@
+use jsonwebtoken::{
+    decode, encode,
+    errors::{Error as JwtError, ErrorKind},
+    Algorithm, DecodingKey, EncodingKey, Header, Validation,
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{env, error::Error};
+
+use crate::secrets::{SecretProvider, JWT_SECRET};
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct JwtValidationOptions {
+    pub leeway_secs: u64, // Tolerated clock skew when checking `exp` and `nbf`
+    pub validate_exp: bool,
+    pub validate_nbf: bool,
+    pub required_claims: Vec<String>,
+}
+
+impl Default for JwtValidationOptions {
+    fn default() -> Self {
+        JwtValidationOptions {
+            leeway_secs: 60,
+            validate_exp: true,
+            validate_nbf: false,
+            required_claims: vec!["exp".to_string()],
+        }
+    }
+}
+
+impl JwtValidationOptions {
+    /// Reads `JWT_LEEWAY_SECS`, `JWT_VALIDATE_EXP`, `JWT_VALIDATE_NBF` and
+    /// `JWT_REQUIRED_CLAIMS` (comma separated), keeping the defaults for unset variables.
+    pub fn from_env() -> Result<Self, Box<dyn Error>> {
+        let mut options = JwtValidationOptions::default();
+
+        if let Ok(leeway) = env::var("JWT_LEEWAY_SECS") {
+            options.leeway_secs = leeway
+                .parse()
+                .map_err(|_| format!("JWT_LEEWAY_SECS must be a number, got '{}'", leeway))?;
+        }
+        if let Ok(value) = env::var("JWT_VALIDATE_EXP") {
+            options.validate_exp = parse_bool("JWT_VALIDATE_EXP", &value)?;
+        }
+        if let Ok(value) = env::var("JWT_VALIDATE_NBF") {
+            options.validate_nbf = parse_bool("JWT_VALIDATE_NBF", &value)?;
+        }
+        if let Ok(claims) = env::var("JWT_REQUIRED_CLAIMS") {
+            options.required_claims = split_list(&claims);
+        }
+
+        Ok(options)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct JwtConfig {
+    pub secret: String,
+    pub issuer: Option<String>,
+    pub audiences: Vec<String>, // `aud` is not validated when empty
+    pub validation: JwtValidationOptions,
+}
+
+impl JwtConfig {
+    pub fn new(secret: &str) -> Self {
+        JwtConfig {
+            secret: secret.to_string(),
+            issuer: None,
+            audiences: vec![],
+            validation: JwtValidationOptions::default(),
+        }
+    }
+
+    /// Reads `JWT_SECRET`, the optional `JWT_ISSUER` / `JWT_AUDIENCE` (comma separated) and the
+    /// validation options described in `JwtValidationOptions::from_env`.
+    pub fn from_env() -> Result<Self, Box<dyn Error>> {
+        let secret = env::var("JWT_SECRET").map_err(|_| "JWT_SECRET must be set")?;
+        Self::with_env_options(secret)
+    }
+
+    /// Like `from_env`, the secret being read from `secrets` (`jwt/secret`) rather than
+    /// `JWT_SECRET`.
+    pub async fn from_secrets(secrets: &dyn SecretProvider) -> Result<Self, Box<dyn Error>> {
+        let secret = secrets
+            .require(JWT_SECRET)
+            .await
+            .map_err(|e| e.to_string())?;
+        Self::with_env_options(secret.expose().to_string())
+    }
+
+    fn with_env_options(secret: String) -> Result<Self, Box<dyn Error>> {
+        Ok(JwtConfig {
+            secret,
+            issuer: env::var("JWT_ISSUER").ok(),
+            audiences: env::var("JWT_AUDIENCE")
+                .map(|aud| split_list(&aud))
+                .unwrap_or_default(),
+            validation: JwtValidationOptions::from_env()?,
+        })
+    }
+
+    pub fn with_issuer(mut self, issuer: &str) -> Self {
+        self.issuer = Some(issuer.to_string());
+        self
+    }
+
+    pub fn with_audiences(mut self, audiences: &[&str]) -> Self {
+        self.audiences = audiences.iter().map(|a| a.to_string()).collect();
+        self
+    }
+
+    pub fn with_validation(mut self, validation: JwtValidationOptions) -> Self {
+        self.validation = validation;
+        self
+    }
+
+    pub fn to_validation(&self) -> Validation {
+        let mut validation = Validation::new(Algorithm::HS256);
+        validation.leeway = self.validation.leeway_secs;
+        validation.validate_exp = self.validation.validate_exp;
+        validation.validate_nbf = self.validation.validate_nbf;
+        validation.set_required_spec_claims(&self.validation.required_claims);
+
+        if let Some(issuer) = &self.issuer {
+            validation.set_issuer(&[issuer]);
+        }
+        if self.audiences.is_empty() {
+            validation.validate_aud = false;
+        } else {
+            validation.set_audience(&self.audiences);
+        }
+
+        validation
+    }
+
+    pub fn decode<T: DeserializeOwned>(&self, token: &str) -> Result<T, JwtError> {
+        let decoding_key = DecodingKey::from_secret(self.secret.as_ref());
+        decode::<T>(token, &decoding_key, &self.to_validation()).map(|data| data.claims)
+    }
+
+    pub fn encode<T: Serialize>(&self, claims: &T) -> Result<String, JwtError> {
+        let encoding_key = EncodingKey::from_secret(self.secret.as_ref());
+        encode(&Header::new(Algorithm::HS256), claims, &encoding_key)
+    }
+}
+
+// Why a token was rejected, detailed enough for clients to choose between refreshing the token
+// and logging in again
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenErrorKind {
+    Missing,
+    Expired,
+    InvalidSignature,
+    Malformed,
+    WrongAudience,
+    WrongIssuer,
+    Revoked,
+    Invalid,
+}
+
+impl TokenErrorKind {
+    pub fn error_code(&self) -> &'static str {
+        match self {
+            TokenErrorKind::Missing => "token_missing",
+            TokenErrorKind::Expired => "token_expired",
+            TokenErrorKind::InvalidSignature => "token_invalid_signature",
+            TokenErrorKind::Malformed => "token_malformed",
+            TokenErrorKind::WrongAudience => "token_wrong_audience",
+            TokenErrorKind::WrongIssuer => "token_wrong_issuer",
+            TokenErrorKind::Revoked => "token_revoked",
+            TokenErrorKind::Invalid => "token_invalid",
+        }
+    }
+
+    pub fn message(&self) -> &'static str {
+        match self {
+            TokenErrorKind::Missing => "No token was provided",
+            TokenErrorKind::Expired => "The token has expired",
+            TokenErrorKind::InvalidSignature => "The token signature is invalid",
+            TokenErrorKind::Malformed => "The token is malformed",
+            TokenErrorKind::WrongAudience => "The token was issued for another audience",
+            TokenErrorKind::WrongIssuer => "The token was issued by an untrusted issuer",
+            TokenErrorKind::Revoked => "The token has been revoked",
+            TokenErrorKind::Invalid => "The token is invalid",
+        }
+    }
+}
+
+impl From<&JwtError> for TokenErrorKind {
+    fn from(error: &JwtError) -> Self {
+        match error.kind() {
+            ErrorKind::ExpiredSignature => TokenErrorKind::Expired,
+            ErrorKind::InvalidSignature => TokenErrorKind::InvalidSignature,
+            ErrorKind::InvalidToken
+            | ErrorKind::MissingRequiredClaim(_)
+            | ErrorKind::Base64(_)
+            | ErrorKind::Json(_)
+            | ErrorKind::Utf8(_) => TokenErrorKind::Malformed,
+            ErrorKind::InvalidAudience => TokenErrorKind::WrongAudience,
+            ErrorKind::InvalidIssuer => TokenErrorKind::WrongIssuer,
+            _ => TokenErrorKind::Invalid,
+        }
+    }
+}
+
+fn parse_bool(name: &str, value: &str) -> Result<bool, Box<dyn Error>> {
+    match value.trim().to_lowercase().as_str() {
+        "true" | "1" | "yes" => Ok(true),
+        "false" | "0" | "no" => Ok(false),
+        _ => Err(format!("{} must be a boolean, got '{}'", name, value).into()),
+    }
+}
+
+fn split_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|v| !v.is_empty())
+        .map(str::to_string)
+        .collect()
+}
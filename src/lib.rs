@@ -2,22 +2,144 @@ use clap::ValueEnum;
 use std::{
     cmp::Ordering,
     collections::HashMap,
+    env,
     error::Error,
     fmt,
     fs::{self, File},
-    io::{Read, Write},
+    io::Read,
     path::Path,
     process::exit,
     str::FromStr,
 };
 
+pub use claims::ISCClaims;
+use config_crypto::encrypt_toml_like_file;
+use config_io::{write_file, WriteMode, WritePreview};
+use config_warnings::Deprecations;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-
+pub use services::ServiceRef;
+
+#[cfg(feature = "rocket")]
+pub mod api_key;
+#[cfg(feature = "rocket")]
+pub mod audit;
+pub mod auth;
+#[cfg(feature = "rocket")]
+pub mod authz;
+#[cfg(feature = "rocket")]
+pub mod batch;
+#[cfg(feature = "rocket")]
+pub mod body_limits;
+pub mod branch;
+pub mod cache;
+pub mod cache_schema;
+pub mod claims;
+#[cfg(feature = "cli")]
+pub mod cli_output;
+pub mod cli_result;
+#[cfg(feature = "codegen")]
+pub mod codegen;
+pub mod commits;
+pub mod completions;
+#[cfg(feature = "compression")]
+pub mod compression;
+pub mod config_crypto;
+pub mod config_error;
+pub mod config_io;
+pub mod config_layers;
+pub mod config_schema;
+pub mod config_strict;
+pub mod config_warnings;
+pub mod connection;
+#[cfg(feature = "rocket")]
+pub mod csrf;
+#[cfg(feature = "diesel")]
+pub mod db_pool;
+pub mod dead_letter;
+#[cfg(feature = "mongodb")]
+pub mod documentdb;
+pub mod etag;
+pub mod events;
+pub mod export;
+pub mod feature_flags;
+pub mod git;
+#[cfg(feature = "graphql")]
+pub mod graphql_utils;
+#[cfg(feature = "tonic")]
+pub mod grpc_utils;
+#[cfg(feature = "rocket")]
+pub mod idempotency;
+pub mod jwt;
+pub mod links;
+pub mod lint;
+pub mod migrations;
+#[cfg(feature = "rocket")]
+pub mod mock;
+#[cfg(any(feature = "amqp", feature = "kafka"))]
+pub mod mq;
+pub mod mq_schema;
+#[cfg(feature = "mtls")]
+pub mod mtls;
+pub mod normalize;
+pub mod notify;
+#[cfg(feature = "client")]
+pub mod oauth;
+pub mod org;
+pub mod outbox;
+pub mod passwords;
+pub mod ports;
+#[cfg(feature = "interactive")]
+pub mod prompts;
+pub mod provenance;
+pub mod publish;
+pub mod realtime;
+#[cfg(feature = "redis")]
+pub mod redis_utils;
+#[cfg(feature = "client")]
+pub mod registry;
+pub mod release_plan;
+pub mod revocation;
+#[cfg(feature = "rocket")]
+pub mod rocket_errors;
 pub mod rocket_models;
+#[cfg(feature = "rocket")]
 pub mod rocket_utils;
+pub mod scheduler;
+pub mod schema;
+pub mod schema_diff;
+#[cfg(feature = "client")]
+pub mod schema_registry;
+pub mod secrets;
+#[cfg(feature = "rocket")]
+pub mod security_headers;
+pub mod seeds;
+pub mod services;
+pub mod sessions;
+pub mod shutdown;
+pub mod snapshots;
+#[cfg(feature = "rocket")]
+pub mod spa;
+pub mod spec;
+pub mod spec_diff;
+pub mod storage;
+#[cfg(feature = "rocket")]
+pub mod streaming;
+pub mod table_selection;
+pub mod telemetry;
+pub mod tenancy;
+#[cfg(feature = "test-util")]
+pub mod testing;
+pub mod totp;
+pub mod type_map;
+pub mod updates;
+#[cfg(feature = "rocket")]
+pub mod upload;
 pub mod utils;
+pub mod validation;
+pub mod webhooks;
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, JsonSchema)]
 pub enum ORM {
     TypeORM,
     SQLAlchemy,
@@ -36,11 +158,18 @@ impl fmt::Display for ORM {
     }
 }
 
-#[derive(Deserialize, Debug, Serialize)]
+impl ORM {
+    pub fn all() -> Vec<ORM> {
+        vec![ORM::TypeORM, ORM::SQLAlchemy, ORM::DjangoORM, ORM::Diesel]
+    }
+}
+
+#[derive(Deserialize, Debug, Serialize, JsonSchema)]
 pub struct ConsumerDBSchema {
     pub url: String,
     pub lang: LANG,
     pub orm: ORM,
+    #[serde(default)] // Inferred from `lang` and `orm` when missing
     pub root: String,
     pub schema_id: Option<String>,
     pub cache_schema_id: Option<String>,
@@ -48,21 +177,40 @@ pub struct ConsumerDBSchema {
     pub branch: Option<String>,
 }
 
-#[derive(Deserialize, Debug, Serialize)]
+#[derive(Deserialize, Debug, Serialize, JsonSchema)]
 pub struct ConsumerDBTables {
     pub names: Vec<String>,
 }
 
-#[derive(Deserialize, Debug, Serialize)]
+#[derive(Deserialize, Debug, Serialize, JsonSchema)]
 pub struct ConsumerDBConfig {
     pub schema: ConsumerDBSchema,
     pub tables: ConsumerDBTables,
 }
 
-pub fn write_consumer_db_config<P: AsRef<Path>>(path: P, config: &ConsumerDBConfig) -> () {
-    let toml_string = toml::to_string(config).unwrap();
-    let mut file = File::create(path).unwrap();
-    file.write_all(toml_string.as_bytes()).unwrap();
+pub fn write_consumer_db_config<P: AsRef<Path>>(
+    path: P,
+    config: &ConsumerDBConfig,
+) -> Result<(), Box<dyn Error>> {
+    write_consumer_db_config_with_mode(path, config, WriteMode::Apply).map(|_| ())
+}
+
+pub fn write_consumer_db_config_dry_run<P: AsRef<Path>>(
+    path: P,
+    config: &ConsumerDBConfig,
+) -> Result<WritePreview, Box<dyn Error>> {
+    write_consumer_db_config_with_mode(path, config, WriteMode::DryRun)
+}
+
+/// Like `write_consumer_db_config`, with `WriteMode::ApplyWithBackup` keeping the
+/// previous contents in `<file>.bak`.
+pub fn write_consumer_db_config_with_mode<P: AsRef<Path>>(
+    path: P,
+    config: &ConsumerDBConfig,
+    mode: WriteMode,
+) -> Result<WritePreview, Box<dyn Error>> {
+    let contents = encrypt_toml_like_file(&toml::to_string(config)?, &path)?;
+    write_file(path, &contents, mode)
 }
 
 pub fn read_consumer_db_config<P: AsRef<Path>>(
@@ -89,14 +237,10 @@ pub fn read_consumer_db_config<P: AsRef<Path>>(
     })?;
 
     // Deserialize the TOML contents into the ConsumerDBConfig struct
-    toml::from_str(&contents).map_err(|e| {
-        format!(
-            "Failed to parse TOML from file '{}': {}",
-            path.as_ref().display(),
-            e
-        )
-        .into()
-    })
+    let path = path.as_ref().display().to_string();
+    let mut config: ConsumerDBConfig = config_strict::parse(&path, &contents)?;
+    config.normalize().log("consumer db config");
+    Ok(config)
 }
 
 #[derive(Debug, Clone)]
@@ -105,11 +249,17 @@ pub struct Service {
     pub name: String,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone, Copy, ValueEnum)]
+#[derive(
+    Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, ValueEnum, JsonSchema,
+)]
 pub enum LANG {
+    #[value(help = "Rust crates", alias = "rs")]
     Rust,
+    #[value(help = "TypeScript packages", alias = "typescript")]
     TS,
+    #[value(help = "Python packages", alias = "py")]
     Python,
+    #[value(help = "Shell scripts", alias = "sh", alias = "bash")]
     Shell,
 }
 
@@ -130,15 +280,16 @@ impl LANG {
     }
 }
 
-#[derive(Deserialize, Debug, Serialize, Clone)]
+#[derive(Deserialize, Debug, Serialize, Clone, JsonSchema)]
 pub struct ServiceConfig {
-    pub services: Option<HashMap<String, HashMap<String, String>>>,
+    #[serde(default, deserialize_with = "services::deserialize_services")]
+    pub services: Option<HashMap<String, ServiceRef>>,
     pub portals_refs: Option<HashMap<String, HashMap<String, String>>>,
     pub ws_refs: Option<HashMap<String, HashMap<String, String>>>,
     pub lang: LANG,
     pub organization_id: String,
     pub dir: Option<String>, // in case the project does not need any service integration
-    pub refs_file: Option<String>,
+    pub refs_file: Option<String>, // Formerly `portal_refs_file`, see `config_warnings`
     pub spec_url: Option<String>,
     pub urls: Option<HashMap<String, String>>,
     pub urls_ws: Option<HashMap<String, String>>,
@@ -147,7 +298,7 @@ pub struct ServiceConfig {
     pub portal_config: Option<PortalConfig>,
 }
 
-#[derive(Deserialize, Debug, Serialize, Clone)]
+#[derive(Deserialize, Debug, Serialize, Clone, JsonSchema)]
 pub struct PortalConfig {
     pub id: String,
     pub logo_url: String,
@@ -160,12 +311,15 @@ pub struct PortalConfig {
     pub friendly_name: String,
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone, JsonSchema)]
 pub struct Link {
     pub internal: bool,
     pub label: String,
     pub icon: String,
     pub link: String,
+    // Inferred from the icon and URL when missing, see `Link::kind`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kind: Option<links::LinkKind>,
 }
 
 impl fmt::Display for Link {
@@ -184,10 +338,11 @@ impl PartialEq for Link {
             && self.label == other.label
             && self.icon == other.icon
             && self.link == other.link
+            && self.kind == other.kind
     }
 }
 
-#[derive(Deserialize, Debug, Serialize, Clone)]
+#[derive(Deserialize, Debug, Serialize, Clone, JsonSchema)]
 pub struct PackageMetadata {
     pub lang: LANG,
     pub package_type: String,
@@ -229,11 +384,27 @@ impl fmt::Display for FileType {
     }
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Ord, PartialOrd)]
+#[derive(
+    Debug,
+    Deserialize,
+    Serialize,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    Ord,
+    PartialOrd,
+    ValueEnum,
+    JsonSchema,
+)]
 pub enum Channel {
+    #[value(help = "Stable releases", alias = "stable")]
     Final,
+    #[value(help = "Builds of the dev branch", alias = "dev")]
     Nightly, // Also known as Dev branch
+    #[value(help = "Early previews")]
     Alpha,
+    #[value(help = "Release candidates", alias = "rc")]
     Beta,
 }
 impl fmt::Display for Channel {
@@ -262,7 +433,7 @@ impl From<&str> for Channel {
     }
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone, Copy, Eq)]
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Eq, JsonSchema)]
 pub struct Version {
     pub channel: Channel,
     pub major: u32,
@@ -292,8 +463,9 @@ impl Version {
         )
     }
 
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(version: &str) -> Self {
-        let parts: Vec<&str> = version.split(|c| c == '.' || c == '-').collect();
+        let parts: Vec<&str> = version.split(['.', '-']).collect();
         let major = parts[0].parse().unwrap_or(0);
         let minor = parts[1].parse().unwrap_or(0);
         let patch = parts[2].parse().unwrap_or(0);
@@ -339,7 +511,7 @@ impl PartialEq for Version {
             && self.revision == other.revision
     }
 }
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
 pub enum OutputType {
     String,
     Tuple,
@@ -354,7 +526,7 @@ impl fmt::Display for OutputType {
     }
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone, JsonSchema)]
 pub struct Reference {
     pub file_name: String,
     #[serde(default = "default_output_type")] // Use a default value function
@@ -372,7 +544,7 @@ fn default_output_type() -> OutputType {
     OutputType::String // Default value is "string"
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone, JsonSchema)]
 pub struct ReleaserSettings {
     pub git_url_prefix: Option<String>,
     #[serde(default = "default_take_snapshots")]
@@ -383,7 +555,7 @@ fn default_take_snapshots() -> bool {
     false
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone, JsonSchema)]
 pub struct ReleaserConfig {
     pub settings: ReleaserSettings,
     pub version: Version,
@@ -399,10 +571,12 @@ pub fn read_releaser_config_file<P: AsRef<Path>>(
     file_path: P,
 ) -> Result<ReleaserConfig, Box<dyn std::error::Error>> {
     // Read the file content into a string
-    let contents = fs::read_to_string(file_path)?;
+    let contents = fs::read_to_string(&file_path)?;
 
     // Parse the TOML string into the Settings struct
-    let settings: ReleaserConfig = toml::de::from_str(&contents)?;
+    let mut settings: ReleaserConfig =
+        config_strict::parse(&file_path.as_ref().display().to_string(), &contents)?;
+    settings.normalize().log("releaser config");
 
     Ok(settings)
 }
@@ -411,22 +585,48 @@ pub fn write_releaser_config_file(
     file_path: &str,
     config: &ReleaserConfig,
 ) -> Result<(), Box<dyn Error>> {
-    let toml_str = toml::to_string(config)?;
-    fs::write(file_path, toml_str)?;
-    Ok(())
+    write_releaser_config_file_with_mode(file_path, config, WriteMode::Apply).map(|_| ())
+}
+
+pub fn write_releaser_config_file_dry_run(
+    file_path: &str,
+    config: &ReleaserConfig,
+) -> Result<WritePreview, Box<dyn Error>> {
+    write_releaser_config_file_with_mode(file_path, config, WriteMode::DryRun)
+}
+
+/// Like `write_releaser_config_file`, with `WriteMode::ApplyWithBackup` keeping the
+/// previous contents in `<file>.bak`.
+pub fn write_releaser_config_file_with_mode(
+    file_path: &str,
+    config: &ReleaserConfig,
+    mode: WriteMode,
+) -> Result<WritePreview, Box<dyn Error>> {
+    let contents = encrypt_toml_like_file(&toml::to_string(config)?, file_path)?;
+    write_file(file_path, &contents, mode)
 }
 
 pub fn read_service_config_file<P: AsRef<Path>>(path: P) -> Result<ServiceConfig, Box<dyn Error>> {
-    let content = fs::read_to_string(path)?;
-    let config: ServiceConfig = toml::from_str(&content)?;
+    let content = fs::read_to_string(&path)?;
+    let config_path = path.as_ref().display().to_string();
+    let mut raw: toml::Table = config_strict::from_str(&config_path, &content)?;
+    ServiceConfig::deprecation_warnings(&raw).record();
+    let mut config: ServiceConfig = match ServiceConfig::replace_deprecated(&mut raw) {
+        true => config_strict::parse_table(&config_path, raw)?,
+        false => config_strict::parse(&config_path, &content)?,
+    };
+    config.normalize().log("service config");
+    config.validate_urls()?;
     Ok(config)
 }
 
 pub fn read_package_metadata_file<P: AsRef<Path>>(
     path: P,
 ) -> Result<PackageMetadata, Box<dyn Error>> {
-    let content = fs::read_to_string(path)?;
-    let config: PackageMetadata = toml::from_str(&content)?;
+    let content = fs::read_to_string(&path)?;
+    let mut config: PackageMetadata =
+        config_strict::parse(&path.as_ref().display().to_string(), &content)?;
+    config.normalize().log("package metadata");
     Ok(config)
 }
 
@@ -434,19 +634,68 @@ pub fn write_service_config_file<P: AsRef<Path>>(
     path: P,
     config: &ServiceConfig,
 ) -> Result<(), Box<dyn Error>> {
-    let content = toml::to_string(config)?;
-    fs::write(path, content)?;
-    Ok(())
+    write_service_config_file_with_mode(path, config, WriteMode::Apply).map(|_| ())
+}
+
+pub fn write_service_config_file_dry_run<P: AsRef<Path>>(
+    path: P,
+    config: &ServiceConfig,
+) -> Result<WritePreview, Box<dyn Error>> {
+    write_service_config_file_with_mode(path, config, WriteMode::DryRun)
 }
 
-#[derive(Debug, Deserialize, Serialize, PartialEq)]
+/// Like `write_service_config_file`, with `WriteMode::ApplyWithBackup` keeping the
+/// previous contents in `<file>.bak`.
+pub fn write_service_config_file_with_mode<P: AsRef<Path>>(
+    path: P,
+    config: &ServiceConfig,
+    mode: WriteMode,
+) -> Result<WritePreview, Box<dyn Error>> {
+    let contents = encrypt_toml_like_file(&toml::to_string(config)?, &path)?;
+    write_file(path, &contents, mode)
+}
+
+#[derive(Debug, Deserialize, Serialize, PartialEq, JsonSchema)]
 pub struct GingerDBConfig {
     pub branch: String,
     pub organization_id: String,
     pub database: Vec<DatabaseConfig>, // Unified all db types in one vector
 }
 
-#[derive(Debug, Deserialize, Serialize, PartialEq, Clone)]
+impl GingerDBConfig {
+    /// The enabled database of type `db_type` named `name`, or the only one when no name is
+    /// given.
+    pub fn enabled_database(
+        &self,
+        db_type: &DbType,
+        name: Option<&str>,
+    ) -> Result<&DatabaseConfig, Box<dyn Error>> {
+        let kind = match db_type {
+            DbType::Rdbms => "relational database",
+            DbType::DocumentDb => "document database",
+            DbType::Cache => "cache",
+            DbType::MessageQueue => "message queue database",
+        };
+        let databases: Vec<&DatabaseConfig> = self
+            .database
+            .iter()
+            .filter(|db| &db.db_type == db_type && db.enable)
+            .collect();
+        match name {
+            Some(name) => databases
+                .into_iter()
+                .find(|db| db.name == name)
+                .ok_or_else(|| format!("There is no enabled {} named '{}'", kind, name).into()),
+            None => match databases.as_slice() {
+                [database] => Ok(database),
+                [] => Err(format!("There is no enabled {} in the db config", kind).into()),
+                _ => Err(format!("There are several {}s, name the one to use", kind).into()),
+            },
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone, JsonSchema)]
 pub struct DatabaseConfig {
     pub db_type: DbType, // Use DbType enum
     pub description: String,
@@ -455,6 +704,7 @@ pub struct DatabaseConfig {
     pub name: String,
     pub port: String,
     pub studio_port: Option<String>,
+    pub engine: Option<DbEngine>, // Falls back to the default engine of `db_type`
     #[serde(default = "default_links")]
     pub links: Vec<Link>,
 }
@@ -465,12 +715,26 @@ impl fmt::Display for DatabaseConfig {
     }
 }
 
-#[derive(Debug, Deserialize, Serialize, PartialEq, Clone)]
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone, ValueEnum, JsonSchema)]
 #[serde(rename_all = "lowercase")] // This will map the enum to/from lowercase strings
 pub enum DbType {
+    #[value(help = "Relational database", alias = "sql")]
     Rdbms,
+    #[value(
+        name = "documentdb",
+        help = "Document database",
+        alias = "document-db",
+        alias = "nosql"
+    )]
     DocumentDb,
+    #[value(help = "Key-value cache")]
     Cache,
+    #[value(
+        name = "messagequeue",
+        help = "Message broker",
+        alias = "message-queue",
+        alias = "mq"
+    )]
     MessageQueue,
 }
 
@@ -500,9 +764,73 @@ impl FromStr for DbType {
     }
 }
 
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone, Copy, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum DbEngine {
+    Postgres,
+    Mysql,
+    Mongodb,
+    Redis,
+    Rabbitmq,
+    Kafka,
+}
+
+impl DbEngine {
+    pub fn default_for(db_type: &DbType) -> DbEngine {
+        match db_type {
+            DbType::Rdbms => DbEngine::Postgres,
+            DbType::DocumentDb => DbEngine::Mongodb,
+            DbType::Cache => DbEngine::Redis,
+            DbType::MessageQueue => DbEngine::Rabbitmq,
+        }
+    }
+
+    pub fn default_port(&self) -> u16 {
+        match self {
+            DbEngine::Postgres => 5432,
+            DbEngine::Mysql => 3306,
+            DbEngine::Mongodb => 27017,
+            DbEngine::Redis => 6379,
+            DbEngine::Rabbitmq => 5672,
+            DbEngine::Kafka => 9092,
+        }
+    }
+}
+
+impl fmt::Display for DbEngine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let engine_str = match self {
+            DbEngine::Postgres => "postgres",
+            DbEngine::Mysql => "mysql",
+            DbEngine::Mongodb => "mongodb",
+            DbEngine::Redis => "redis",
+            DbEngine::Rabbitmq => "rabbitmq",
+            DbEngine::Kafka => "kafka",
+        };
+        write!(f, "{}", engine_str)
+    }
+}
+
+impl FromStr for DbEngine {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "postgres" => Ok(DbEngine::Postgres),
+            "mysql" => Ok(DbEngine::Mysql),
+            "mongodb" => Ok(DbEngine::Mongodb),
+            "redis" => Ok(DbEngine::Redis),
+            "rabbitmq" => Ok(DbEngine::Rabbitmq),
+            "kafka" => Ok(DbEngine::Kafka),
+            _ => Err(format!("'{}' is not a valid DbEngine", s)),
+        }
+    }
+}
+
 pub fn read_db_config(file_path: &str) -> Result<GingerDBConfig, Box<dyn std::error::Error>> {
     let contents = fs::read_to_string(file_path)?;
-    let config: GingerDBConfig = toml::from_str(&contents)?;
+    let mut config: GingerDBConfig = config_strict::parse(file_path, &contents)?;
+    config.normalize().log("db config");
     Ok(config)
 }
 
@@ -510,18 +838,39 @@ pub fn write_db_config(
     file_path: &str,
     config: &GingerDBConfig,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let toml_string = toml::to_string(config)?;
-    let mut file = fs::File::create(file_path)?;
-    file.write_all(toml_string.as_bytes())?;
-    Ok(())
+    write_db_config_with_mode(file_path, config, WriteMode::Apply).map(|_| ())
+}
+
+pub fn write_db_config_dry_run(
+    file_path: &str,
+    config: &GingerDBConfig,
+) -> Result<WritePreview, Box<dyn Error>> {
+    write_db_config_with_mode(file_path, config, WriteMode::DryRun)
 }
 
-#[derive(ValueEnum, Clone, PartialEq)]
+/// Like `write_db_config`, with `WriteMode::ApplyWithBackup` keeping the
+/// previous contents in `<file>.bak`.
+pub fn write_db_config_with_mode(
+    file_path: &str,
+    config: &GingerDBConfig,
+    mode: WriteMode,
+) -> Result<WritePreview, Box<dyn Error>> {
+    let contents = encrypt_toml_like_file(&toml::to_string(config)?, file_path)?;
+    write_file(file_path, &contents, mode)
+}
+
+#[derive(ValueEnum, Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
 pub enum Environment {
+    #[value(help = "Local development", alias = "development")]
     Dev,
+    #[value(help = "Staging servers", alias = "staging")]
     Stage,
+    #[value(help = "Production servers", alias = "production")]
     Prod,
+    #[value(help = "Production on Kubernetes", alias = "prod_k8", alias = "prodk8")]
     ProdK8,
+    #[value(help = "Staging on Kubernetes", alias = "stage_k8", alias = "stagek8")]
     StageK8,
 }
 
@@ -537,10 +886,90 @@ impl fmt::Display for Environment {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct ISCClaims {
-    pub sub: String,
-    pub exp: usize,
-    pub org_id: String,
-    pub scopes: Vec<String>,
+impl FromStr for Environment {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().replace('-', "_").as_str() {
+            "dev" => Ok(Environment::Dev),
+            "stage" => Ok(Environment::Stage),
+            "prod" => Ok(Environment::Prod),
+            "prod_k8" | "prodk8" => Ok(Environment::ProdK8),
+            "stage_k8" | "stagek8" => Ok(Environment::StageK8),
+            _ => Err(format!("'{}' is not a valid Environment", s)),
+        }
+    }
+}
+
+pub const ENVIRONMENT_ENV: &str = "GINGER_ENV";
+
+impl Environment {
+    pub fn all() -> Vec<Environment> {
+        vec![
+            Environment::Dev,
+            Environment::Stage,
+            Environment::Prod,
+            Environment::ProdK8,
+            Environment::StageK8,
+        ]
+    }
+
+    /// Works out the environment the binary runs in:
+    /// 1. `GINGER_ENV` when set (`dev`, `stage`, `prod`, `prod_k8` or `stage_k8`)
+    /// 2. inside a k8s pod, the namespace (from the `POD_NAMESPACE` / `K8S_NAMESPACE` downward
+    ///    API variables or the service account) has to contain `prod` or `stage`
+    /// 3. `Dev` otherwise
+    pub fn detect() -> Result<Environment, Box<dyn Error>> {
+        if let Some(value) = env::var(ENVIRONMENT_ENV)
+            .ok()
+            .filter(|v| !v.trim().is_empty())
+        {
+            return Ok(value
+                .parse()
+                .map_err(|e| format!("Invalid {}: {}", ENVIRONMENT_ENV, e))?);
+        }
+
+        if env::var("KUBERNETES_SERVICE_HOST").is_err() {
+            return Ok(Environment::Dev);
+        }
+
+        let namespace = ["POD_NAMESPACE", "K8S_NAMESPACE"]
+            .iter()
+            .find_map(|var| env::var(var).ok())
+            .or_else(|| {
+                fs::read_to_string("/var/run/secrets/kubernetes.io/serviceaccount/namespace").ok()
+            })
+            .map(|ns| ns.trim().to_lowercase())
+            .unwrap_or_default();
+
+        if namespace.contains("prod") {
+            Ok(Environment::ProdK8)
+        } else if namespace.contains("stag") {
+            Ok(Environment::StageK8)
+        } else {
+            Err(format!(
+                "Running in k8s but the namespace '{}' does not tell the environment, set {}",
+                namespace, ENVIRONMENT_ENV
+            )
+            .into())
+        }
+    }
+
+    pub fn is_k8(&self) -> bool {
+        matches!(self, Environment::ProdK8 | Environment::StageK8)
+    }
+
+    pub fn is_prod_like(&self) -> bool {
+        matches!(self, Environment::Prod | Environment::ProdK8)
+    }
+
+    /// The environment of the backing services, i.e. `ProdK8` talks to `Prod` and `StageK8` to
+    /// `Stage`.
+    pub fn as_backend(&self) -> Environment {
+        match self {
+            Environment::ProdK8 => Environment::Prod,
+            Environment::StageK8 => Environment::Stage,
+            env => *env,
+        }
+    }
 }
@@ -0,0 +1,245 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::fmt::{self, Write};
+
+use crate::{lint::closest, Link, PackageMetadata, LANG};
+
+#[derive(
+    Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, JsonSchema,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum LinkKind {
+    Repo,
+    Docs,
+    Registry,
+    Pipeline,
+    Dashboard,
+    Other,
+}
+
+impl fmt::Display for LinkKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LinkKind::Repo => write!(f, "repo"),
+            LinkKind::Docs => write!(f, "docs"),
+            LinkKind::Registry => write!(f, "registry"),
+            LinkKind::Pipeline => write!(f, "pipeline"),
+            LinkKind::Dashboard => write!(f, "dashboard"),
+            LinkKind::Other => write!(f, "other"),
+        }
+    }
+}
+
+// Icons the portal ships, named after their `react-icons` components
+pub const KNOWN_ICONS: [&str; 24] = [
+    "FaBook",
+    "FaBox",
+    "FaChartBar",
+    "FaChartLine",
+    "FaCodeBranch",
+    "FaCube",
+    "FaDatabase",
+    "FaDocker",
+    "FaExternalLinkAlt",
+    "FaFileAlt",
+    "FaGithub",
+    "FaGitlab",
+    "FaGlobe",
+    "FaJs",
+    "FaLink",
+    "FaNpm",
+    "FaPython",
+    "FaRegPlayCircle",
+    "FaRocket",
+    "FaRust",
+    "FaServer",
+    "FaTachometerAlt",
+    "FaTerminal",
+    "FaTools",
+];
+
+const REGISTRY_HOSTS: [&str; 5] = [
+    "crates.io",
+    "npmjs.com",
+    "pypi.org",
+    "hub.docker.com",
+    "ghcr.io",
+];
+
+impl Link {
+    /// The explicit `kind`, or one inferred from the icon and URL.
+    pub fn kind(&self) -> LinkKind {
+        if let Some(kind) = self.kind {
+            return kind;
+        }
+        let url = self.link.to_lowercase();
+        match self.icon.as_str() {
+            "FaRegPlayCircle" | "FaRocket" => return LinkKind::Pipeline,
+            "FaBook" | "FaFileAlt" => return LinkKind::Docs,
+            "FaChartBar" | "FaChartLine" | "FaTachometerAlt" => return LinkKind::Dashboard,
+            _ => {}
+        }
+        if url.contains("/actions") || url.contains("/pipelines") || url.contains("/-/jobs") {
+            LinkKind::Pipeline
+        } else if REGISTRY_HOSTS.iter().any(|host| url.contains(host)) {
+            LinkKind::Registry
+        } else if url.contains("docs.rs") || url.contains("/docs") || url.contains("readthedocs") {
+            LinkKind::Docs
+        } else if url.contains("grafana") || url.contains("dashboard") {
+            LinkKind::Dashboard
+        } else if url.contains("github.com") || url.contains("gitlab.com") {
+            LinkKind::Repo
+        } else {
+            LinkKind::Other
+        }
+    }
+
+    pub fn has_known_icon(&self) -> bool {
+        KNOWN_ICONS.contains(&self.icon.as_str())
+    }
+}
+
+impl PackageMetadata {
+    /// Problems with the links, e.g. unknown icons, with a suggestion when one is close.
+    pub fn validate_links(&self) -> Vec<String> {
+        let icons: Vec<String> = KNOWN_ICONS.iter().map(|icon| icon.to_string()).collect();
+        let mut problems = vec![];
+        for link in &self.links {
+            if link.label.trim().is_empty() {
+                problems.push(format!("The link to '{}' has no label", link.link));
+            }
+            if link.link.trim().is_empty() {
+                problems.push(format!("The link '{}' has no URL", link.label));
+            }
+            if !link.has_known_icon() {
+                let mut problem = format!("Unknown icon '{}' on link '{}'", link.icon, link.label);
+                if let Some(icon) = closest(&link.icon, &icons) {
+                    write!(problem, ", did you mean '{}'?", icon).unwrap();
+                }
+                problems.push(problem);
+            }
+        }
+        problems
+    }
+
+    /// The links in the order cards show them: by kind, then as configured.
+    pub fn links_by_kind(&self) -> Vec<(LinkKind, &Link)> {
+        let mut links: Vec<(LinkKind, &Link)> =
+            self.links.iter().map(|link| (link.kind(), link)).collect();
+        links.sort_by_key(|(kind, _)| *kind);
+        links
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, JsonSchema)]
+pub struct PortalCardLink {
+    pub kind: LinkKind,
+    pub label: String,
+    pub url: String,
+    pub icon: String, // Falls back to `FaLink` for unknown icons
+    pub internal: bool,
+}
+
+// What the developer portal and CLIs render for a package
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, JsonSchema)]
+pub struct PortalCard {
+    pub name: String,
+    pub description: Option<String>,
+    pub version: Option<String>,
+    pub lang: LANG,
+    pub lang_icon: String,
+    pub package_type: String,
+    pub repo_url: Option<String>,
+    pub links: Vec<PortalCardLink>,
+}
+
+impl PortalCard {
+    pub fn builder(name: &str, metadata: &PackageMetadata) -> PortalCardBuilder {
+        PortalCardBuilder {
+            name: name.to_string(),
+            metadata: metadata.clone(),
+            description: None,
+            version: None,
+        }
+    }
+
+    pub fn render_text(&self) -> String {
+        let mut out = String::new();
+        write!(out, "{}", self.name).unwrap();
+        if let Some(version) = &self.version {
+            write!(out, " {}", version).unwrap();
+        }
+        writeln!(out, " ({} {})", self.lang, self.package_type).unwrap();
+        if let Some(description) = &self.description {
+            writeln!(out, "  {}", description).unwrap();
+        }
+        for link in &self.links {
+            writeln!(out, "  [{}] {}: {}", link.kind, link.label, link.url).unwrap();
+        }
+        out
+    }
+}
+
+pub struct PortalCardBuilder {
+    name: String,
+    metadata: PackageMetadata,
+    description: Option<String>,
+    version: Option<String>,
+}
+
+impl PortalCardBuilder {
+    pub fn description(mut self, description: &str) -> Self {
+        self.description = Some(description.to_string());
+        self
+    }
+
+    pub fn version(mut self, version: &str) -> Self {
+        self.version = Some(version.to_string());
+        self
+    }
+
+    pub fn build(self) -> PortalCard {
+        let links: Vec<PortalCardLink> = self
+            .metadata
+            .links_by_kind()
+            .into_iter()
+            .map(|(kind, link)| PortalCardLink {
+                kind,
+                label: link.label.clone(),
+                url: link.link.clone(),
+                icon: if link.has_known_icon() {
+                    link.icon.clone()
+                } else {
+                    "FaLink".to_string()
+                },
+                internal: link.internal,
+            })
+            .collect();
+        // The repository root rather than e.g. its pull requests page
+        let repo_url = links
+            .iter()
+            .filter(|link| link.kind == LinkKind::Repo)
+            .min_by_key(|link| link.url.trim_end_matches('/').matches('/').count())
+            .map(|link| link.url.clone());
+
+        PortalCard {
+            name: self.name,
+            description: self.description,
+            version: self.version,
+            lang: self.metadata.lang,
+            lang_icon: lang_icon(&self.metadata.lang).to_string(),
+            package_type: self.metadata.package_type,
+            repo_url,
+            links,
+        }
+    }
+}
+
+fn lang_icon(lang: &LANG) -> &'static str {
+    match lang {
+        LANG::Rust => "FaRust",
+        LANG::TS => "FaJs",
+        LANG::Python => "FaPython",
+        LANG::Shell => "FaTerminal",
+    }
+}
@@ -0,0 +1,487 @@
+use serde::{Deserialize, Serialize};
+use std::{error::Error, fmt, fmt::Write, fs, path::Path};
+
+use crate::{
+    config_warnings::Deprecations, read_service_config_file, schema::SchemaDocument,
+    table_selection::glob_match, ConsumerDBConfig, GingerDBConfig, PackageMetadata, ReleaserConfig,
+    ServiceConfig,
+};
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    Info,
+    Warning,
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Info => write!(f, "info"),
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct Finding {
+    pub rule: String,
+    pub severity: Severity,
+    pub config: String, // e.g. `services.toml`, the config the finding belongs to
+    pub key: Option<String>,
+    pub message: String,
+    pub suggestion: Option<String>,
+}
+
+impl Finding {
+    pub fn new(rule: &str, severity: Severity, config: &str, message: &str) -> Self {
+        Finding {
+            rule: rule.to_string(),
+            severity,
+            config: config.to_string(),
+            key: None,
+            message: message.to_string(),
+            suggestion: None,
+        }
+    }
+
+    pub fn at(mut self, key: &str) -> Self {
+        self.key = Some(key.to_string());
+        self
+    }
+
+    pub fn suggest(mut self, suggestion: &str) -> Self {
+        self.suggestion = Some(suggestion.to_string());
+        self
+    }
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.severity, self.config)?;
+        if let Some(key) = &self.key {
+            write!(f, " [{}]", key)?;
+        }
+        write!(f, ": {} ({})", self.message, self.rule)?;
+        if let Some(suggestion) = &self.suggestion {
+            write!(f, "\n  help: {}", suggestion)?;
+        }
+        Ok(())
+    }
+}
+
+// The configs of a project a lint run looks at, rules skip the ones that are missing
+#[derive(Debug, Default)]
+pub struct LintContext {
+    pub service_config: Option<ServiceConfig>,
+    // Raw `services.toml`, for keys the typed config no longer knows about
+    pub raw_service_config: Option<toml::Table>,
+    pub db_config: Option<GingerDBConfig>,
+    pub consumer_db_config: Option<ConsumerDBConfig>,
+    pub schema: Option<SchemaDocument>,
+    pub releaser_config: Option<ReleaserConfig>,
+    pub package_metadata: Option<PackageMetadata>,
+}
+
+impl LintContext {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_service_config(mut self, config: ServiceConfig) -> Self {
+        self.service_config = Some(config);
+        self
+    }
+
+    /// Reads `services.toml` at `path` as `read_service_config_file` does, so the config linted
+    /// is the one services load, keeping the raw table for the deprecated keys check.
+    pub fn with_service_config_file<P: AsRef<Path>>(
+        mut self,
+        path: P,
+    ) -> Result<Self, Box<dyn Error>> {
+        let contents = fs::read_to_string(&path)?;
+        self.raw_service_config = Some(toml::from_str(&contents)?);
+        self.service_config = Some(read_service_config_file(&path)?);
+        Ok(self)
+    }
+
+    pub fn with_db_config(mut self, config: GingerDBConfig) -> Self {
+        self.db_config = Some(config);
+        self
+    }
+
+    pub fn with_consumer_db_config(mut self, config: ConsumerDBConfig) -> Self {
+        self.consumer_db_config = Some(config);
+        self
+    }
+
+    /// The schema the consumer's table names are checked against.
+    pub fn with_schema(mut self, schema: SchemaDocument) -> Self {
+        self.schema = Some(schema);
+        self
+    }
+
+    pub fn with_releaser_config(mut self, config: ReleaserConfig) -> Self {
+        self.releaser_config = Some(config);
+        self
+    }
+
+    pub fn with_package_metadata(mut self, metadata: PackageMetadata) -> Self {
+        self.package_metadata = Some(metadata);
+        self
+    }
+}
+
+pub trait LintRule: Send + Sync {
+    /// Stable identifier, used in reports and to disable the rule.
+    fn name(&self) -> &str;
+
+    fn check(&self, context: &LintContext) -> Vec<Finding>;
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Default)]
+pub struct LintReport {
+    pub findings: Vec<Finding>,
+}
+
+impl LintReport {
+    pub fn is_clean(&self) -> bool {
+        self.findings.is_empty()
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.count(Severity::Error) > 0
+    }
+
+    pub fn count(&self, severity: Severity) -> usize {
+        self.findings
+            .iter()
+            .filter(|finding| finding.severity == severity)
+            .count()
+    }
+
+    /// Findings at or above `severity`, e.g. to fail a CI run on warnings.
+    pub fn at_least(&self, severity: Severity) -> Vec<&Finding> {
+        self.findings
+            .iter()
+            .filter(|finding| finding.severity >= severity)
+            .collect()
+    }
+
+    pub fn render_text(&self) -> String {
+        if self.is_clean() {
+            return "No lint findings".to_string();
+        }
+        let mut out = String::new();
+        for finding in &self.findings {
+            writeln!(out, "{}", finding).unwrap();
+        }
+        writeln!(
+            out,
+            "{} error(s), {} warning(s), {} info",
+            self.count(Severity::Error),
+            self.count(Severity::Warning),
+            self.count(Severity::Info)
+        )
+        .unwrap();
+        out
+    }
+}
+
+pub struct Linter {
+    rules: Vec<Box<dyn LintRule>>,
+}
+
+impl Default for Linter {
+    fn default() -> Self {
+        Self::with_default_rules()
+    }
+}
+
+impl Linter {
+    /// A linter without any rule.
+    pub fn new() -> Self {
+        Linter { rules: vec![] }
+    }
+
+    pub fn with_default_rules() -> Self {
+        Self::new()
+            .register(MissingOrgId)
+            .register(UnusedTableNames)
+            .register(DeadServiceRefs)
+            .register(DeprecatedFields)
+            .register(PortConflicts)
+            .register(InvalidLinks)
+    }
+
+    pub fn register<R: LintRule + 'static>(mut self, rule: R) -> Self {
+        self.rules.push(Box::new(rule));
+        self
+    }
+
+    /// Removes the rule called `name`, does nothing if it is not registered.
+    pub fn disable(mut self, name: &str) -> Self {
+        self.rules.retain(|rule| rule.name() != name);
+        self
+    }
+
+    pub fn rule_names(&self) -> Vec<&str> {
+        self.rules.iter().map(|rule| rule.name()).collect()
+    }
+
+    /// Runs every rule, findings are sorted by severity, most severe first.
+    pub fn run(&self, context: &LintContext) -> LintReport {
+        let mut findings: Vec<Finding> = self
+            .rules
+            .iter()
+            .flat_map(|rule| rule.check(context))
+            .collect();
+        findings.sort_by_key(|finding| std::cmp::Reverse(finding.severity));
+        LintReport { findings }
+    }
+}
+
+pub struct MissingOrgId;
+
+impl LintRule for MissingOrgId {
+    fn name(&self) -> &str {
+        "missing-org-id"
+    }
+
+    fn check(&self, context: &LintContext) -> Vec<Finding> {
+        let mut findings = vec![];
+        let suggestion = "Set `organization_id` to the id of your organization on the portal";
+        if let Some(config) = &context.service_config {
+            if config.organization_id.trim().is_empty() {
+                findings.push(
+                    Finding::new(
+                        self.name(),
+                        Severity::Error,
+                        "services.toml",
+                        "No organization id is set",
+                    )
+                    .at("organization_id")
+                    .suggest(suggestion),
+                );
+            }
+        }
+        if let Some(config) = &context.db_config {
+            if config.organization_id.trim().is_empty() {
+                findings.push(
+                    Finding::new(
+                        self.name(),
+                        Severity::Error,
+                        "database config",
+                        "No organization id is set",
+                    )
+                    .at("organization_id")
+                    .suggest(suggestion),
+                );
+            }
+        }
+        findings
+    }
+}
+
+pub struct UnusedTableNames;
+
+impl LintRule for UnusedTableNames {
+    fn name(&self) -> &str {
+        "unused-table-names"
+    }
+
+    fn check(&self, context: &LintContext) -> Vec<Finding> {
+        let (config, schema) = match (&context.consumer_db_config, &context.schema) {
+            (Some(config), Some(schema)) => (config, schema),
+            _ => return vec![],
+        };
+        let tables = schema.table_names();
+
+        config
+            .tables
+            .names
+            .iter()
+            .filter(|name| {
+                let pattern = name.trim().trim_start_matches('!').trim();
+                !tables.iter().any(|table| glob_match(pattern, table))
+            })
+            .map(|name| {
+                let finding = Finding::new(
+                    self.name(),
+                    Severity::Warning,
+                    "consumer database config",
+                    &format!("'{}' does not match any table of the schema", name),
+                )
+                .at("tables.names");
+                match closest(name.trim_start_matches('!'), &tables) {
+                    Some(table) => finding.suggest(&format!("Did you mean '{}'?", table)),
+                    None => finding.suggest("Remove the entry"),
+                }
+            })
+            .collect()
+    }
+}
+
+pub struct DeadServiceRefs;
+
+impl LintRule for DeadServiceRefs {
+    fn name(&self) -> &str {
+        "dead-service-refs"
+    }
+
+    fn check(&self, context: &LintContext) -> Vec<Finding> {
+        let config = match &context.service_config {
+            Some(config) => config,
+            None => return vec![],
+        };
+        let mut findings = vec![];
+
+        let mut services: Vec<_> = config.services.iter().flatten().collect();
+        services.sort_by_key(|(name, _)| name.as_str());
+        for (name, service) in services {
+            if service.env_urls.is_empty() && service.schema_url.is_none() {
+                findings.push(
+                    Finding::new(
+                        self.name(),
+                        Severity::Warning,
+                        "services.toml",
+                        &format!("Service '{}' has no URL for any environment", name),
+                    )
+                    .at(&format!("services.{}", name))
+                    .suggest("Add the URLs of the service or remove the reference"),
+                );
+            }
+        }
+
+        for (table, refs) in [
+            ("portals_refs", &config.portals_refs),
+            ("ws_refs", &config.ws_refs),
+        ] {
+            let mut refs: Vec<_> = refs.iter().flatten().collect();
+            refs.sort_by_key(|(name, _)| name.as_str());
+            for (name, urls) in refs {
+                if urls.values().all(|url| url.trim().is_empty()) {
+                    findings.push(
+                        Finding::new(
+                            self.name(),
+                            Severity::Warning,
+                            "services.toml",
+                            &format!("Reference '{}' has no URL for any environment", name),
+                        )
+                        .at(&format!("{}.{}", table, name))
+                        .suggest("Add the URLs of the reference or remove it"),
+                    );
+                }
+            }
+        }
+        findings
+    }
+}
+
+pub struct DeprecatedFields;
+
+impl LintRule for DeprecatedFields {
+    fn name(&self) -> &str {
+        "deprecated-fields"
+    }
+
+    fn check(&self, context: &LintContext) -> Vec<Finding> {
+        let mut keys: Vec<&str> = vec![];
+        if let Some(raw) = &context.raw_service_config {
+            keys.extend(raw.keys().map(String::as_str));
+        }
+        if let Some(config) = &context.service_config {
+            if config.override_name.is_some() {
+                keys.push("override_name");
+            }
+        }
+
+        ServiceConfig::deprecated_fields()
+            .iter()
+            .filter(|field| keys.contains(&field.key))
+            .map(|field| {
+                Finding::new(
+                    self.name(),
+                    Severity::Warning,
+                    ServiceConfig::CONFIG,
+                    &format!("`{}` is deprecated", field.key),
+                )
+                .at(field.key)
+                .suggest(field.hint)
+            })
+            .collect()
+    }
+}
+
+pub struct PortConflicts;
+
+impl LintRule for PortConflicts {
+    fn name(&self) -> &str {
+        "port-conflicts"
+    }
+
+    fn check(&self, context: &LintContext) -> Vec<Finding> {
+        context
+            .db_config
+            .iter()
+            .flat_map(|config| config.check_port_conflicts())
+            .map(|conflict| {
+                Finding::new(
+                    self.name(),
+                    Severity::Error,
+                    "database config",
+                    &conflict.to_string(),
+                )
+                .suggest("Give every database its own port")
+            })
+            .collect()
+    }
+}
+
+pub struct InvalidLinks;
+
+impl LintRule for InvalidLinks {
+    fn name(&self) -> &str {
+        "invalid-links"
+    }
+
+    fn check(&self, context: &LintContext) -> Vec<Finding> {
+        context
+            .package_metadata
+            .iter()
+            .flat_map(|metadata| metadata.validate_links())
+            .map(|problem| {
+                Finding::new(self.name(), Severity::Warning, "metadata.toml", &problem)
+                    .at("links")
+                    .suggest("Use one of the icons listed in `links::KNOWN_ICONS`")
+            })
+            .collect()
+    }
+}
+
+// Closest candidate by edit distance, if it is close enough to be a typo
+pub(crate) fn closest<'a>(value: &str, candidates: &'a [String]) -> Option<&'a String> {
+    let max_distance = (value.len() / 3).max(1);
+    candidates
+        .iter()
+        .map(|candidate| (candidate, edit_distance(value, candidate)))
+        .filter(|(_, distance)| *distance <= max_distance)
+        .min_by_key(|(_, distance)| *distance)
+        .map(|(candidate, _)| candidate)
+}
+
+pub(crate) fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
@@ -0,0 +1,418 @@
+use serde::{Deserialize, Serialize};
+use std::{error::Error, fmt};
+
+use crate::{ConsumerDBSchema, ORM};
+
+/// Table recording the schema registry version a database was migrated to.
+pub const VERSION_TABLE: &str = "ginger_schema_version";
+
+// The schema registry version a database was last migrated to
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[cfg_attr(feature = "diesel", derive(diesel::QueryableByName))]
+pub struct SchemaVersion {
+    #[cfg_attr(feature = "diesel", diesel(sql_type = diesel::sql_types::Text))]
+    pub schema_id: String,
+    #[cfg_attr(feature = "diesel", diesel(sql_type = diesel::sql_types::Text))]
+    pub branch: String,
+    #[cfg_attr(
+        feature = "diesel",
+        diesel(sql_type = diesel::sql_types::Nullable<diesel::sql_types::Text>)
+    )]
+    pub version: Option<String>,
+    #[cfg_attr(feature = "diesel", diesel(sql_type = diesel::sql_types::Text))]
+    pub applied_at: String, // RFC 3339
+}
+
+// The schema a build of the service was generated from
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct ExpectedSchema {
+    pub schema_id: String,
+    pub branch: String,
+    pub version: Option<String>, // Only the schema and branch are compared when unknown
+}
+
+impl ExpectedSchema {
+    pub fn new(schema_id: &str, branch: &str) -> Self {
+        ExpectedSchema {
+            schema_id: schema_id.to_string(),
+            branch: branch.to_string(),
+            version: None,
+        }
+    }
+
+    /// The `schema_id` and `branch` of the `[schema]` section of the consumer db config.
+    pub fn from_config(schema: &ConsumerDBSchema) -> Result<Self, Box<dyn Error>> {
+        let schema_id = schema
+            .schema_id
+            .as_deref()
+            .ok_or("The `schema_id` of the database is not configured")?;
+        let branch = schema
+            .branch
+            .as_deref()
+            .ok_or("The `branch` of the database schema is not configured")?;
+        Ok(Self::new(schema_id, branch))
+    }
+
+    pub fn with_version(mut self, version: &str) -> Self {
+        self.version = Some(version.to_string());
+        self
+    }
+
+    /// Takes the version of the branch from the schema registry.
+    #[cfg(feature = "client")]
+    pub async fn resolve_version(
+        mut self,
+        registry: &crate::schema_registry::SchemaRegistryClient,
+    ) -> Result<Self, Box<dyn Error>> {
+        self.version = registry
+            .fetch_schema(&self.schema_id, &self.branch)
+            .await?
+            .version;
+        Ok(self)
+    }
+
+    pub fn to_applied(&self) -> SchemaVersion {
+        SchemaVersion {
+            schema_id: self.schema_id.clone(),
+            branch: self.branch.clone(),
+            version: self.version.clone(),
+            applied_at: chrono::Utc::now().to_rfc3339(),
+        }
+    }
+}
+
+impl fmt::Display for ExpectedSchema {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}@{}", self.schema_id, self.branch)?;
+        if let Some(version) = &self.version {
+            write!(f, " ({})", version)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum VersionCheck {
+    UpToDate,
+    NeverMigrated,
+    WrongSchema { applied: String },
+    WrongBranch { applied: String },
+    Stale { applied: Option<String> },
+}
+
+impl VersionCheck {
+    pub fn is_up_to_date(&self) -> bool {
+        *self == VersionCheck::UpToDate
+    }
+}
+
+/// Compares the version recorded in the database with the one the service expects.
+pub fn check_version(applied: Option<&SchemaVersion>, expected: &ExpectedSchema) -> VersionCheck {
+    let applied = match applied {
+        Some(applied) => applied,
+        None => return VersionCheck::NeverMigrated,
+    };
+    if applied.schema_id != expected.schema_id {
+        return VersionCheck::WrongSchema {
+            applied: applied.schema_id.clone(),
+        };
+    }
+    if applied.branch != expected.branch {
+        return VersionCheck::WrongBranch {
+            applied: applied.branch.clone(),
+        };
+    }
+    match &expected.version {
+        Some(version) if applied.version.as_ref() != Some(version) => VersionCheck::Stale {
+            applied: applied.version.clone(),
+        },
+        _ => VersionCheck::UpToDate,
+    }
+}
+
+// What has to be run to bring a database to the expected schema. Services not using Diesel run
+// the commands of their ORM, e.g. in a release step.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct MigrationPlan {
+    pub orm: ORM,
+    pub expected: ExpectedSchema,
+    pub pending: Vec<String>, // Names of the pending migrations, when known
+    pub commands: Vec<String>,
+}
+
+impl MigrationPlan {
+    pub fn for_orm(orm: ORM, expected: &ExpectedSchema) -> Self {
+        let commands = match orm {
+            ORM::Diesel => vec!["diesel migration run"],
+            ORM::TypeORM => vec!["npx typeorm migration:run -d <data-source>"],
+            ORM::SQLAlchemy => vec!["alembic upgrade head"],
+            ORM::DjangoORM => vec!["python manage.py migrate"],
+        };
+        MigrationPlan {
+            orm,
+            expected: expected.clone(),
+            pending: vec![],
+            commands: commands.into_iter().map(str::to_string).collect(),
+        }
+    }
+}
+
+impl fmt::Display for MigrationPlan {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Migrate to {} with {}:", self.expected, self.orm)?;
+        for migration in &self.pending {
+            writeln!(f, "  pending: {}", migration)?;
+        }
+        for command in &self.commands {
+            writeln!(f, "  $ {}", command)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum MigrationMode {
+    Run,      // Applies the pending migrations and records the expected version
+    Validate, // Fails when migrations are pending or the recorded version differs
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct MigrationReport {
+    pub expected: ExpectedSchema,
+    pub applied: Vec<String>, // Migrations run by this report's invocation
+    pub previous: Option<SchemaVersion>,
+}
+
+#[cfg(feature = "diesel")]
+pub use self::diesel_migrations_runner::{applied_version, migrate, record_version};
+
+#[cfg(feature = "diesel")]
+mod diesel_migrations_runner {
+    use diesel::{
+        migration::MigrationSource,
+        query_builder::SqlQuery,
+        query_dsl::{methods::ExecuteDsl, LoadQuery},
+        sql_query, Connection, RunQueryDsl,
+    };
+    use diesel_migrations::MigrationHarness;
+    use std::error::Error;
+
+    use super::{
+        check_version, ExpectedSchema, MigrationMode, MigrationReport, SchemaVersion, VersionCheck,
+        VERSION_TABLE,
+    };
+
+    // Raw SQL has no portable placeholders, values are checked to be plain identifiers instead
+    fn literal(field: &str, value: &str) -> Result<String, Box<dyn Error>> {
+        if value.is_empty()
+            || !value
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "-_./+:".contains(c))
+        {
+            return Err(format!("The {} '{}' has unsupported characters", field, value).into());
+        }
+        Ok(format!("'{}'", value))
+    }
+
+    fn ensure_table<C>(conn: &mut C) -> Result<(), Box<dyn Error>>
+    where
+        C: Connection,
+        SqlQuery: ExecuteDsl<C>,
+    {
+        sql_query(format!(
+            "CREATE TABLE IF NOT EXISTS {} (\
+             schema_id VARCHAR(255) PRIMARY KEY, \
+             branch VARCHAR(255) NOT NULL, \
+             version VARCHAR(255), \
+             applied_at VARCHAR(64) NOT NULL)",
+            VERSION_TABLE
+        ))
+        .execute(conn)?;
+        Ok(())
+    }
+
+    /// The version recorded for `schema_id`, `None` for a database never migrated by ginger.
+    pub fn applied_version<C>(
+        conn: &mut C,
+        schema_id: &str,
+    ) -> Result<Option<SchemaVersion>, Box<dyn Error>>
+    where
+        C: Connection,
+        SqlQuery: ExecuteDsl<C> + LoadQuery<'static, C, SchemaVersion>,
+    {
+        ensure_table(conn)?;
+        let rows: Vec<SchemaVersion> = sql_query(format!(
+            "SELECT schema_id, branch, version, applied_at FROM {} WHERE schema_id = {}",
+            VERSION_TABLE,
+            literal("schema id", schema_id)?
+        ))
+        .load(conn)?;
+        Ok(rows.into_iter().next())
+    }
+
+    pub fn record_version<C>(conn: &mut C, version: &SchemaVersion) -> Result<(), Box<dyn Error>>
+    where
+        C: Connection,
+        SqlQuery: ExecuteDsl<C>,
+    {
+        ensure_table(conn)?;
+        let schema_id = literal("schema id", &version.schema_id)?;
+        let branch = literal("branch", &version.branch)?;
+        let value = match &version.version {
+            Some(value) => literal("version", value)?,
+            None => "NULL".to_string(),
+        };
+        let applied_at = literal("date", &version.applied_at)?;
+        conn.transaction(|conn| {
+            sql_query(format!(
+                "DELETE FROM {} WHERE schema_id = {}",
+                VERSION_TABLE, schema_id
+            ))
+            .execute(conn)?;
+            sql_query(format!(
+                "INSERT INTO {} (schema_id, branch, version, applied_at) VALUES ({}, {}, {}, {})",
+                VERSION_TABLE, schema_id, branch, value, applied_at
+            ))
+            .execute(conn)
+        })?;
+        Ok(())
+    }
+
+    /// Brings the database to `expected`: runs the pending migrations of `source` and records
+    /// the version, or with `MigrationMode::Validate` only fails when it is not there already.
+    pub fn migrate<C, S>(
+        conn: &mut C,
+        source: S,
+        expected: &ExpectedSchema,
+        mode: MigrationMode,
+    ) -> Result<MigrationReport, Box<dyn Error>>
+    where
+        C: Connection + MigrationHarness<C::Backend>,
+        S: MigrationSource<C::Backend>,
+        SqlQuery: ExecuteDsl<C> + LoadQuery<'static, C, SchemaVersion>,
+    {
+        let previous = applied_version(conn, &expected.schema_id)?;
+        let pending = conn
+            .pending_migrations(source)
+            .map_err(|e| format!("Failed to list the pending migrations: {}", e))?;
+        let pending_names: Vec<String> = pending.iter().map(|m| m.name().to_string()).collect();
+
+        let mut applied = vec![];
+        match mode {
+            MigrationMode::Validate => {
+                if !pending_names.is_empty() {
+                    return Err(format!(
+                        "The database is not migrated to {}, pending migrations: {}",
+                        expected,
+                        pending_names.join(", ")
+                    )
+                    .into());
+                }
+                let check = check_version(previous.as_ref(), expected);
+                if !check.is_up_to_date() {
+                    return Err(describe(&check, expected).into());
+                }
+            }
+            MigrationMode::Run => {
+                for migration in &pending {
+                    conn.run_migration(migration.as_ref())
+                        .map_err(|e| format!("The migration {} failed: {}", migration.name(), e))?;
+                    tracing::info!(migration = %migration.name(), "migration applied");
+                    applied.push(migration.name().to_string());
+                }
+                if !check_version(previous.as_ref(), expected).is_up_to_date() {
+                    record_version(conn, &expected.to_applied())?;
+                }
+            }
+        }
+
+        Ok(MigrationReport {
+            expected: expected.clone(),
+            applied,
+            previous,
+        })
+    }
+
+    fn describe(check: &VersionCheck, expected: &ExpectedSchema) -> String {
+        match check {
+            VersionCheck::UpToDate => format!("The database is migrated to {}", expected),
+            VersionCheck::NeverMigrated => {
+                format!("The database was never migrated, {} is expected", expected)
+            }
+            VersionCheck::WrongSchema { applied } => format!(
+                "The database holds the schema '{}', {} is expected",
+                applied, expected
+            ),
+            VersionCheck::WrongBranch { applied } => format!(
+                "The database was migrated from the branch '{}', {} is expected",
+                applied, expected
+            ),
+            VersionCheck::Stale { applied } => format!(
+                "The database is at version {}, {} is expected",
+                applied.as_deref().unwrap_or("unknown"),
+                expected
+            ),
+        }
+    }
+}
+
+#[cfg(all(feature = "diesel", feature = "rocket"))]
+pub use self::fairing::fairing;
+
+#[cfg(all(feature = "diesel", feature = "rocket"))]
+mod fairing {
+    use diesel::{
+        migration::MigrationSource,
+        query_builder::SqlQuery,
+        query_dsl::{methods::ExecuteDsl, LoadQuery},
+        r2d2::R2D2Connection,
+    };
+    use diesel_migrations::MigrationHarness;
+    use rocket::fairing::AdHoc;
+
+    use super::{migrate, ExpectedSchema, MigrationMode, SchemaVersion};
+    use crate::db_pool::DbPool;
+
+    /// Migrates (or validates) the database of the managed pool when Rocket ignites, so a service
+    /// never starts against a stale database. Attach it after `db_pool::fairing`.
+    pub fn fairing<C, S>(source: S, expected: ExpectedSchema, mode: MigrationMode) -> AdHoc
+    where
+        C: R2D2Connection + MigrationHarness<C::Backend> + 'static,
+        S: MigrationSource<C::Backend> + Send + Sync + 'static,
+        SqlQuery: ExecuteDsl<C> + LoadQuery<'static, C, SchemaVersion>,
+    {
+        AdHoc::try_on_ignite("Database migrations", move |rocket| async move {
+            let pool = match rocket.state::<DbPool<C>>() {
+                Some(pool) => pool.clone(),
+                None => {
+                    tracing::error!("no database pool is managed, attach `db_pool::fairing` first");
+                    return Err(rocket);
+                }
+            };
+            let result = rocket::tokio::task::spawn_blocking(move || {
+                let mut conn = pool.get().map_err(|e| e.to_string())?;
+                migrate(&mut *conn, source, &expected, mode).map_err(|e| e.to_string())
+            })
+            .await;
+            match result {
+                Ok(Ok(report)) => {
+                    tracing::info!(
+                        expected = %report.expected,
+                        applied = report.applied.len(),
+                        "database schema checked"
+                    );
+                    Ok(rocket)
+                }
+                Ok(Err(e)) => {
+                    tracing::error!(error = %e, "the database schema is not usable");
+                    Err(rocket)
+                }
+                Err(e) => {
+                    tracing::error!(error = %e, "the database migrations panicked");
+                    Err(rocket)
+                }
+            }
+        })
+    }
+}
@@ -0,0 +1,267 @@
+use rocket::{
+    fairing::AdHoc,
+    http::{Method, Status},
+    response::status::Custom,
+    route::{self, Handler, Route},
+    serde::json::Json,
+    Build, Config, Data, Request, Rocket, Shutdown,
+};
+use serde_json::{json, Map, Value};
+use std::{error::Error, net::TcpListener, sync::Arc};
+use tokio::sync::oneshot;
+
+use crate::{rocket_models::ApiError, spec::OpenApiSpec};
+
+// Deep enough for any real schema, stops recursive `$ref`s from looping forever
+const MAX_EXAMPLE_DEPTH: usize = 16;
+const METHODS: [Method; 7] = [
+    Method::Get,
+    Method::Put,
+    Method::Post,
+    Method::Delete,
+    Method::Options,
+    Method::Head,
+    Method::Patch,
+];
+
+/// Example value for a schema: the schema's own `example`, the first `enum` value or a value
+/// built from its type.
+pub fn example_for_schema(document: &Value, schema: &Value) -> Value {
+    example_at_depth(document, schema, 0)
+}
+
+fn example_at_depth(document: &Value, schema: &Value, depth: usize) -> Value {
+    if depth > MAX_EXAMPLE_DEPTH {
+        return Value::Null;
+    }
+    let schema = match schema.get("$ref").and_then(Value::as_str) {
+        Some(reference) => document
+            .pointer(reference.trim_start_matches('#'))
+            .unwrap_or(&Value::Null),
+        None => schema,
+    };
+
+    if let Some(example) = schema.get("example") {
+        return example.clone();
+    }
+    if let Some(value) = schema["enum"].as_array().and_then(|values| values.first()) {
+        return value.clone();
+    }
+    if let Some(value) = schema.get("default") {
+        return value.clone();
+    }
+    for combinator in ["allOf", "oneOf", "anyOf"] {
+        if let Some(variants) = schema[combinator].as_array() {
+            if combinator == "allOf" {
+                // Merge the properties of every part
+                let mut merged = Map::new();
+                for variant in variants {
+                    if let Value::Object(part) = example_at_depth(document, variant, depth + 1) {
+                        merged.extend(part);
+                    }
+                }
+                return Value::Object(merged);
+            }
+            if let Some(first) = variants.first() {
+                return example_at_depth(document, first, depth + 1);
+            }
+        }
+    }
+
+    let schema_type = schema["type"].as_str().or_else(|| {
+        if schema.get("properties").is_some() {
+            Some("object")
+        } else if schema.get("items").is_some() {
+            Some("array")
+        } else {
+            None
+        }
+    });
+
+    match schema_type {
+        Some("object") => {
+            let properties = schema["properties"]
+                .as_object()
+                .map(|properties| {
+                    properties
+                        .iter()
+                        .map(|(name, property)| {
+                            (
+                                name.clone(),
+                                example_at_depth(document, property, depth + 1),
+                            )
+                        })
+                        .collect()
+                })
+                .unwrap_or_default();
+            Value::Object(properties)
+        }
+        Some("array") => json!([example_at_depth(document, &schema["items"], depth + 1)]),
+        Some("integer") => json!(0),
+        Some("number") => json!(0.0),
+        Some("boolean") => json!(true),
+        Some("string") => match schema["format"].as_str() {
+            Some("date-time") => json!("2024-01-01T00:00:00Z"),
+            Some("date") => json!("2024-01-01"),
+            Some("uuid") => json!("00000000-0000-0000-0000-000000000000"),
+            Some("email") => json!("user@example.com"),
+            Some("uri") | Some("url") => json!("https://example.com"),
+            _ => json!("string"),
+        },
+        _ => Value::Null,
+    }
+}
+
+fn path_matches(template: &str, path: &str) -> bool {
+    let template: Vec<&str> = template.split('/').filter(|s| !s.is_empty()).collect();
+    let path: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+    template.len() == path.len()
+        && template
+            .iter()
+            .zip(&path)
+            .all(|(t, p)| (t.starts_with('{') && t.ends_with('}')) || t == p)
+}
+
+/// Status and body of the example response of an operation, the first 2xx response is used.
+pub fn example_response(spec: &OpenApiSpec, method: &str, path: &str) -> Option<(u16, Value)> {
+    let document = &spec.document;
+    let template = spec
+        .paths()
+        .into_iter()
+        .filter(|template| path_matches(template, path))
+        // Prefer literal segments over parameters, i.e. `/users/me` over `/users/{id}`
+        .min_by_key(|template| template.matches('{').count())?;
+    let operation = document["paths"][template].get(method.to_lowercase())?;
+    let responses = operation["responses"].as_object()?;
+
+    let mut statuses: Vec<&String> = responses.keys().collect();
+    statuses.sort();
+    let status = statuses
+        .iter()
+        .find(|status| status.starts_with('2'))
+        .or_else(|| statuses.iter().find(|status| status.as_str() == "default"))?;
+    let code = status.parse().unwrap_or(200);
+
+    let mut response = &responses[status.as_str()];
+    if let Some(reference) = response.get("$ref").and_then(Value::as_str) {
+        response = document
+            .pointer(reference.trim_start_matches('#'))
+            .unwrap_or(&Value::Null);
+    }
+    let content = &response["content"];
+    let media = match content
+        .get("application/json")
+        .or_else(|| content.as_object().and_then(|c| c.values().next()))
+    {
+        Some(media) => media,
+        None => return Some((code, Value::Null)),
+    };
+
+    let body = if let Some(example) = media.get("example") {
+        example.clone()
+    } else if let Some(example) = media["examples"]
+        .as_object()
+        .and_then(|examples| examples.values().next())
+    {
+        example["value"].clone()
+    } else {
+        example_for_schema(document, &media["schema"])
+    };
+    Some((code, body))
+}
+
+#[derive(Clone)]
+struct MockHandler(Arc<OpenApiSpec>);
+
+#[rocket::async_trait]
+impl Handler for MockHandler {
+    async fn handle<'r>(&self, request: &'r Request<'_>, _data: Data<'r>) -> route::Outcome<'r> {
+        let path = request.uri().path().to_string();
+        match example_response(&self.0, request.method().as_str(), &path) {
+            Some((code, body)) => {
+                let status = Status::from_code(code).unwrap_or(Status::Ok);
+                route::Outcome::from(request, Custom(status, Json(body)))
+            }
+            None => route::Outcome::from(
+                request,
+                ApiError::not_found(&format!(
+                    "The spec has no operation for {} {}",
+                    request.method(),
+                    path
+                )),
+            ),
+        }
+    }
+}
+
+/// A Rocket answering every operation of `spec` with its example response. Use it with
+/// `rocket::local` clients or launch it with `MockServer::launch`.
+pub fn mock_rocket(spec: OpenApiSpec) -> Rocket<Build> {
+    let handler = MockHandler(Arc::new(spec));
+    let routes: Vec<Route> = METHODS
+        .iter()
+        .map(|method| Route::new(*method, "/<path..>", handler.clone()))
+        .collect();
+    rocket::build().mount("/", routes)
+}
+
+// A mock server listening on a free local port, stopped when dropped
+pub struct MockServer {
+    pub base_url: String,
+    shutdown: Shutdown,
+}
+
+impl MockServer {
+    pub async fn launch(spec: OpenApiSpec) -> Result<Self, Box<dyn Error>> {
+        let port = TcpListener::bind("127.0.0.1:0")?.local_addr()?.port();
+        let config = Config {
+            port,
+            address: [127, 0, 0, 1].into(),
+            log_level: rocket::config::LogLevel::Off,
+            ..Config::debug_default()
+        };
+
+        let (ready, launched) = oneshot::channel();
+        let ready = std::sync::Mutex::new(Some(ready));
+        let rocket = mock_rocket(spec)
+            .configure(config)
+            .attach(AdHoc::on_liftoff("Mock server ready", move |_| {
+                Box::pin(async move {
+                    if let Some(ready) = ready.lock().unwrap().take() {
+                        let _ = ready.send(());
+                    }
+                })
+            }))
+            .ignite()
+            .await?;
+        let shutdown = rocket.shutdown();
+
+        tokio::spawn(async move {
+            if let Err(e) = rocket.launch().await {
+                tracing::warn!(error = %e, "mock server failed");
+            }
+        });
+        launched
+            .await
+            .map_err(|_| "The mock server stopped before it was ready")?;
+
+        Ok(MockServer {
+            base_url: format!("http://127.0.0.1:{}", port),
+            shutdown,
+        })
+    }
+
+    pub fn url(&self, path: &str) -> String {
+        format!("{}/{}", self.base_url, path.trim_start_matches('/'))
+    }
+
+    pub fn stop(&self) {
+        self.shutdown.clone().notify();
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
@@ -0,0 +1,267 @@
+use serde::{Deserialize, Serialize};
+use std::{collections::HashSet, error::Error, fs, path::Path};
+
+use crate::schema::FieldSchema;
+
+fn default_true() -> bool {
+    true
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ExchangeKind {
+    Direct,
+    Topic,
+    Fanout,
+    Headers,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct ExchangeSchema {
+    pub name: String,
+    pub kind: ExchangeKind,
+    #[serde(default = "default_true")]
+    pub durable: bool,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct QueueSchema {
+    pub name: String,
+    #[serde(default = "default_true")]
+    pub durable: bool,
+    pub dead_letter_exchange: Option<String>,
+    pub message_ttl_ms: Option<u64>,
+    pub max_length: Option<u64>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct BindingSchema {
+    pub exchange: String,
+    pub queue: String,
+    #[serde(default)]
+    pub routing_key: String, // Pattern for topic exchanges, ignored by fanout exchanges
+}
+
+// A message published on `exchange` with `routing_key`
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct MessageTypeSchema {
+    pub name: String,
+    pub exchange: String,
+    #[serde(default)]
+    pub routing_key: String,
+    #[serde(default)]
+    pub fields: Vec<FieldSchema>,
+}
+
+// What a consumer needs to declare and read its queue
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct QueueConsumerConfig {
+    pub queue: QueueSchema,
+    pub bindings: Vec<BindingSchema>,
+    pub message_types: Vec<String>, // Message types the bindings route to the queue
+}
+
+// The schema behind `ConsumerDBSchema::message_queue_schema_id`, shared by the MQ code
+// generators and the docs
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct MessageQueueSchema {
+    pub schema_id: String,
+    pub branch: String,
+    pub version: Option<String>,
+    #[serde(default)]
+    pub exchanges: Vec<ExchangeSchema>,
+    #[serde(default)]
+    pub queues: Vec<QueueSchema>,
+    #[serde(default)]
+    pub bindings: Vec<BindingSchema>,
+    #[serde(default)]
+    pub message_types: Vec<MessageTypeSchema>,
+}
+
+/// Whether a binding `pattern` routes `routing_key` with AMQP topic semantics: `*` matches
+/// exactly one word, `#` zero or more.
+pub fn routing_key_matches(pattern: &str, routing_key: &str) -> bool {
+    fn matches(pattern: &[&str], key: &[&str]) -> bool {
+        match pattern.split_first() {
+            None => key.is_empty(),
+            Some((&"#", rest)) => (0..=key.len()).any(|skip| matches(rest, &key[skip..])),
+            Some((word, rest)) => match key.split_first() {
+                Some((first, key_rest)) => {
+                    (*word == "*" || word == first) && matches(rest, key_rest)
+                }
+                None => false,
+            },
+        }
+    }
+    let pattern: Vec<&str> = pattern.split('.').collect();
+    let key: Vec<&str> = routing_key.split('.').collect();
+    matches(&pattern, &key)
+}
+
+impl MessageQueueSchema {
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error>> {
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path).map_err(|e| {
+            format!(
+                "Failed to read the message queue schema '{}': {}",
+                path.display(),
+                e
+            )
+        })?;
+        Ok(serde_json::from_str(&contents)?)
+    }
+
+    pub fn exchange(&self, name: &str) -> Option<&ExchangeSchema> {
+        self.exchanges.iter().find(|e| e.name == name)
+    }
+
+    pub fn queue(&self, name: &str) -> Option<&QueueSchema> {
+        self.queues.iter().find(|q| q.name == name)
+    }
+
+    pub fn message_type(&self, name: &str) -> Option<&MessageTypeSchema> {
+        self.message_types.iter().find(|m| m.name == name)
+    }
+
+    fn binding_routes(&self, binding: &BindingSchema, message: &MessageTypeSchema) -> bool {
+        binding.exchange == message.exchange && self.binding_matches(binding, &message.routing_key)
+    }
+
+    /// Whether `binding` routes a message sent with `routing_key` on its exchange.
+    pub fn binding_matches(&self, binding: &BindingSchema, routing_key: &str) -> bool {
+        match self.exchange(&binding.exchange).map(|e| e.kind) {
+            Some(ExchangeKind::Fanout | ExchangeKind::Headers) => true,
+            Some(ExchangeKind::Topic) => routing_key_matches(&binding.routing_key, routing_key),
+            Some(ExchangeKind::Direct) => binding.routing_key == routing_key,
+            None => false,
+        }
+    }
+
+    /// Queues a message type ends up in.
+    pub fn queues_for(&self, message_type: &str) -> Vec<&QueueSchema> {
+        let message = match self.message_type(message_type) {
+            Some(message) => message,
+            None => return vec![],
+        };
+        let mut queues: Vec<&QueueSchema> = vec![];
+        for binding in &self.bindings {
+            if self.binding_routes(binding, message) {
+                if let Some(queue) = self.queue(&binding.queue) {
+                    if !queues.iter().any(|q| q.name == queue.name) {
+                        queues.push(queue);
+                    }
+                }
+            }
+        }
+        queues
+    }
+
+    pub fn consumer_config(&self, queue: &str) -> Result<QueueConsumerConfig, Box<dyn Error>> {
+        let schema = self.queue(queue).ok_or_else(|| {
+            format!(
+                "The queue '{}' is not part of the message queue schema '{}'",
+                queue, self.schema_id
+            )
+        })?;
+        let bindings: Vec<BindingSchema> = self
+            .bindings
+            .iter()
+            .filter(|b| b.queue == queue)
+            .cloned()
+            .collect();
+        let message_types = self
+            .message_types
+            .iter()
+            .filter(|m| bindings.iter().any(|b| self.binding_routes(b, m)))
+            .map(|m| m.name.clone())
+            .collect();
+        Ok(QueueConsumerConfig {
+            queue: schema.clone(),
+            bindings,
+            message_types,
+        })
+    }
+
+    /// Problems with the schema: duplicate names, references to unknown exchanges or queues,
+    /// invalid routing patterns and message types no queue receives.
+    pub fn validate(&self) -> Vec<String> {
+        let mut problems = vec![];
+        for (kind, names) in [
+            ("exchange", self.exchanges.iter().map(|e| &e.name).collect()),
+            ("queue", self.queues.iter().map(|q| &q.name).collect()),
+            (
+                "message type",
+                self.message_types
+                    .iter()
+                    .map(|m| &m.name)
+                    .collect::<Vec<_>>(),
+            ),
+        ] {
+            let mut seen = HashSet::new();
+            for name in names {
+                if name.trim().is_empty() {
+                    problems.push(format!("A {} has an empty name", kind));
+                } else if !seen.insert(name) {
+                    problems.push(format!("The {} '{}' is defined more than once", kind, name));
+                }
+            }
+        }
+
+        for queue in &self.queues {
+            if let Some(exchange) = &queue.dead_letter_exchange {
+                if self.exchange(exchange).is_none() {
+                    problems.push(format!(
+                        "The queue '{}' dead-letters to the unknown exchange '{}'",
+                        queue.name, exchange
+                    ));
+                }
+            }
+        }
+
+        for binding in &self.bindings {
+            if self.queue(&binding.queue).is_none() {
+                problems.push(format!(
+                    "A binding refers to the unknown queue '{}'",
+                    binding.queue
+                ));
+            }
+            match self.exchange(&binding.exchange) {
+                None => problems.push(format!(
+                    "The binding of '{}' refers to the unknown exchange '{}'",
+                    binding.queue, binding.exchange
+                )),
+                Some(exchange) if exchange.kind == ExchangeKind::Topic => {
+                    if binding.routing_key.split('.').any(|word| {
+                        word.is_empty() || (word.len() > 1 && word.contains(['*', '#']))
+                    }) {
+                        problems.push(format!(
+                            "The binding of '{}' has the invalid topic pattern '{}'",
+                            binding.queue, binding.routing_key
+                        ));
+                    }
+                }
+                Some(_) => {}
+            }
+        }
+
+        for message in &self.message_types {
+            if self.exchange(&message.exchange).is_none() {
+                problems.push(format!(
+                    "The message type '{}' is published on the unknown exchange '{}'",
+                    message.name, message.exchange
+                ));
+            } else if message.routing_key.contains(['*', '#']) {
+                problems.push(format!(
+                    "The message type '{}' has wildcards in its routing key '{}'",
+                    message.name, message.routing_key
+                ));
+            } else if self.queues_for(&message.name).is_empty() {
+                problems.push(format!(
+                    "The message type '{}' is not routed to any queue",
+                    message.name
+                ));
+            }
+        }
+        problems
+    }
+}
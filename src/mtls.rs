@@ -0,0 +1,91 @@
+use rocket::{
+    http::Status,
+    mtls::{
+        x509::{GeneralName, ParsedExtension},
+        Certificate,
+    },
+    request::{FromRequest, Outcome, Request},
+};
+use serde::{Deserialize, Serialize};
+
+// Identity of the calling service, taken from the client certificate presented during the TLS
+// handshake. Used for intra-cluster calls in the K8 environments as an alternative to ISC JWTs.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct ServiceIdentity {
+    pub common_name: Option<String>,
+    pub dns_names: Vec<String>,
+    pub uris: Vec<String>,
+    pub serial: String,
+    pub issuer: Option<String>,
+}
+
+impl ServiceIdentity {
+    pub fn from_certificate(cert: &Certificate<'_>) -> Self {
+        let mut dns_names = vec![];
+        let mut uris = vec![];
+
+        for extension in cert.extensions() {
+            if let ParsedExtension::SubjectAlternativeName(san) = extension.parsed_extension() {
+                for name in &san.general_names {
+                    match name {
+                        GeneralName::DNSName(dns) => dns_names.push(dns.to_string()),
+                        GeneralName::URI(uri) => uris.push(uri.to_string()),
+                        _ => {}
+                    }
+                }
+            }
+        }
+
+        ServiceIdentity {
+            common_name: cert.subject().common_name().map(str::to_string),
+            dns_names,
+            uris,
+            serial: cert.serial().to_string(),
+            issuer: cert.issuer().common_name().map(str::to_string),
+        }
+    }
+
+    /// Name of the calling service: the common name, or else the first label of the first DNS
+    /// SAN (e.g. `billing` for `billing.prod.svc.cluster.local`).
+    pub fn service_name(&self) -> Option<&str> {
+        self.common_name
+            .as_deref()
+            .or_else(|| self.dns_names.first().and_then(|dns| dns.split('.').next()))
+    }
+
+    pub fn matches(&self, name: &str) -> bool {
+        self.common_name.as_deref() == Some(name)
+            || self.dns_names.iter().any(|dns| dns == name)
+            || self.uris.iter().any(|uri| uri == name)
+    }
+}
+
+#[derive(Debug)]
+pub enum ServiceIdentityError {
+    MissingCertificate,
+    Unidentified,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ServiceIdentity {
+    type Error = ServiceIdentityError;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let cert = match request.guard::<Certificate<'r>>().await {
+            Outcome::Success(cert) => cert,
+            _ => {
+                return Outcome::Error((
+                    Status::Unauthorized,
+                    ServiceIdentityError::MissingCertificate,
+                ))
+            }
+        };
+
+        let identity = ServiceIdentity::from_certificate(&cert);
+        if identity.service_name().is_none() && identity.uris.is_empty() {
+            return Outcome::Error((Status::Forbidden, ServiceIdentityError::Unidentified));
+        }
+
+        Outcome::Success(identity)
+    }
+}
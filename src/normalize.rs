@@ -0,0 +1,229 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+use crate::{
+    ConsumerDBConfig, GingerDBConfig, PackageMetadata, ReleaserConfig, ServiceConfig, LANG, ORM,
+};
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum NormalizeKind {
+    Inferred,
+    Trimmed,
+    Lowercased,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct NormalizedField {
+    pub key: String,
+    pub kind: NormalizeKind,
+    pub from: Option<String>, // `None` when the value was missing
+    pub to: String,
+}
+
+impl fmt::Display for NormalizedField {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let action = match self.kind {
+            NormalizeKind::Inferred => "inferred as",
+            NormalizeKind::Trimmed => "trimmed to",
+            NormalizeKind::Lowercased => "lowercased to",
+        };
+        write!(f, "{} {} '{}'", self.key, action, self.to)
+    }
+}
+
+// What a `normalize()` call changed, empty when the config was already normalized
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Default)]
+pub struct NormalizeReport {
+    pub changes: Vec<NormalizedField>,
+}
+
+impl NormalizeReport {
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    pub fn inferred(&self) -> Vec<&NormalizedField> {
+        self.changes
+            .iter()
+            .filter(|change| change.kind == NormalizeKind::Inferred)
+            .collect()
+    }
+
+    /// Logs every change at debug level, used by the `read_*` functions.
+    pub(crate) fn log(&self, config: &str) {
+        for change in &self.changes {
+            tracing::debug!(config, "{}", change);
+        }
+    }
+
+    fn trim(&mut self, key: &str, value: &mut String) {
+        let trimmed = value.trim();
+        if trimmed.len() != value.len() {
+            let trimmed = trimmed.to_string();
+            self.record(key, NormalizeKind::Trimmed, Some(value), &trimmed);
+            *value = trimmed;
+        }
+    }
+
+    fn trim_option(&mut self, key: &str, value: &mut Option<String>) {
+        if let Some(value) = value {
+            self.trim(key, value);
+        }
+    }
+
+    fn lowercase(&mut self, key: &str, value: &mut String) {
+        self.trim(key, value);
+        let lowercased = value.to_lowercase();
+        if lowercased != *value {
+            self.record(key, NormalizeKind::Lowercased, Some(value), &lowercased);
+            *value = lowercased;
+        }
+    }
+
+    fn infer(&mut self, key: &str, value: &str) {
+        self.record(key, NormalizeKind::Inferred, None, value);
+    }
+
+    fn record(&mut self, key: &str, kind: NormalizeKind, from: Option<&str>, to: &str) {
+        self.changes.push(NormalizedField {
+            key: key.to_string(),
+            kind,
+            from: from.map(str::to_string),
+            to: to.to_string(),
+        });
+    }
+}
+
+impl ORM {
+    /// Directory the models of `lang` are generated in, `None` for ORMs of another language.
+    pub fn default_root(&self, lang: &LANG) -> Option<&'static str> {
+        match (lang, self) {
+            (LANG::Rust, ORM::Diesel) => Some("src"),
+            (LANG::TS, ORM::TypeORM) => Some("src"),
+            (LANG::Python, ORM::SQLAlchemy) => Some("src"),
+            (LANG::Python, ORM::DjangoORM) => Some("."),
+            _ => None,
+        }
+    }
+}
+
+impl ServiceConfig {
+    /// Trims the string fields and lowercases the organization id.
+    pub fn normalize(&mut self) -> NormalizeReport {
+        let mut report = NormalizeReport::default();
+        report.lowercase("organization_id", &mut self.organization_id);
+        report.trim_option("dir", &mut self.dir);
+        report.trim_option("refs_file", &mut self.refs_file);
+        report.trim_option("spec_url", &mut self.spec_url);
+        report.trim_option("override_name", &mut self.override_name);
+        report.trim_option("service_type", &mut self.service_type);
+
+        for (table, urls) in [("urls", &mut self.urls), ("urls_ws", &mut self.urls_ws)] {
+            for (env, url) in urls.iter_mut().flatten() {
+                report.trim(&format!("{}.{}", table, env), url);
+            }
+        }
+        for (table, refs) in [
+            ("portals_refs", &mut self.portals_refs),
+            ("ws_refs", &mut self.ws_refs),
+        ] {
+            for (name, urls) in refs.iter_mut().flatten() {
+                for (env, url) in urls.iter_mut() {
+                    report.trim(&format!("{}.{}.{}", table, name, env), url);
+                }
+            }
+        }
+        report
+    }
+}
+
+impl GingerDBConfig {
+    /// Trims the string fields, lowercases the organization id and sets a missing
+    /// `studio_port` to `port + 1`.
+    pub fn normalize(&mut self) -> NormalizeReport {
+        let mut report = NormalizeReport::default();
+        report.trim("branch", &mut self.branch);
+        report.lowercase("organization_id", &mut self.organization_id);
+
+        for db in &mut self.database {
+            let prefix = format!("database.{}", db.name.trim());
+            report.trim(&format!("{}.name", prefix), &mut db.name);
+            report.trim(&format!("{}.port", prefix), &mut db.port);
+            report.trim_option(&format!("{}.studio_port", prefix), &mut db.studio_port);
+            report.trim_option(&format!("{}.id", prefix), &mut db.id);
+
+            if db.studio_port.is_none() {
+                // Left alone when the port is invalid, the port checks report it
+                if let Some(studio_port) =
+                    db.port.parse::<u16>().ok().and_then(|p| p.checked_add(1))
+                {
+                    let studio_port = studio_port.to_string();
+                    report.infer(&format!("{}.studio_port", prefix), &studio_port);
+                    db.studio_port = Some(studio_port);
+                }
+            }
+        }
+        report
+    }
+}
+
+impl ConsumerDBConfig {
+    /// Trims the string fields and fills an empty `root` from the language and ORM.
+    pub fn normalize(&mut self) -> NormalizeReport {
+        let mut report = NormalizeReport::default();
+        let schema = &mut self.schema;
+        report.trim("schema.url", &mut schema.url);
+        report.trim("schema.root", &mut schema.root);
+        report.trim_option("schema.schema_id", &mut schema.schema_id);
+        report.trim_option("schema.cache_schema_id", &mut schema.cache_schema_id);
+        report.trim_option(
+            "schema.message_queue_schema_id",
+            &mut schema.message_queue_schema_id,
+        );
+        report.trim_option("schema.branch", &mut schema.branch);
+
+        if schema.root.is_empty() {
+            if let Some(root) = schema.orm.default_root(&schema.lang) {
+                report.infer("schema.root", root);
+                schema.root = root.to_string();
+            }
+        }
+
+        for (index, name) in self.tables.names.iter_mut().enumerate() {
+            report.trim(&format!("tables.names[{}]", index), name);
+        }
+        report
+    }
+}
+
+impl ReleaserConfig {
+    pub fn normalize(&mut self) -> NormalizeReport {
+        let mut report = NormalizeReport::default();
+        report.trim_option("settings.git_url_prefix", &mut self.settings.git_url_prefix);
+        for (index, reference) in self.references.iter_mut().enumerate() {
+            report.trim(
+                &format!("references[{}].file_name", index),
+                &mut reference.file_name,
+            );
+            report.trim(
+                &format!("references[{}].variable", index),
+                &mut reference.variable,
+            );
+        }
+        report
+    }
+}
+
+impl PackageMetadata {
+    pub fn normalize(&mut self) -> NormalizeReport {
+        let mut report = NormalizeReport::default();
+        report.trim("package_type", &mut self.package_type);
+        for (index, link) in self.links.iter_mut().enumerate() {
+            report.trim(&format!("links[{}].label", index), &mut link.label);
+            report.trim(&format!("links[{}].icon", index), &mut link.icon);
+            report.trim(&format!("links[{}].link", index), &mut link.link);
+        }
+        report
+    }
+}
@@ -0,0 +1,469 @@
+use data_encoding::BASE64URL_NOPAD;
+use jsonwebtoken::{
+    decode, decode_header,
+    jwk::{AlgorithmParameters, EllipticCurve, Jwk, JwkSet},
+    Algorithm, DecodingKey, Validation,
+};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    error::Error,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use url::Url;
+
+use crate::{
+    cache::{Cache, LruCache},
+    claims::Claims,
+};
+
+const JWKS_TTL: Duration = Duration::from_secs(60 * 60);
+// Shortest time between two fetches of the keys for tokens naming an unknown key, so callers
+// cannot make the service hit the provider on every login attempt
+const JWKS_MIN_REFETCH: Duration = Duration::from_secs(60);
+
+fn random_token() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill(&mut bytes);
+    BASE64URL_NOPAD.encode(&bytes)
+}
+
+// The endpoints of an identity provider. OpenID Connect providers have a `jwks_url` and return
+// an ID token, the others are asked for the user through `userinfo_url`.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct OAuthProvider {
+    pub name: String,
+    pub authorize_url: String,
+    pub token_url: String,
+    pub userinfo_url: Option<String>,
+    pub jwks_url: Option<String>,
+    pub issuers: Vec<String>, // Accepted `iss` of the ID tokens
+    pub scopes: Vec<String>,
+}
+
+impl OAuthProvider {
+    pub fn google() -> Self {
+        OAuthProvider {
+            name: "google".to_string(),
+            authorize_url: "https://accounts.google.com/o/oauth2/v2/auth".to_string(),
+            token_url: "https://oauth2.googleapis.com/token".to_string(),
+            userinfo_url: None,
+            jwks_url: Some("https://www.googleapis.com/oauth2/v3/certs".to_string()),
+            issuers: vec![
+                "https://accounts.google.com".to_string(),
+                "accounts.google.com".to_string(),
+            ],
+            scopes: vec![
+                "openid".to_string(),
+                "email".to_string(),
+                "profile".to_string(),
+            ],
+        }
+    }
+
+    /// GitHub is plain OAuth2: the user is read from its API.
+    pub fn github() -> Self {
+        OAuthProvider {
+            name: "github".to_string(),
+            authorize_url: "https://github.com/login/oauth/authorize".to_string(),
+            token_url: "https://github.com/login/oauth/access_token".to_string(),
+            userinfo_url: Some("https://api.github.com/user".to_string()),
+            jwks_url: None,
+            issuers: vec![],
+            scopes: vec!["read:user".to_string(), "user:email".to_string()],
+        }
+    }
+
+    pub fn is_oidc(&self) -> bool {
+        self.jwks_url.is_some()
+    }
+}
+
+// What a login has to remember until the provider redirects back, e.g. in the session: the
+// `state` to compare, the PKCE verifier and the OIDC nonce
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct PendingLogin {
+    pub state: String,
+    pub pkce_verifier: String,
+    pub nonce: String,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct TokenResponse {
+    pub access_token: String,
+    pub token_type: String,
+    pub expires_in: Option<u64>,
+    pub refresh_token: Option<String>,
+    pub id_token: Option<String>,
+    pub scope: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct IdTokenClaims {
+    pub sub: String,
+    pub iss: String,
+    pub exp: usize,
+    pub nonce: Option<String>,
+    pub email: Option<String>,
+    #[serde(default)]
+    pub email_verified: bool,
+    pub name: Option<String>,
+    pub given_name: Option<String>,
+    pub family_name: Option<String>,
+}
+
+// A user authenticated by a provider
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct ExternalIdentity {
+    pub provider: String,
+    pub subject: String, // Stable id of the user at the provider
+    pub email: Option<String>,
+    pub email_verified: bool,
+    pub first_name: Option<String>,
+    pub last_name: Option<String>,
+}
+
+impl ExternalIdentity {
+    /// Access token claims for the local account `user_id` linked to this identity. The
+    /// provider and its subject are kept as the `idp` and `idp_sub` claims. The subject is the
+    /// email only when the provider verified it, `provider:subject` otherwise.
+    pub fn to_claims(&self, user_id: &str, valid_for: Duration) -> Claims {
+        let sub = match (&self.email, self.email_verified) {
+            (Some(email), true) => email.clone(),
+            _ => format!("{}:{}", self.provider, self.subject),
+        };
+        let mut claims = Claims::new(&sub, user_id, "access", valid_for)
+            .with_extra("idp", self.provider.clone().into())
+            .with_extra("idp_sub", self.subject.clone().into());
+        claims.first_name = self.first_name.clone();
+        claims.last_name = self.last_name.clone();
+        claims
+    }
+}
+
+// The signing keys of a provider, fetched again when they are older than an hour or a token
+// names an unknown key, as providers rotate them, at most once a minute for the latter
+struct JwksCache {
+    url: String,
+    keys: LruCache<String, JwkSet>,
+    refetched_at: Mutex<Option<Instant>>,
+}
+
+impl JwksCache {
+    fn new(url: &str) -> Self {
+        JwksCache {
+            url: url.to_string(),
+            keys: LruCache::new(1),
+            refetched_at: Mutex::new(None),
+        }
+    }
+
+    // Takes the refetch slot, unless it was taken less than `JWKS_MIN_REFETCH` ago
+    fn may_refetch(&self) -> bool {
+        let mut refetched_at = self.refetched_at.lock().unwrap();
+        if refetched_at.is_some_and(|at| at.elapsed() < JWKS_MIN_REFETCH) {
+            return false;
+        }
+        *refetched_at = Some(Instant::now());
+        true
+    }
+
+    async fn fetch(&self, http: &reqwest::Client) -> Result<JwkSet, String> {
+        let failed =
+            |e: reqwest::Error| format!("Failed to fetch the keys of '{}': {}", self.url, e);
+        http.get(&self.url)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(failed)?
+            .json()
+            .await
+            .map_err(failed)
+    }
+
+    /// The key `kid` and the algorithm it signs with.
+    async fn key(
+        &self,
+        http: &reqwest::Client,
+        kid: &str,
+    ) -> Result<(DecodingKey, Algorithm), Box<dyn Error>> {
+        let keys = self
+            .keys
+            .get_or_compute(self.url.clone(), JWKS_TTL, || self.fetch(http))
+            .await?;
+        let unknown = || format!("No key '{}' at '{}'", kid, self.url);
+        let jwk = match keys.find(kid) {
+            Some(jwk) => jwk.clone(),
+            None if !self.may_refetch() => return Err(unknown().into()),
+            None => {
+                let keys = self.fetch(http).await?;
+                self.keys
+                    .set(self.url.clone(), keys.clone(), JWKS_TTL)
+                    .await;
+                keys.find(kid).cloned().ok_or_else(unknown)?
+            }
+        };
+        Ok((DecodingKey::from_jwk(&jwk)?, key_algorithm(&jwk)?))
+    }
+}
+
+// The algorithm of a key, from its `alg` or else its type, so that a token cannot pick another
+// one than the key was made for
+fn key_algorithm(jwk: &Jwk) -> Result<Algorithm, Box<dyn Error>> {
+    if let Some(algorithm) = jwk.common.key_algorithm {
+        return algorithm
+            .to_string()
+            .parse()
+            .map_err(|_| format!("The key algorithm {} does not sign tokens", algorithm).into());
+    }
+    match &jwk.algorithm {
+        AlgorithmParameters::RSA(_) => Ok(Algorithm::RS256),
+        AlgorithmParameters::EllipticCurve(params) => match params.curve {
+            EllipticCurve::P256 => Ok(Algorithm::ES256),
+            EllipticCurve::P384 => Ok(Algorithm::ES384),
+            _ => Err(format!("Unsupported curve {:?}", params.curve).into()),
+        },
+        AlgorithmParameters::OctetKeyPair(_) => Ok(Algorithm::EdDSA),
+        AlgorithmParameters::OctetKey(_) => Err("Symmetric keys cannot verify ID tokens".into()),
+    }
+}
+
+// Authorization code flow with PKCE against one provider:
+//
+// let (url, pending) = client.authorize_url()?;   // redirect to `url`, keep `pending`
+// let identity = client.login(&pending, &state, &code).await?;
+pub struct OAuthClient {
+    provider: OAuthProvider,
+    client_id: String,
+    client_secret: String,
+    redirect_uri: String,
+    http: reqwest::Client,
+    jwks: Option<JwksCache>,
+}
+
+impl OAuthClient {
+    pub fn new(
+        provider: OAuthProvider,
+        client_id: &str,
+        client_secret: &str,
+        redirect_uri: &str,
+    ) -> Self {
+        let jwks = provider.jwks_url.as_deref().map(JwksCache::new);
+        OAuthClient {
+            provider,
+            client_id: client_id.to_string(),
+            client_secret: client_secret.to_string(),
+            redirect_uri: redirect_uri.to_string(),
+            http: reqwest::Client::builder()
+                .timeout(Duration::from_secs(10))
+                .user_agent("ginger-society")
+                .build()
+                .unwrap_or_default(),
+            jwks,
+        }
+    }
+
+    pub fn provider(&self) -> &OAuthProvider {
+        &self.provider
+    }
+
+    /// The URL to redirect the browser to, and the values to keep until it comes back.
+    pub fn authorize_url(&self) -> Result<(String, PendingLogin), Box<dyn Error>> {
+        let pending = PendingLogin {
+            state: random_token(),
+            pkce_verifier: random_token(),
+            nonce: random_token(),
+        };
+        let challenge = BASE64URL_NOPAD.encode(&Sha256::digest(pending.pkce_verifier.as_bytes()));
+        let mut url = Url::parse(&self.provider.authorize_url)
+            .map_err(|e| format!("Invalid authorize URL: {}", e))?;
+        url.query_pairs_mut()
+            .append_pair("response_type", "code")
+            .append_pair("client_id", &self.client_id)
+            .append_pair("redirect_uri", &self.redirect_uri)
+            .append_pair("scope", &self.provider.scopes.join(" "))
+            .append_pair("state", &pending.state)
+            .append_pair("code_challenge", &challenge)
+            .append_pair("code_challenge_method", "S256");
+        if self.provider.is_oidc() {
+            url.query_pairs_mut().append_pair("nonce", &pending.nonce);
+        }
+        Ok((url.to_string(), pending))
+    }
+
+    pub async fn exchange_code(
+        &self,
+        code: &str,
+        pkce_verifier: &str,
+    ) -> Result<TokenResponse, Box<dyn Error>> {
+        let response = self
+            .http
+            .post(&self.provider.token_url)
+            .header("Accept", "application/json")
+            .form(&[
+                ("grant_type", "authorization_code"),
+                ("code", code),
+                ("redirect_uri", &self.redirect_uri),
+                ("client_id", &self.client_id),
+                ("client_secret", &self.client_secret),
+                ("code_verifier", pkce_verifier),
+            ])
+            .send()
+            .await?;
+        let status = response.status();
+        let body: serde_json::Value = response.json().await?;
+        // GitHub answers errors with a 200 and an `error` field
+        if !status.is_success() || body.get("error").is_some() {
+            let reason = body
+                .get("error_description")
+                .or_else(|| body.get("error"))
+                .and_then(|reason| reason.as_str())
+                .map(str::to_string)
+                .unwrap_or_else(|| body.to_string());
+            return Err(format!(
+                "{} refused the authorization code: {}",
+                self.provider.name, reason
+            )
+            .into());
+        }
+        Ok(serde_json::from_value(body)?)
+    }
+
+    /// Checks the signature against the keys of the provider, the audience, the issuer and the
+    /// nonce of the login.
+    pub async fn validate_id_token(
+        &self,
+        id_token: &str,
+        nonce: &str,
+    ) -> Result<IdTokenClaims, Box<dyn Error>> {
+        let jwks = self
+            .jwks
+            .as_ref()
+            .ok_or_else(|| format!("{} does not issue ID tokens", self.provider.name))?;
+        let header = decode_header(id_token)?;
+        let kid = header.kid.ok_or("The ID token names no signing key")?;
+        let (key, algorithm) = jwks.key(&self.http, &kid).await?;
+        if header.alg != algorithm {
+            return Err(format!(
+                "The ID token is signed with {:?}, its key with {:?}",
+                header.alg, algorithm
+            )
+            .into());
+        }
+
+        let mut validation = Validation::new(algorithm);
+        validation.set_audience(&[&self.client_id]);
+        validation.set_issuer(&self.provider.issuers);
+        let claims = decode::<IdTokenClaims>(id_token, &key, &validation)?.claims;
+        if claims.nonce.as_deref() != Some(nonce) {
+            return Err("The ID token was not issued for this login".into());
+        }
+        Ok(claims)
+    }
+
+    /// Completes a login from the `state` and `code` query parameters of the redirect.
+    pub async fn login(
+        &self,
+        pending: &PendingLogin,
+        state: &str,
+        code: &str,
+    ) -> Result<ExternalIdentity, Box<dyn Error>> {
+        if state != pending.state {
+            return Err("The login state does not match, restart the login".into());
+        }
+        let tokens = self.exchange_code(code, &pending.pkce_verifier).await?;
+        if self.provider.is_oidc() {
+            let id_token = tokens
+                .id_token
+                .as_deref()
+                .ok_or_else(|| format!("{} returned no ID token", self.provider.name))?;
+            let claims = self.validate_id_token(id_token, &pending.nonce).await?;
+            return Ok(ExternalIdentity {
+                provider: self.provider.name.clone(),
+                subject: claims.sub,
+                email: claims.email,
+                email_verified: claims.email_verified,
+                first_name: claims.given_name,
+                last_name: claims.family_name,
+            });
+        }
+        self.userinfo(&tokens.access_token).await
+    }
+
+    async fn userinfo(&self, access_token: &str) -> Result<ExternalIdentity, Box<dyn Error>> {
+        let url = self
+            .provider
+            .userinfo_url
+            .as_deref()
+            .ok_or_else(|| format!("{} has no userinfo endpoint", self.provider.name))?;
+        let user: serde_json::Value = self
+            .http
+            .get(url)
+            .bearer_auth(access_token)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        let subject = match user.get("id").or_else(|| user.get("sub")) {
+            Some(serde_json::Value::String(id)) => id.clone(),
+            Some(id) => id.to_string(),
+            None => return Err(format!("{} returned no user id", self.provider.name).into()),
+        };
+        let (first_name, last_name) = match user.get("name").and_then(|name| name.as_str()) {
+            Some(name) => match name.split_once(' ') {
+                Some((first, last)) => (Some(first.to_string()), Some(last.to_string())),
+                None => (Some(name.to_string()), None),
+            },
+            None => (None, None),
+        };
+        let (email, email_verified) = match self.github_email(access_token).await {
+            Some(email) => (Some(email), true),
+            None => (
+                user.get("email")
+                    .and_then(|email| email.as_str())
+                    .map(str::to_string),
+                false,
+            ),
+        };
+        Ok(ExternalIdentity {
+            provider: self.provider.name.clone(),
+            subject,
+            email,
+            email_verified,
+            first_name,
+            last_name,
+        })
+    }
+
+    // The profile email of GitHub users may be hidden or unverified, their primary verified one
+    // is listed separately
+    async fn github_email(&self, access_token: &str) -> Option<String> {
+        #[derive(Deserialize)]
+        struct GithubEmail {
+            email: String,
+            primary: bool,
+            verified: bool,
+        }
+
+        if self.provider.name != "github" {
+            return None;
+        }
+        let emails: Vec<GithubEmail> = self
+            .http
+            .get("https://api.github.com/user/emails")
+            .bearer_auth(access_token)
+            .send()
+            .await
+            .ok()?
+            .json()
+            .await
+            .ok()?;
+        emails
+            .into_iter()
+            .find(|email| email.primary && email.verified)
+            .map(|email| email.email)
+    }
+}
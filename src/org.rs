@@ -0,0 +1,186 @@
+use jsonwebtoken::{decode, DecodingKey, Validation};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::{error::Error, fmt, str::FromStr};
+
+use crate::{utils::read_stored_token, GingerDBConfig, ServiceConfig};
+
+const MAX_LEN: usize = 64;
+// Claims naming the organization a token was issued for, `org_id` being the one of ISC tokens
+const ORG_CLAIMS: [&str; 2] = ["org_id", "organization_id"];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum OrgError {
+    Invalid { value: String, reason: String },
+    // The config file and the stored token target different organizations
+    Mismatch { config: OrgId, token: OrgId },
+    Token(String),
+}
+
+impl fmt::Display for OrgError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OrgError::Invalid { value, reason } => {
+                write!(f, "'{}' is not a valid organization id: {}", value, reason)
+            }
+            OrgError::Mismatch { config, token } => write!(
+                f,
+                "The config file targets the organization '{}' but the stored token was issued for '{}'. \
+                 Log in to '{}' or set `organization_id` to '{}', the API rejects the requests with 403 otherwise",
+                config, token, config, token
+            ),
+            OrgError::Token(reason) => write!(f, "Failed to read the stored token: {}", reason),
+        }
+    }
+}
+
+impl Error for OrgError {}
+
+// Organization ids are lowercase slugs, e.g. `ginger-society`. Parsing trims and lowercases like
+// `ServiceConfig::normalize` does.
+#[derive(
+    Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize, JsonSchema,
+)]
+#[serde(try_from = "String", into = "String")]
+pub struct OrgId(String);
+
+impl OrgId {
+    pub fn parse(value: &str) -> Result<Self, OrgError> {
+        let id = value.trim().to_lowercase();
+        let invalid = |reason: &str| OrgError::Invalid {
+            value: value.to_string(),
+            reason: reason.to_string(),
+        };
+        if id.is_empty() {
+            return Err(invalid("it is empty"));
+        }
+        if id.len() > MAX_LEN {
+            return Err(invalid(&format!(
+                "it is longer than {} characters",
+                MAX_LEN
+            )));
+        }
+        if !id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            return Err(invalid("only letters, digits, '-' and '_' are allowed"));
+        }
+        if !id.starts_with(|c: char| c.is_ascii_alphanumeric()) {
+            return Err(invalid("it must start with a letter or a digit"));
+        }
+        Ok(OrgId(id))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for OrgId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl FromStr for OrgId {
+    type Err = OrgError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        OrgId::parse(s)
+    }
+}
+
+impl TryFrom<String> for OrgId {
+    type Error = OrgError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        OrgId::parse(&value)
+    }
+}
+
+impl From<OrgId> for String {
+    fn from(id: OrgId) -> Self {
+        id.0
+    }
+}
+
+impl AsRef<str> for OrgId {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl ServiceConfig {
+    pub fn org_id(&self) -> Result<OrgId, OrgError> {
+        OrgId::parse(&self.organization_id)
+    }
+}
+
+impl GingerDBConfig {
+    pub fn org_id(&self) -> Result<OrgId, OrgError> {
+        OrgId::parse(&self.organization_id)
+    }
+}
+
+/// The organization a token was issued for, read without checking its signature: the CLI has no
+/// key to check it with, the API does. `None` when the token names no organization.
+pub fn token_org_id(token: &str) -> Result<Option<OrgId>, OrgError> {
+    let mut validation = Validation::default();
+    validation.insecure_disable_signature_validation();
+    validation.validate_exp = false;
+    validation.required_spec_claims.clear();
+    let claims = decode::<Map<String, Value>>(token, &DecodingKey::from_secret(&[]), &validation)
+        .map_err(|e| OrgError::Token(e.to_string()))?
+        .claims;
+    ORG_CLAIMS
+        .iter()
+        .find_map(|claim| claims.get(*claim).and_then(Value::as_str))
+        .map(OrgId::parse)
+        .transpose()
+}
+
+// The organization a command acts on, checked against the logged in token when there is one
+#[derive(Debug, Clone, PartialEq)]
+pub struct OrgContext {
+    pub org_id: OrgId,
+    pub token_org_id: Option<OrgId>, // `None` when not logged in or the token names no organization
+}
+
+impl OrgContext {
+    /// Cross-checks the organization of the config file with the one of `token`.
+    pub fn resolve(config_org: &str, token: Option<&str>) -> Result<Self, OrgError> {
+        let org_id = OrgId::parse(config_org)?;
+        let token_org_id = match token {
+            Some(token) => token_org_id(token)?,
+            None => None,
+        };
+        if let Some(token_org) = &token_org_id {
+            if *token_org != org_id {
+                return Err(OrgError::Mismatch {
+                    config: org_id,
+                    token: token_org.clone(),
+                });
+            }
+        }
+        Ok(OrgContext {
+            org_id,
+            token_org_id,
+        })
+    }
+
+    /// Like `resolve`, with the token stored in `~/.ginger-society/auth.json` if any.
+    pub fn from_stored_token(config_org: &str) -> Result<Self, OrgError> {
+        let token = read_stored_token().ok();
+        Self::resolve(config_org, token.as_deref())
+    }
+
+    pub fn for_service(config: &ServiceConfig) -> Result<Self, OrgError> {
+        Self::from_stored_token(&config.organization_id)
+    }
+
+    pub fn for_db(config: &GingerDBConfig) -> Result<Self, OrgError> {
+        Self::from_stored_token(&config.organization_id)
+    }
+}
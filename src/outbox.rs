@@ -0,0 +1,362 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use uuid::Uuid;
+
+use crate::{
+    config_io::write_atomic,
+    events::{EventEnvelope, PublishError, RealtimePublisher},
+    rocket_models::RealtimeMessage,
+    scheduler::Job,
+};
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct OutboxEntry {
+    pub id: Uuid, // Id of the staged event
+    pub topic: String,
+    pub payload: String, // The serialized envelope
+    pub created_at: DateTime<Utc>,
+    #[serde(default)]
+    pub attempts: u32,
+    pub last_error: Option<String>,
+}
+
+impl OutboxEntry {
+    pub fn from_envelope<T: Serialize>(envelope: &EventEnvelope<T>) -> Result<Self, PublishError> {
+        Ok(OutboxEntry {
+            id: envelope.id,
+            topic: envelope.topic.clone(),
+            payload: serde_json::to_string(envelope)?,
+            created_at: Utc::now(),
+            attempts: 0,
+            last_error: None,
+        })
+    }
+}
+
+// Where events wait until they are published. Services storing their data in a database
+// implement this over an outbox table so events are staged in the same transaction as the data
+// they describe.
+#[async_trait]
+pub trait OutboxStore: Send + Sync {
+    async fn stage(&self, entry: OutboxEntry) -> Result<(), PublishError>;
+
+    /// Unsent entries with fewer than `max_attempts` attempts, oldest first.
+    async fn pending(
+        &self,
+        limit: usize,
+        max_attempts: u32,
+    ) -> Result<Vec<OutboxEntry>, PublishError>;
+
+    async fn mark_sent(&self, id: Uuid) -> Result<(), PublishError>;
+
+    async fn mark_failed(&self, id: Uuid, error: &str) -> Result<(), PublishError>;
+}
+
+#[derive(Default)]
+pub struct MemoryOutbox {
+    entries: Mutex<BTreeMap<(DateTime<Utc>, Uuid), OutboxEntry>>,
+}
+
+impl MemoryOutbox {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[async_trait]
+impl OutboxStore for MemoryOutbox {
+    async fn stage(&self, entry: OutboxEntry) -> Result<(), PublishError> {
+        self.entries
+            .lock()
+            .unwrap()
+            .insert((entry.created_at, entry.id), entry);
+        Ok(())
+    }
+
+    async fn pending(
+        &self,
+        limit: usize,
+        max_attempts: u32,
+    ) -> Result<Vec<OutboxEntry>, PublishError> {
+        Ok(self
+            .entries
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|entry| entry.attempts < max_attempts)
+            .take(limit)
+            .cloned()
+            .collect())
+    }
+
+    async fn mark_sent(&self, id: Uuid) -> Result<(), PublishError> {
+        self.entries
+            .lock()
+            .unwrap()
+            .retain(|(_, key), _| *key != id);
+        Ok(())
+    }
+
+    async fn mark_failed(&self, id: Uuid, error: &str) -> Result<(), PublishError> {
+        if let Some(entry) = self
+            .entries
+            .lock()
+            .unwrap()
+            .values_mut()
+            .find(|entry| entry.id == id)
+        {
+            entry.attempts += 1;
+            entry.last_error = Some(error.to_string());
+        }
+        Ok(())
+    }
+}
+
+// One JSON file per entry under `<dir>/pending`, moved to `<dir>/sent` once published, or to
+// `<dir>/invalid` when it cannot be read back. Meant for services without a database; a single
+// flusher per directory is assumed.
+pub struct FileOutbox {
+    dir: PathBuf,
+}
+
+impl FileOutbox {
+    pub fn new<P: AsRef<Path>>(dir: P) -> Result<Self, PublishError> {
+        let dir = dir.as_ref().to_path_buf();
+        for sub in ["pending", "sent", "invalid"] {
+            fs::create_dir_all(dir.join(sub)).map_err(|e| {
+                format!(
+                    "Failed to create the outbox directory '{}': {}",
+                    dir.join(sub).display(),
+                    e
+                )
+            })?;
+        }
+        Ok(FileOutbox { dir })
+    }
+
+    // Names sort in staging order
+    fn file_name(entry: &OutboxEntry) -> String {
+        format!(
+            "{:020}-{}.json",
+            entry.created_at.timestamp_micros(),
+            entry.id
+        )
+    }
+
+    fn find_pending(&self, id: Uuid) -> Result<Option<PathBuf>, PublishError> {
+        let suffix = format!("-{}.json", id);
+        for file in fs::read_dir(self.dir.join("pending"))? {
+            let path = file?.path();
+            if path.to_string_lossy().ends_with(&suffix) {
+                return Ok(Some(path));
+            }
+        }
+        Ok(None)
+    }
+
+    fn write(path: &Path, entry: &OutboxEntry) -> Result<(), PublishError> {
+        write_atomic(path, &serde_json::to_string(entry)?, false).map_err(|e| e.to_string())?;
+        Ok(())
+    }
+
+    // Moves an unreadable entry out of the way so it does not hold up the following ones
+    fn set_aside(&self, path: &Path, error: &str) {
+        let invalid = self
+            .dir
+            .join("invalid")
+            .join(path.file_name().unwrap_or_default());
+        match fs::rename(path, &invalid) {
+            Ok(()) => tracing::error!(
+                path = %invalid.display(),
+                error,
+                "set aside an invalid outbox entry"
+            ),
+            Err(e) => tracing::error!(
+                path = %path.display(),
+                error,
+                rename_error = %e,
+                "failed to set aside an invalid outbox entry, skipping it"
+            ),
+        }
+    }
+
+    /// Deletes sent entries older than `age`.
+    pub fn prune_sent(&self, age: Duration) -> Result<usize, PublishError> {
+        let mut pruned = 0;
+        for file in fs::read_dir(self.dir.join("sent"))? {
+            let file = file?;
+            if file.metadata()?.modified()?.elapsed().unwrap_or_default() > age {
+                fs::remove_file(file.path())?;
+                pruned += 1;
+            }
+        }
+        Ok(pruned)
+    }
+}
+
+#[async_trait]
+impl OutboxStore for FileOutbox {
+    async fn stage(&self, entry: OutboxEntry) -> Result<(), PublishError> {
+        let path = self.dir.join("pending").join(Self::file_name(&entry));
+        Self::write(&path, &entry)
+    }
+
+    async fn pending(
+        &self,
+        limit: usize,
+        max_attempts: u32,
+    ) -> Result<Vec<OutboxEntry>, PublishError> {
+        let mut paths: Vec<PathBuf> = fs::read_dir(self.dir.join("pending"))?
+            .filter_map(|file| file.ok().map(|file| file.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+            .collect();
+        paths.sort();
+
+        let mut entries = vec![];
+        for path in paths {
+            if entries.len() == limit {
+                break;
+            }
+            let entry: OutboxEntry = match fs::read_to_string(&path)
+                .map_err(|e| e.to_string())
+                .and_then(|contents| serde_json::from_str(&contents).map_err(|e| e.to_string()))
+            {
+                Ok(entry) => entry,
+                Err(e) => {
+                    self.set_aside(&path, &e);
+                    continue;
+                }
+            };
+            if entry.attempts < max_attempts {
+                entries.push(entry);
+            }
+        }
+        Ok(entries)
+    }
+
+    async fn mark_sent(&self, id: Uuid) -> Result<(), PublishError> {
+        if let Some(path) = self.find_pending(id)? {
+            let sent = self
+                .dir
+                .join("sent")
+                .join(path.file_name().unwrap_or_default());
+            fs::rename(&path, sent)?;
+        }
+        Ok(())
+    }
+
+    async fn mark_failed(&self, id: Uuid, error: &str) -> Result<(), PublishError> {
+        if let Some(path) = self.find_pending(id)? {
+            let mut entry: OutboxEntry = serde_json::from_str(&fs::read_to_string(&path)?)?;
+            entry.attempts += 1;
+            entry.last_error = Some(error.to_string());
+            Self::write(&path, &entry)?;
+        }
+        Ok(())
+    }
+}
+
+/// Stages `envelope` for publishing. Call it where the request commits its changes instead of
+/// publishing directly, the flusher takes it from there.
+pub async fn stage<T: Serialize>(
+    store: &dyn OutboxStore,
+    envelope: &EventEnvelope<T>,
+) -> Result<(), PublishError> {
+    store.stage(OutboxEntry::from_envelope(envelope)?).await
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+pub struct FlushReport {
+    pub sent: usize,
+    pub failed: usize,
+}
+
+// Publishes staged entries and marks them sent. A failed entry stops the batch so events go out
+// in the order they were staged; after `max_attempts` failures it is skipped and stays in the
+// store for inspection.
+#[derive(Clone)]
+pub struct OutboxFlusher {
+    store: Arc<dyn OutboxStore>,
+    publisher: Arc<dyn RealtimePublisher>,
+    batch_size: usize,
+    max_attempts: u32,
+}
+
+impl OutboxFlusher {
+    pub fn new(store: Arc<dyn OutboxStore>, publisher: Arc<dyn RealtimePublisher>) -> Self {
+        OutboxFlusher {
+            store,
+            publisher,
+            batch_size: 100,
+            max_attempts: 10,
+        }
+    }
+
+    pub fn batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size;
+        self
+    }
+
+    pub fn max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = max_attempts;
+        self
+    }
+
+    pub async fn flush_once(&self) -> Result<FlushReport, PublishError> {
+        let mut report = FlushReport::default();
+        for entry in self
+            .store
+            .pending(self.batch_size, self.max_attempts)
+            .await?
+        {
+            let message = RealtimeMessage {
+                topic: entry.topic.clone(),
+                payload: entry.payload.clone(),
+            };
+            match self.publisher.publish(message).await {
+                Ok(()) => {
+                    self.store.mark_sent(entry.id).await?;
+                    report.sent += 1;
+                }
+                Err(e) => {
+                    tracing::warn!(
+                        event_id = %entry.id,
+                        topic = %entry.topic,
+                        attempts = entry.attempts + 1,
+                        error = %e,
+                        "failed to publish an outbox entry"
+                    );
+                    self.store.mark_failed(entry.id, &e.to_string()).await?;
+                    report.failed += 1;
+                    break;
+                }
+            }
+        }
+        Ok(report)
+    }
+
+    /// A scheduler job flushing every `interval`, e.g.
+    /// `Scheduler::new().job(flusher.job(Duration::from_secs(1)))`.
+    pub fn job(self, interval: Duration) -> Job {
+        Job::every("outbox-flush", interval, move || {
+            let flusher = self.clone();
+            async move { flusher.flush_once().await.map(|_| ()) }
+        })
+    }
+}
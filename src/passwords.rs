@@ -0,0 +1,239 @@
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Algorithm, Argon2, Params, Version,
+};
+use std::{collections::HashSet, error::Error, fmt};
+
+use crate::Environment;
+
+// Argon2id costs, see the OWASP password storage cheat sheet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HashParams {
+    pub memory_kib: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+}
+
+impl HashParams {
+    /// Cheap in development so tests and seeds stay fast, at or above the OWASP baseline
+    /// (19 MiB, 2 iterations) elsewhere.
+    pub fn for_environment(env: &Environment) -> Self {
+        match env {
+            Environment::Dev => HashParams {
+                memory_kib: 4 * 1024,
+                iterations: 1,
+                parallelism: 1,
+            },
+            Environment::Stage | Environment::StageK8 => HashParams {
+                memory_kib: 19 * 1024,
+                iterations: 2,
+                parallelism: 1,
+            },
+            Environment::Prod | Environment::ProdK8 => HashParams {
+                memory_kib: 64 * 1024,
+                iterations: 3,
+                parallelism: 1,
+            },
+        }
+    }
+
+    fn hasher(&self) -> Result<Argon2<'static>, Box<dyn Error>> {
+        let params = Params::new(self.memory_kib, self.iterations, self.parallelism, None)
+            .map_err(|e| format!("Invalid Argon2 parameters: {}", e))?;
+        Ok(Argon2::new(Algorithm::Argon2id, Version::V0x13, params))
+    }
+}
+
+/// Longest password hashed or verified, in characters, whatever the `PasswordPolicy`. It bounds
+/// the work of a single request, and caps `PasswordPolicy::max_length` so both agree.
+pub const MAX_HASHED_LENGTH: usize = 1024;
+
+fn check_length(password: &str) -> Result<(), PolicyViolation> {
+    match password.chars().count() > MAX_HASHED_LENGTH {
+        true => Err(PolicyViolation::TooLong {
+            max: MAX_HASHED_LENGTH,
+        }),
+        false => Ok(()),
+    }
+}
+
+/// Hashes `password` with a random salt into a PHC string, e.g. `$argon2id$v=19$m=65536,...`,
+/// which records the parameters it was hashed with. Passwords over `MAX_HASHED_LENGTH` are
+/// refused before any hashing work.
+pub fn hash_password(password: &str, env: &Environment) -> Result<String, Box<dyn Error>> {
+    check_length(password)?;
+    let salt = SaltString::generate(&mut OsRng);
+    let hash = HashParams::for_environment(env)
+        .hasher()?
+        .hash_password(password.as_bytes(), &salt)
+        .map_err(|e| format!("Failed to hash the password: {}", e))?;
+    Ok(hash.to_string())
+}
+
+/// Whether `password` matches `hash`, compared in constant time. Fails for hashes that are
+/// not Argon2 PHC strings. Passwords too long to have been hashed do not match.
+pub fn verify_password(password: &str, hash: &str) -> Result<bool, Box<dyn Error>> {
+    let parsed = PasswordHash::new(hash).map_err(|e| format!("Invalid password hash: {}", e))?;
+    if check_length(password).is_err() {
+        return Ok(false);
+    }
+    match Argon2::default().verify_password(password.as_bytes(), &parsed) {
+        Ok(()) => Ok(true),
+        Err(argon2::password_hash::Error::Password) => Ok(false),
+        Err(e) => Err(format!("Failed to verify the password: {}", e).into()),
+    }
+}
+
+/// Spends the time of a verification without a hash to check, for logins of unknown users, so
+/// response times do not reveal which accounts exist.
+pub fn dummy_verify(password: &str, env: &Environment) {
+    if check_length(password).is_err() {
+        return;
+    }
+    let salt = SaltString::generate(&mut OsRng);
+    if let Ok(hasher) = HashParams::for_environment(env).hasher() {
+        let _ = hasher.hash_password(password.as_bytes(), &salt);
+    }
+}
+
+/// Whether `hash` was made with weaker parameters than those of `env`, or is not Argon2id, and
+/// should be replaced by a new hash after the next successful login.
+pub fn needs_rehash(hash: &str, env: &Environment) -> bool {
+    let parsed = match PasswordHash::new(hash) {
+        Ok(parsed) => parsed,
+        Err(_) => return true,
+    };
+    if parsed.algorithm != Algorithm::Argon2id.ident() {
+        return true;
+    }
+    let expected = HashParams::for_environment(env);
+    match Params::try_from(&parsed) {
+        Ok(params) => {
+            params.m_cost() < expected.memory_kib
+                || params.t_cost() < expected.iterations
+                || params.p_cost() < expected.parallelism
+        }
+        Err(_) => true,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PolicyViolation {
+    TooShort { min: usize },
+    TooLong { max: usize },
+    TooWeak { bits: f64, min: f64 },
+    ContainsUserInput(String), // e.g. the email or name of the user
+}
+
+impl fmt::Display for PolicyViolation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PolicyViolation::TooShort { min } => {
+                write!(f, "The password must be at least {} characters long", min)
+            }
+            PolicyViolation::TooLong { max } => {
+                write!(f, "The password must be at most {} characters long", max)
+            }
+            PolicyViolation::TooWeak { .. } => write!(
+                f,
+                "The password is too easy to guess, use more varied characters"
+            ),
+            PolicyViolation::ContainsUserInput(input) => {
+                write!(f, "The password must not contain '{}'", input)
+            }
+        }
+    }
+}
+
+impl Error for PolicyViolation {}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PasswordPolicy {
+    pub min_length: usize,
+    pub max_length: usize, // At most `MAX_HASHED_LENGTH`
+    pub min_entropy_bits: f64,
+}
+
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        PasswordPolicy {
+            min_length: 12,
+            max_length: 128,
+            min_entropy_bits: 50.0,
+        }
+    }
+}
+
+impl PasswordPolicy {
+    /// Every rule `password` breaks. `user_inputs` are values the password must not contain,
+    /// compared case insensitively, e.g. the email of the user.
+    pub fn check(&self, password: &str, user_inputs: &[&str]) -> Result<(), Vec<PolicyViolation>> {
+        let mut violations = vec![];
+        let length = password.chars().count();
+        if length < self.min_length {
+            violations.push(PolicyViolation::TooShort {
+                min: self.min_length,
+            });
+        }
+        let max_length = self.max_length.min(MAX_HASHED_LENGTH);
+        if length > max_length {
+            violations.push(PolicyViolation::TooLong { max: max_length });
+        }
+        let bits = estimate_entropy(password);
+        if bits < self.min_entropy_bits {
+            violations.push(PolicyViolation::TooWeak {
+                bits,
+                min: self.min_entropy_bits,
+            });
+        }
+        let lowercase = password.to_lowercase();
+        for input in user_inputs {
+            let input = input.trim().to_lowercase();
+            // Short inputs, e.g. initials, would reject too many passwords
+            if input.chars().count() >= 4 && lowercase.contains(&input) {
+                violations.push(PolicyViolation::ContainsUserInput(input));
+            }
+        }
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(violations)
+        }
+    }
+}
+
+/// Rough strength of `password` in bits: the size of the character classes it uses, counted
+/// once per character that differs from its predecessor, so `aaaaaaaa` scores as `a`.
+pub fn estimate_entropy(password: &str) -> f64 {
+    let mut pool = 0;
+    let mut classes = HashSet::new();
+    for c in password.chars() {
+        let (class, size) = if c.is_ascii_lowercase() {
+            (0, 26)
+        } else if c.is_ascii_uppercase() {
+            (1, 26)
+        } else if c.is_ascii_digit() {
+            (2, 10)
+        } else if c.is_ascii() {
+            (3, 33)
+        } else {
+            (4, 100)
+        };
+        if classes.insert(class) {
+            pool += size;
+        }
+    }
+    let mut previous = None;
+    let effective_length = password
+        .chars()
+        .filter(|c| {
+            let differs = previous != Some(*c);
+            previous = Some(*c);
+            differs
+        })
+        .count();
+    if pool == 0 {
+        return 0.0;
+    }
+    effective_length as f64 * (pool as f64).log2()
+}
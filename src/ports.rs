@@ -0,0 +1,135 @@
+use std::{collections::HashMap, fmt, ops::RangeInclusive};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{DatabaseConfig, GingerDBConfig};
+
+pub const DEFAULT_PORT_RANGE: RangeInclusive<u16> = 1024..=65535;
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PortField {
+    Port,
+    StudioPort,
+}
+
+impl fmt::Display for PortField {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PortField::Port => write!(f, "port"),
+            PortField::StudioPort => write!(f, "studio_port"),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum PortConflict {
+    NotNumeric {
+        database: String,
+        field: PortField,
+        value: String,
+    },
+    OutOfRange {
+        database: String,
+        field: PortField,
+        port: u16,
+    },
+    Duplicate {
+        port: u16,
+        first: (String, PortField),
+        second: (String, PortField),
+    },
+}
+
+impl fmt::Display for PortConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PortConflict::NotNumeric {
+                database,
+                field,
+                value,
+            } => write!(f, "{}.{} is not a valid port: '{}'", database, field, value),
+            PortConflict::OutOfRange {
+                database,
+                field,
+                port,
+            } => write!(
+                f,
+                "{}.{} is out of the allowed range: {}",
+                database, field, port
+            ),
+            PortConflict::Duplicate {
+                port,
+                first,
+                second,
+            } => write!(
+                f,
+                "port {} is used by both {}.{} and {}.{}",
+                port, first.0, first.1, second.0, second.1
+            ),
+        }
+    }
+}
+
+impl GingerDBConfig {
+    pub fn check_port_conflicts(&self) -> Vec<PortConflict> {
+        self.check_port_conflicts_in_range(DEFAULT_PORT_RANGE)
+    }
+
+    /// Checks that every `port` / `studio_port` is numeric and inside `range`, and that no two
+    /// enabled databases share a port. Disabled databases are validated but never collide.
+    pub fn check_port_conflicts_in_range(&self, range: RangeInclusive<u16>) -> Vec<PortConflict> {
+        let mut conflicts = vec![];
+        let mut seen: HashMap<u16, (String, PortField)> = HashMap::new();
+
+        for db in &self.database {
+            for (field, value) in ports_of(db) {
+                let port = match value.trim().parse::<u16>() {
+                    Ok(port) => port,
+                    Err(_) => {
+                        conflicts.push(PortConflict::NotNumeric {
+                            database: db.name.clone(),
+                            field,
+                            value: value.to_string(),
+                        });
+                        continue;
+                    }
+                };
+
+                if !range.contains(&port) {
+                    conflicts.push(PortConflict::OutOfRange {
+                        database: db.name.clone(),
+                        field,
+                        port,
+                    });
+                }
+
+                if !db.enable {
+                    continue;
+                }
+
+                match seen.get(&port) {
+                    Some(first) => conflicts.push(PortConflict::Duplicate {
+                        port,
+                        first: first.clone(),
+                        second: (db.name.clone(), field),
+                    }),
+                    None => {
+                        seen.insert(port, (db.name.clone(), field));
+                    }
+                }
+            }
+        }
+
+        conflicts
+    }
+}
+
+fn ports_of(db: &DatabaseConfig) -> Vec<(PortField, &str)> {
+    let mut ports = vec![(PortField::Port, db.port.as_str())];
+    if let Some(studio_port) = &db.studio_port {
+        ports.push((PortField::StudioPort, studio_port.as_str()));
+    }
+    ports
+}
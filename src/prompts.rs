@@ -0,0 +1,150 @@
+use dialoguer::{theme::ColorfulTheme, Confirm, Input, MultiSelect, Select};
+use std::{
+    error::Error,
+    fmt::Display,
+    io::{self, IsTerminal},
+};
+
+use crate::{schema::SchemaDocument, Environment, LANG, ORM};
+
+/// Whether prompts can be shown, i.e. stdin and stderr are terminals.
+pub fn is_interactive() -> bool {
+    io::stdin().is_terminal() && io::stderr().is_terminal()
+}
+
+// Without a terminal the default is taken, and a missing default is an error naming what the
+// command needs, so scripts fail instead of hanging on a prompt
+fn non_interactive<T>(what: &str, default: Option<T>) -> Result<T, Box<dyn Error>> {
+    default.ok_or_else(|| {
+        format!(
+            "{} is required, pass it as an argument when not running interactively",
+            what
+        )
+        .into()
+    })
+}
+
+/// Lets the user pick one of `items`, `default` being preselected.
+pub fn select<T: Display + Clone + PartialEq>(
+    prompt: &str,
+    items: &[T],
+    default: Option<&T>,
+) -> Result<T, Box<dyn Error>> {
+    if items.is_empty() {
+        return Err(format!("There is nothing to choose for '{}'", prompt).into());
+    }
+    if !is_interactive() {
+        return non_interactive(prompt, default.cloned());
+    }
+    let index = Select::with_theme(&ColorfulTheme::default())
+        .with_prompt(prompt)
+        .items(items)
+        .default(
+            default
+                .and_then(|d| items.iter().position(|item| item == d))
+                .unwrap_or(0),
+        )
+        .interact()?;
+    Ok(items[index].clone())
+}
+
+pub fn confirm(prompt: &str, default: bool) -> Result<bool, Box<dyn Error>> {
+    if !is_interactive() {
+        return Ok(default);
+    }
+    Ok(Confirm::with_theme(&ColorfulTheme::default())
+        .with_prompt(prompt)
+        .default(default)
+        .interact()?)
+}
+
+/// Asks for a non blank value, trimmed, checked with `validate` before it is accepted.
+pub fn text<F>(prompt: &str, default: Option<&str>, validate: F) -> Result<String, Box<dyn Error>>
+where
+    F: Fn(&str) -> Result<(), String>,
+{
+    if !is_interactive() {
+        let value = non_interactive(prompt, default.map(str::trim).filter(|d| !d.is_empty()))?;
+        validate(value).map_err(|e| format!("Invalid {}: {}", prompt, e))?;
+        return Ok(value.to_string());
+    }
+    let theme = ColorfulTheme::default();
+    let mut input = Input::<String>::with_theme(&theme)
+        .with_prompt(prompt)
+        .validate_with(|value: &String| -> Result<(), String> {
+            let value = value.trim();
+            if value.is_empty() {
+                return Err("A value is required".to_string());
+            }
+            validate(value)
+        });
+    if let Some(default) = default {
+        input = input.default(default.to_string());
+    }
+    Ok(input.interact_text()?.trim().to_string())
+}
+
+/// Identifiers such as package or organization names: letters, digits, `-` and `_`.
+pub fn identifier(value: &str) -> Result<(), String> {
+    if value
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        Ok(())
+    } else {
+        Err("Only letters, digits, '-' and '_' are allowed".to_string())
+    }
+}
+
+pub fn choose_lang(default: Option<LANG>) -> Result<LANG, Box<dyn Error>> {
+    select("Language", &LANG::all(), default.as_ref())
+}
+
+/// The ORMs generating code for `lang`, or all of them.
+pub fn choose_orm(lang: Option<LANG>, default: Option<ORM>) -> Result<ORM, Box<dyn Error>> {
+    let orms: Vec<ORM> = ORM::all()
+        .into_iter()
+        .filter(|orm| lang.is_none_or(|lang| orm.lang() == lang))
+        .collect();
+    if orms.len() == 1 {
+        return Ok(orms[0]);
+    }
+    let default = default.filter(|orm| orms.contains(orm));
+    select("ORM", &orms, default.as_ref())
+}
+
+pub fn choose_environment(default: Option<Environment>) -> Result<Environment, Box<dyn Error>> {
+    select("Environment", &Environment::all(), default.as_ref())
+}
+
+/// Lets the user tick tables of `schema`, `selected` being ticked already. Without a terminal
+/// `selected` is kept as is.
+pub fn pick_tables(
+    schema: &SchemaDocument,
+    selected: &[String],
+) -> Result<Vec<String>, Box<dyn Error>> {
+    let tables = schema.table_names();
+    if tables.is_empty() {
+        return Err(format!("The schema {} has no tables", schema.schema_id).into());
+    }
+    if !is_interactive() {
+        return Ok(selected
+            .iter()
+            .filter(|table| tables.contains(table))
+            .cloned()
+            .collect());
+    }
+    let ticked: Vec<bool> = tables
+        .iter()
+        .map(|table| selected.contains(table))
+        .collect();
+    let picked = MultiSelect::with_theme(&ColorfulTheme::default())
+        .with_prompt("Tables (space to toggle, enter to confirm)")
+        .items(&tables)
+        .defaults(&ticked)
+        .interact()?;
+    Ok(picked
+        .into_iter()
+        .map(|index| tables[index].clone())
+        .collect())
+}
@@ -0,0 +1,218 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    env,
+    error::Error,
+    fmt, fs,
+    fs::File,
+    io::{self, Read},
+    path::{Path, PathBuf},
+};
+
+use crate::{config_io::write_atomic, git::GitRepo, Version};
+
+pub const RELEASE_MANIFEST_FILE: &str = "release-manifest.json";
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct ArtifactChecksum {
+    pub path: String, // Relative to the release root
+    pub sha256: String,
+    pub size: u64,
+}
+
+// Who produced a release: a CI run, or a developer on their machine
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct BuilderIdentity {
+    pub id: String,
+    pub run_url: Option<String>,
+}
+
+impl BuilderIdentity {
+    /// Identifies GitHub Actions and GitLab CI runs, falls back to `user@host`.
+    pub fn detect() -> Self {
+        if env::var("GITHUB_ACTIONS").is_ok() {
+            let run_url = match (
+                env::var("GITHUB_SERVER_URL"),
+                env::var("GITHUB_REPOSITORY"),
+                env::var("GITHUB_RUN_ID"),
+            ) {
+                (Ok(server), Ok(repository), Ok(run_id)) => {
+                    Some(format!("{}/{}/actions/runs/{}", server, repository, run_id))
+                }
+                _ => None,
+            };
+            return BuilderIdentity {
+                id: format!(
+                    "github-actions:{}",
+                    env::var("GITHUB_WORKFLOW").unwrap_or_default()
+                ),
+                run_url,
+            };
+        }
+        if env::var("GITLAB_CI").is_ok() {
+            return BuilderIdentity {
+                id: format!("gitlab-ci:{}", env::var("CI_JOB_NAME").unwrap_or_default()),
+                run_url: env::var("CI_JOB_URL").ok(),
+            };
+        }
+
+        let user = env::var("USER")
+            .or_else(|_| env::var("USERNAME"))
+            .unwrap_or_else(|_| "unknown".to_string());
+        let host = env::var("HOSTNAME")
+            .ok()
+            .or_else(|| fs::read_to_string("/etc/hostname").ok())
+            .map(|host| host.trim().to_string())
+            .unwrap_or_else(|| "unknown".to_string());
+        BuilderIdentity {
+            id: format!("{}@{}", user, host),
+            run_url: None,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct ReleaseManifest {
+    pub package: String,
+    pub version: Version,
+    pub git_sha: String,
+    pub builder: BuilderIdentity,
+    pub created_at: DateTime<Utc>,
+    pub artifacts: Vec<ArtifactChecksum>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[serde(tag = "problem", rename_all = "snake_case")]
+pub enum ArtifactMismatch {
+    Missing {
+        path: String,
+    },
+    ChecksumChanged {
+        path: String,
+        expected: String,
+        actual: String,
+    },
+}
+
+impl fmt::Display for ArtifactMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ArtifactMismatch::Missing { path } => write!(f, "{} is missing", path),
+            ArtifactMismatch::ChecksumChanged {
+                path,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "{} has checksum {}, the manifest records {}",
+                path, actual, expected
+            ),
+        }
+    }
+}
+
+/// Hex encoded SHA-256 of the file at `path`, read in chunks so large artifacts are fine.
+pub fn sha256_file<P: AsRef<Path>>(path: P) -> Result<(String, u64), Box<dyn Error>> {
+    let path = path.as_ref();
+    let mut file = File::open(path)
+        .map_err(|e| format!("Failed to open the artifact '{}': {}", path.display(), e))?;
+    let mut hasher = Sha256::new();
+    let mut buffer = [0u8; 64 * 1024];
+    let mut size = 0;
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+        size += read as u64;
+    }
+    let digest = hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    Ok((digest, size))
+}
+
+impl ReleaseManifest {
+    /// Checksums `artifacts` (relative to `root`) and records them with the HEAD commit of the
+    /// repository at `root` and the detected builder.
+    pub fn record<P: AsRef<Path>>(
+        root: P,
+        package: &str,
+        version: &Version,
+        artifacts: &[&str],
+    ) -> Result<Self, Box<dyn Error>> {
+        let root = root.as_ref();
+        let git_sha = GitRepo::open(root).head_sha()?;
+
+        let mut checksums = vec![];
+        for artifact in artifacts {
+            let (sha256, size) = sha256_file(root.join(artifact))?;
+            checksums.push(ArtifactChecksum {
+                path: artifact.to_string(),
+                sha256,
+                size,
+            });
+        }
+
+        Ok(ReleaseManifest {
+            package: package.to_string(),
+            version: *version,
+            git_sha,
+            builder: BuilderIdentity::detect(),
+            created_at: Utc::now(),
+            artifacts: checksums,
+        })
+    }
+
+    /// Writes the manifest as `release-manifest.json` in `dir`.
+    pub fn write<P: AsRef<Path>>(&self, dir: P) -> Result<PathBuf, Box<dyn Error>> {
+        let path = dir.as_ref().join(RELEASE_MANIFEST_FILE);
+        write_atomic(&path, &serde_json::to_string_pretty(self)?, false)?;
+        Ok(path)
+    }
+
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error>> {
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path).map_err(|e| {
+            format!(
+                "Failed to read the release manifest '{}': {}",
+                path.display(),
+                e
+            )
+        })?;
+        Ok(serde_json::from_str(&contents)?)
+    }
+
+    /// Recomputes the checksums of the artifacts under `root`, an empty list means every
+    /// artifact matches the manifest.
+    pub fn verify<P: AsRef<Path>>(&self, root: P) -> Result<Vec<ArtifactMismatch>, Box<dyn Error>> {
+        let root = root.as_ref();
+        let mut mismatches = vec![];
+        for artifact in &self.artifacts {
+            let path = root.join(&artifact.path);
+            match fs::metadata(&path) {
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                    mismatches.push(ArtifactMismatch::Missing {
+                        path: artifact.path.clone(),
+                    });
+                    continue;
+                }
+                Err(e) => return Err(e.into()),
+                Ok(_) => {}
+            }
+            let (actual, _) = sha256_file(&path)?;
+            if actual != artifact.sha256 {
+                mismatches.push(ArtifactMismatch::ChecksumChanged {
+                    path: artifact.path.clone(),
+                    expected: artifact.sha256.clone(),
+                    actual,
+                });
+            }
+        }
+        Ok(mismatches)
+    }
+}
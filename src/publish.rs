@@ -0,0 +1,350 @@
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::{error::Error, fmt, fmt::Write};
+
+use crate::{Channel, PackageMetadata, Version};
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct PackageDependency {
+    pub name: String,
+    pub requirement: String, // e.g. `^1.4`, see `Version::satisfies`
+}
+
+// The package about to be published, as the releaser sees it
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct PackageManifest {
+    pub name: String,
+    pub version: Version,
+    pub branch: String, // The branch the release is cut from
+    #[serde(default)]
+    pub dependencies: Vec<PackageDependency>, // Internal dependencies only
+}
+
+impl PackageManifest {
+    pub fn new(name: &str, version: Version, branch: &str) -> Self {
+        PackageManifest {
+            name: name.to_string(),
+            version,
+            branch: branch.to_string(),
+            dependencies: vec![],
+        }
+    }
+
+    pub fn dependency(mut self, name: &str, requirement: &str) -> Self {
+        self.dependencies.push(PackageDependency {
+            name: name.to_string(),
+            requirement: requirement.to_string(),
+        });
+        self
+    }
+}
+
+impl Version {
+    /// Whether the version matches `requirement`: comma separated comparators made of an
+    /// operator (`^`, `~`, `=`, `>`, `>=`, `<`, `<=`, none meaning `^`) and a `major[.minor[.patch]]`
+    /// version, or `*`. Pre-release channels only match requirements on their own
+    /// `major.minor.patch`, like Cargo does.
+    pub fn satisfies(&self, requirement: &str) -> Result<bool, String> {
+        let mut matched_exactly = false;
+        for comparator in requirement.split(',').map(str::trim) {
+            if comparator == "*" || comparator.is_empty() {
+                continue;
+            }
+            let operator_len = comparator
+                .find(|c: char| c.is_ascii_digit())
+                .ok_or_else(|| format!("'{}' is not a valid version requirement", requirement))?;
+            let (operator, version) = comparator.split_at(operator_len);
+            let parts: Vec<u32> = version
+                .split('.')
+                .map(|part| part.parse::<u32>())
+                .collect::<Result<_, _>>()
+                .map_err(|_| format!("'{}' is not a valid version requirement", requirement))?;
+            if parts.is_empty() || parts.len() > 3 {
+                return Err(format!(
+                    "'{}' is not a valid version requirement",
+                    requirement
+                ));
+            }
+
+            let wanted = (
+                parts[0],
+                parts.get(1).copied().unwrap_or(0),
+                parts.get(2).copied().unwrap_or(0),
+            );
+            let own = (self.major, self.minor, self.patch);
+            matched_exactly |= own == wanted;
+
+            let ok = match operator.trim() {
+                "^" | "" => {
+                    own >= wanted
+                        && match wanted {
+                            (0, 0, _) if parts.len() == 3 => {
+                                own.0 == 0 && own.1 == 0 && own.2 == wanted.2
+                            }
+                            (0, minor, _) if parts.len() > 1 => own.0 == 0 && own.1 == minor,
+                            (major, _, _) => own.0 == major,
+                        }
+                }
+                "~" => {
+                    own >= wanted && own.0 == wanted.0 && (parts.len() == 1 || own.1 == wanted.1)
+                }
+                "=" => {
+                    own.0 == wanted.0
+                        && (parts.len() < 2 || own.1 == wanted.1)
+                        && (parts.len() < 3 || own.2 == wanted.2)
+                }
+                ">" => own > wanted,
+                ">=" => own >= wanted,
+                "<" => own < wanted,
+                "<=" => own <= wanted,
+                other => {
+                    return Err(format!(
+                        "Unknown operator '{}' in version requirement '{}'",
+                        other, requirement
+                    ))
+                }
+            };
+            if !ok {
+                return Ok(false);
+            }
+        }
+        Ok(self.channel == Channel::Final || matched_exactly)
+    }
+}
+
+// Where versions are published, implemented per ecosystem by `registry`
+#[async_trait]
+pub trait VersionRegistry: Send + Sync {
+    async fn published_versions(
+        &self,
+        name: &str,
+    ) -> Result<Vec<Version>, Box<dyn Error + Send + Sync>>;
+}
+
+#[async_trait]
+pub trait LinkChecker: Send + Sync {
+    /// `Err` with the reason when `url` does not resolve.
+    async fn check(&self, url: &str) -> Result<(), String>;
+}
+
+// Sends a HEAD request, falling back to GET for servers that do not allow HEAD
+#[cfg(feature = "client")]
+pub struct HttpLinkChecker {
+    client: reqwest::Client,
+}
+
+#[cfg(feature = "client")]
+impl Default for HttpLinkChecker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "client")]
+impl HttpLinkChecker {
+    pub fn new() -> Self {
+        HttpLinkChecker {
+            client: reqwest::Client::builder()
+                .timeout(std::time::Duration::from_secs(10))
+                .build()
+                .unwrap_or_default(),
+        }
+    }
+}
+
+#[cfg(feature = "client")]
+#[async_trait]
+impl LinkChecker for HttpLinkChecker {
+    async fn check(&self, url: &str) -> Result<(), String> {
+        let mut response = self
+            .client
+            .head(url)
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+        if response.status() == reqwest::StatusCode::METHOD_NOT_ALLOWED {
+            response = self
+                .client
+                .get(url)
+                .send()
+                .await
+                .map_err(|e| e.to_string())?;
+        }
+        if response.status().is_success() || response.status().is_redirection() {
+            Ok(())
+        } else {
+            Err(format!("responded with {}", response.status()))
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CheckStatus {
+    Passed,
+    Failed,
+    Skipped,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct ReadinessCheck {
+    pub check: String,
+    pub status: CheckStatus,
+    pub message: String,
+}
+
+impl fmt::Display for ReadinessCheck {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let marker = match self.status {
+            CheckStatus::Passed => "ok",
+            CheckStatus::Failed => "FAILED",
+            CheckStatus::Skipped => "skipped",
+        };
+        write!(f, "[{}] {}: {}", marker, self.check, self.message)
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct PublishReport {
+    pub package: String,
+    pub version: String,
+    pub checks: Vec<ReadinessCheck>,
+}
+
+impl PublishReport {
+    pub fn is_ready(&self) -> bool {
+        self.failures().is_empty()
+    }
+
+    pub fn failures(&self) -> Vec<&ReadinessCheck> {
+        self.checks
+            .iter()
+            .filter(|check| check.status == CheckStatus::Failed)
+            .collect()
+    }
+
+    pub fn render_text(&self) -> String {
+        let mut out = String::new();
+        for check in &self.checks {
+            writeln!(out, "{}", check).unwrap();
+        }
+        let verdict = if self.is_ready() {
+            "ready to publish"
+        } else {
+            "not ready to publish"
+        };
+        writeln!(out, "{} {} is {}", self.package, self.version, verdict).unwrap();
+        out
+    }
+
+    fn push(&mut self, check: &str, status: CheckStatus, message: String) {
+        self.checks.push(ReadinessCheck {
+            check: check.to_string(),
+            status,
+            message,
+        });
+    }
+}
+
+impl PackageMetadata {
+    /// Runs every check before `manifest` gets published. Links are only checked when a
+    /// `link_checker` is given, so offline runs can skip them.
+    pub async fn check_publish_readiness(
+        &self,
+        manifest: &PackageManifest,
+        registry: &dyn VersionRegistry,
+        link_checker: Option<&dyn LinkChecker>,
+    ) -> PublishReport {
+        let version = manifest.version.formatted();
+        let mut report = PublishReport {
+            package: manifest.name.clone(),
+            version: version.clone(),
+            checks: vec![],
+        };
+
+        match registry.published_versions(&manifest.name).await {
+            Ok(published) if published.contains(&manifest.version) => report.push(
+                "version-unpublished",
+                CheckStatus::Failed,
+                format!("{} is already published", version),
+            ),
+            Ok(_) => report.push(
+                "version-unpublished",
+                CheckStatus::Passed,
+                format!("{} is not published yet", version),
+            ),
+            Err(e) => report.push(
+                "version-unpublished",
+                CheckStatus::Failed,
+                format!("Could not query the registry: {}", e),
+            ),
+        }
+
+        let channel = manifest.version.channel;
+        match Channel::from_branch(&manifest.branch) {
+            Some(expected) if expected == channel => report.push(
+                "channel-matches-branch",
+                CheckStatus::Passed,
+                format!("{} releases come from '{}'", channel, manifest.branch),
+            ),
+            Some(expected) => report.push(
+                "channel-matches-branch",
+                CheckStatus::Failed,
+                format!(
+                    "'{}' releases the {} channel, not {}",
+                    manifest.branch, expected, channel
+                ),
+            ),
+            None => report.push(
+                "channel-matches-branch",
+                CheckStatus::Failed,
+                format!("'{}' is not a release branch", manifest.branch),
+            ),
+        }
+
+        for link in &self.links {
+            let (status, message) = match link_checker {
+                None => (CheckStatus::Skipped, format!("{} not checked", link.link)),
+                Some(checker) => match checker.check(&link.link).await {
+                    Ok(()) => (CheckStatus::Passed, format!("{} resolves", link.link)),
+                    Err(e) => (CheckStatus::Failed, format!("{} {}", link.link, e)),
+                },
+            };
+            report.push("link-resolves", status, message);
+        }
+
+        for dependency in &manifest.dependencies {
+            let (status, message) = match registry.published_versions(&dependency.name).await {
+                Ok(published) => {
+                    let compatible = published
+                        .iter()
+                        .filter(|v| v.satisfies(&dependency.requirement).unwrap_or(false))
+                        .max();
+                    match compatible {
+                        Some(v) => (
+                            CheckStatus::Passed,
+                            format!("{} {} is published", dependency.name, v.formatted()),
+                        ),
+                        None => (
+                            CheckStatus::Failed,
+                            format!(
+                                "No published version of {} matches '{}'",
+                                dependency.name, dependency.requirement
+                            ),
+                        ),
+                    }
+                }
+                Err(e) => (
+                    CheckStatus::Failed,
+                    format!(
+                        "Could not query the registry for {}: {}",
+                        dependency.name, e
+                    ),
+                ),
+            };
+            report.push("dependency-published", status, message);
+        }
+
+        report
+    }
+}
@@ -0,0 +1,617 @@
+use async_trait::async_trait;
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex, Weak},
+    time::{Duration, Instant},
+};
+use tokio::sync::broadcast;
+
+use crate::{
+    events::{PublishError, RealtimePublisher},
+    rocket_models::RealtimeMessage,
+    shutdown::ShutdownToken,
+};
+
+// Messages a slow subscriber may fall behind by before it misses some
+const DEFAULT_CAPACITY: usize = 256;
+// What the replay buffers keep per topic by default
+const DEFAULT_REPLAY_MESSAGES: usize = 100;
+const DEFAULT_REPLAY_AGE: Duration = Duration::from_secs(300);
+// Longest a long poll waits, under the 60 seconds proxies commonly time out idle requests after
+const MAX_LONG_POLL: Duration = Duration::from_secs(55);
+
+// A message as delivered by the hub, `id` counting up from 1 within its topic
+#[derive(Debug, Serialize, Clone, PartialEq, JsonSchema)]
+pub struct HubMessage {
+    pub id: u64,
+    #[serde(flatten)]
+    pub message: RealtimeMessage,
+}
+
+// Numbers the messages of each topic and keeps the recent ones, for subscribers reconnecting
+// after a dropped connection to get what they missed
+#[async_trait]
+pub trait ReplayBuffer: Send + Sync {
+    /// Stores `message` under the next id of its topic.
+    async fn append(&self, message: RealtimeMessage) -> Result<HubMessage, PublishError>;
+
+    /// The kept messages of `topic` with an id above `offset`, oldest first.
+    async fn since(&self, topic: &str, offset: u64) -> Result<Vec<HubMessage>, PublishError>;
+}
+
+#[derive(Default)]
+struct RingTopic {
+    next_id: u64,
+    messages: VecDeque<(Instant, HubMessage)>,
+    last_sent: Option<Instant>,
+}
+
+// Keeps the last `max_messages` of each topic sent within `max_age` in memory. Ids start over
+// when the process restarts, and for topics quiet for `max_age`, which are dropped.
+pub struct MemoryReplay {
+    topics: Mutex<HashMap<String, RingTopic>>,
+    max_messages: usize,
+    max_age: Duration,
+}
+
+impl Default for MemoryReplay {
+    fn default() -> Self {
+        MemoryReplay {
+            topics: Mutex::new(HashMap::new()),
+            max_messages: DEFAULT_REPLAY_MESSAGES,
+            max_age: DEFAULT_REPLAY_AGE,
+        }
+    }
+}
+
+impl MemoryReplay {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Messages kept per topic, 0 only numbers them.
+    pub fn max_messages(mut self, max_messages: usize) -> Self {
+        self.max_messages = max_messages;
+        self
+    }
+
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = max_age;
+        self
+    }
+}
+
+#[async_trait]
+impl ReplayBuffer for MemoryReplay {
+    async fn append(&self, message: RealtimeMessage) -> Result<HubMessage, PublishError> {
+        let mut topics = self.topics.lock().unwrap();
+        let now = Instant::now();
+        topics.retain(|_, topic| {
+            topic
+                .last_sent
+                .is_some_and(|sent_at| now.duration_since(sent_at) <= self.max_age)
+        });
+        let topic = topics.entry(message.topic.clone()).or_default();
+        topic.next_id += 1;
+        topic.last_sent = Some(now);
+        let message = HubMessage {
+            id: topic.next_id,
+            message,
+        };
+        topic.messages.push_back((now, message.clone()));
+        while topic.messages.len() > self.max_messages
+            || topic
+                .messages
+                .front()
+                .is_some_and(|(sent_at, _)| now.duration_since(*sent_at) > self.max_age)
+        {
+            topic.messages.pop_front();
+        }
+        Ok(message)
+    }
+
+    async fn since(&self, topic: &str, offset: u64) -> Result<Vec<HubMessage>, PublishError> {
+        let topics = self.topics.lock().unwrap();
+        Ok(topics
+            .get(topic)
+            .map(|topic| {
+                topic
+                    .messages
+                    .iter()
+                    .filter(|(sent_at, message)| {
+                        message.id > offset && sent_at.elapsed() <= self.max_age
+                    })
+                    .map(|(_, message)| message.clone())
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
+}
+
+#[cfg(feature = "redis")]
+pub use self::redis_replay::RedisReplay;
+
+#[cfg(feature = "redis")]
+mod redis_replay {
+    use async_trait::async_trait;
+    use redis::{aio::ConnectionManager, streams::StreamRangeReply, AsyncCommands};
+    use std::{
+        error::Error,
+        time::{Duration, SystemTime, UNIX_EPOCH},
+    };
+
+    use super::{HubMessage, ReplayBuffer, DEFAULT_REPLAY_AGE, DEFAULT_REPLAY_MESSAGES};
+    use crate::{events::PublishError, rocket_models::RealtimeMessage};
+
+    // Numbers and keeps a message in one step, so concurrent publishers cannot add their entries
+    // out of order
+    const APPEND: &str = r#"
+local id = redis.call("INCR", KEYS[2])
+redis.call("XADD", KEYS[1], "MAXLEN", "~", ARGV[1], id .. "-0", "payload", ARGV[2], "at", ARGV[3])
+redis.call("EXPIRE", KEYS[1], ARGV[4])
+redis.call("EXPIRE", KEYS[2], ARGV[4])
+return id
+"#;
+
+    fn now_millis() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_millis() as u64)
+            .unwrap_or_default()
+    }
+
+    // Keeps the recent messages of each topic in the Redis stream `{prefix}:{topic}`, its entry
+    // ids being the message ids, so replicas and restarted processes resume from the same ids.
+    // Topics quiet for `max_age` are dropped along with their numbering.
+    pub struct RedisReplay {
+        connection: ConnectionManager,
+        prefix: String,
+        max_messages: usize,
+        max_age: Duration,
+    }
+
+    impl RedisReplay {
+        pub async fn new(url: &str, prefix: &str) -> Result<Self, Box<dyn Error>> {
+            let client = redis::Client::open(url)
+                .map_err(|e| format!("Invalid Redis URL '{}': {}", url, e))?;
+            let connection = ConnectionManager::new(client)
+                .await
+                .map_err(|e| format!("Failed to connect to Redis at '{}': {}", url, e))?;
+            Ok(RedisReplay {
+                connection,
+                prefix: prefix.to_string(),
+                max_messages: DEFAULT_REPLAY_MESSAGES,
+                max_age: DEFAULT_REPLAY_AGE,
+            })
+        }
+
+        /// Messages kept per topic, approximately: Redis trims streams by whole nodes.
+        pub fn max_messages(mut self, max_messages: usize) -> Self {
+            self.max_messages = max_messages;
+            self
+        }
+
+        pub fn max_age(mut self, max_age: Duration) -> Self {
+            self.max_age = max_age;
+            self
+        }
+
+        fn stream_key(&self, topic: &str) -> String {
+            format!("{}:{}", self.prefix, topic)
+        }
+    }
+
+    #[async_trait]
+    impl ReplayBuffer for RedisReplay {
+        async fn append(&self, message: RealtimeMessage) -> Result<HubMessage, PublishError> {
+            let mut connection = self.connection.clone();
+            let stream_key = self.stream_key(&message.topic);
+            let sequence_key = format!("{}:seq", stream_key);
+            let id: u64 = redis::cmd("EVAL")
+                .arg(APPEND)
+                .arg(2)
+                .arg(&stream_key)
+                .arg(&sequence_key)
+                .arg(self.max_messages)
+                .arg(&message.payload)
+                .arg(now_millis())
+                .arg(self.max_age.as_secs().max(1))
+                .query_async(&mut connection)
+                .await
+                .map_err(|e| format!("Failed to keep a message of '{}': {}", message.topic, e))?;
+            Ok(HubMessage { id, message })
+        }
+
+        async fn since(&self, topic: &str, offset: u64) -> Result<Vec<HubMessage>, PublishError> {
+            let mut connection = self.connection.clone();
+            let reply: StreamRangeReply = connection
+                .xrange(
+                    self.stream_key(topic),
+                    format!("{}-0", offset.saturating_add(1)),
+                    "+",
+                )
+                .await
+                .map_err(|e| format!("Failed to read the messages of '{}': {}", topic, e))?;
+            let oldest = now_millis().saturating_sub(self.max_age.as_millis() as u64);
+            Ok(reply
+                .ids
+                .into_iter()
+                .filter(|entry| entry.get::<u64>("at").is_some_and(|at| at >= oldest))
+                .filter_map(|entry| {
+                    let id = entry.id.split('-').next()?.parse().ok()?;
+                    if id <= offset {
+                        return None; // The start saturates at `u64::MAX`
+                    }
+                    Some(HubMessage {
+                        id,
+                        message: RealtimeMessage {
+                            topic: topic.to_string(),
+                            payload: entry.get("payload")?,
+                        },
+                    })
+                })
+                .collect())
+        }
+    }
+}
+
+#[derive(Clone)]
+struct HubTopic {
+    sender: broadcast::Sender<HubMessage>,
+    // Keeps messages of the topic from overtaking each other between numbering and delivery
+    sending: Arc<tokio::sync::Mutex<()>>,
+}
+
+// Fans the messages of each topic out to the subscribers of this process, e.g. the SSE streams
+// of connected browsers, numbering them through a `ReplayBuffer` so reconnecting subscribers
+// resume where they left off. Publish through it like any `RealtimePublisher`. Topics are
+// dropped along with their last subscriber.
+pub struct RealtimeHub {
+    topics: Arc<Mutex<HashMap<String, HubTopic>>>,
+    capacity: usize,
+    replay: Arc<dyn ReplayBuffer>,
+    shutdown: ShutdownToken,
+}
+
+impl Default for RealtimeHub {
+    fn default() -> Self {
+        RealtimeHub {
+            topics: Arc::new(Mutex::new(HashMap::new())),
+            capacity: DEFAULT_CAPACITY,
+            replay: Arc::new(MemoryReplay::default()),
+            shutdown: ShutdownToken::new(),
+        }
+    }
+}
+
+// Forgets `topic` once it has no more than `receivers` subscribers and no message is being sent
+fn prune_topic(topics: &Mutex<HashMap<String, HubTopic>>, topic: &str, receivers: usize) {
+    let mut topics = topics.lock().unwrap();
+    let unused = topics.get(topic).is_some_and(|hub_topic| {
+        hub_topic.sender.receiver_count() <= receivers && Arc::strong_count(&hub_topic.sending) == 1
+    });
+    if unused {
+        topics.remove(topic);
+    }
+}
+
+impl RealtimeHub {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replaces the default `MemoryReplay`, e.g. with a `RedisReplay` shared by the replicas.
+    pub fn with_replay<R: ReplayBuffer + 'static>(mut self, replay: R) -> Self {
+        self.replay = Arc::new(replay);
+        self
+    }
+
+    /// Ends the subscriptions, and so the event streams and long polls, once `shutdown` is
+    /// triggered, so Rocket does not wait on them to stop.
+    pub fn with_shutdown(mut self, shutdown: ShutdownToken) -> Self {
+        self.shutdown = shutdown;
+        self
+    }
+
+    fn topic<T>(&self, topic: &str, f: impl FnOnce(&HubTopic) -> T) -> T {
+        let mut topics = self.topics.lock().unwrap();
+        let hub_topic = topics.entry(topic.to_string()).or_insert_with(|| HubTopic {
+            sender: broadcast::channel(self.capacity).0,
+            sending: Arc::new(tokio::sync::Mutex::new(())),
+        });
+        f(hub_topic)
+    }
+
+    /// Delivers `message` to the current subscribers of its topic and returns its id. Fails,
+    /// without delivering it, when the replay buffer does.
+    pub async fn send(&self, message: RealtimeMessage) -> Result<u64, PublishError> {
+        let name = message.topic.clone();
+        let topic = self.topic(&name, HubTopic::clone);
+        let sent = {
+            let _sending = topic.sending.lock().await;
+            self.replay.append(message).await.map(|message| {
+                // Fails only without subscribers
+                let _ = topic.sender.send(message.clone());
+                message.id
+            })
+        };
+        drop(topic);
+        prune_topic(&self.topics, &name, 0);
+        sent
+    }
+
+    /// The messages of `topic` from now on.
+    pub fn subscribe(&self, topic: &str) -> Subscription {
+        Subscription {
+            backlog: VecDeque::new(),
+            receiver: self.topic(topic, |hub_topic| hub_topic.sender.subscribe()),
+            replayed_until: None,
+            last_id: None,
+            topic: topic.to_string(),
+            topics: Arc::downgrade(&self.topics),
+            shutdown: self.shutdown.clone(),
+        }
+    }
+
+    /// The messages of `topic` after the id `offset`, those kept by the replay buffer first,
+    /// e.g. from the `Last-Event-ID` of a reconnecting browser.
+    pub async fn subscribe_from(&self, topic: &str, offset: u64) -> Subscription {
+        // Subscribed before reading the buffer, messages sent in between are skipped once
+        let mut subscription = self.subscribe(topic);
+        subscription.last_id = Some(offset);
+        match self.replay.since(topic, offset).await {
+            Ok(messages) => {
+                subscription.replayed_until = messages.last().map(|message| message.id);
+                subscription.backlog = messages.into();
+            }
+            Err(e) => tracing::warn!(error = %e, topic, "failed to replay realtime messages"),
+        }
+        subscription
+    }
+
+    /// Waits up to `timeout`, capped at 55 seconds, for messages of `topic` after the id `since`
+    /// and returns all that arrived, none when it timed out. Without `since` only messages sent
+    /// from now on count.
+    pub async fn wait_for_message(
+        &self,
+        topic: &str,
+        since: Option<u64>,
+        timeout: Duration,
+    ) -> LongPoll {
+        let mut subscription = match since {
+            Some(offset) => self.subscribe_from(topic, offset).await,
+            None => self.subscribe(topic),
+        };
+        let mut messages = vec![];
+        if let Ok(Some(message)) =
+            tokio::time::timeout(timeout.min(MAX_LONG_POLL), subscription.next()).await
+        {
+            messages.push(message);
+            while let Some(message) = subscription.try_next() {
+                messages.push(message);
+            }
+        }
+        LongPoll {
+            messages,
+            last_id: subscription.last_id(),
+        }
+    }
+
+    pub fn subscriber_count(&self, topic: &str) -> usize {
+        self.topics
+            .lock()
+            .unwrap()
+            .get(topic)
+            .map(|topic| topic.sender.receiver_count())
+            .unwrap_or(0)
+    }
+}
+
+#[async_trait]
+impl RealtimePublisher for RealtimeHub {
+    async fn publish(&self, message: RealtimeMessage) -> Result<(), PublishError> {
+        self.send(message).await?;
+        Ok(())
+    }
+}
+
+pub struct Subscription {
+    backlog: VecDeque<HubMessage>,
+    receiver: broadcast::Receiver<HubMessage>,
+    // Live messages up to this id were already in the backlog. Other ids are never skipped, so
+    // a topic numbered anew, e.g. after a restart, still reaches its subscribers
+    replayed_until: Option<u64>,
+    last_id: Option<u64>,
+    topic: String,
+    topics: Weak<Mutex<HashMap<String, HubTopic>>>,
+    shutdown: ShutdownToken,
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        if let Some(topics) = self.topics.upgrade() {
+            // The receiver of the subscription is dropped after this, so it still counts
+            prune_topic(&topics, &self.topic, 1);
+        }
+    }
+}
+
+impl Subscription {
+    fn replayed(&self, message: &HubMessage) -> bool {
+        self.replayed_until.is_some_and(|until| message.id <= until)
+    }
+
+    /// The next message, `None` once the hub is gone or shutting down. A subscriber too slow
+    /// to keep up skips the messages it fell behind on.
+    pub async fn next(&mut self) -> Option<HubMessage> {
+        if self.shutdown.is_triggered() {
+            return None;
+        }
+        let message = match self.backlog.pop_front() {
+            Some(message) => message,
+            None => loop {
+                let received = tokio::select! {
+                    received = self.receiver.recv() => received,
+                    _ = self.shutdown.cancelled() => return None,
+                };
+                match received {
+                    Ok(message) if self.replayed(&message) => {}
+                    Ok(message) => break message,
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        tracing::warn!(skipped, "realtime subscriber fell behind");
+                    }
+                    Err(broadcast::error::RecvError::Closed) => return None,
+                }
+            },
+        };
+        self.last_id = Some(message.id);
+        Some(message)
+    }
+
+    // The next message if one is already there
+    fn try_next(&mut self) -> Option<HubMessage> {
+        let message = match self.backlog.pop_front() {
+            Some(message) => message,
+            None => loop {
+                match self.receiver.try_recv() {
+                    Ok(message) if self.replayed(&message) => {}
+                    Ok(message) => break message,
+                    Err(broadcast::error::TryRecvError::Lagged(skipped)) => {
+                        tracing::warn!(skipped, "realtime subscriber fell behind");
+                    }
+                    Err(_) => return None,
+                }
+            },
+        };
+        self.last_id = Some(message.id);
+        Some(message)
+    }
+
+    pub fn last_id(&self) -> Option<u64> {
+        self.last_id
+    }
+}
+
+// The answer of a long poll, for clients without persistent connections, e.g.
+//
+// #[get("/builds/<id>/poll?<since>&<timeout>")]
+// async fn poll_build(hub: &State<RealtimeHub>, id: &str, since: Option<u64>, timeout: Option<u64>)
+//     -> Json<LongPoll> {
+//     let timeout = Duration::from_secs(timeout.unwrap_or(30));
+//     Json(hub.wait_for_message(&format!("build.{}", id), since, timeout).await)
+// }
+//
+// Clients pass `last_id` as `since` of the next poll, so nothing is missed between polls as far
+// as the replay buffer of the hub goes back.
+#[derive(Debug, Serialize, Clone, PartialEq, JsonSchema)]
+pub struct LongPoll {
+    pub messages: Vec<HubMessage>,
+    pub last_id: Option<u64>,
+}
+
+#[cfg(feature = "rocket")]
+pub use self::sse::{event_stream, LastEventId};
+
+#[cfg(feature = "rocket")]
+mod sse {
+    use okapi::openapi3::{Object, Parameter, ParameterValue};
+    use rocket::{
+        futures::{stream, Stream},
+        request::{FromRequest, Outcome, Request},
+        response::stream::{Event, EventStream},
+    };
+    use rocket_okapi::{
+        gen::OpenApiGenerator,
+        request::{OpenApiFromRequest, RequestHeaderInput},
+    };
+    use std::{convert::Infallible, time::Duration};
+
+    use super::{RealtimeHub, Subscription};
+
+    const LAST_EVENT_ID_HEADER: &str = "Last-Event-ID";
+
+    // The id of the last event an `EventSource` received, sent by browsers when they reconnect
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub struct LastEventId(pub Option<u64>);
+
+    impl LastEventId {
+        /// Resumes `topic` after this id, or subscribes from now on for a first connection.
+        pub async fn subscribe(&self, hub: &RealtimeHub, topic: &str) -> Subscription {
+            match self.0 {
+                Some(offset) => hub.subscribe_from(topic, offset).await,
+                None => hub.subscribe(topic),
+            }
+        }
+    }
+
+    #[rocket::async_trait]
+    impl<'r> FromRequest<'r> for LastEventId {
+        type Error = Infallible;
+
+        async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+            let id = request
+                .headers()
+                .get_one(LAST_EVENT_ID_HEADER)
+                .and_then(|id| id.trim().parse().ok());
+            Outcome::Success(LastEventId(id))
+        }
+    }
+
+    impl<'a> OpenApiFromRequest<'a> for LastEventId {
+        fn from_request_input(
+            gen: &mut OpenApiGenerator,
+            _name: String,
+            _required: bool,
+        ) -> rocket_okapi::Result<RequestHeaderInput> {
+            let schema = gen.json_schema::<u64>();
+            Ok(RequestHeaderInput::Parameter(Parameter {
+                name: LAST_EVENT_ID_HEADER.to_owned(),
+                location: "header".to_owned(),
+                description: Some(
+                    "Id of the last event received, the events missed since are sent first"
+                        .to_owned(),
+                ),
+                required: false,
+                deprecated: false,
+                allow_empty_value: false,
+                value: ParameterValue::Schema {
+                    style: None,
+                    explode: None,
+                    allow_reserved: false,
+                    schema,
+                    example: None,
+                    examples: None,
+                },
+                extensions: Object::default(),
+            }))
+        }
+    }
+
+    /// The messages of `subscription` as server-sent events, named after their topic, with a
+    /// comment sent every `heartbeat` so proxies keep the idle connection open. The stream ends
+    /// with the shutdown token of the hub, see `RealtimeHub::with_shutdown`, e.g.
+    ///
+    /// ```ignore
+    /// #[get("/builds/<id>/events")]
+    /// async fn build_events(hub: &State<RealtimeHub>, id: &str, last: LastEventId)
+    ///     -> EventStream<impl Stream<Item = Event>> {
+    ///     let subscription = last.subscribe(hub, &format!("build.{}", id)).await;
+    ///     event_stream(subscription, Duration::from_secs(15))
+    /// }
+    /// ```
+    pub fn event_stream(
+        subscription: Subscription,
+        heartbeat: Duration,
+    ) -> EventStream<impl Stream<Item = Event>> {
+        let events = stream::unfold(subscription, |mut subscription| async move {
+            let message = subscription.next().await?;
+            let event = Event::data(message.message.payload)
+                .event(message.message.topic)
+                .id(message.id.to_string());
+            Some((event, subscription))
+        });
+        EventStream::from(events).heartbeat(heartbeat)
+    }
+}
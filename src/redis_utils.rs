@@ -0,0 +1,249 @@
+use redis::{
+    aio::{ConnectionManager, ConnectionManagerConfig},
+    AsyncCommands,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use std::{error::Error, marker::PhantomData, time::Duration};
+
+use crate::{
+    connection::DbCredentials, DatabaseConfig, DbEngine, DbType, Environment, GingerDBConfig,
+};
+
+pub type RedisError = Box<dyn Error + Send + Sync>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RedisSettings {
+    pub connection_timeout: Duration,
+    pub response_timeout: Duration,
+    pub retries: usize, // Reconnection attempts before a command fails
+}
+
+impl RedisSettings {
+    pub fn for_environment(env: &Environment) -> Self {
+        match env {
+            Environment::Dev => RedisSettings {
+                connection_timeout: Duration::from_secs(2),
+                response_timeout: Duration::from_secs(2),
+                retries: 2,
+            },
+            _ => RedisSettings {
+                connection_timeout: Duration::from_secs(5),
+                response_timeout: Duration::from_secs(3),
+                retries: 6,
+            },
+        }
+    }
+}
+
+// A multiplexed, reconnecting connection shared by the whole service, cheap to clone
+#[derive(Clone)]
+pub struct RedisPool {
+    connection: ConnectionManager,
+}
+
+impl RedisPool {
+    pub async fn connect(url: &str, settings: &RedisSettings) -> Result<Self, RedisError> {
+        let client = redis::Client::open(url).map_err(|e| format!("Invalid Redis URL: {}", e))?;
+        let config = ConnectionManagerConfig::new()
+            .set_connection_timeout(settings.connection_timeout)
+            .set_response_timeout(settings.response_timeout)
+            .set_number_of_retries(settings.retries);
+        let connection = ConnectionManager::new_with_config(client, config)
+            .await
+            .map_err(|e| format!("Failed to connect to Redis: {}", e))?;
+        Ok(RedisPool { connection })
+    }
+
+    /// Connects to a cache entry of the db config as seen from `env`.
+    pub async fn for_database(
+        database: &DatabaseConfig,
+        env: &Environment,
+        credentials: &DbCredentials,
+    ) -> Result<Self, RedisError> {
+        if database.effective_engine() != DbEngine::Redis {
+            return Err(format!("The database '{}' is not a Redis database", database.name).into());
+        }
+        let url = database
+            .connection_url(env, credentials)
+            .map_err(|e| e.to_string())?;
+        Self::connect(&url, &RedisSettings::for_environment(env)).await
+    }
+
+    pub fn connection(&self) -> ConnectionManager {
+        self.connection.clone()
+    }
+
+    pub async fn ping(&self) -> Result<(), RedisError> {
+        let _: String = redis::cmd("PING")
+            .query_async(&mut self.connection())
+            .await?;
+        Ok(())
+    }
+}
+
+/// The enabled cache database named `name`, or the only one when no name is given.
+pub fn cache_database<'a>(
+    config: &'a GingerDBConfig,
+    name: Option<&str>,
+) -> Result<&'a DatabaseConfig, Box<dyn Error>> {
+    let caches: Vec<&DatabaseConfig> = config
+        .database
+        .iter()
+        .filter(|db| db.db_type == DbType::Cache && db.enable)
+        .collect();
+    match name {
+        Some(name) => caches
+            .into_iter()
+            .find(|db| db.name == name)
+            .ok_or_else(|| format!("There is no enabled cache database named '{}'", name).into()),
+        None => match caches.as_slice() {
+            [cache] => Ok(cache),
+            [] => Err("There is no enabled cache database in the db config".into()),
+            _ => Err("There are several cache databases, name the one to use".into()),
+        },
+    }
+}
+
+// Values of one type stored as JSON under `{namespace}:{id}`, e.g.
+// `TypedRedis::<SessionData>::new("sessions").get(&pool, "abc")` reads `sessions:abc`
+pub struct TypedRedis<T> {
+    namespace: String,
+    ttl: Option<Duration>,
+    _value: PhantomData<fn() -> T>,
+}
+
+impl<T> Clone for TypedRedis<T> {
+    fn clone(&self) -> Self {
+        TypedRedis {
+            namespace: self.namespace.clone(),
+            ttl: self.ttl,
+            _value: PhantomData,
+        }
+    }
+}
+
+impl<T: Serialize + DeserializeOwned> TypedRedis<T> {
+    pub fn new(namespace: &str) -> Self {
+        TypedRedis {
+            namespace: namespace.trim_end_matches(':').to_string(),
+            ttl: None,
+            _value: PhantomData,
+        }
+    }
+
+    /// Expiry of the values written by `set`, none by default.
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    /// A namespace nested in this one, e.g. `sessions:revoked`.
+    pub fn child<U: Serialize + DeserializeOwned>(&self, namespace: &str) -> TypedRedis<U> {
+        TypedRedis::new(&format!("{}:{}", self.namespace, namespace))
+    }
+
+    pub fn key(&self, id: &str) -> String {
+        format!("{}:{}", self.namespace, id)
+    }
+
+    pub async fn get(&self, pool: &RedisPool, id: &str) -> Result<Option<T>, RedisError> {
+        let raw: Option<String> = pool.connection().get(self.key(id)).await?;
+        match raw {
+            Some(raw) => Ok(Some(serde_json::from_str(&raw).map_err(|e| {
+                format!("The value of '{}' cannot be read: {}", self.key(id), e)
+            })?)),
+            None => Ok(None),
+        }
+    }
+
+    pub async fn set(&self, pool: &RedisPool, id: &str, value: &T) -> Result<(), RedisError> {
+        match self.ttl {
+            Some(ttl) => self.set_ex(pool, id, value, ttl).await,
+            None => {
+                let raw = serde_json::to_string(value)?;
+                let _: () = pool.connection().set(self.key(id), raw).await?;
+                Ok(())
+            }
+        }
+    }
+
+    pub async fn set_ex(
+        &self,
+        pool: &RedisPool,
+        id: &str,
+        value: &T,
+        ttl: Duration,
+    ) -> Result<(), RedisError> {
+        let raw = serde_json::to_string(value)?;
+        let _: () = pool
+            .connection()
+            .set_ex(self.key(id), raw, ttl.as_secs().max(1))
+            .await?;
+        Ok(())
+    }
+
+    pub async fn delete(&self, pool: &RedisPool, id: &str) -> Result<bool, RedisError> {
+        let removed: usize = pool.connection().del(self.key(id)).await?;
+        Ok(removed > 0)
+    }
+
+    pub async fn exists(&self, pool: &RedisPool, id: &str) -> Result<bool, RedisError> {
+        Ok(pool.connection().exists(self.key(id)).await?)
+    }
+
+    /// Pushes the expiry of `id` back to `ttl` from now, false when it does not exist.
+    pub async fn expire(
+        &self,
+        pool: &RedisPool,
+        id: &str,
+        ttl: Duration,
+    ) -> Result<bool, RedisError> {
+        Ok(pool
+            .connection()
+            .expire(self.key(id), ttl.as_secs().max(1) as i64)
+            .await?)
+    }
+}
+
+#[cfg(feature = "rocket")]
+pub use self::fairings::{fairing, fairing_for_database};
+
+#[cfg(feature = "rocket")]
+mod fairings {
+    use rocket::fairing::AdHoc;
+
+    use super::{RedisPool, RedisSettings};
+    use crate::{connection::DbCredentials, DatabaseConfig, Environment};
+
+    /// Connects when Rocket ignites and manages the `RedisPool`, failing the launch when Redis
+    /// cannot be reached.
+    pub fn fairing(url: &str, env: Environment) -> AdHoc {
+        let url = url.to_string();
+        AdHoc::try_on_ignite("Redis", move |rocket| async move {
+            let pool = RedisPool::connect(&url, &RedisSettings::for_environment(&env)).await;
+            match pool {
+                Ok(pool) => Ok(rocket.manage(pool)),
+                Err(e) => {
+                    tracing::error!(error = %e, "failed to connect to Redis");
+                    Err(rocket)
+                }
+            }
+        })
+    }
+
+    pub fn fairing_for_database(
+        database: DatabaseConfig,
+        env: Environment,
+        credentials: DbCredentials,
+    ) -> AdHoc {
+        AdHoc::try_on_ignite("Redis", move |rocket| async move {
+            match RedisPool::for_database(&database, &env, &credentials).await {
+                Ok(pool) => Ok(rocket.manage(pool)),
+                Err(e) => {
+                    tracing::error!(error = %e, database = %database.name, "failed to connect to Redis");
+                    Err(rocket)
+                }
+            }
+        })
+    }
+}
@@ -0,0 +1,256 @@
+use async_trait::async_trait;
+use reqwest::{Client, StatusCode};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{error::Error, fmt};
+
+use crate::{publish::VersionRegistry, Channel, Version, LANG};
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Registry {
+    Npm,
+    PyPi,
+    CratesIo,
+}
+
+impl Registry {
+    /// The registry packages of `lang` are published to, shell packages are not published.
+    pub fn for_lang(lang: &LANG) -> Option<Registry> {
+        match lang {
+            LANG::Rust => Some(Registry::CratesIo),
+            LANG::TS => Some(Registry::Npm),
+            LANG::Python => Some(Registry::PyPi),
+            LANG::Shell => None,
+        }
+    }
+
+    pub fn default_base_url(&self) -> &'static str {
+        match self {
+            Registry::Npm => "https://registry.npmjs.org",
+            Registry::PyPi => "https://pypi.org",
+            Registry::CratesIo => "https://crates.io",
+        }
+    }
+}
+
+impl fmt::Display for Registry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Registry::Npm => write!(f, "npm"),
+            Registry::PyPi => write!(f, "PyPI"),
+            Registry::CratesIo => write!(f, "crates.io"),
+        }
+    }
+}
+
+/// Parses a published version into our model, `None` for versions the releaser would never
+/// have produced (e.g. `1.0.0-rc.1`). Besides our `1.2.3-nightly.4` form, PEP 440 pre-releases
+/// (`1.2.3a4`, `1.2.3b4`, `1.2.3.dev4`) are understood.
+pub fn parse_published_version(version: &str) -> Option<Version> {
+    let (base, channel, revision) = match version.split_once('-') {
+        Some((base, pre)) => {
+            let (channel, revision) = pre.split_once('.')?;
+            (base, channel_of(channel)?, revision.parse().ok()?)
+        }
+        None => match version.find(['a', 'b']).or_else(|| version.find(".dev")) {
+            Some(index) => {
+                let (base, pre) = version.split_at(index);
+                let (channel, revision) = if let Some(revision) = pre.strip_prefix(".dev") {
+                    (Channel::Nightly, revision)
+                } else if let Some(revision) = pre.strip_prefix('a') {
+                    (Channel::Alpha, revision)
+                } else {
+                    (Channel::Beta, pre.strip_prefix('b')?)
+                };
+                (base, channel, revision.parse().ok()?)
+            }
+            None => (version, Channel::Final, 0),
+        },
+    };
+
+    let parts: Vec<u32> = base
+        .split('.')
+        .map(|part| part.parse().ok())
+        .collect::<Option<_>>()?;
+    match parts[..] {
+        [major, minor, patch] => Some(Version {
+            channel,
+            major,
+            minor,
+            patch,
+            revision,
+        }),
+        _ => None,
+    }
+}
+
+fn channel_of(channel: &str) -> Option<Channel> {
+    match channel {
+        "nightly" | "dev" => Some(Channel::Nightly),
+        "alpha" => Some(Channel::Alpha),
+        "beta" => Some(Channel::Beta),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RegistryClient {
+    registry: Registry,
+    base_url: String,
+    http: Client,
+}
+
+impl RegistryClient {
+    pub fn new(registry: Registry) -> Self {
+        Self::with_base_url(registry, registry.default_base_url())
+    }
+
+    /// A client of a mirror or private registry speaking the API of `registry`.
+    pub fn with_base_url(registry: Registry, base_url: &str) -> Self {
+        RegistryClient {
+            registry,
+            base_url: base_url.trim_end_matches('/').to_string(),
+            // crates.io rejects requests without a user agent
+            http: Client::builder()
+                .user_agent(concat!("ginger-shared-rs/", env!("CARGO_PKG_VERSION")))
+                .build()
+                .unwrap_or_default(),
+        }
+    }
+
+    pub fn registry(&self) -> Registry {
+        self.registry
+    }
+
+    /// Every published version of `name` our model can represent, empty when the package was
+    /// never published. Yanked crates are left out.
+    pub async fn versions(&self, name: &str) -> Result<Vec<Version>, Box<dyn Error + Send + Sync>> {
+        let url = match self.registry {
+            // Scoped packages keep their `@` but need the `/` escaped
+            Registry::Npm => format!("{}/{}", self.base_url, name.replace('/', "%2f")),
+            Registry::PyPi => format!("{}/pypi/{}/json", self.base_url, name),
+            Registry::CratesIo => format!("{}/api/v1/crates/{}", self.base_url, name),
+        };
+        let response = self.http.get(&url).send().await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(vec![]);
+        }
+        if !response.status().is_success() {
+            return Err(format!(
+                "{} responded with {} for '{}'",
+                self.registry,
+                response.status(),
+                name
+            )
+            .into());
+        }
+        let body: Value = response.json().await?;
+
+        let raw: Vec<&str> = match self.registry {
+            Registry::Npm => body["versions"]
+                .as_object()
+                .map(|versions| versions.keys().map(String::as_str).collect())
+                .unwrap_or_default(),
+            Registry::PyPi => body["releases"]
+                .as_object()
+                .map(|releases| releases.keys().map(String::as_str).collect())
+                .unwrap_or_default(),
+            Registry::CratesIo => body["versions"]
+                .as_array()
+                .map(|versions| {
+                    versions
+                        .iter()
+                        .filter(|version| !version["yanked"].as_bool().unwrap_or(false))
+                        .filter_map(|version| version["num"].as_str())
+                        .collect()
+                })
+                .unwrap_or_default(),
+        };
+
+        let mut versions: Vec<Version> = raw
+            .into_iter()
+            .filter_map(parse_published_version)
+            .collect();
+        versions.sort();
+        Ok(versions)
+    }
+
+    /// The highest published version of `channel`.
+    pub async fn latest_in_channel(
+        &self,
+        name: &str,
+        channel: Channel,
+    ) -> Result<Option<Version>, Box<dyn Error + Send + Sync>> {
+        Ok(self
+            .versions(name)
+            .await?
+            .into_iter()
+            .filter(|version| version.channel == channel)
+            .max())
+    }
+
+    pub async fn is_published(
+        &self,
+        name: &str,
+        version: &Version,
+    ) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        Ok(self.versions(name).await?.contains(version))
+    }
+
+    /// The revision the next pre-release of `version`'s `major.minor.patch` and channel gets,
+    /// i.e. one past the highest published revision, `1` if none is published yet.
+    pub async fn next_revision(
+        &self,
+        name: &str,
+        version: &Version,
+    ) -> Result<u32, Box<dyn Error + Send + Sync>> {
+        let highest = self
+            .versions(name)
+            .await?
+            .into_iter()
+            .filter(|published| {
+                (
+                    published.major,
+                    published.minor,
+                    published.patch,
+                    published.channel,
+                ) == (version.major, version.minor, version.patch, version.channel)
+            })
+            .map(|published| published.revision)
+            .max();
+        Ok(highest.map_or(1, |revision| revision + 1))
+    }
+}
+
+#[async_trait]
+impl VersionRegistry for RegistryClient {
+    async fn published_versions(
+        &self,
+        name: &str,
+    ) -> Result<Vec<Version>, Box<dyn Error + Send + Sync>> {
+        self.versions(name).await
+    }
+}
+
+/// The highest final version of `name` published to `registry`.
+pub async fn latest_version(
+    name: &str,
+    registry: Registry,
+) -> Result<Option<Version>, Box<dyn Error>> {
+    RegistryClient::new(registry)
+        .latest_in_channel(name, Channel::Final)
+        .await
+        .map_err(|e| -> Box<dyn Error> { e })
+}
+
+pub async fn is_version_published(
+    name: &str,
+    version: &Version,
+    registry: Registry,
+) -> Result<bool, Box<dyn Error>> {
+    RegistryClient::new(registry)
+        .is_published(name, version)
+        .await
+        .map_err(|e| -> Box<dyn Error> { e })
+}
@@ -0,0 +1,227 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fmt,
+    path::Path,
+};
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum BumpKind {
+    Patch,
+    Minor,
+    Major,
+}
+
+impl fmt::Display for BumpKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BumpKind::Patch => write!(f, "patch"),
+            BumpKind::Minor => write!(f, "minor"),
+            BumpKind::Major => write!(f, "major"),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct WorkspacePackage {
+    pub name: String,
+    pub path: String, // Directory of the package, relative to the repository root
+    #[serde(default)]
+    pub dependencies: Vec<String>, // Names of other packages of the workspace
+}
+
+// The packages of a monorepo and how they depend on each other
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Default)]
+pub struct WorkspaceGraph {
+    pub packages: Vec<WorkspacePackage>,
+}
+
+impl WorkspaceGraph {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn package(mut self, name: &str, path: &str, dependencies: &[&str]) -> Self {
+        self.packages.push(WorkspacePackage {
+            name: name.to_string(),
+            path: path.trim_matches('/').to_string(),
+            dependencies: dependencies.iter().map(|d| d.to_string()).collect(),
+        });
+        self
+    }
+
+    /// The package a file belongs to, the innermost one when packages are nested.
+    pub fn package_for_path(&self, path: &str) -> Option<&WorkspacePackage> {
+        let path = Path::new(path.trim_start_matches("./"));
+        self.packages
+            .iter()
+            .filter(|package| package.path.is_empty() || path.starts_with(&package.path))
+            .max_by_key(|package| package.path.len())
+    }
+
+    /// Packages depending on `name`, directly or through other packages.
+    pub fn dependents_of(&self, name: &str) -> BTreeSet<String> {
+        let mut dependents = BTreeSet::new();
+        let mut pending = vec![name.to_string()];
+        while let Some(current) = pending.pop() {
+            for package in &self.packages {
+                if package.dependencies.contains(&current)
+                    && dependents.insert(package.name.clone())
+                {
+                    pending.push(package.name.clone());
+                }
+            }
+        }
+        dependents.remove(name);
+        dependents
+    }
+
+    /// Package names with dependencies before their dependents, names that are part of a
+    /// cycle come last in name order.
+    pub fn release_order(&self) -> Vec<String> {
+        let mut remaining: BTreeMap<&str, BTreeSet<&str>> = self
+            .packages
+            .iter()
+            .map(|package| {
+                let internal = package
+                    .dependencies
+                    .iter()
+                    .map(String::as_str)
+                    .filter(|d| self.packages.iter().any(|p| p.name == *d))
+                    .collect();
+                (package.name.as_str(), internal)
+            })
+            .collect();
+
+        let mut order = vec![];
+        loop {
+            let ready: Vec<&str> = remaining
+                .iter()
+                .filter(|(_, dependencies)| dependencies.is_empty())
+                .map(|(name, _)| *name)
+                .collect();
+            if ready.is_empty() {
+                break;
+            }
+            for name in ready {
+                remaining.remove(name);
+                for dependencies in remaining.values_mut() {
+                    dependencies.remove(name);
+                }
+                order.push(name.to_string());
+            }
+        }
+        order.extend(remaining.into_keys().map(str::to_string));
+        order
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[serde(tag = "reason", rename_all = "snake_case")]
+pub enum ReleaseReason {
+    FilesChanged { files: Vec<String> },
+    DependencyReleased { dependency: String },
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct ReleaseAction {
+    pub package: String,
+    pub bump: BumpKind,
+    pub reasons: Vec<ReleaseReason>,
+}
+
+impl fmt::Display for ReleaseAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let reasons: Vec<String> = self
+            .reasons
+            .iter()
+            .map(|reason| match reason {
+                ReleaseReason::FilesChanged { files } => format!("{} file(s) changed", files.len()),
+                ReleaseReason::DependencyReleased { dependency } => {
+                    format!("{} is released", dependency)
+                }
+            })
+            .collect();
+        write!(
+            f,
+            "{} ({}): {}",
+            self.package,
+            self.bump,
+            reasons.join(", ")
+        )
+    }
+}
+
+// Changes that never need a release on their own
+fn is_release_neutral(path: &str) -> bool {
+    let path = Path::new(path);
+    path.extension().is_some_and(|ext| ext == "md")
+        || path.components().any(|c| c.as_os_str() == "docs")
+        || path
+            .file_name()
+            .is_some_and(|name| name == "CHANGELOG" || name == ".gitignore")
+}
+
+/// Packages to release for `changed_paths` (e.g. from `git::changed_files_since`), each with a
+/// patch bump, dependents included. Use `release_plan_with_bumps` to raise the bumps, e.g. from
+/// the commit messages.
+pub fn release_plan(graph: &WorkspaceGraph, changed_paths: &[String]) -> Vec<ReleaseAction> {
+    release_plan_with_bumps(graph, changed_paths, &HashMap::new())
+}
+
+/// Like `release_plan`, packages with an entry in `bumps` get at least that bump. Dependents of
+/// a released package get a patch bump, actions come in the order packages must be released.
+pub fn release_plan_with_bumps(
+    graph: &WorkspaceGraph,
+    changed_paths: &[String],
+    bumps: &HashMap<String, BumpKind>,
+) -> Vec<ReleaseAction> {
+    let mut changed: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for path in changed_paths {
+        if is_release_neutral(path) {
+            continue;
+        }
+        if let Some(package) = graph.package_for_path(path) {
+            changed
+                .entry(package.name.clone())
+                .or_default()
+                .push(path.clone());
+        }
+    }
+
+    let mut actions: HashMap<String, ReleaseAction> = HashMap::new();
+    for (package, files) in &changed {
+        let bump = bumps.get(package).copied().unwrap_or(BumpKind::Patch);
+        actions.insert(
+            package.clone(),
+            ReleaseAction {
+                package: package.clone(),
+                bump,
+                reasons: vec![ReleaseReason::FilesChanged {
+                    files: files.clone(),
+                }],
+            },
+        );
+    }
+    for package in changed.keys() {
+        for dependent in graph.dependents_of(package) {
+            let action = actions
+                .entry(dependent.clone())
+                .or_insert_with(|| ReleaseAction {
+                    package: dependent.clone(),
+                    bump: BumpKind::Patch,
+                    reasons: vec![],
+                });
+            action.reasons.push(ReleaseReason::DependencyReleased {
+                dependency: package.clone(),
+            });
+        }
+    }
+
+    graph
+        .release_order()
+        .into_iter()
+        .filter_map(|name| actions.remove(&name))
+        .collect()
+}
@@ -0,0 +1,308 @@
+use async_trait::async_trait;
+use serde_json::Value;
+use std::{
+    collections::HashMap,
+    error::Error,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use crate::cache::{Cache, LruCache};
+
+pub type StoreError = Box<dyn Error + Send + Sync>;
+
+const DEFAULT_CACHE_TTL: Duration = Duration::from_secs(30);
+const CACHE_CAPACITY: usize = 10_000;
+
+/// The `jti` claim of a token, among the extra claims of `Claims`, `ISCClaims` or `APIClaims`.
+pub fn token_id(extra: &HashMap<String, Value>) -> Option<&str> {
+    extra.get("jti").and_then(|jti| jti.as_str())
+}
+
+/// The `iat` claim of a token, in seconds since the epoch, among its extra claims.
+pub fn issued_at(extra: &HashMap<String, Value>) -> Option<u64> {
+    extra.get("iat").and_then(|iat| iat.as_u64())
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+// A denylist of tokens, by `jti` for a single leaked token or by `sub` for every token of a user
+// or service issued before the revocation. Entries are kept for a TTL, the longest lifetime of
+// the tokens they revoke, after which those tokens have expired anyway.
+#[async_trait]
+pub trait RevocationStore: Send + Sync {
+    /// Tokens without an `iat` are revoked while their subject is.
+    async fn is_revoked(
+        &self,
+        jti: Option<&str>,
+        sub: &str,
+        issued_at: Option<u64>,
+    ) -> Result<bool, StoreError>;
+}
+
+// Lets a service keep a handle on the store it hands to `Revocation`, e.g. to revoke tokens
+#[async_trait]
+impl<S: RevocationStore + ?Sized> RevocationStore for Arc<S> {
+    async fn is_revoked(
+        &self,
+        jti: Option<&str>,
+        sub: &str,
+        issued_at: Option<u64>,
+    ) -> Result<bool, StoreError> {
+        (**self).is_revoked(jti, sub, issued_at).await
+    }
+}
+
+// Whether a token issued at `issued_at` is revoked by a revocation of its subject at `revoked_at`
+fn revoked_by_subject(revoked_at: Option<u64>, issued_at: Option<u64>) -> bool {
+    match (revoked_at, issued_at) {
+        (Some(revoked_at), Some(issued_at)) => issued_at <= revoked_at,
+        (Some(_), None) => true,
+        (None, _) => false,
+    }
+}
+
+#[derive(Default)]
+pub struct MemoryRevocationStore {
+    tokens: Mutex<HashMap<String, Instant>>, // Expiry by `jti`
+    subjects: Mutex<HashMap<String, (u64, Instant)>>, // Revocation time and expiry by `sub`
+}
+
+impl MemoryRevocationStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn revoke_token(&self, jti: &str, ttl: Duration) {
+        let mut tokens = self.tokens.lock().unwrap();
+        let now = Instant::now();
+        tokens.retain(|_, expires_at| *expires_at > now);
+        tokens.insert(jti.to_string(), now + ttl);
+    }
+
+    /// Revokes the tokens of `sub` issued until now, those issued later are accepted.
+    pub fn revoke_subject(&self, sub: &str, ttl: Duration) {
+        let mut subjects = self.subjects.lock().unwrap();
+        let now = Instant::now();
+        subjects.retain(|_, (_, expires_at)| *expires_at > now);
+        subjects.insert(sub.to_string(), (now_secs(), now + ttl));
+    }
+}
+
+#[async_trait]
+impl RevocationStore for MemoryRevocationStore {
+    async fn is_revoked(
+        &self,
+        jti: Option<&str>,
+        sub: &str,
+        issued_at: Option<u64>,
+    ) -> Result<bool, StoreError> {
+        let now = Instant::now();
+        let token_revoked = jti.is_some_and(|jti| {
+            self.tokens
+                .lock()
+                .unwrap()
+                .get(jti)
+                .is_some_and(|expires_at| *expires_at > now)
+        });
+        let revoked_at = self
+            .subjects
+            .lock()
+            .unwrap()
+            .get(sub)
+            .filter(|(_, expires_at)| *expires_at > now)
+            .map(|(revoked_at, _)| *revoked_at);
+        Ok(token_revoked || revoked_by_subject(revoked_at, issued_at))
+    }
+}
+
+#[cfg(feature = "redis")]
+pub use self::redis_store::RedisRevocationStore;
+
+#[cfg(feature = "redis")]
+mod redis_store {
+    use async_trait::async_trait;
+    use std::time::Duration;
+
+    use super::{now_secs, revoked_by_subject, RevocationStore, StoreError};
+    use crate::redis_utils::RedisPool;
+
+    // The denylist shared by every service, as `{prefix}:jti:{jti}` keys and `{prefix}:sub:{sub}`
+    // keys holding the revocation time, each expiring after its TTL
+    pub struct RedisRevocationStore {
+        pool: RedisPool,
+        prefix: String,
+    }
+
+    impl RedisRevocationStore {
+        pub fn new(pool: RedisPool, prefix: &str) -> Self {
+            RedisRevocationStore {
+                pool,
+                prefix: prefix.to_string(),
+            }
+        }
+
+        pub async fn revoke_token(&self, jti: &str, ttl: Duration) -> Result<(), StoreError> {
+            self.set(&format!("jti:{}", jti), now_secs(), ttl).await
+        }
+
+        /// Revokes the tokens of `sub` issued until now, those issued later are accepted.
+        pub async fn revoke_subject(&self, sub: &str, ttl: Duration) -> Result<(), StoreError> {
+            self.set(&format!("sub:{}", sub), now_secs(), ttl).await
+        }
+
+        async fn set(&self, key: &str, revoked_at: u64, ttl: Duration) -> Result<(), StoreError> {
+            let _: () = redis::cmd("SET")
+                .arg(format!("{}:{}", self.prefix, key))
+                .arg(revoked_at)
+                .arg("EX")
+                .arg(ttl.as_secs().max(1))
+                .query_async(&mut self.pool.connection())
+                .await?;
+            Ok(())
+        }
+    }
+
+    #[async_trait]
+    impl RevocationStore for RedisRevocationStore {
+        async fn is_revoked(
+            &self,
+            jti: Option<&str>,
+            sub: &str,
+            issued_at: Option<u64>,
+        ) -> Result<bool, StoreError> {
+            let mut keys = vec![format!("{}:sub:{}", self.prefix, sub)];
+            if let Some(jti) = jti {
+                keys.push(format!("{}:jti:{}", self.prefix, jti));
+            }
+            let values: Vec<Option<u64>> = redis::cmd("MGET")
+                .arg(keys)
+                .query_async(&mut self.pool.connection())
+                .await?;
+            let token_revoked = values.get(1).is_some_and(Option::is_some);
+            Ok(token_revoked || revoked_by_subject(values[0], issued_at))
+        }
+    }
+}
+
+#[cfg(feature = "client")]
+pub use self::http_store::HttpRevocationStore;
+
+#[cfg(feature = "client")]
+mod http_store {
+    use async_trait::async_trait;
+    use serde::Deserialize;
+    use std::time::Duration;
+
+    use super::{RevocationStore, StoreError};
+
+    #[derive(Deserialize)]
+    struct RevocationStatus {
+        revoked: bool,
+    }
+
+    // Asks the identity service, which answers `GET url?sub=..&jti=..&iat=..` with
+    // `{"revoked": bool}`
+    pub struct HttpRevocationStore {
+        url: String,
+        http: reqwest::Client,
+    }
+
+    impl HttpRevocationStore {
+        pub fn new(url: &str) -> Self {
+            HttpRevocationStore {
+                url: url.to_string(),
+                http: reqwest::Client::new(),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl RevocationStore for HttpRevocationStore {
+        async fn is_revoked(
+            &self,
+            jti: Option<&str>,
+            sub: &str,
+            issued_at: Option<u64>,
+        ) -> Result<bool, StoreError> {
+            let issued_at = issued_at.map(|iat| iat.to_string());
+            let mut query = vec![("sub", sub)];
+            if let Some(jti) = jti {
+                query.push(("jti", jti));
+            }
+            if let Some(iat) = &issued_at {
+                query.push(("iat", iat));
+            }
+            let status: RevocationStatus = self
+                .http
+                .get(&self.url)
+                .query(&query)
+                .timeout(Duration::from_secs(2))
+                .send()
+                .await?
+                .error_for_status()?
+                .json()
+                .await?;
+            Ok(status.revoked)
+        }
+    }
+}
+
+// Rocket managed state enabling the revocation check of the claims guards. Answers are cached
+// for a few seconds, so a revoked token keeps working for at most `cache_ttl`.
+#[derive(Clone)]
+pub struct Revocation {
+    store: Arc<dyn RevocationStore>,
+    cache: Arc<LruCache<String, bool>>,
+    cache_ttl: Duration,
+    fail_closed: bool,
+}
+
+impl Revocation {
+    pub fn new<S: RevocationStore + 'static>(store: S) -> Self {
+        Revocation {
+            store: Arc::new(store),
+            cache: Arc::new(LruCache::new(CACHE_CAPACITY)),
+            cache_ttl: DEFAULT_CACHE_TTL,
+            fail_closed: false,
+        }
+    }
+
+    pub fn with_cache_ttl(mut self, ttl: Duration) -> Self {
+        self.cache_ttl = ttl;
+        self
+    }
+
+    /// Rejects every token while the store cannot be reached, instead of accepting them.
+    pub fn fail_closed(mut self) -> Self {
+        self.fail_closed = true;
+        self
+    }
+
+    pub async fn is_revoked(&self, jti: Option<&str>, sub: &str, issued_at: Option<u64>) -> bool {
+        let key = format!(
+            "{}|{}|{}",
+            sub,
+            jti.unwrap_or_default(),
+            issued_at.unwrap_or_default()
+        );
+        if let Some(revoked) = self.cache.get(&key).await {
+            return revoked;
+        }
+        match self.store.is_revoked(jti, sub, issued_at).await {
+            Ok(revoked) => {
+                self.cache.set(key, revoked, self.cache_ttl).await;
+                revoked
+            }
+            Err(e) => {
+                tracing::warn!(error = %e, fail_closed = self.fail_closed, "failed to check the token revocation list");
+                self.fail_closed
+            }
+        }
+    }
+}
@@ -0,0 +1,192 @@
+use okapi::openapi3::Responses;
+use rocket::{
+    catch, catchers, get,
+    http::{uri::Origin, Header, Method, Status},
+    request::{FromRequest, Outcome, Request},
+    response::{self, Responder, Response},
+    routes,
+    serde::json::Json,
+    Build, Catcher, Rocket,
+};
+use rocket_okapi::{
+    gen::OpenApiGenerator, response::OpenApiResponderInner, util::add_schema_response,
+};
+use uuid::Uuid;
+
+use crate::{
+    jwt::TokenErrorKind, rocket_models::ApiError, rocket_utils::auth_failure,
+    validation::validation_failure,
+};
+
+pub const CORRELATION_ID_HEADER: &str = "X-Correlation-ID";
+const DENIED_BASE: &str = "/__denied";
+
+// Identifier of the current request, taken from the incoming `X-Correlation-ID` (or
+// `X-Request-ID`) header when present and generated otherwise
+#[derive(Debug, Clone, PartialEq)]
+pub struct CorrelationId(pub String);
+
+pub fn correlation_id(request: &Request<'_>) -> String {
+    request
+        .local_cache(|| {
+            let incoming = request
+                .headers()
+                .get_one(CORRELATION_ID_HEADER)
+                .or_else(|| request.headers().get_one("X-Request-ID"))
+                .map(str::trim)
+                .filter(|id| !id.is_empty());
+
+            CorrelationId(match incoming {
+                Some(id) => id.to_string(),
+                None => Uuid::new_v4().to_string(),
+            })
+        })
+        .0
+        .clone()
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for CorrelationId {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        Outcome::Success(CorrelationId(correlation_id(request)))
+    }
+}
+
+impl<'r> Responder<'r, 'static> for ApiError {
+    fn respond_to(mut self, request: &'r Request<'_>) -> response::Result<'static> {
+        let correlation_id = self
+            .correlation_id
+            .get_or_insert_with(|| correlation_id(request))
+            .clone();
+        let status = Status::from_code(self.status).unwrap_or(Status::InternalServerError);
+
+        Response::build_from(Json(self).respond_to(request)?)
+            .status(status)
+            .header(Header::new(CORRELATION_ID_HEADER, correlation_id))
+            .ok()
+    }
+}
+
+impl OpenApiResponderInner for ApiError {
+    fn responses(gen: &mut OpenApiGenerator) -> rocket_okapi::Result<Responses> {
+        let schema = gen.json_schema::<ApiError>();
+        let mut responses = Responses::default();
+        for status in [400, 401, 403, 404, 422, 500] {
+            add_schema_response(&mut responses, status, "application/json", schema.clone())?;
+        }
+        Ok(responses)
+    }
+}
+
+#[catch(400)]
+fn bad_request(request: &Request<'_>) -> ApiError {
+    validation_failure(request)
+        .unwrap_or_else(|| ApiError::bad_request("The request could not be understood"))
+}
+
+#[catch(401)]
+fn unauthorized(request: &Request<'_>) -> ApiError {
+    if let Some(error) = validation_failure(request) {
+        return error;
+    }
+    match auth_failure(request) {
+        Some(kind) => ApiError::new(401, kind.error_code(), kind.message()),
+        None => ApiError::new(
+            401,
+            TokenErrorKind::Invalid.error_code(),
+            "Authentication is required to access this resource",
+        ),
+    }
+}
+
+#[catch(403)]
+fn forbidden(request: &Request<'_>) -> ApiError {
+    validation_failure(request)
+        .unwrap_or_else(|| ApiError::forbidden("You are not allowed to access this resource"))
+}
+
+#[catch(404)]
+fn not_found(request: &Request<'_>) -> ApiError {
+    ApiError::not_found(&format!("No route matches {}", request.uri().path()))
+}
+
+#[catch(422)]
+fn unprocessable(request: &Request<'_>) -> ApiError {
+    validation_failure(request)
+        .unwrap_or_else(|| ApiError::unprocessable("The request body could not be processed"))
+}
+
+#[catch(500)]
+fn internal_error() -> ApiError {
+    ApiError::internal("An unexpected error occurred")
+}
+
+#[catch(default)]
+fn default_catcher(status: Status, request: &Request<'_>) -> ApiError {
+    if let Some(error) = validation_failure(request) {
+        return error;
+    }
+    let code = status.reason_lossy().to_lowercase().replace(' ', "_");
+    ApiError::new(status.code, &code, status.reason_lossy())
+}
+
+/// Catchers answering errors with the `ApiError` JSON body instead of Rocket's HTML pages.
+pub fn default_catchers() -> Vec<Catcher> {
+    catchers![
+        bad_request,
+        unauthorized,
+        forbidden,
+        not_found,
+        unprocessable,
+        internal_error,
+        default_catcher
+    ]
+}
+
+pub fn register_default_catchers(rocket: Rocket<Build>) -> Rocket<Build> {
+    rocket.register("/", default_catchers())
+}
+
+// The error a fairing answers the current request with, see `deny`
+struct Denial(Option<ApiError>);
+
+/// Answers the current request with `error` instead of its route, from the `on_request` of a
+/// fairing whose `on_ignite` called `mount_denied`. Fairings cannot respond, so the request is
+/// rerouted to an internal route that only answers requests denied this way, and 404s others.
+pub(crate) fn deny(request: &mut Request<'_>, error: ApiError) {
+    request.local_cache(|| Denial(Some(error)));
+    if let Ok(origin) = Origin::parse_owned(DENIED_BASE.to_string()) {
+        request.set_method(Method::Get);
+        request.set_uri(origin);
+    }
+}
+
+/// Mounts the route of `deny`, once whatever the number of fairings denying requests.
+pub(crate) fn mount_denied(rocket: Rocket<Build>) -> Rocket<Build> {
+    if rocket.routes().any(|route| route.uri.base() == DENIED_BASE) {
+        return rocket;
+    }
+    rocket.mount(DENIED_BASE, routes![denied])
+}
+
+// The error `deny` recorded, requests that were not denied do not match
+struct Denied(ApiError);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Denied {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        match &request.local_cache(|| Denial(None)).0 {
+            Some(error) => Outcome::Success(Denied(error.clone())),
+            None => Outcome::Forward(Status::NotFound),
+        }
+    }
+}
+
+#[get("/")]
+fn denied(denied: Denied) -> ApiError {
+    denied.0
+}
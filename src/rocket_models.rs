@@ -1,17 +1,20 @@
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use std::fmt;
 
-#[derive(Serialize)]
+#[derive(Debug, Serialize, Clone, PartialEq, JsonSchema)]
 pub struct RealtimeMessage {
     pub topic: String,
     pub payload: String,
 }
 
-// Implement the `ToString` trait for the struct
-impl ToString for RealtimeMessage {
-    fn to_string(&self) -> String {
+// Implement `Display` (and therefore `ToString`) for the struct
+impl fmt::Display for RealtimeMessage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         // Convert the struct to a JSON string using serde_json
-        serde_json::to_string(self).unwrap_or_else(|_| "Failed to serialize".to_string())
+        let json =
+            serde_json::to_string(self).unwrap_or_else(|_| "Failed to serialize".to_string());
+        write!(f, "{}", json)
     }
 }
 
@@ -20,3 +23,86 @@ pub struct MessageResponse {
     /// This is a message from the server.
     pub message: String,
 }
+
+#[derive(Debug, Deserialize, Serialize, JsonSchema, Clone, PartialEq)]
+pub struct FieldError {
+    /// Path of the invalid field in the request body, e.g. `address.city` or `items[0].name`.
+    pub field: String,
+    pub message: String,
+}
+
+#[derive(Debug, Deserialize, Serialize, JsonSchema, Clone, PartialEq)]
+pub struct ApiError {
+    pub status: u16,
+    /// Machine readable reason, e.g. `not_found` or `token_expired`.
+    pub error_code: String,
+    pub message: String,
+    /// Identifier of the request, to correlate the error with the service logs.
+    pub correlation_id: Option<String>,
+    /// Per-field problems of a rejected request body.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub details: Vec<FieldError>,
+}
+
+impl ApiError {
+    pub fn new(status: u16, error_code: &str, message: &str) -> Self {
+        ApiError {
+            status,
+            error_code: error_code.to_string(),
+            message: message.to_string(),
+            correlation_id: None,
+            details: vec![],
+        }
+    }
+
+    pub fn bad_request(message: &str) -> Self {
+        Self::new(400, "bad_request", message)
+    }
+
+    pub fn unauthorized(message: &str) -> Self {
+        Self::new(401, "unauthorized", message)
+    }
+
+    pub fn forbidden(message: &str) -> Self {
+        Self::new(403, "forbidden", message)
+    }
+
+    pub fn not_found(message: &str) -> Self {
+        Self::new(404, "not_found", message)
+    }
+
+    pub fn conflict(message: &str) -> Self {
+        Self::new(409, "conflict", message)
+    }
+
+    pub fn unprocessable(message: &str) -> Self {
+        Self::new(422, "unprocessable_entity", message)
+    }
+
+    /// 422 listing every invalid field.
+    pub fn validation(details: Vec<FieldError>) -> Self {
+        Self::new(422, "validation_failed", "The request body is invalid").with_details(details)
+    }
+
+    pub fn internal(message: &str) -> Self {
+        Self::new(500, "internal_error", message)
+    }
+
+    pub fn with_details(mut self, details: Vec<FieldError>) -> Self {
+        self.details = details;
+        self
+    }
+
+    pub fn with_correlation_id(mut self, correlation_id: &str) -> Self {
+        self.correlation_id = Some(correlation_id.to_string());
+        self
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}: {}", self.status, self.error_code, self.message)
+    }
+}
+
+impl std::error::Error for ApiError {}
@@ -1,74 +1,292 @@
-use jsonwebtoken::decode;
-use jsonwebtoken::DecodingKey;
-use jsonwebtoken::Validation;
 use okapi::openapi3::Object;
 use okapi::openapi3::SecurityRequirement;
 use okapi::openapi3::SecurityScheme;
 use okapi::openapi3::SecuritySchemeData;
-use rocket::serde::Deserialize;
-use rocket::serde::Serialize;
+use rocket::serde::DeserializeOwned;
 use rocket_okapi::gen::OpenApiGenerator;
 use rocket_okapi::request::OpenApiFromRequest;
 use rocket_okapi::request::RequestHeaderInput;
-use std::env;
 
 use rocket::{
     http::Status,
     request::{FromRequest, Outcome, Request},
 };
 
-#[derive(Serialize, Deserialize)]
-pub struct APIClaims {
-    pub sub: String,
-    pub exp: usize,
-    pub group_id: i64,
-    pub scopes: Vec<String>,
-}
+use crate::{
+    auth::rotation::ACCESS_TOKEN_TYPE,
+    jwt::{JwtConfig, TokenErrorKind},
+    revocation::{issued_at, token_id, Revocation},
+};
+
+pub use crate::claims::{APIClaims, Claims, ISCClaims};
 
 #[derive(Debug)]
 pub enum APIClaimsError {
     Missing,
+    Expired,
+    InvalidSignature,
+    Malformed,
+    WrongAudience,
+    WrongIssuer,
+    Revoked,
     Invalid,
 }
 
-#[derive(Serialize, Deserialize)]
-pub struct Claims {
-    pub sub: String,
-    pub exp: usize,
-    pub user_id: String,
-    pub token_type: String, // Add token_type to distinguish between access and refresh tokens
-    pub first_name: Option<String>,
-    pub last_name: Option<String>,
-    pub middle_name: Option<String>,
-    pub client_id: Option<String>,
+impl From<TokenErrorKind> for APIClaimsError {
+    fn from(kind: TokenErrorKind) -> Self {
+        match kind {
+            TokenErrorKind::Missing => APIClaimsError::Missing,
+            TokenErrorKind::Expired => APIClaimsError::Expired,
+            TokenErrorKind::InvalidSignature => APIClaimsError::InvalidSignature,
+            TokenErrorKind::Malformed => APIClaimsError::Malformed,
+            TokenErrorKind::WrongAudience => APIClaimsError::WrongAudience,
+            TokenErrorKind::WrongIssuer => APIClaimsError::WrongIssuer,
+            TokenErrorKind::Revoked => APIClaimsError::Revoked,
+            TokenErrorKind::Invalid => APIClaimsError::Invalid,
+        }
+    }
 }
+
 #[derive(Debug)]
 pub enum ClaimsError {
     Missing,
+    Expired,
+    InvalidSignature,
+    Malformed,
+    WrongAudience,
+    WrongIssuer,
+    Revoked,
+    Invalid,
+}
+
+impl From<TokenErrorKind> for ClaimsError {
+    fn from(kind: TokenErrorKind) -> Self {
+        match kind {
+            TokenErrorKind::Missing => ClaimsError::Missing,
+            TokenErrorKind::Expired => ClaimsError::Expired,
+            TokenErrorKind::InvalidSignature => ClaimsError::InvalidSignature,
+            TokenErrorKind::Malformed => ClaimsError::Malformed,
+            TokenErrorKind::WrongAudience => ClaimsError::WrongAudience,
+            TokenErrorKind::WrongIssuer => ClaimsError::WrongIssuer,
+            TokenErrorKind::Revoked => ClaimsError::Revoked,
+            TokenErrorKind::Invalid => ClaimsError::Invalid,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ISCClaimsError {
+    Missing,
+    Expired,
+    InvalidSignature,
+    Malformed,
+    WrongAudience,
+    WrongIssuer,
+    Revoked,
     Invalid,
 }
 
+impl From<TokenErrorKind> for ISCClaimsError {
+    fn from(kind: TokenErrorKind) -> Self {
+        match kind {
+            TokenErrorKind::Missing => ISCClaimsError::Missing,
+            TokenErrorKind::Expired => ISCClaimsError::Expired,
+            TokenErrorKind::InvalidSignature => ISCClaimsError::InvalidSignature,
+            TokenErrorKind::Malformed => ISCClaimsError::Malformed,
+            TokenErrorKind::WrongAudience => ISCClaimsError::WrongAudience,
+            TokenErrorKind::WrongIssuer => ISCClaimsError::WrongIssuer,
+            TokenErrorKind::Revoked => ISCClaimsError::Revoked,
+            TokenErrorKind::Invalid => ISCClaimsError::Invalid,
+        }
+    }
+}
+
+// Remembers why authentication failed so the 401 catcher can report it
+struct AuthFailure(Option<TokenErrorKind>);
+
+// The caller authenticated by the first claims guard that succeeded on a request
+#[derive(Debug, Clone, PartialEq)]
+pub struct Actor {
+    pub sub: String,
+    pub org_id: Option<String>, // Only set for service tokens
+}
+
+struct AuthenticatedActor(Option<Actor>);
+
+// Decodes the bearer token carried in `header` into the claims type `T`, using the `JwtConfig`
+// managed by Rocket or, when none is managed, the one described by the environment
+fn decode_from_header<T: DeserializeOwned>(
+    request: &Request<'_>,
+    header: &str,
+) -> Result<T, TokenErrorKind> {
+    let keys: Vec<_> = request.headers().get(header).collect();
+    if keys.len() != 1 {
+        return Err(TokenErrorKind::Missing);
+    }
+
+    let token_str = keys[0].trim_start_matches("Bearer ").trim();
+    let decoded = match request.rocket().state::<JwtConfig>() {
+        Some(config) => config.decode::<T>(token_str),
+        None => match JwtConfig::from_env() {
+            Ok(config) => config.decode::<T>(token_str),
+            Err(e) => {
+                tracing::error!(error = %e, "no JwtConfig is managed and the environment has none");
+                return Err(TokenErrorKind::Invalid);
+            }
+        },
+    };
+
+    decoded.map_err(|e| TokenErrorKind::from(&e))
+}
+
+// Claims checked against the token revocation list
+pub trait RevocableClaims {
+    fn subject(&self) -> &str;
+
+    fn token_id(&self) -> Option<&str>;
+
+    fn issued_at(&self) -> Option<u64>;
+
+    /// Whether the token may be used as a bearer token, refresh tokens may not.
+    fn is_access_token(&self) -> bool {
+        true
+    }
+}
+
+impl RevocableClaims for Claims {
+    fn subject(&self) -> &str {
+        &self.sub
+    }
+
+    fn token_id(&self) -> Option<&str> {
+        token_id(&self.extra)
+    }
+
+    fn issued_at(&self) -> Option<u64> {
+        issued_at(&self.extra)
+    }
+
+    // Refresh tokens are signed with the same key, they are only accepted by `TokenRotation`
+    fn is_access_token(&self) -> bool {
+        self.token_type == ACCESS_TOKEN_TYPE
+    }
+}
+
+impl RevocableClaims for ISCClaims {
+    fn subject(&self) -> &str {
+        &self.sub
+    }
+
+    fn token_id(&self) -> Option<&str> {
+        token_id(&self.extra)
+    }
+
+    fn issued_at(&self) -> Option<u64> {
+        issued_at(&self.extra)
+    }
+}
+
+impl RevocableClaims for APIClaims {
+    fn subject(&self) -> &str {
+        &self.sub
+    }
+
+    fn token_id(&self) -> Option<&str> {
+        token_id(&self.extra)
+    }
+
+    fn issued_at(&self) -> Option<u64> {
+        issued_at(&self.extra)
+    }
+}
+
+/// Decodes the token of `header`, then rejects it when it is revoked, if Rocket manages a
+/// `Revocation`. Unlike the guards, failures are not recorded for the 401 catcher, so fairings
+/// can look at the caller without changing what the handler's guard reports.
+pub(crate) async fn authenticate<T: DeserializeOwned + RevocableClaims>(
+    request: &Request<'_>,
+    header: &str,
+) -> Result<T, TokenErrorKind> {
+    let claims = decode_from_header::<T>(request, header)?;
+    if !claims.is_access_token() {
+        return Err(TokenErrorKind::Invalid);
+    }
+    if let Some(revocation) = request.rocket().state::<Revocation>() {
+        if revocation
+            .is_revoked(claims.token_id(), claims.subject(), claims.issued_at())
+            .await
+        {
+            return Err(TokenErrorKind::Revoked);
+        }
+    }
+    Ok(claims)
+}
+
+pub(crate) fn record_auth_failure(request: &Request<'_>, kind: TokenErrorKind) -> TokenErrorKind {
+    request.local_cache(|| AuthFailure(Some(kind)));
+    kind
+}
+
+/// Reason recorded by the claims guards for rejecting the current request, if any.
+pub fn auth_failure(request: &Request<'_>) -> Option<TokenErrorKind> {
+    request.local_cache(|| AuthFailure(None)).0
+}
+
+pub(crate) fn record_actor(request: &Request<'_>, sub: &str, org_id: Option<&str>) {
+    request.local_cache(|| {
+        AuthenticatedActor(Some(Actor {
+            sub: sub.to_string(),
+            org_id: org_id.map(str::to_string),
+        }))
+    });
+}
+
+/// Caller recorded by the claims guards for the current request, if any of them ran and
+/// succeeded. Read by fairings after the handler, without verifying the token again.
+pub fn authenticated_actor(request: &Request<'_>) -> Option<Actor> {
+    request.local_cache(|| AuthenticatedActor(None)).0.clone()
+}
+
+// Documents a bearer token carried in `header` as an API key security scheme
+fn bearer_security_input(header: &str, scheme_name: &str) -> RequestHeaderInput {
+    header_security_input(header, scheme_name, "Requires a Bearer token to access")
+}
+
+// Documents a credential carried in `header` as an API key security scheme
+pub(crate) fn header_security_input(
+    header: &str,
+    scheme_name: &str,
+    description: &str,
+) -> RequestHeaderInput {
+    let security_scheme = SecurityScheme {
+        description: Some(description.to_owned()),
+        data: SecuritySchemeData::ApiKey {
+            name: header.to_owned(),
+            location: "header".to_owned(),
+        },
+        extensions: Object::default(),
+    };
+
+    let mut security_req = SecurityRequirement::new();
+    security_req.insert(scheme_name.to_owned(), Vec::new());
+
+    RequestHeaderInput::Security(scheme_name.to_owned(), security_scheme, security_req)
+}
+
 #[rocket::async_trait]
 impl<'r> FromRequest<'r> for APIClaims {
     type Error = APIClaimsError;
 
     async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
-        let keys: Vec<_> = request.headers().get("X-API-Authorization").collect();
-        if keys.len() != 1 {
-            return Outcome::Error((Status::Unauthorized, APIClaimsError::Missing));
-        }
-
-        let token_str = keys[0].trim_start_matches("Bearer ").trim();
-        let secret = env::var("JWT_SECRET").expect("JWT_SECRET must be set");
-        let decoding_key = DecodingKey::from_secret(secret.as_ref());
-
-        match decode::<APIClaims>(
-            token_str,
-            &decoding_key,
-            &Validation::new(jsonwebtoken::Algorithm::HS256),
-        ) {
-            Ok(token_data) => Outcome::Success(token_data.claims),
-            Err(_) => Outcome::Error((Status::Unauthorized, APIClaimsError::Invalid)),
+        match authenticate::<APIClaims>(request, "X-API-Authorization").await {
+            Ok(claims) => {
+                record_actor(request, &claims.sub, None);
+                Outcome::Success(claims)
+            }
+            Err(kind) => Outcome::Error((
+                Status::Unauthorized,
+                APIClaimsError::from(record_auth_failure(request, kind)),
+            )),
         }
     }
 }
@@ -79,22 +297,9 @@ impl<'a> OpenApiFromRequest<'a> for APIClaims {
         _name: String,
         _required: bool,
     ) -> rocket_okapi::Result<RequestHeaderInput> {
-        let security_scheme = SecurityScheme {
-            description: Some("Requires a Bearer token to access".to_owned()),
-            data: SecuritySchemeData::ApiKey {
-                name: "X-API-Authorization".to_owned(),
-                location: "header".to_owned(),
-            },
-            extensions: Object::default(),
-        };
-
-        let mut security_req = SecurityRequirement::new();
-        security_req.insert("BearerAPIAuth".to_owned(), Vec::new());
-
-        Ok(RequestHeaderInput::Security(
-            "BearerAPIAuth".to_owned(),
-            security_scheme,
-            security_req,
+        Ok(bearer_security_input(
+            "X-API-Authorization",
+            "BearerAPIAuth",
         ))
     }
 
@@ -110,48 +315,62 @@ impl<'r> FromRequest<'r> for Claims {
     type Error = ClaimsError;
 
     async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
-        let keys: Vec<_> = request.headers().get("Authorization").collect();
-        if keys.len() != 1 {
-            return Outcome::Error((Status::Unauthorized, ClaimsError::Missing));
+        match authenticate::<Claims>(request, "Authorization").await {
+            Ok(claims) => {
+                record_actor(request, &claims.sub, None);
+                Outcome::Success(claims)
+            }
+            Err(kind) => Outcome::Error((
+                Status::Unauthorized,
+                ClaimsError::from(record_auth_failure(request, kind)),
+            )),
         }
+    }
+}
 
-        let token_str = keys[0].trim_start_matches("Bearer ").trim();
-        let secret = env::var("JWT_SECRET").expect("JWT_SECRET must be set");
-        let decoding_key = DecodingKey::from_secret(secret.as_ref());
-
-        match decode::<Claims>(
-            token_str,
-            &decoding_key,
-            &Validation::new(jsonwebtoken::Algorithm::HS256),
-        ) {
-            Ok(token_data) => Outcome::Success(token_data.claims),
-            Err(_) => Outcome::Error((Status::Unauthorized, ClaimsError::Invalid)),
+impl<'a> OpenApiFromRequest<'a> for Claims {
+    fn from_request_input(
+        _gen: &mut OpenApiGenerator,
+        _name: String,
+        _required: bool,
+    ) -> rocket_okapi::Result<RequestHeaderInput> {
+        Ok(bearer_security_input("Authorization", "BearerAuth"))
+    }
+
+    fn get_responses(
+        _gen: &mut rocket_okapi::gen::OpenApiGenerator,
+    ) -> rocket_okapi::Result<okapi::openapi3::Responses> {
+        Ok(okapi::openapi3::Responses::default())
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ISCClaims {
+    type Error = ISCClaimsError;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        match authenticate::<ISCClaims>(request, "X-ISC-Authorization").await {
+            Ok(claims) => {
+                record_actor(request, &claims.sub, Some(&claims.org_id));
+                Outcome::Success(claims)
+            }
+            Err(kind) => Outcome::Error((
+                Status::Unauthorized,
+                ISCClaimsError::from(record_auth_failure(request, kind)),
+            )),
         }
     }
 }
 
-impl<'a> OpenApiFromRequest<'a> for Claims {
+impl<'a> OpenApiFromRequest<'a> for ISCClaims {
     fn from_request_input(
         _gen: &mut OpenApiGenerator,
         _name: String,
         _required: bool,
     ) -> rocket_okapi::Result<RequestHeaderInput> {
-        let security_scheme = SecurityScheme {
-            description: Some("Requires a Bearer token to access".to_owned()),
-            data: SecuritySchemeData::ApiKey {
-                name: "Authorization".to_owned(),
-                location: "header".to_owned(),
-            },
-            extensions: Object::default(),
-        };
-
-        let mut security_req = SecurityRequirement::new();
-        security_req.insert("BearerAuth".to_owned(), Vec::new());
-
-        Ok(RequestHeaderInput::Security(
-            "BearerAuth".to_owned(),
-            security_scheme,
-            security_req,
+        Ok(bearer_security_input(
+            "X-ISC-Authorization",
+            "BearerISCAuth",
         ))
     }
 
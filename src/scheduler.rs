@@ -0,0 +1,474 @@
+use chrono::{DateTime, Datelike, Duration as ChronoDuration, DurationRound, Timelike, Utc};
+use rand::Rng;
+use std::{
+    collections::BTreeSet, error::Error, fmt, future::Future, pin::Pin, str::FromStr, sync::Arc,
+    time::Duration,
+};
+use tokio::{sync::watch, task::JoinHandle};
+
+use crate::shutdown::{DrainGuard, ShutdownToken};
+
+type JobFuture = Pin<Box<dyn Future<Output = Result<(), String>> + Send>>;
+type JobFn = Arc<dyn Fn() -> JobFuture + Send + Sync>;
+
+// Standard five field cron expression: minute, hour, day of month, month and day of week.
+// Fields accept `*`, values, ranges (`1-5`), lists (`1,15`) and steps (`*/10`, `0-30/5`).
+// Times are evaluated in UTC.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CronSchedule {
+    expression: String,
+    minutes: BTreeSet<u32>,
+    hours: BTreeSet<u32>,
+    days_of_month: BTreeSet<u32>,
+    months: BTreeSet<u32>,
+    days_of_week: BTreeSet<u32>,
+    // Cron matches either day field when both are restricted
+    day_of_month_any: bool,
+    day_of_week_any: bool,
+}
+
+impl CronSchedule {
+    pub fn expression(&self) -> &str {
+        &self.expression
+    }
+
+    fn day_matches(&self, time: &DateTime<Utc>) -> bool {
+        let dom = self.days_of_month.contains(&time.day());
+        let dow = self
+            .days_of_week
+            .contains(&time.weekday().num_days_from_sunday());
+        match (self.day_of_month_any, self.day_of_week_any) {
+            (true, true) => true,
+            (true, false) => dow,
+            (false, true) => dom,
+            (false, false) => dom || dow,
+        }
+    }
+
+    /// The first matching minute strictly after `after`.
+    pub fn next_after(&self, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let mut time =
+            after.duration_trunc(ChronoDuration::minutes(1)).ok()? + ChronoDuration::minutes(1);
+        // A valid expression matches at least once every few years (e.g. Feb 29th)
+        let limit = after + ChronoDuration::days(366 * 5);
+
+        while time <= limit {
+            if !self.months.contains(&time.month()) {
+                let (year, month) = if time.month() == 12 {
+                    (time.year() + 1, 1)
+                } else {
+                    (time.year(), time.month() + 1)
+                };
+                time = time
+                    .with_day(1)?
+                    .with_hour(0)?
+                    .with_minute(0)?
+                    .with_month(month)?
+                    .with_year(year)?;
+                continue;
+            }
+            if !self.day_matches(&time) {
+                time = time.with_hour(0)?.with_minute(0)? + ChronoDuration::days(1);
+                continue;
+            }
+            if !self.hours.contains(&time.hour()) {
+                time = time.with_minute(0)? + ChronoDuration::hours(1);
+                continue;
+            }
+            if !self.minutes.contains(&time.minute()) {
+                time += ChronoDuration::minutes(1);
+                continue;
+            }
+            return Some(time);
+        }
+        None
+    }
+}
+
+impl fmt::Display for CronSchedule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.expression)
+    }
+}
+
+impl FromStr for CronSchedule {
+    type Err = String;
+
+    fn from_str(expression: &str) -> Result<Self, Self::Err> {
+        let fields: Vec<&str> = expression.split_whitespace().collect();
+        if fields.len() != 5 {
+            return Err(format!(
+                "Invalid cron expression '{}': expected 5 fields, found {}",
+                expression,
+                fields.len()
+            ));
+        }
+
+        let parse = |field: &str, min: u32, max: u32| {
+            parse_cron_field(field, min, max)
+                .map_err(|e| format!("Invalid cron expression '{}': {}", expression, e))
+        };
+
+        let mut days_of_week = parse(fields[4], 0, 7)?;
+        // Both 0 and 7 are Sunday
+        if days_of_week.remove(&7) {
+            days_of_week.insert(0);
+        }
+
+        Ok(CronSchedule {
+            expression: expression.to_string(),
+            minutes: parse(fields[0], 0, 59)?,
+            hours: parse(fields[1], 0, 23)?,
+            days_of_month: parse(fields[2], 1, 31)?,
+            months: parse(fields[3], 1, 12)?,
+            days_of_week,
+            day_of_month_any: fields[2] == "*",
+            day_of_week_any: fields[4] == "*",
+        })
+    }
+}
+
+fn parse_cron_field(field: &str, min: u32, max: u32) -> Result<BTreeSet<u32>, String> {
+    let mut values = BTreeSet::new();
+
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (
+                range,
+                step.parse::<u32>()
+                    .ok()
+                    .filter(|step| *step > 0)
+                    .ok_or_else(|| format!("invalid step in '{}'", part))?,
+            ),
+            None => (part, 1),
+        };
+
+        let (start, end) = if range == "*" {
+            (min, max)
+        } else if let Some((start, end)) = range.split_once('-') {
+            (
+                start
+                    .parse()
+                    .map_err(|_| format!("invalid value in '{}'", part))?,
+                end.parse()
+                    .map_err(|_| format!("invalid value in '{}'", part))?,
+            )
+        } else {
+            let value = range
+                .parse()
+                .map_err(|_| format!("invalid value in '{}'", part))?;
+            // `5/15` means from 5 to the end of the range every 15
+            if step > 1 {
+                (value, max)
+            } else {
+                (value, value)
+            }
+        };
+
+        if start < min || end > max || start > end {
+            return Err(format!(
+                "'{}' is outside of the range {}-{}",
+                part, min, max
+            ));
+        }
+        values.extend((start..=end).step_by(step as usize));
+    }
+
+    Ok(values)
+}
+
+// Shortest wait between runs of an `Every` schedule, so a zero interval is not a busy loop
+const MIN_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Schedule {
+    Every(Duration), // At least `MIN_INTERVAL`, shorter intervals are raised to it
+    Cron(CronSchedule),
+}
+
+impl Schedule {
+    pub fn cron(expression: &str) -> Result<Self, Box<dyn Error>> {
+        Ok(Schedule::Cron(expression.parse()?))
+    }
+
+    /// Time to wait from `now` until the next run.
+    fn delay_from(&self, now: DateTime<Utc>) -> Option<Duration> {
+        match self {
+            Schedule::Every(interval) => Some((*interval).max(MIN_INTERVAL)),
+            Schedule::Cron(cron) => cron
+                .next_after(now)
+                .and_then(|next| (next - now).to_std().ok()),
+        }
+    }
+}
+
+pub struct Job {
+    name: String,
+    schedule: Schedule,
+    jitter: Duration,
+    allow_overlap: bool,
+    run_on_start: bool,
+    task: JobFn,
+}
+
+impl Job {
+    pub fn new<F, Fut, E>(name: &str, schedule: Schedule, task: F) -> Self
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), E>> + Send + 'static,
+        E: fmt::Display,
+    {
+        if let Schedule::Every(interval) = schedule {
+            if interval < MIN_INTERVAL {
+                tracing::warn!(
+                    job = name,
+                    ?interval,
+                    "job interval is below {:?}, it runs every {:?} instead",
+                    MIN_INTERVAL,
+                    MIN_INTERVAL
+                );
+            }
+        }
+        let task: JobFn = Arc::new(move || {
+            let run = task();
+            Box::pin(async move { run.await.map_err(|e| e.to_string()) })
+        });
+        Job {
+            name: name.to_string(),
+            schedule,
+            jitter: Duration::ZERO,
+            allow_overlap: false,
+            run_on_start: false,
+            task,
+        }
+    }
+
+    pub fn every<F, Fut, E>(name: &str, interval: Duration, task: F) -> Self
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), E>> + Send + 'static,
+        E: fmt::Display,
+    {
+        Self::new(name, Schedule::Every(interval), task)
+    }
+
+    pub fn cron<F, Fut, E>(name: &str, expression: &str, task: F) -> Result<Self, Box<dyn Error>>
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), E>> + Send + 'static,
+        E: fmt::Display,
+    {
+        Ok(Self::new(name, Schedule::cron(expression)?, task))
+    }
+
+    /// Delays every run by a random duration up to `jitter`, so replicas do not all hit a
+    /// dependency at the same time.
+    pub fn with_jitter(mut self, jitter: Duration) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// Lets a run start while the previous one is still going. By default such runs are skipped.
+    pub fn allow_overlap(mut self) -> Self {
+        self.allow_overlap = true;
+        self
+    }
+
+    pub fn run_on_start(mut self) -> Self {
+        self.run_on_start = true;
+        self
+    }
+
+    fn random_jitter(&self) -> Duration {
+        if self.jitter.is_zero() {
+            return Duration::ZERO;
+        }
+        let millis = self.jitter.as_millis().min(u64::MAX as u128) as u64;
+        Duration::from_millis(rand::thread_rng().gen_range(0..=millis))
+    }
+
+    async fn run_loop(self, mut shutdown: watch::Receiver<bool>, _drain: Option<DrainGuard>) {
+        let mut running: Vec<JoinHandle<()>> = vec![];
+        let mut first = true;
+
+        loop {
+            let delay = if first && self.run_on_start {
+                Some(self.random_jitter())
+            } else {
+                self.schedule
+                    .delay_from(Utc::now())
+                    .map(|delay| delay + self.random_jitter())
+            };
+            first = false;
+
+            let delay = match delay {
+                Some(delay) => delay,
+                None => {
+                    tracing::warn!(job = %self.name, "schedule has no upcoming run, stopping");
+                    break;
+                }
+            };
+
+            tokio::select! {
+                _ = tokio::time::sleep(delay) => {}
+                _ = shutdown.wait_for(|stop| *stop) => break,
+            }
+
+            if self.allow_overlap {
+                running.retain(|handle| !handle.is_finished());
+                let name = self.name.clone();
+                let task = self.task.clone();
+                running.push(tokio::spawn(async move { run_job(&name, &task).await }));
+            } else {
+                // Runs are awaited in place, ticks missed while a run is in progress are skipped
+                run_job(&self.name, &self.task).await;
+            }
+
+            if *shutdown.borrow() {
+                break;
+            }
+        }
+
+        for handle in running {
+            let _ = handle.await;
+        }
+    }
+}
+
+async fn run_job(name: &str, task: &JobFn) {
+    tracing::debug!(job = %name, "running scheduled job");
+    if let Err(e) = task().await {
+        tracing::warn!(job = %name, error = %e, "scheduled job failed");
+    }
+}
+
+#[derive(Default)]
+pub struct Scheduler {
+    jobs: Vec<Job>,
+}
+
+impl Scheduler {
+    pub fn new() -> Self {
+        Scheduler { jobs: vec![] }
+    }
+
+    pub fn job(mut self, job: Job) -> Self {
+        self.jobs.push(job);
+        self
+    }
+
+    /// Starts every job. Jobs stop scheduling new runs once `shutdown` resolves and the runs in
+    /// progress are left to finish.
+    pub fn start<S>(self, shutdown: S) -> SchedulerHandle
+    where
+        S: Future<Output = ()> + Send + 'static,
+    {
+        self.spawn(shutdown, None)
+    }
+
+    /// Starts every job and stops them when `token` is triggered. The jobs hold a drain guard
+    /// until their last run finishes, so `ShutdownToken::wait_for_drain` covers them.
+    pub fn start_with_token(self, token: &ShutdownToken) -> SchedulerHandle {
+        self.spawn(token.cancelled(), Some(token))
+    }
+
+    fn spawn<S>(self, shutdown: S, token: Option<&ShutdownToken>) -> SchedulerHandle
+    where
+        S: Future<Output = ()> + Send + 'static,
+    {
+        let (stop, receiver) = watch::channel(false);
+        let trigger = stop.clone();
+        tokio::spawn(async move {
+            shutdown.await;
+            let _ = trigger.send(true);
+        });
+
+        let jobs = self
+            .jobs
+            .into_iter()
+            .map(|job| {
+                let drain = token.map(ShutdownToken::guard);
+                tokio::spawn(job.run_loop(receiver.clone(), drain))
+            })
+            .collect();
+
+        SchedulerHandle { stop, jobs }
+    }
+
+    /// Starts the jobs once Rocket has launched and stops them with Rocket's shutdown.
+    #[cfg(feature = "rocket")]
+    pub fn fairing(self) -> SchedulerFairing {
+        SchedulerFairing {
+            scheduler: std::sync::Mutex::new(Some(self)),
+            handle: tokio::sync::Mutex::new(None),
+            grace: Duration::from_secs(30),
+        }
+    }
+}
+
+pub struct SchedulerHandle {
+    stop: watch::Sender<bool>,
+    jobs: Vec<JoinHandle<()>>,
+}
+
+impl SchedulerHandle {
+    pub fn stop(&self) {
+        let _ = self.stop.send(true);
+    }
+
+    /// Waits for every job loop, including the runs in progress, to finish.
+    pub async fn join(self) {
+        for job in self.jobs {
+            let _ = job.await;
+        }
+    }
+
+    /// Stops the scheduler and waits up to `timeout` for the runs in progress. Returns whether
+    /// everything finished in time.
+    pub async fn shutdown(self, timeout: Duration) -> bool {
+        self.stop();
+        tokio::time::timeout(timeout, self.join()).await.is_ok()
+    }
+}
+
+#[cfg(feature = "rocket")]
+pub struct SchedulerFairing {
+    scheduler: std::sync::Mutex<Option<Scheduler>>,
+    handle: tokio::sync::Mutex<Option<SchedulerHandle>>,
+    grace: Duration,
+}
+
+#[cfg(feature = "rocket")]
+impl SchedulerFairing {
+    /// How long shutdown waits for the runs in progress, 30 seconds by default.
+    pub fn with_grace_period(mut self, grace: Duration) -> Self {
+        self.grace = grace;
+        self
+    }
+}
+
+#[cfg(feature = "rocket")]
+#[rocket::async_trait]
+impl rocket::fairing::Fairing for SchedulerFairing {
+    fn info(&self) -> rocket::fairing::Info {
+        rocket::fairing::Info {
+            name: "Background job scheduler",
+            kind: rocket::fairing::Kind::Liftoff | rocket::fairing::Kind::Shutdown,
+        }
+    }
+
+    async fn on_liftoff(&self, rocket: &rocket::Rocket<rocket::Orbit>) {
+        let scheduler = self.scheduler.lock().unwrap().take();
+        if let Some(scheduler) = scheduler {
+            *self.handle.lock().await = Some(scheduler.start(rocket.shutdown()));
+        }
+    }
+
+    async fn on_shutdown(&self, _rocket: &rocket::Rocket<rocket::Orbit>) {
+        if let Some(handle) = self.handle.lock().await.take() {
+            if !handle.shutdown(self.grace).await {
+                tracing::warn!("scheduled jobs did not finish within the grace period");
+            }
+        }
+    }
+}
@@ -0,0 +1,64 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct SchemaDocument {
+    pub schema_id: String,
+    pub branch: String,
+    pub version: Option<String>,
+    #[serde(default)]
+    pub tables: Vec<TableSchema>,
+}
+
+impl SchemaDocument {
+    pub fn table(&self, name: &str) -> Option<&TableSchema> {
+        self.tables.iter().find(|t| t.name == name)
+    }
+
+    pub fn table_names(&self) -> Vec<String> {
+        self.tables.iter().map(|t| t.name.clone()).collect()
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct TableSchema {
+    pub name: String,
+    #[serde(default)]
+    pub fields: Vec<FieldSchema>,
+    #[serde(default)]
+    pub relations: Vec<RelationSchema>,
+}
+
+impl TableSchema {
+    pub fn field(&self, name: &str) -> Option<&FieldSchema> {
+        self.fields.iter().find(|f| f.name == name)
+    }
+
+    pub fn relation(&self, field: &str) -> Option<&RelationSchema> {
+        self.relations.iter().find(|r| r.field == field)
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct FieldSchema {
+    pub name: String,
+    pub field_type: String,
+    #[serde(default)]
+    pub nullable: bool,
+    #[serde(default)]
+    pub primary_key: bool,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct RelationSchema {
+    pub field: String,
+    pub target_table: String,
+    pub kind: RelationKind,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RelationKind {
+    ForeignKey,
+    OneToOne,
+    ManyToMany,
+}
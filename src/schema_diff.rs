@@ -0,0 +1,266 @@
+use serde::{Deserialize, Serialize};
+use std::fmt::Write;
+
+use crate::schema::{RelationSchema, SchemaDocument, TableSchema};
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[serde(tag = "change", rename_all = "snake_case")]
+pub enum SchemaChange {
+    TableAdded {
+        table: String,
+    },
+    TableRemoved {
+        table: String,
+    },
+    FieldAdded {
+        table: String,
+        field: String,
+        field_type: String,
+        nullable: bool,
+    },
+    FieldRemoved {
+        table: String,
+        field: String,
+    },
+    FieldTypeChanged {
+        table: String,
+        field: String,
+        from: String,
+        to: String,
+    },
+    NullabilityChanged {
+        table: String,
+        field: String,
+        nullable: bool,
+    },
+    RelationAdded {
+        table: String,
+        relation: RelationSchema,
+    },
+    RelationRemoved {
+        table: String,
+        relation: RelationSchema,
+    },
+    RelationChanged {
+        table: String,
+        from: RelationSchema,
+        to: RelationSchema,
+    },
+}
+
+impl SchemaChange {
+    /// A change is breaking when code generated from the old schema can no longer work
+    /// against the new one.
+    pub fn is_breaking(&self) -> bool {
+        match self {
+            SchemaChange::TableAdded { .. } | SchemaChange::RelationAdded { .. } => false,
+            // New required fields break inserts done by existing code
+            SchemaChange::FieldAdded { nullable, .. } => !nullable,
+            // Either direction breaks generated models (optional vs. required types)
+            SchemaChange::NullabilityChanged { .. } => true,
+            SchemaChange::TableRemoved { .. }
+            | SchemaChange::FieldRemoved { .. }
+            | SchemaChange::FieldTypeChanged { .. }
+            | SchemaChange::RelationRemoved { .. }
+            | SchemaChange::RelationChanged { .. } => true,
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        match self {
+            SchemaChange::TableAdded { table } => format!("table `{}` added", table),
+            SchemaChange::TableRemoved { table } => format!("table `{}` removed", table),
+            SchemaChange::FieldAdded {
+                table,
+                field,
+                field_type,
+                nullable,
+            } => format!(
+                "field `{}.{}` added ({}{})",
+                table,
+                field,
+                field_type,
+                if *nullable { ", nullable" } else { "" }
+            ),
+            SchemaChange::FieldRemoved { table, field } => {
+                format!("field `{}.{}` removed", table, field)
+            }
+            SchemaChange::FieldTypeChanged {
+                table,
+                field,
+                from,
+                to,
+            } => format!(
+                "field `{}.{}` changed type {} -> {}",
+                table, field, from, to
+            ),
+            SchemaChange::NullabilityChanged {
+                table,
+                field,
+                nullable,
+            } => format!(
+                "field `{}.{}` is now {}",
+                table,
+                field,
+                if *nullable { "nullable" } else { "required" }
+            ),
+            SchemaChange::RelationAdded { table, relation } => format!(
+                "relation `{}.{}` -> `{}` added",
+                table, relation.field, relation.target_table
+            ),
+            SchemaChange::RelationRemoved { table, relation } => format!(
+                "relation `{}.{}` -> `{}` removed",
+                table, relation.field, relation.target_table
+            ),
+            SchemaChange::RelationChanged { table, from, to } => format!(
+                "relation `{}.{}` changed from `{}` ({:?}) to `{}` ({:?})",
+                table, from.field, from.target_table, from.kind, to.target_table, to.kind
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Default)]
+pub struct SchemaDiff {
+    pub changes: Vec<SchemaChange>,
+}
+
+impl SchemaDiff {
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    pub fn has_breaking_changes(&self) -> bool {
+        self.changes.iter().any(|c| c.is_breaking())
+    }
+
+    pub fn breaking_changes(&self) -> Vec<&SchemaChange> {
+        self.changes.iter().filter(|c| c.is_breaking()).collect()
+    }
+
+    pub fn render_text(&self) -> String {
+        if self.is_empty() {
+            return "No schema changes".to_string();
+        }
+        let mut out = String::new();
+        for change in &self.changes {
+            let marker = if change.is_breaking() { "!" } else { " " };
+            writeln!(out, "{} {}", marker, change.describe()).unwrap();
+        }
+        out
+    }
+
+    pub fn render_markdown(&self) -> String {
+        if self.is_empty() {
+            return "_No schema changes_\n".to_string();
+        }
+        let (breaking, safe): (Vec<_>, Vec<_>) = self.changes.iter().partition(|c| c.is_breaking());
+
+        let mut out = String::new();
+        if !breaking.is_empty() {
+            out.push_str("### Breaking changes\n\n");
+            for change in breaking {
+                writeln!(out, "- {}", change.describe()).unwrap();
+            }
+            out.push('\n');
+        }
+        if !safe.is_empty() {
+            out.push_str("### Other changes\n\n");
+            for change in safe {
+                writeln!(out, "- {}", change.describe()).unwrap();
+            }
+            out.push('\n');
+        }
+        out
+    }
+}
+
+pub fn diff_schemas(old: &SchemaDocument, new: &SchemaDocument) -> SchemaDiff {
+    let mut changes = vec![];
+
+    for old_table in &old.tables {
+        match new.table(&old_table.name) {
+            Some(new_table) => diff_tables(old_table, new_table, &mut changes),
+            None => changes.push(SchemaChange::TableRemoved {
+                table: old_table.name.clone(),
+            }),
+        }
+    }
+
+    for new_table in &new.tables {
+        if old.table(&new_table.name).is_none() {
+            changes.push(SchemaChange::TableAdded {
+                table: new_table.name.clone(),
+            });
+        }
+    }
+
+    SchemaDiff { changes }
+}
+
+fn diff_tables(old: &TableSchema, new: &TableSchema, changes: &mut Vec<SchemaChange>) {
+    let table = &old.name;
+
+    for old_field in &old.fields {
+        match new.field(&old_field.name) {
+            Some(new_field) => {
+                if old_field.field_type != new_field.field_type {
+                    changes.push(SchemaChange::FieldTypeChanged {
+                        table: table.clone(),
+                        field: old_field.name.clone(),
+                        from: old_field.field_type.clone(),
+                        to: new_field.field_type.clone(),
+                    });
+                }
+                if old_field.nullable != new_field.nullable {
+                    changes.push(SchemaChange::NullabilityChanged {
+                        table: table.clone(),
+                        field: old_field.name.clone(),
+                        nullable: new_field.nullable,
+                    });
+                }
+            }
+            None => changes.push(SchemaChange::FieldRemoved {
+                table: table.clone(),
+                field: old_field.name.clone(),
+            }),
+        }
+    }
+
+    for new_field in &new.fields {
+        if old.field(&new_field.name).is_none() {
+            changes.push(SchemaChange::FieldAdded {
+                table: table.clone(),
+                field: new_field.name.clone(),
+                field_type: new_field.field_type.clone(),
+                nullable: new_field.nullable,
+            });
+        }
+    }
+
+    for old_relation in &old.relations {
+        match new.relation(&old_relation.field) {
+            Some(new_relation) if new_relation != old_relation => {
+                changes.push(SchemaChange::RelationChanged {
+                    table: table.clone(),
+                    from: old_relation.clone(),
+                    to: new_relation.clone(),
+                })
+            }
+            Some(_) => {}
+            None => changes.push(SchemaChange::RelationRemoved {
+                table: table.clone(),
+                relation: old_relation.clone(),
+            }),
+        }
+    }
+
+    for new_relation in &new.relations {
+        if old.relation(&new_relation.field).is_none() {
+            changes.push(SchemaChange::RelationAdded {
+                table: table.clone(),
+                relation: new_relation.clone(),
+            });
+        }
+    }
+}
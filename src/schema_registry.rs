@@ -0,0 +1,174 @@
+use data_encoding::BASE64;
+use reqwest::{Client, RequestBuilder, Response};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::{error::Error, fs, path::Path};
+
+use crate::{schema::SchemaDocument, utils::get_token_from_file_storage};
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct SchemaBranch {
+    pub name: String,
+    pub version: Option<String>,
+}
+
+// A compiled protobuf `FileDescriptorSet`, as written by `protoc --descriptor_set_out`, versioned
+// on a schema branch like the tables of a `SchemaDocument`
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct ProtoDescriptor {
+    pub schema_id: String,
+    pub branch: String,
+    pub version: Option<String>,
+    #[serde(serialize_with = "to_base64", deserialize_with = "from_base64")]
+    pub descriptor_set: Vec<u8>,
+}
+
+impl ProtoDescriptor {
+    pub fn from_file<P: AsRef<Path>>(
+        schema_id: &str,
+        branch: &str,
+        path: P,
+    ) -> Result<Self, Box<dyn Error>> {
+        let path = path.as_ref();
+        let descriptor_set = fs::read(path).map_err(|e| {
+            format!(
+                "Failed to read the descriptor set '{}': {}",
+                path.display(),
+                e
+            )
+        })?;
+        if descriptor_set.is_empty() {
+            return Err(format!("The descriptor set '{}' is empty", path.display()).into());
+        }
+        Ok(ProtoDescriptor {
+            schema_id: schema_id.to_string(),
+            branch: branch.to_string(),
+            version: None,
+            descriptor_set,
+        })
+    }
+
+    /// Writes the descriptor set to `path`, e.g. for `ProtocStubs` to generate from.
+    pub fn write_to<P: AsRef<Path>>(&self, path: P) -> Result<(), Box<dyn Error>> {
+        let path = path.as_ref();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, &self.descriptor_set)
+            .map_err(|e| format!("Failed to write '{}': {}", path.display(), e).into())
+    }
+}
+
+fn to_base64<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&BASE64.encode(bytes))
+}
+
+fn from_base64<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+    let encoded = String::deserialize(deserializer)?;
+    BASE64
+        .decode(encoded.as_bytes())
+        .map_err(serde::de::Error::custom)
+}
+
+#[derive(Debug, Clone)]
+pub struct SchemaRegistryClient {
+    base_url: String,
+    token: String,
+    http: Client,
+}
+
+impl SchemaRegistryClient {
+    pub fn new(base_url: &str, token: &str) -> Self {
+        SchemaRegistryClient {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            token: token.to_string(),
+            http: Client::new(),
+        }
+    }
+
+    /// Creates a client authenticated with the API token stored by `ginger-auth`.
+    pub fn from_stored_token(base_url: &str) -> Self {
+        Self::new(base_url, &get_token_from_file_storage())
+    }
+
+    pub async fn fetch_schema(
+        &self,
+        schema_id: &str,
+        branch: &str,
+    ) -> Result<SchemaDocument, Box<dyn Error>> {
+        let url = format!(
+            "{}/schemas/{}/branches/{}",
+            self.base_url, schema_id, branch
+        );
+        let response = self.authorized(self.http.get(&url)).send().await?;
+        Ok(check_status(response, &url).await?.json().await?)
+    }
+
+    pub async fn publish_schema(&self, schema: &SchemaDocument) -> Result<(), Box<dyn Error>> {
+        let url = format!(
+            "{}/schemas/{}/branches/{}",
+            self.base_url, schema.schema_id, schema.branch
+        );
+        let response = self
+            .authorized(self.http.put(&url))
+            .json(schema)
+            .send()
+            .await?;
+        check_status(response, &url).await?;
+        Ok(())
+    }
+
+    /// The descriptor set published on `branch`, along with its version.
+    pub async fn fetch_proto(
+        &self,
+        schema_id: &str,
+        branch: &str,
+    ) -> Result<ProtoDescriptor, Box<dyn Error>> {
+        let url = format!(
+            "{}/schemas/{}/branches/{}/proto",
+            self.base_url, schema_id, branch
+        );
+        let response = self.authorized(self.http.get(&url)).send().await?;
+        Ok(check_status(response, &url).await?.json().await?)
+    }
+
+    pub async fn publish_proto(&self, descriptor: &ProtoDescriptor) -> Result<(), Box<dyn Error>> {
+        let url = format!(
+            "{}/schemas/{}/branches/{}/proto",
+            self.base_url, descriptor.schema_id, descriptor.branch
+        );
+        let response = self
+            .authorized(self.http.put(&url))
+            .json(descriptor)
+            .send()
+            .await?;
+        check_status(response, &url).await?;
+        Ok(())
+    }
+
+    pub async fn list_branches(
+        &self,
+        schema_id: &str,
+    ) -> Result<Vec<SchemaBranch>, Box<dyn Error>> {
+        let url = format!("{}/schemas/{}/branches", self.base_url, schema_id);
+        let response = self.authorized(self.http.get(&url)).send().await?;
+        Ok(check_status(response, &url).await?.json().await?)
+    }
+
+    fn authorized(&self, request: RequestBuilder) -> RequestBuilder {
+        // Same header the `APIClaims` guard expects on the server side
+        request.header("X-API-Authorization", format!("Bearer {}", self.token))
+    }
+}
+
+async fn check_status(response: Response, url: &str) -> Result<Response, Box<dyn Error>> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let body = response.text().await.unwrap_or_default();
+    Err(format!(
+        "Request to '{}' failed with status {}: {}",
+        url, status, body
+    )
+    .into())
+}
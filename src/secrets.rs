@@ -0,0 +1,309 @@
+use async_trait::async_trait;
+use std::{
+    env,
+    error::Error,
+    fmt,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use crate::Environment;
+
+#[cfg(feature = "vault")]
+pub use self::vault::VaultSecrets;
+
+pub type SecretError = Box<dyn Error + Send + Sync>;
+
+/// Overrides where `FileSecrets::from_env` reads mounted secrets.
+pub const SECRETS_DIR_ENV: &str = "GINGER_SECRETS_DIR";
+pub const DEFAULT_SECRETS_DIR: &str = "/var/run/secrets/ginger";
+
+/// Secret signing and checking the tokens, `JWT_SECRET` as an env var.
+pub const JWT_SECRET: &str = "jwt/secret";
+/// Token the service sends to the others in `X-ISC-Authorization`, `ISC_TOKEN` as an env var.
+pub const ISC_TOKEN: &str = "isc/token";
+/// Key of the encrypted config values, `CONFIG_KEY` as an env var.
+pub const CONFIG_KEY: &str = "config/key";
+
+/// Secret named `field` of the database `database`, e.g. `db/orders/password`.
+pub fn db_secret(database: &str, field: &str) -> String {
+    format!("db/{}/{}", database, field)
+}
+
+// A secret value, kept out of `Debug` output so it does not end up in logs by accident
+#[derive(Clone, PartialEq, Eq)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn new(value: &str) -> Self {
+        Secret(value.to_string())
+    }
+
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Secret(***)")
+    }
+}
+
+// Where secrets come from. Secrets have `/` separated lowercase names, e.g. `jwt/secret`, each
+// provider mapping them to its own naming.
+#[async_trait]
+pub trait SecretProvider: Send + Sync {
+    /// The secret `name`, `None` when this provider does not have it.
+    async fn get(&self, name: &str) -> Result<Option<Secret>, SecretError>;
+}
+
+impl dyn SecretProvider + '_ {
+    /// Like `get`, failing when the secret is missing.
+    pub async fn require(&self, name: &str) -> Result<Secret, SecretError> {
+        self.get(name)
+            .await?
+            .ok_or_else(|| format!("The secret '{}' is not set", name).into())
+    }
+}
+
+// Secrets read from env vars, `db/orders/password` from `DB_ORDERS_PASSWORD`
+#[derive(Debug, Clone, Default)]
+pub struct EnvSecrets;
+
+impl EnvSecrets {
+    /// The env var holding the secret `name`.
+    pub fn var_name(name: &str) -> String {
+        name.chars()
+            .map(|c| match c.is_ascii_alphanumeric() {
+                true => c.to_ascii_uppercase(),
+                false => '_',
+            })
+            .collect()
+    }
+}
+
+#[async_trait]
+impl SecretProvider for EnvSecrets {
+    async fn get(&self, name: &str) -> Result<Option<Secret>, SecretError> {
+        Ok(env::var(Self::var_name(name))
+            .ok()
+            .map(|value| Secret::new(&value)))
+    }
+}
+
+// Secrets mounted as files, `db/orders/password` read from `{dir}/db/orders/password`, e.g. a
+// Kubernetes secret with a `password` key mounted at `{dir}/db/orders`
+#[derive(Debug, Clone)]
+pub struct FileSecrets {
+    dir: PathBuf,
+}
+
+impl FileSecrets {
+    pub fn new<P: AsRef<Path>>(dir: P) -> Self {
+        FileSecrets {
+            dir: dir.as_ref().to_path_buf(),
+        }
+    }
+
+    /// Reads from `GINGER_SECRETS_DIR`, `/var/run/secrets/ginger` by default.
+    pub fn from_env() -> Self {
+        Self::new(env::var(SECRETS_DIR_ENV).unwrap_or_else(|_| DEFAULT_SECRETS_DIR.to_string()))
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+}
+
+#[async_trait]
+impl SecretProvider for FileSecrets {
+    async fn get(&self, name: &str) -> Result<Option<Secret>, SecretError> {
+        if name
+            .split('/')
+            .any(|segment| segment.is_empty() || segment == "." || segment == "..")
+        {
+            return Err(format!("'{}' is not a valid secret name", name).into());
+        }
+        let path = self.dir.join(name);
+        match tokio::fs::read_to_string(&path).await {
+            // Files written by editors and `echo` end with a newline that is not part of the secret
+            Ok(value) => Ok(Some(Secret::new(value.trim_end_matches(['\n', '\r'])))),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(format!("Failed to read the secret '{}': {}", path.display(), e).into()),
+        }
+    }
+}
+
+// Tries each provider in turn, the first one having a secret wins
+#[derive(Clone, Default)]
+pub struct ChainedSecrets {
+    providers: Vec<Arc<dyn SecretProvider>>,
+}
+
+impl ChainedSecrets {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn then<P: SecretProvider + 'static>(mut self, provider: P) -> Self {
+        self.providers.push(Arc::new(provider));
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.providers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.providers.is_empty()
+    }
+}
+
+#[async_trait]
+impl SecretProvider for ChainedSecrets {
+    async fn get(&self, name: &str) -> Result<Option<Secret>, SecretError> {
+        for provider in &self.providers {
+            if let Some(secret) = provider.get(name).await? {
+                return Ok(Some(secret));
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// The providers of `env`: Vault when `VAULT_ADDR` is set (with the `vault` feature), then the
+/// mounted files, then env vars. Env vars leak through process listings and crash dumps, so
+/// they are not read in `Prod` and `ProdK8`.
+pub fn provider_for(env: &Environment) -> Result<Arc<dyn SecretProvider>, SecretError> {
+    #[allow(unused_mut)]
+    let mut chain = ChainedSecrets::new();
+    #[cfg(feature = "vault")]
+    if env::var(vault::VAULT_ADDR_ENV).is_ok() {
+        chain = chain.then(VaultSecrets::from_env()?);
+    }
+    chain = chain.then(FileSecrets::from_env());
+    if !matches!(env, Environment::Prod | Environment::ProdK8) {
+        chain = chain.then(EnvSecrets);
+    }
+    Ok(Arc::new(chain))
+}
+
+#[cfg(feature = "vault")]
+mod vault {
+    use async_trait::async_trait;
+    use reqwest::{Client, StatusCode};
+    use serde_json::{Map, Value};
+    use std::{collections::HashMap, env, sync::Mutex};
+
+    use super::{Secret, SecretError, SecretProvider};
+
+    pub const VAULT_ADDR_ENV: &str = "VAULT_ADDR";
+    pub const VAULT_TOKEN_ENV: &str = "VAULT_TOKEN";
+    /// Mount of the KV v2 engine, `secret` by default.
+    pub const VAULT_MOUNT_ENV: &str = "VAULT_MOUNT";
+    /// Path under the mount the secret names are relative to, e.g. the service name.
+    pub const VAULT_PREFIX_ENV: &str = "VAULT_PREFIX";
+
+    type Entries = Option<Map<String, Value>>;
+
+    // Secrets of a Vault KV v2 engine, `db/orders/password` being the `password` key of the
+    // `{prefix}/db/orders` entry. Entries are read once and kept for the life of the process.
+    pub struct VaultSecrets {
+        addr: String,
+        token: String,
+        mount: String,
+        prefix: Option<String>,
+        http: Client,
+        entries: Mutex<HashMap<String, Entries>>,
+    }
+
+    impl VaultSecrets {
+        pub fn new(addr: &str, token: &str, mount: &str) -> Self {
+            VaultSecrets {
+                addr: addr.trim_end_matches('/').to_string(),
+                token: token.to_string(),
+                mount: mount.trim_matches('/').to_string(),
+                prefix: None,
+                http: Client::new(),
+                entries: Mutex::new(HashMap::new()),
+            }
+        }
+
+        pub fn with_prefix(mut self, prefix: &str) -> Self {
+            self.prefix = Some(prefix.trim_matches('/').to_string());
+            self
+        }
+
+        /// Reads `VAULT_ADDR`, `VAULT_TOKEN` and the optional `VAULT_MOUNT` and `VAULT_PREFIX`.
+        pub fn from_env() -> Result<Self, SecretError> {
+            let var = |name: &str| env::var(name).map_err(|_| format!("{} must be set", name));
+            let mount = env::var(VAULT_MOUNT_ENV).unwrap_or_else(|_| "secret".to_string());
+            let vault = Self::new(&var(VAULT_ADDR_ENV)?, &var(VAULT_TOKEN_ENV)?, &mount);
+            Ok(match env::var(VAULT_PREFIX_ENV) {
+                Ok(prefix) => vault.with_prefix(&prefix),
+                Err(_) => vault,
+            })
+        }
+
+        async fn entries(&self, path: &str) -> Result<Entries, SecretError> {
+            if let Some(entries) = self.entries.lock().unwrap().get(path) {
+                return Ok(entries.clone());
+            }
+            let url = format!("{}/v1/{}/data/{}", self.addr, self.mount, path);
+            let response = self
+                .http
+                .get(&url)
+                .header("X-Vault-Token", &self.token)
+                .send()
+                .await
+                .map_err(|e| format!("Failed to reach Vault at '{}': {}", self.addr, e))?;
+            let entries = match response.status() {
+                StatusCode::NOT_FOUND => None,
+                status if status.is_success() => {
+                    let mut body: Value = response.json().await?;
+                    match body["data"]["data"].take() {
+                        Value::Object(entries) => Some(entries),
+                        _ => None,
+                    }
+                }
+                status => {
+                    return Err(format!("Vault answered {} for '{}'", status, path).into());
+                }
+            };
+            self.entries
+                .lock()
+                .unwrap()
+                .insert(path.to_string(), entries.clone());
+            Ok(entries)
+        }
+    }
+
+    #[async_trait]
+    impl SecretProvider for VaultSecrets {
+        async fn get(&self, name: &str) -> Result<Option<Secret>, SecretError> {
+            let (path, key) = match name.rsplit_once('/') {
+                Some((path, key)) => (Some(path), key),
+                None => (None, name),
+            };
+            let path = match (&self.prefix, path) {
+                (Some(prefix), Some(path)) => format!("{}/{}", prefix, path),
+                (Some(prefix), None) => prefix.clone(),
+                (None, Some(path)) => path.to_string(),
+                (None, None) => {
+                    return Err(format!(
+                        "The secret '{}' needs a path or a Vault prefix to be read from",
+                        name
+                    )
+                    .into())
+                }
+            };
+            let entries = self.entries(&path).await?;
+            Ok(entries
+                .as_ref()
+                .and_then(|entries| entries.get(key))
+                .and_then(Value::as_str)
+                .map(Secret::new))
+        }
+    }
+}
@@ -0,0 +1,198 @@
+use rocket::{
+    fairing::{Fairing, Info, Kind},
+    http::Header,
+    shield::{Frame, Hsts, NoSniff, Shield},
+    Build, Request, Response, Rocket,
+};
+use serde::{Deserialize, Serialize};
+use std::{error::Error, fs, path::Path};
+
+use crate::Environment;
+
+const HSTS: &str = "Strict-Transport-Security";
+const CSP: &str = "Content-Security-Policy";
+const CSP_REPORT_ONLY: &str = "Content-Security-Policy-Report-Only";
+const REFERRER_POLICY: &str = "Referrer-Policy";
+const FRAME_OPTIONS: &str = "X-Frame-Options";
+const CONTENT_TYPE_OPTIONS: &str = "X-Content-Type-Options";
+
+const STRICT_CSP: &str = "default-src 'self'; object-src 'none'; base-uri 'self'; \
+                          form-action 'self'; frame-ancestors 'none'";
+// Dev servers inject inline scripts and talk to their hot reload socket
+const DEV_CSP: &str = "default-src 'self' 'unsafe-inline' 'unsafe-eval' http://localhost:* \
+                       ws://localhost:*; object-src 'none'";
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum FrameOptions {
+    Deny,
+    SameOrigin,
+    Allow, // No header, the CSP's `frame-ancestors` still applies
+}
+
+// Headers set on every response by the `SecurityHeaders` fairing. Empty values and a zero HSTS
+// max-age leave the header out.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct SecurityHeadersConfig {
+    pub hsts_max_age: u64, // In seconds
+    pub hsts_include_subdomains: bool,
+    pub hsts_preload: bool,
+    pub content_security_policy: String,
+    /// Sends the policy as `Content-Security-Policy-Report-Only`, to try one out.
+    pub csp_report_only: bool,
+    pub referrer_policy: String,
+    pub frame_options: FrameOptions,
+    pub no_sniff: bool,
+}
+
+impl SecurityHeadersConfig {
+    /// Relaxed in development: no HSTS, so `localhost` is not pinned to HTTPS, and a report only
+    /// policy allowing the dev server. Staging uses the production policy with a one day HSTS
+    /// max-age, production pins HTTPS for two years.
+    pub fn for_environment(env: &Environment) -> Self {
+        let strict = SecurityHeadersConfig {
+            hsts_max_age: 63_072_000,
+            hsts_include_subdomains: true,
+            hsts_preload: false,
+            content_security_policy: STRICT_CSP.to_string(),
+            csp_report_only: false,
+            referrer_policy: "strict-origin-when-cross-origin".to_string(),
+            frame_options: FrameOptions::Deny,
+            no_sniff: true,
+        };
+        match env {
+            Environment::Dev => SecurityHeadersConfig {
+                hsts_max_age: 0,
+                hsts_include_subdomains: false,
+                content_security_policy: DEV_CSP.to_string(),
+                csp_report_only: true,
+                frame_options: FrameOptions::SameOrigin,
+                ..strict
+            },
+            Environment::Stage | Environment::StageK8 => SecurityHeadersConfig {
+                hsts_max_age: 86_400,
+                ..strict
+            },
+            Environment::Prod | Environment::ProdK8 => strict,
+        }
+    }
+
+    /// The defaults of `env` overridden by the `[security_headers]` section of a TOML file, if
+    /// any, e.g.
+    ///
+    /// ```toml
+    /// [security_headers]
+    /// content_security_policy = "default-src 'self'; img-src 'self' https://cdn.example.com"
+    /// frame_options = "same_origin"
+    /// ```
+    pub fn from_file<P: AsRef<Path>>(env: &Environment, path: P) -> Result<Self, Box<dyn Error>> {
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read the file '{}': {}", path.display(), e))?;
+        let file: toml::Table = toml::from_str(&contents)
+            .map_err(|e| format!("Failed to parse TOML from file '{}': {}", path.display(), e))?;
+        let defaults = Self::for_environment(env);
+        let overrides = match file.get("security_headers") {
+            Some(toml::Value::Table(overrides)) => overrides.clone(),
+            Some(_) => {
+                return Err(format!(
+                    "The security_headers entry of '{}' must be a table",
+                    path.display()
+                )
+                .into())
+            }
+            None => return Ok(defaults),
+        };
+        let mut merged = toml::Table::try_from(defaults)?;
+        merged.extend(overrides);
+        toml::Value::Table(merged).try_into().map_err(|e| {
+            format!(
+                "Invalid security_headers section in '{}': {}",
+                path.display(),
+                e
+            )
+            .into()
+        })
+    }
+
+    pub fn headers(&self) -> Vec<Header<'static>> {
+        let mut headers = vec![];
+        if self.hsts_max_age > 0 {
+            let mut value = format!("max-age={}", self.hsts_max_age);
+            if self.hsts_include_subdomains {
+                value.push_str("; includeSubDomains");
+            }
+            if self.hsts_preload {
+                value.push_str("; preload");
+            }
+            headers.push(Header::new(HSTS, value));
+        }
+        if !self.content_security_policy.is_empty() {
+            let name = if self.csp_report_only {
+                CSP_REPORT_ONLY
+            } else {
+                CSP
+            };
+            headers.push(Header::new(name, self.content_security_policy.clone()));
+        }
+        if !self.referrer_policy.is_empty() {
+            headers.push(Header::new(REFERRER_POLICY, self.referrer_policy.clone()));
+        }
+        match self.frame_options {
+            FrameOptions::Deny => headers.push(Header::new(FRAME_OPTIONS, "DENY")),
+            FrameOptions::SameOrigin => headers.push(Header::new(FRAME_OPTIONS, "SAMEORIGIN")),
+            FrameOptions::Allow => {}
+        }
+        if self.no_sniff {
+            headers.push(Header::new(CONTENT_TYPE_OPTIONS, "nosniff"));
+        }
+        headers
+    }
+}
+
+// Sets the headers of a `SecurityHeadersConfig` on every response, leaving alone those a route
+// already set, e.g. a page relaxing the CSP for an embedded editor. Rocket's default `Shield`
+// is replaced by one without the policies configured here, so the two never disagree.
+#[derive(Debug, Clone)]
+pub struct SecurityHeaders {
+    headers: Vec<Header<'static>>,
+}
+
+impl SecurityHeaders {
+    pub fn new(config: &SecurityHeadersConfig) -> Self {
+        SecurityHeaders {
+            headers: config.headers(),
+        }
+    }
+
+    pub fn for_environment(env: &Environment) -> Self {
+        Self::new(&SecurityHeadersConfig::for_environment(env))
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for SecurityHeaders {
+    fn info(&self) -> Info {
+        Info {
+            name: "Security headers",
+            kind: Kind::Ignite | Kind::Response,
+        }
+    }
+
+    async fn on_ignite(&self, rocket: Rocket<Build>) -> rocket::fairing::Result {
+        let shield = Shield::default()
+            .disable::<Hsts>()
+            .disable::<Frame>()
+            .disable::<NoSniff>();
+        Ok(rocket.attach(shield))
+    }
+
+    async fn on_response<'r>(&self, _request: &'r Request<'_>, response: &mut Response<'r>) {
+        for header in &self.headers {
+            if !response.headers().contains(header.name()) {
+                response.set_header(header.clone());
+            }
+        }
+    }
+}
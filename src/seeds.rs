@@ -0,0 +1,384 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::{
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{DatabaseConfig, DbEngine, Environment, GingerDBConfig};
+
+// Rows upserted into one table, matched on the `key` columns so loading twice changes nothing
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct SeedTable {
+    pub name: String,
+    pub key: Vec<String>,
+    #[serde(default)]
+    pub rows: Vec<Map<String, Value>>,
+}
+
+// A seed file, e.g. `seeds/users.toml`:
+//
+// database = "main"
+// environments = ["dev"]
+//
+// [[tables]]
+// name = "users"
+// key = ["email"]
+// rows = [{ email = "ada@example.com", name = "Ada" }]
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct SeedFixture {
+    pub database: String, // Name of a database of `GingerDBConfig`
+    #[serde(default)]
+    pub environments: Vec<Environment>, // Every environment but the production ones when empty
+    #[serde(default)]
+    pub tables: Vec<SeedTable>,
+    #[serde(skip)]
+    pub source: Option<PathBuf>,
+}
+
+impl SeedFixture {
+    /// Reads a `.toml` or `.json` fixture.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error>> {
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read the seed file '{}': {}", path.display(), e))?;
+        let mut fixture: SeedFixture = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => toml::from_str(&contents).map_err(|e| e.to_string()),
+            Some("json") => serde_json::from_str(&contents).map_err(|e| e.to_string()),
+            _ => Err("only .toml and .json seed files are supported".to_string()),
+        }
+        .map_err(|e| format!("Invalid seed file '{}': {}", path.display(), e))?;
+        fixture.source = Some(path.to_path_buf());
+        fixture.validate()?;
+        Ok(fixture)
+    }
+
+    /// Production is only seeded by fixtures naming it explicitly.
+    pub fn applies_to(&self, env: &Environment) -> bool {
+        if self.environments.is_empty() {
+            return !matches!(env, Environment::Prod | Environment::ProdK8);
+        }
+        self.environments.contains(env)
+    }
+
+    fn name(&self) -> String {
+        self.source
+            .as_ref()
+            .map(|source| source.display().to_string())
+            .unwrap_or_else(|| self.database.clone())
+    }
+
+    pub fn validate(&self) -> Result<(), Box<dyn Error>> {
+        for table in &self.tables {
+            if table.key.is_empty() {
+                return Err(format!(
+                    "{}: the table '{}' has no `key` to upsert on",
+                    self.name(),
+                    table.name
+                )
+                .into());
+            }
+            for (index, row) in table.rows.iter().enumerate() {
+                if let Some(missing) = table.key.iter().find(|key| !row.contains_key(*key)) {
+                    return Err(format!(
+                        "{}: row {} of '{}' has no value for the key '{}'",
+                        self.name(),
+                        index + 1,
+                        table.name,
+                        missing
+                    )
+                    .into());
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The fixtures of `dir` and of its `<env>` sub directory (e.g. `seeds/dev`) applying to `env`,
+/// in file name order.
+pub fn load_seeds<P: AsRef<Path>>(
+    dir: P,
+    env: &Environment,
+) -> Result<Vec<SeedFixture>, Box<dyn Error>> {
+    let dir = dir.as_ref();
+    let mut fixtures = vec![];
+    for dir in [dir.to_path_buf(), dir.join(env.to_string())] {
+        if !dir.is_dir() {
+            continue;
+        }
+        let mut files: Vec<PathBuf> = fs::read_dir(&dir)?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| {
+                path.is_file()
+                    && matches!(
+                        path.extension().and_then(|ext| ext.to_str()),
+                        Some("toml") | Some("json")
+                    )
+            })
+            .collect();
+        files.sort();
+        for file in files {
+            let fixture = SeedFixture::from_file(&file)?;
+            if fixture.applies_to(env) {
+                fixtures.push(fixture);
+            }
+        }
+    }
+    Ok(fixtures)
+}
+
+// The fixtures of one database, ready to be applied
+#[derive(Debug, Clone, PartialEq)]
+pub struct SeedPlan {
+    pub database: DatabaseConfig,
+    pub engine: DbEngine,
+    pub fixtures: Vec<SeedFixture>,
+}
+
+impl SeedPlan {
+    pub fn row_count(&self) -> usize {
+        self.fixtures
+            .iter()
+            .flat_map(|fixture| &fixture.tables)
+            .map(|table| table.rows.len())
+            .sum()
+    }
+}
+
+/// Groups `fixtures` by database, failing for databases missing from `config`, disabled or of
+/// an engine seeds cannot be loaded into.
+pub fn plan_seeds(
+    config: &GingerDBConfig,
+    fixtures: Vec<SeedFixture>,
+) -> Result<Vec<SeedPlan>, Box<dyn Error>> {
+    let mut plans: Vec<SeedPlan> = vec![];
+    for fixture in fixtures {
+        if let Some(plan) = plans
+            .iter_mut()
+            .find(|plan| plan.database.name == fixture.database)
+        {
+            plan.fixtures.push(fixture);
+            continue;
+        }
+        let database = config
+            .database
+            .iter()
+            .find(|db| db.name == fixture.database)
+            .ok_or_else(|| {
+                format!(
+                    "{}: there is no database '{}' in the db config",
+                    fixture.name(),
+                    fixture.database
+                )
+            })?;
+        if !database.enable {
+            return Err(format!(
+                "{}: the database '{}' is disabled",
+                fixture.name(),
+                database.name
+            )
+            .into());
+        }
+        let engine = database.effective_engine();
+        if !matches!(
+            engine,
+            DbEngine::Postgres | DbEngine::Mysql | DbEngine::Redis
+        ) {
+            return Err(format!(
+                "{}: seeding {} databases is not supported",
+                fixture.name(),
+                engine
+            )
+            .into());
+        }
+        plans.push(SeedPlan {
+            database: database.clone(),
+            engine,
+            fixtures: vec![fixture],
+        });
+    }
+    Ok(plans)
+}
+
+fn identifier(engine: DbEngine, name: &str) -> Result<String, Box<dyn Error>> {
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return Err(format!("'{}' is not a valid table or column name", name).into());
+    }
+    Ok(match engine {
+        DbEngine::Mysql => format!("`{}`", name),
+        _ => format!("\"{}\"", name),
+    })
+}
+
+fn literal(engine: DbEngine, value: &Value) -> String {
+    let quote = |s: &str| {
+        let escaped = s.replace('\'', "''");
+        // MySQL also treats backslashes as escapes in string literals
+        let escaped = match engine {
+            DbEngine::Mysql => escaped.replace('\\', "\\\\"),
+            _ => escaped,
+        };
+        format!("'{}'", escaped)
+    };
+    match value {
+        Value::Null => "NULL".to_string(),
+        Value::Bool(b) => if *b { "TRUE" } else { "FALSE" }.to_string(),
+        Value::Number(n) => n.to_string(),
+        Value::String(s) => quote(s),
+        // Stored as JSON text, e.g. in `jsonb` columns
+        Value::Array(_) | Value::Object(_) => quote(&value.to_string()),
+    }
+}
+
+impl SeedTable {
+    /// One upsert per row, inserting it or updating its non key columns.
+    pub fn upsert_sql(&self, engine: DbEngine) -> Result<Vec<String>, Box<dyn Error>> {
+        let table = identifier(engine, &self.name)?;
+        let keys = self
+            .key
+            .iter()
+            .map(|key| identifier(engine, key))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        self.rows
+            .iter()
+            .map(|row| {
+                let mut columns = vec![];
+                let mut values = vec![];
+                let mut updates = vec![];
+                for (column, value) in row {
+                    let quoted = identifier(engine, column)?;
+                    if !self.key.contains(column) {
+                        updates.push(match engine {
+                            DbEngine::Mysql => format!("{} = VALUES({})", quoted, quoted),
+                            _ => format!("{} = EXCLUDED.{}", quoted, quoted),
+                        });
+                    }
+                    columns.push(quoted);
+                    values.push(literal(engine, value));
+                }
+                let insert = format!(
+                    "INSERT INTO {} ({}) VALUES ({})",
+                    table,
+                    columns.join(", "),
+                    values.join(", ")
+                );
+                Ok(match engine {
+                    DbEngine::Postgres if updates.is_empty() => {
+                        format!("{} ON CONFLICT ({}) DO NOTHING", insert, keys.join(", "))
+                    }
+                    DbEngine::Postgres => format!(
+                        "{} ON CONFLICT ({}) DO UPDATE SET {}",
+                        insert,
+                        keys.join(", "),
+                        updates.join(", ")
+                    ),
+                    DbEngine::Mysql if updates.is_empty() => {
+                        format!(
+                            "{} ON DUPLICATE KEY UPDATE {} = {}",
+                            insert, keys[0], keys[0]
+                        )
+                    }
+                    DbEngine::Mysql => {
+                        format!("{} ON DUPLICATE KEY UPDATE {}", insert, updates.join(", "))
+                    }
+                    _ => return Err(format!("{} has no SQL upserts", engine).into()),
+                })
+            })
+            .collect()
+    }
+
+    /// The cache key of a row, e.g. `users:ada@example.com`.
+    pub fn row_key(&self, row: &Map<String, Value>) -> String {
+        let values: Vec<String> = self
+            .key
+            .iter()
+            .map(|key| match row.get(key) {
+                Some(Value::String(s)) => s.clone(),
+                Some(value) => value.to_string(),
+                None => String::new(),
+            })
+            .collect();
+        format!("{}:{}", self.name, values.join(":"))
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct SeedReport {
+    pub database: String,
+    pub rows: usize,
+}
+
+#[cfg(feature = "diesel")]
+pub use self::sql::apply_sql;
+
+#[cfg(feature = "diesel")]
+mod sql {
+    use diesel::{
+        query_builder::SqlQuery, query_dsl::methods::ExecuteDsl, sql_query, Connection, RunQueryDsl,
+    };
+    use std::error::Error;
+
+    use super::{SeedPlan, SeedReport};
+
+    /// Upserts the rows of `plan` in one transaction.
+    pub fn apply_sql<C>(conn: &mut C, plan: &SeedPlan) -> Result<SeedReport, Box<dyn Error>>
+    where
+        C: Connection,
+        SqlQuery: ExecuteDsl<C>,
+    {
+        let mut statements = vec![];
+        for table in plan.fixtures.iter().flat_map(|fixture| &fixture.tables) {
+            statements.extend(table.upsert_sql(plan.engine)?);
+        }
+        conn.transaction(|conn| {
+            for statement in &statements {
+                sql_query(statement.as_str()).execute(conn)?;
+            }
+            Ok::<_, diesel::result::Error>(())
+        })
+        .map_err(|e| {
+            format!(
+                "Failed to seed the database '{}': {}",
+                plan.database.name, e
+            )
+        })?;
+        Ok(SeedReport {
+            database: plan.database.name.clone(),
+            rows: statements.len(),
+        })
+    }
+}
+
+#[cfg(feature = "redis")]
+pub use self::cache::apply_redis;
+
+#[cfg(feature = "redis")]
+mod cache {
+    use redis::AsyncCommands;
+    use std::error::Error;
+
+    use super::{SeedPlan, SeedReport};
+
+    /// Sets every row as JSON under its `row_key`.
+    pub async fn apply_redis(url: &str, plan: &SeedPlan) -> Result<SeedReport, Box<dyn Error>> {
+        let client = redis::Client::open(url)
+            .map_err(|e| format!("Invalid redis url for '{}': {}", plan.database.name, e))?;
+        let mut connection = client.get_multiplexed_async_connection().await?;
+        let mut rows = 0;
+        for table in plan.fixtures.iter().flat_map(|fixture| &fixture.tables) {
+            for row in &table.rows {
+                let _: () = connection
+                    .set(table.row_key(row), serde_json::to_string(row)?)
+                    .await?;
+                rows += 1;
+            }
+        }
+        Ok(SeedReport {
+            database: plan.database.name.clone(),
+            rows,
+        })
+    }
+}
@@ -0,0 +1,274 @@
+use schemars::{
+    gen::SchemaGenerator,
+    schema::{InstanceType, ObjectValidation, Schema, SchemaObject},
+    JsonSchema,
+};
+use serde::{
+    de::{self, Deserializer},
+    ser::Serializer,
+    Deserialize, Serialize,
+};
+use std::{
+    collections::{BTreeMap, HashMap},
+    error::Error,
+    fmt,
+};
+use url::Url;
+
+use crate::{Environment, ServiceConfig};
+
+// A service referenced from `services.toml`. In the file it stays a flat table keyed by
+// environment, e.g.
+//
+// [services."@ginger-society/IAMService"]
+// dev = "http://localhost:8080"
+// prod = "https://api.gingersociety.org/iam"
+// schema_url = "https://api.gingersociety.org/iam/openapi.json"
+// version = "^1.4"
+//
+// Keys that are neither an environment nor a known field are kept in `extra`.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ServiceRef {
+    pub name: String,
+    pub env_urls: HashMap<Environment, Url>,
+    pub schema_url: Option<Url>,
+    pub version_pin: Option<String>,
+    pub extra: HashMap<String, String>,
+}
+
+const SCHEMA_URL_KEY: &str = "schema_url";
+const VERSION_PIN_KEY: &str = "version";
+
+impl ServiceRef {
+    pub fn new(name: &str) -> Self {
+        ServiceRef {
+            name: name.to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// URL for `env`, falling back to the backend environment (`prod` for `prod_k8`).
+    pub fn url_for(&self, env: &Environment) -> Option<&Url> {
+        self.env_urls
+            .get(env)
+            .or_else(|| self.env_urls.get(&env.as_backend()))
+    }
+
+    pub fn environments(&self) -> Vec<Environment> {
+        Environment::all()
+            .into_iter()
+            .filter(|env| self.env_urls.contains_key(env))
+            .collect()
+    }
+
+    fn from_table(name: &str, table: HashMap<String, String>) -> Result<Self, UrlResolveError> {
+        let mut service = ServiceRef::new(name);
+
+        for (key, value) in table {
+            if let Ok(env) = key.parse::<Environment>() {
+                service.env_urls.insert(env, parse_url(name, &key, &value)?);
+            } else if key == SCHEMA_URL_KEY {
+                service.schema_url = Some(parse_url(name, &key, &value)?);
+            } else if key == VERSION_PIN_KEY || key == "version_pin" {
+                service.version_pin = Some(value);
+            } else {
+                service.extra.insert(key, value);
+            }
+        }
+
+        Ok(service)
+    }
+
+    fn to_table(&self) -> BTreeMap<String, String> {
+        let mut table: BTreeMap<String, String> = self
+            .extra
+            .iter()
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect();
+        for (env, url) in &self.env_urls {
+            table.insert(env.to_string(), url_to_string(url));
+        }
+        if let Some(schema_url) = &self.schema_url {
+            table.insert(SCHEMA_URL_KEY.to_string(), url_to_string(schema_url));
+        }
+        if let Some(version_pin) = &self.version_pin {
+            table.insert(VERSION_PIN_KEY.to_string(), version_pin.clone());
+        }
+        table
+    }
+}
+
+// `Url` always adds a `/` path to bare hosts, drop it so base URLs are written back as they are
+// usually typed and joining paths onto them does not produce `//`
+fn url_to_string(url: &Url) -> String {
+    let url = url.to_string();
+    match url.strip_suffix('/') {
+        Some(base) if !base.ends_with('/') && base.matches('/').count() == 2 => base.to_string(),
+        _ => url,
+    }
+}
+
+impl Serialize for ServiceRef {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.to_table().serialize(serializer)
+    }
+}
+
+// The name is the key of the table in `services`, `deserialize_services` fills it in
+impl<'de> Deserialize<'de> for ServiceRef {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let table = HashMap::<String, String>::deserialize(deserializer)?;
+        ServiceRef::from_table("", table).map_err(de::Error::custom)
+    }
+}
+
+// Mirrors the flat table: a URL per environment, the known fields and free-form extras
+impl JsonSchema for ServiceRef {
+    fn schema_name() -> String {
+        "ServiceRef".to_string()
+    }
+
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+        let url = || -> Schema {
+            SchemaObject {
+                instance_type: Some(InstanceType::String.into()),
+                format: Some("uri".to_string()),
+                ..Default::default()
+            }
+            .into()
+        };
+
+        let mut object = ObjectValidation::default();
+        for env in Environment::all() {
+            object.properties.insert(env.to_string(), url());
+        }
+        object.properties.insert(SCHEMA_URL_KEY.to_string(), url());
+        object
+            .properties
+            .insert(VERSION_PIN_KEY.to_string(), gen.subschema_for::<String>());
+        object.additional_properties = Some(Box::new(gen.subschema_for::<String>()));
+
+        SchemaObject {
+            instance_type: Some(InstanceType::Object.into()),
+            object: Some(Box::new(object)),
+            ..Default::default()
+        }
+        .into()
+    }
+}
+
+pub(crate) fn deserialize_services<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<HashMap<String, ServiceRef>>, D::Error> {
+    let services = Option::<HashMap<String, HashMap<String, String>>>::deserialize(deserializer)?;
+    services
+        .map(|services| {
+            services
+                .into_iter()
+                .map(|(name, table)| {
+                    ServiceRef::from_table(&name, table)
+                        .map(|service| (name, service))
+                        .map_err(de::Error::custom)
+                })
+                .collect()
+        })
+        .transpose()
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum UrlResolveError {
+    UnknownService(String),
+    MissingEnvironment {
+        service: String,
+        env: Environment,
+    },
+    InvalidUrl {
+        service: String,
+        env: String,
+        url: String,
+        reason: String,
+    },
+}
+
+impl fmt::Display for UrlResolveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UrlResolveError::UnknownService(service) => {
+                write!(f, "Service '{}' is not referenced in the config", service)
+            }
+            UrlResolveError::MissingEnvironment { service, env } => {
+                write!(
+                    f,
+                    "Service '{}' has no URL for the '{}' environment",
+                    service, env
+                )
+            }
+            UrlResolveError::InvalidUrl {
+                service,
+                env,
+                url,
+                reason,
+            } => write!(
+                f,
+                "Invalid URL '{}' for service '{}' in the '{}' environment: {}",
+                url, service, env, reason
+            ),
+        }
+    }
+}
+
+impl Error for UrlResolveError {}
+
+fn parse_url(service: &str, env: &str, url: &str) -> Result<Url, UrlResolveError> {
+    Url::parse(url).map_err(|e| UrlResolveError::InvalidUrl {
+        service: service.to_string(),
+        env: env.to_string(),
+        url: url.to_string(),
+        reason: e.to_string(),
+    })
+}
+
+impl ServiceConfig {
+    /// URL of a referenced service in `env`. A URL set for the exact environment (e.g.
+    /// `prod_k8`) overrides the one of its backend environment (`prod`).
+    pub fn url_for(&self, service: &str, env: &Environment) -> Result<Url, UrlResolveError> {
+        self.service(service)
+            .ok_or_else(|| UrlResolveError::UnknownService(service.to_string()))?
+            .url_for(env)
+            .cloned()
+            .ok_or_else(|| UrlResolveError::MissingEnvironment {
+                service: service.to_string(),
+                env: *env,
+            })
+    }
+
+    pub fn service(&self, name: &str) -> Option<&ServiceRef> {
+        self.services
+            .as_ref()
+            .and_then(|services| services.get(name))
+    }
+
+    pub fn service_names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self
+            .services
+            .iter()
+            .flatten()
+            .map(|(name, _)| name.as_str())
+            .collect();
+        names.sort();
+        names
+    }
+
+    /// Checks that the URLs set per environment in the service's own `urls` and `urls_ws`
+    /// parse. Keys that are not environment names are left alone. The URLs of the referenced
+    /// services are already checked when the config is deserialized.
+    pub fn validate_urls(&self) -> Result<(), UrlResolveError> {
+        let own_name = self.override_name.as_deref().unwrap_or("self");
+        for (env, url) in self.urls.iter().chain(self.urls_ws.iter()).flatten() {
+            if env.parse::<Environment>().is_ok() {
+                parse_url(own_name, env, url)?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,446 @@
+use async_trait::async_trait;
+use chrono::{DateTime, TimeDelta, Utc};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::claims::Claims;
+
+pub type StoreError = Box<dyn Error + Send + Sync>;
+
+// Server side state of a login, kept alongside the JWT so it can expire when idle and be revoked
+// before the token does
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct SessionData {
+    pub id: String,
+    pub user_id: String,
+    pub sub: String,
+    pub client_id: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub last_seen_at: DateTime<Utc>,
+    #[serde(default)]
+    pub values: HashMap<String, Value>,
+}
+
+impl SessionData {
+    pub fn from_claims(claims: &Claims) -> Self {
+        let now = Utc::now();
+        SessionData {
+            id: session_id(claims),
+            user_id: claims.user_id.clone(),
+            sub: claims.sub.clone(),
+            client_id: claims.client_id.clone(),
+            created_at: now,
+            last_seen_at: now,
+            values: HashMap::new(),
+        }
+    }
+
+    /// When the session ends unless it is used again before.
+    pub fn expires_at(&self, policy: &SessionPolicy) -> DateTime<Utc> {
+        let idle = self.last_seen_at + to_delta(policy.idle_timeout);
+        let absolute = self.created_at + to_delta(policy.max_lifetime);
+        idle.min(absolute)
+    }
+
+    pub fn is_expired(&self, policy: &SessionPolicy) -> bool {
+        self.expires_at(policy) <= Utc::now()
+    }
+
+    pub fn get<T: DeserializeOwned>(&self, key: &str) -> Option<T> {
+        self.values
+            .get(key)
+            .and_then(|value| serde_json::from_value(value.clone()).ok())
+    }
+}
+
+/// The session of a token: its `sid` claim, else its `jti`, else one per user and token expiry.
+pub fn session_id(claims: &Claims) -> String {
+    ["sid", "jti"]
+        .iter()
+        .find_map(|claim| claims.extra.get(*claim).and_then(|id| id.as_str()))
+        .map(|id| id.to_string())
+        .unwrap_or_else(|| format!("{}:{}", claims.user_id, claims.exp))
+}
+
+fn to_delta(duration: Duration) -> TimeDelta {
+    TimeDelta::from_std(duration).unwrap_or_else(|_| TimeDelta::days(100 * 365))
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SessionPolicy {
+    pub idle_timeout: Duration,   // Sliding, pushed back by every request
+    pub max_lifetime: Duration,   // From the first request, whatever the activity
+    pub revocation_ttl: Duration, // How long revoked ids are kept, at least the tokens lifetime
+}
+
+impl Default for SessionPolicy {
+    fn default() -> Self {
+        SessionPolicy {
+            idle_timeout: Duration::from_secs(30 * 60),
+            max_lifetime: Duration::from_secs(12 * 60 * 60),
+            revocation_ttl: Duration::from_secs(24 * 60 * 60),
+        }
+    }
+}
+
+#[async_trait]
+pub trait SessionStore: Send + Sync {
+    async fn load(&self, id: &str) -> Result<Option<SessionData>, StoreError>;
+
+    /// Stores `session`, dropping it after `ttl`.
+    async fn save(&self, session: &SessionData, ttl: Duration) -> Result<(), StoreError>;
+
+    async fn remove(&self, id: &str) -> Result<(), StoreError>;
+
+    /// Adds `id` to the revocation list for `ttl`.
+    async fn revoke(&self, id: &str, ttl: Duration) -> Result<(), StoreError>;
+
+    async fn is_revoked(&self, id: &str) -> Result<bool, StoreError>;
+}
+
+// Sessions of a single replica, lost on restart
+#[derive(Default)]
+pub struct MemorySessionStore {
+    sessions: Mutex<HashMap<String, (SessionData, Instant)>>,
+    revoked: Mutex<HashMap<String, Instant>>,
+}
+
+impl MemorySessionStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl SessionStore for MemorySessionStore {
+    async fn load(&self, id: &str) -> Result<Option<SessionData>, StoreError> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let now = Instant::now();
+        sessions.retain(|_, (_, expires_at)| *expires_at > now);
+        Ok(sessions.get(id).map(|(session, _)| session.clone()))
+    }
+
+    async fn save(&self, session: &SessionData, ttl: Duration) -> Result<(), StoreError> {
+        self.sessions
+            .lock()
+            .unwrap()
+            .insert(session.id.clone(), (session.clone(), Instant::now() + ttl));
+        Ok(())
+    }
+
+    async fn remove(&self, id: &str) -> Result<(), StoreError> {
+        self.sessions.lock().unwrap().remove(id);
+        Ok(())
+    }
+
+    async fn revoke(&self, id: &str, ttl: Duration) -> Result<(), StoreError> {
+        self.sessions.lock().unwrap().remove(id);
+        self.revoked
+            .lock()
+            .unwrap()
+            .insert(id.to_string(), Instant::now() + ttl);
+        Ok(())
+    }
+
+    async fn is_revoked(&self, id: &str) -> Result<bool, StoreError> {
+        let mut revoked = self.revoked.lock().unwrap();
+        let now = Instant::now();
+        revoked.retain(|_, expires_at| *expires_at > now);
+        Ok(revoked.contains_key(id))
+    }
+}
+
+#[cfg(feature = "redis")]
+pub use self::redis_store::RedisSessionStore;
+
+#[cfg(feature = "redis")]
+mod redis_store {
+    use async_trait::async_trait;
+    use chrono::{DateTime, Utc};
+    use std::time::Duration;
+
+    use super::{SessionData, SessionStore, StoreError};
+    use crate::redis_utils::{RedisPool, TypedRedis};
+
+    // Sessions shared between replicas, under `{namespace}:{id}`, and the revocation list under
+    // `{namespace}:revoked:{id}`
+    pub struct RedisSessionStore {
+        pool: RedisPool,
+        sessions: TypedRedis<SessionData>,
+        revoked: TypedRedis<DateTime<Utc>>,
+    }
+
+    impl RedisSessionStore {
+        pub fn new(pool: RedisPool, namespace: &str) -> Self {
+            let sessions = TypedRedis::new(namespace);
+            let revoked = sessions.child("revoked");
+            RedisSessionStore {
+                pool,
+                sessions,
+                revoked,
+            }
+        }
+    }
+
+    #[async_trait]
+    impl SessionStore for RedisSessionStore {
+        async fn load(&self, id: &str) -> Result<Option<SessionData>, StoreError> {
+            self.sessions.get(&self.pool, id).await
+        }
+
+        async fn save(&self, session: &SessionData, ttl: Duration) -> Result<(), StoreError> {
+            self.sessions
+                .set_ex(&self.pool, &session.id, session, ttl)
+                .await
+        }
+
+        async fn remove(&self, id: &str) -> Result<(), StoreError> {
+            self.sessions.delete(&self.pool, id).await.map(|_| ())
+        }
+
+        async fn revoke(&self, id: &str, ttl: Duration) -> Result<(), StoreError> {
+            self.revoked
+                .set_ex(&self.pool, id, &Utc::now(), ttl)
+                .await?;
+            self.remove(id).await
+        }
+
+        async fn is_revoked(&self, id: &str) -> Result<bool, StoreError> {
+            self.revoked.exists(&self.pool, id).await
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum SessionError {
+    Unauthenticated, // No valid token to bridge the session from
+    Revoked,
+    Expired,
+    Store(StoreError),
+}
+
+impl fmt::Display for SessionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SessionError::Unauthenticated => write!(f, "Authentication is required"),
+            SessionError::Revoked => write!(f, "The session has been revoked"),
+            SessionError::Expired => write!(f, "The session has expired"),
+            SessionError::Store(e) => write!(f, "The session store is unavailable: {}", e),
+        }
+    }
+}
+
+impl Error for SessionError {}
+
+// The store and policy of the sessions, Rocket managed state read by the `Session` guard.
+// Without it, sessions are kept in memory with the default policy.
+#[derive(Clone)]
+pub struct Sessions {
+    store: Arc<dyn SessionStore>,
+    policy: SessionPolicy,
+}
+
+impl Sessions {
+    pub fn new<S: SessionStore + 'static>(store: S, policy: SessionPolicy) -> Self {
+        Sessions {
+            store: Arc::new(store),
+            policy,
+        }
+    }
+
+    pub fn policy(&self) -> &SessionPolicy {
+        &self.policy
+    }
+
+    /// The session of `claims`, started on its first use and slid forward on each one after.
+    /// A session found idle is revoked, so the token it came from cannot start it again.
+    pub async fn resume(&self, claims: &Claims) -> Result<SessionData, SessionError> {
+        let id = session_id(claims);
+        if self
+            .store
+            .is_revoked(&id)
+            .await
+            .map_err(SessionError::Store)?
+        {
+            return Err(SessionError::Revoked);
+        }
+        let mut session = match self.store.load(&id).await.map_err(SessionError::Store)? {
+            Some(session) if session.user_id != claims.user_id => {
+                tracing::warn!(session = %id, "session reused by another user");
+                return Err(SessionError::Revoked);
+            }
+            Some(session) if session.is_expired(&self.policy) => {
+                self.revoke(&id).await.map_err(SessionError::Store)?;
+                return Err(SessionError::Expired);
+            }
+            Some(session) => session,
+            None => SessionData::from_claims(claims),
+        };
+        session.last_seen_at = Utc::now();
+        self.save(&session, claims.exp).await?;
+        Ok(session)
+    }
+
+    /// Stores `session` for as long as it or the token it came from (expiring at `token_exp`)
+    /// lives, so an idle session is still found, and rejected, while its token is valid.
+    pub async fn save(&self, session: &SessionData, token_exp: usize) -> Result<(), SessionError> {
+        let session_end = session.created_at + to_delta(self.policy.max_lifetime);
+        let token_end = DateTime::from_timestamp(token_exp as i64, 0).unwrap_or(session_end);
+        let ttl = (session_end.max(token_end) - Utc::now())
+            .to_std()
+            .unwrap_or(Duration::from_secs(1));
+        self.store
+            .save(session, ttl)
+            .await
+            .map_err(SessionError::Store)
+    }
+
+    /// Ends the session `id`, e.g. on logout or when an administrator locks a user out.
+    pub async fn revoke(&self, id: &str) -> Result<(), StoreError> {
+        self.store.revoke(id, self.policy.revocation_ttl).await
+    }
+
+    pub async fn is_revoked(&self, id: &str) -> Result<bool, StoreError> {
+        self.store.is_revoked(id).await
+    }
+}
+
+#[cfg(feature = "rocket")]
+pub use self::guards::Session;
+
+#[cfg(feature = "rocket")]
+mod guards {
+    use okapi::openapi3::Responses;
+    use rocket::{
+        http::Status,
+        request::{FromRequest, Outcome, Request},
+    };
+    use rocket_okapi::{
+        gen::OpenApiGenerator,
+        request::{OpenApiFromRequest, RequestHeaderInput},
+    };
+    use serde::Serialize;
+    use std::sync::OnceLock;
+
+    use super::{MemorySessionStore, SessionData, SessionError, SessionPolicy, Sessions};
+    use crate::{claims::Claims, rocket_models::ApiError, validation::record_failure};
+
+    impl From<&SessionError> for ApiError {
+        fn from(error: &SessionError) -> Self {
+            let message = error.to_string();
+            match error {
+                SessionError::Revoked => ApiError::new(401, "session_revoked", &message),
+                SessionError::Expired => ApiError::new(401, "session_expired", &message),
+                SessionError::Unauthenticated => ApiError::new(401, "unauthorized", &message),
+                // The details of the store stay in the logs
+                SessionError::Store(_) => {
+                    tracing::error!("{}", message);
+                    ApiError::new(
+                        503,
+                        "session_store_unavailable",
+                        "The session store is unavailable",
+                    )
+                }
+            }
+        }
+    }
+
+    fn default_sessions() -> &'static Sessions {
+        static DEFAULT: OnceLock<Sessions> = OnceLock::new();
+        DEFAULT.get_or_init(|| Sessions::new(MemorySessionStore::new(), SessionPolicy::default()))
+    }
+
+    // The session of the bearer token of a request. Requests without a valid token get a 401 as
+    // with `Claims`, revoked and expired sessions a 401 naming why.
+    pub struct Session {
+        data: SessionData,
+        claims: Claims,
+        sessions: Sessions,
+    }
+
+    impl Session {
+        pub fn id(&self) -> &str {
+            &self.data.id
+        }
+
+        pub fn data(&self) -> &SessionData {
+            &self.data
+        }
+
+        pub fn claims(&self) -> &Claims {
+            &self.claims
+        }
+
+        /// Stores `value` under `key` for the following requests of the session.
+        pub async fn set<T: Serialize>(&mut self, key: &str, value: &T) -> Result<(), ApiError> {
+            let value = serde_json::to_value(value)
+                .map_err(|e| ApiError::internal(&format!("Invalid session value: {}", e)))?;
+            self.data.values.insert(key.to_string(), value);
+            self.sessions
+                .save(&self.data, self.claims.exp)
+                .await
+                .map_err(|e| ApiError::from(&e))
+        }
+
+        /// Logs out: the token the session came from is rejected from now on.
+        pub async fn revoke(self) -> Result<(), ApiError> {
+            self.sessions
+                .revoke(&self.data.id)
+                .await
+                .map_err(|e| ApiError::from(&SessionError::Store(e)))
+        }
+    }
+
+    #[rocket::async_trait]
+    impl<'r> FromRequest<'r> for Session {
+        type Error = SessionError;
+
+        async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+            let claims = match request.guard::<Claims>().await {
+                Outcome::Success(claims) => claims,
+                _ => return Outcome::Error((Status::Unauthorized, SessionError::Unauthenticated)),
+            };
+            let sessions = request
+                .rocket()
+                .state::<Sessions>()
+                .unwrap_or_else(|| default_sessions())
+                .clone();
+            match sessions.resume(&claims).await {
+                Ok(data) => Outcome::Success(Session {
+                    data,
+                    claims,
+                    sessions,
+                }),
+                Err(e) => {
+                    let status = match &e {
+                        SessionError::Store(_) => Status::ServiceUnavailable,
+                        _ => Status::Unauthorized,
+                    };
+                    record_failure(request, ApiError::from(&e));
+                    Outcome::Error((status, e))
+                }
+            }
+        }
+    }
+
+    impl<'a> OpenApiFromRequest<'a> for Session {
+        fn from_request_input(
+            gen: &mut OpenApiGenerator,
+            name: String,
+            required: bool,
+        ) -> rocket_okapi::Result<RequestHeaderInput> {
+            Claims::from_request_input(gen, name, required)
+        }
+
+        fn get_responses(_gen: &mut OpenApiGenerator) -> rocket_okapi::Result<Responses> {
+            Ok(Responses::default())
+        }
+    }
+}
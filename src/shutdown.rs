@@ -0,0 +1,164 @@
+use std::{future::Future, sync::Arc, time::Duration};
+use tokio::{sync::watch, task::JoinHandle};
+
+struct ShutdownState {
+    triggered: watch::Sender<bool>,
+    // Number of `DrainGuard`s alive, i.e. units of work still in progress
+    in_flight: watch::Sender<usize>,
+}
+
+// Shared shutdown signal handed to background tasks. Tasks stop picking up new work once the
+// token is triggered and hold a `DrainGuard` while they finish what is in progress, so the
+// service can wait for them with `wait_for_drain` before the process exits.
+#[derive(Clone)]
+pub struct ShutdownToken {
+    state: Arc<ShutdownState>,
+}
+
+// Marks a unit of work in progress for as long as it is alive
+pub struct DrainGuard {
+    state: Arc<ShutdownState>,
+}
+
+impl Drop for DrainGuard {
+    fn drop(&mut self) {
+        self.state.in_flight.send_modify(|count| *count -= 1);
+    }
+}
+
+impl Default for ShutdownToken {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ShutdownToken {
+    pub fn new() -> Self {
+        ShutdownToken {
+            state: Arc::new(ShutdownState {
+                triggered: watch::channel(false).0,
+                in_flight: watch::channel(0).0,
+            }),
+        }
+    }
+
+    pub fn trigger(&self) {
+        self.state.triggered.send_replace(true);
+    }
+
+    pub fn is_triggered(&self) -> bool {
+        *self.state.triggered.borrow()
+    }
+
+    /// Resolves once the token is triggered. The future does not borrow the token, so it can be
+    /// handed to `Scheduler::start` or `tokio::select!` in a spawned task.
+    pub fn cancelled(&self) -> impl Future<Output = ()> + Send + 'static {
+        let mut triggered = self.state.triggered.subscribe();
+        async move {
+            let _ = triggered.wait_for(|triggered| *triggered).await;
+        }
+    }
+
+    pub fn guard(&self) -> DrainGuard {
+        self.state.in_flight.send_modify(|count| *count += 1);
+        DrainGuard {
+            state: self.state.clone(),
+        }
+    }
+
+    pub fn in_flight(&self) -> usize {
+        *self.state.in_flight.borrow()
+    }
+
+    /// Waits up to `timeout` for every `DrainGuard` to be dropped. Returns whether the work in
+    /// progress finished in time.
+    pub async fn wait_for_drain(&self, timeout: Duration) -> bool {
+        let mut in_flight = self.state.in_flight.subscribe();
+        // Moved into the future: the temporaries of a tail expression outlive the locals
+        tokio::time::timeout(timeout, async move {
+            let _ = in_flight.wait_for(|count| *count == 0).await;
+        })
+        .await
+        .is_ok()
+    }
+
+    /// Triggers the token when the process receives SIGTERM (Ctrl-C on other platforms).
+    pub fn trigger_on_signal(&self) -> JoinHandle<()> {
+        let token = self.clone();
+        tokio::spawn(async move {
+            wait_for_signal().await;
+            tracing::info!("termination signal received, shutting down");
+            token.trigger();
+        })
+    }
+
+    /// Triggers the token with Rocket's shutdown and waits up to `grace` for the work in
+    /// progress before Rocket exits.
+    #[cfg(feature = "rocket")]
+    pub fn fairing(&self, grace: Duration) -> ShutdownFairing {
+        ShutdownFairing {
+            token: self.clone(),
+            grace,
+        }
+    }
+}
+
+#[cfg(unix)]
+async fn wait_for_signal() {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    match signal(SignalKind::terminate()) {
+        Ok(mut sigterm) => {
+            sigterm.recv().await;
+        }
+        Err(e) => {
+            tracing::warn!(error = %e, "failed to listen for SIGTERM");
+            std::future::pending::<()>().await;
+        }
+    }
+}
+
+#[cfg(not(unix))]
+async fn wait_for_signal() {
+    if let Err(e) = tokio::signal::ctrl_c().await {
+        tracing::warn!(error = %e, "failed to listen for Ctrl-C");
+        std::future::pending::<()>().await;
+    }
+}
+
+#[cfg(feature = "rocket")]
+pub struct ShutdownFairing {
+    token: ShutdownToken,
+    grace: Duration,
+}
+
+#[cfg(feature = "rocket")]
+#[rocket::async_trait]
+impl rocket::fairing::Fairing for ShutdownFairing {
+    fn info(&self) -> rocket::fairing::Info {
+        rocket::fairing::Info {
+            name: "Graceful shutdown",
+            kind: rocket::fairing::Kind::Liftoff | rocket::fairing::Kind::Shutdown,
+        }
+    }
+
+    async fn on_liftoff(&self, rocket: &rocket::Rocket<rocket::Orbit>) {
+        // Rocket handles SIGTERM itself, this also covers `Shutdown::notify` from a route
+        let shutdown = rocket.shutdown();
+        let token = self.token.clone();
+        tokio::spawn(async move {
+            shutdown.await;
+            token.trigger();
+        });
+    }
+
+    async fn on_shutdown(&self, _rocket: &rocket::Rocket<rocket::Orbit>) {
+        self.token.trigger();
+        if !self.token.wait_for_drain(self.grace).await {
+            tracing::warn!(
+                in_flight = self.token.in_flight(),
+                "background work did not drain within the grace period"
+            );
+        }
+    }
+}
@@ -0,0 +1,244 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    error::Error,
+    fs,
+    path::{Component, Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    storage::{BlobStore, StorageError},
+    ReleaserConfig, Version,
+};
+
+const SNAPSHOTS_DIR: &str = ".ginger-society/snapshots";
+const METADATA_FILE: &str = "snapshot.toml";
+const FILES_DIR: &str = "files";
+const RELEASER_FILE: &str = "releaser.toml";
+// Prefix of the snapshots kept in a blob store
+const STORE_PREFIX: &str = "snapshots";
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct SnapshotMetadata {
+    pub version: Version,
+    pub created_at: u64, // Seconds since the unix epoch
+    pub files: Vec<String>,
+}
+
+pub fn snapshot_dir<P: AsRef<Path>>(root: P, version: &Version) -> PathBuf {
+    root.as_ref().join(SNAPSHOTS_DIR).join(version.formatted())
+}
+
+/// Archives the reference files and `releaser.toml` for the current version, so a release can
+/// later be reverted with `rollback_to`. Does nothing unless `take_snapshots` is enabled.
+pub fn take_snapshot<P: AsRef<Path>>(
+    root: P,
+    config: &ReleaserConfig,
+) -> Result<Option<PathBuf>, Box<dyn Error>> {
+    if !config.settings.take_snapshots {
+        return Ok(None);
+    }
+
+    let root = root.as_ref();
+    let dir = snapshot_dir(root, &config.version);
+    let files_dir = dir.join(FILES_DIR);
+    fs::create_dir_all(&files_dir)?;
+
+    let files = snapshot_files(root, config)?;
+    for file_name in &files {
+        let source = root.join(file_name);
+        let target = files_dir.join(file_name);
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::copy(&source, &target)
+            .map_err(|e| format!("Failed to snapshot '{}': {}", source.display(), e))?;
+    }
+
+    let metadata = SnapshotMetadata {
+        version: config.version,
+        created_at: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+        files,
+    };
+    fs::write(dir.join(METADATA_FILE), toml::to_string(&metadata)?)?;
+
+    Ok(Some(dir))
+}
+
+// `releaser.toml` and the reference files of `config` that exist under `root`
+fn snapshot_files(root: &Path, config: &ReleaserConfig) -> Result<Vec<String>, String> {
+    let mut files: Vec<String> = vec![];
+    let candidates = std::iter::once(RELEASER_FILE)
+        .chain(config.references.iter().map(|r| r.file_name.as_str()));
+    for file_name in candidates {
+        check_file_name(file_name)?;
+        if !files.iter().any(|f| f == file_name) && root.join(file_name).is_file() {
+            files.push(file_name.to_string());
+        }
+    }
+    Ok(files)
+}
+
+// Snapshotted files are relative to the root and outside of the snapshots, so neither taking
+// nor restoring a snapshot writes anywhere else or copies a file onto itself
+fn check_file_name(file_name: &str) -> Result<(), String> {
+    let path = Path::new(file_name);
+    let relative = !file_name.is_empty()
+        && path
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)));
+    if !relative || path.starts_with(SNAPSHOTS_DIR) {
+        return Err(format!(
+            "'{}' cannot be snapshotted, files must be relative to the project root",
+            file_name
+        ));
+    }
+    Ok(())
+}
+
+/// Lists the available snapshots, oldest version first.
+pub fn list_snapshots<P: AsRef<Path>>(root: P) -> Result<Vec<SnapshotMetadata>, Box<dyn Error>> {
+    let dir = root.as_ref().join(SNAPSHOTS_DIR);
+    if !dir.is_dir() {
+        return Ok(vec![]);
+    }
+
+    let mut snapshots = vec![];
+    for entry in fs::read_dir(&dir)? {
+        let metadata_path = entry?.path().join(METADATA_FILE);
+        if !metadata_path.is_file() {
+            continue;
+        }
+        let contents = fs::read_to_string(&metadata_path)?;
+        let metadata: SnapshotMetadata = toml::from_str(&contents).map_err(|e| {
+            format!(
+                "Failed to parse snapshot metadata '{}': {}",
+                metadata_path.display(),
+                e
+            )
+        })?;
+        snapshots.push(metadata);
+    }
+
+    snapshots.sort_by_key(|s| s.version);
+    Ok(snapshots)
+}
+
+/// Restores every file archived for `version` into the working tree.
+pub fn rollback_to<P: AsRef<Path>>(
+    root: P,
+    version: &Version,
+) -> Result<SnapshotMetadata, Box<dyn Error>> {
+    let root = root.as_ref();
+    let dir = snapshot_dir(root, version);
+    let metadata_path = dir.join(METADATA_FILE);
+
+    let contents = fs::read_to_string(&metadata_path)
+        .map_err(|_| format!("No snapshot found for version {}", version.formatted()))?;
+    let metadata: SnapshotMetadata = toml::from_str(&contents)?;
+
+    for file_name in &metadata.files {
+        check_file_name(file_name)?;
+        let target = root.join(file_name);
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::copy(dir.join(FILES_DIR).join(file_name), &target)
+            .map_err(|e| format!("Failed to restore '{}': {}", target.display(), e))?;
+    }
+
+    Ok(metadata)
+}
+
+fn store_key(version: &Version, path: &str) -> String {
+    format!("{}/{}/{}", STORE_PREFIX, version.formatted(), path)
+}
+
+/// Like `take_snapshot`, archiving to `store` rather than next to the working tree, e.g. to a
+/// bucket shared by the CI runners.
+pub async fn take_snapshot_to<P: AsRef<Path>>(
+    store: &dyn BlobStore,
+    root: P,
+    config: &ReleaserConfig,
+) -> Result<Option<SnapshotMetadata>, StorageError> {
+    if !config.settings.take_snapshots {
+        return Ok(None);
+    }
+
+    let root = root.as_ref();
+    let files = snapshot_files(root, config)?;
+    for file_name in &files {
+        let key = store_key(&config.version, &format!("{}/{}", FILES_DIR, file_name));
+        store.put_file(&key, &root.join(file_name), None).await?;
+    }
+
+    // Written last, snapshots without metadata are incomplete and ignored
+    let metadata = SnapshotMetadata {
+        version: config.version,
+        created_at: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+        files,
+    };
+    let contents = toml::to_string(&metadata)?.into_bytes();
+    store
+        .put(&store_key(&config.version, METADATA_FILE), contents, None)
+        .await?;
+    Ok(Some(metadata))
+}
+
+/// Lists the snapshots of `store`, oldest version first.
+pub async fn list_snapshots_in(
+    store: &dyn BlobStore,
+) -> Result<Vec<SnapshotMetadata>, StorageError> {
+    let mut snapshots = vec![];
+    let suffix = format!("/{}", METADATA_FILE);
+    for key in store.list(&format!("{}/", STORE_PREFIX)).await? {
+        let is_metadata = key
+            .strip_prefix(STORE_PREFIX)
+            .and_then(|rest| rest.strip_suffix(&suffix))
+            .is_some_and(|version| version.matches('/').count() == 1);
+        if !is_metadata {
+            continue;
+        }
+        let Some(contents) = store.get(&key).await? else {
+            continue;
+        };
+        let metadata: SnapshotMetadata = toml::from_str(&String::from_utf8_lossy(&contents))
+            .map_err(|e| format!("Failed to parse snapshot metadata '{}': {}", key, e))?;
+        snapshots.push(metadata);
+    }
+
+    snapshots.sort_by_key(|s| s.version);
+    Ok(snapshots)
+}
+
+/// Like `rollback_to`, with the snapshot of `version` taken from `store`.
+pub async fn rollback_from<P: AsRef<Path>>(
+    store: &dyn BlobStore,
+    root: P,
+    version: &Version,
+) -> Result<SnapshotMetadata, StorageError> {
+    let root = root.as_ref();
+    let contents = store
+        .get(&store_key(version, METADATA_FILE))
+        .await?
+        .ok_or_else(|| format!("No snapshot found for version {}", version.formatted()))?;
+    let metadata: SnapshotMetadata = toml::from_str(&String::from_utf8_lossy(&contents))?;
+
+    for file_name in &metadata.files {
+        check_file_name(file_name)?;
+        let key = store_key(version, &format!("{}/{}", FILES_DIR, file_name));
+        let contents = store
+            .get(&key)
+            .await?
+            .ok_or_else(|| format!("The snapshot of '{}' is missing", file_name))?;
+        let target = root.join(file_name);
+        if let Some(parent) = target.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        tokio::fs::write(&target, contents)
+            .await
+            .map_err(|e| format!("Failed to restore '{}': {}", target.display(), e))?;
+    }
+
+    Ok(metadata)
+}
@@ -0,0 +1,237 @@
+use rocket::{
+    fairing::AdHoc,
+    http::{ContentType, Header, Method, Status},
+    route::{self, Handler, Route},
+    Data, Request, Response,
+};
+use std::path::{Path, PathBuf};
+
+use crate::ServiceConfig;
+
+const DEFAULT_DIR: &str = "dist";
+const INDEX_FILE: &str = "index.html";
+// Below the rank of any route declared with the `#[get]` macros, so the service's own routes win
+const DEFAULT_RANK: isize = 100;
+const IMMUTABLE: &str = "public, max-age=31536000, immutable";
+const REVALIDATE: &str = "no-cache";
+const SHORT_LIVED: &str = "public, max-age=3600";
+
+// Pre-compressed siblings looked up next to each file, in order of preference
+const ENCODINGS: [(&str, &str); 2] = [("br", "br"), ("gzip", "gz")];
+
+// Serves a built frontend: files of `dir`, their `.br`/`.gz` siblings when the client accepts
+// them and `index.html` for every other page path so the history API routing works on reload.
+#[derive(Debug, Clone)]
+pub struct Spa {
+    dir: PathBuf,
+    rank: isize,
+    excluded: Vec<String>, // Path prefixes never answered with the app, e.g. `api`
+}
+
+impl Spa {
+    pub fn new<P: AsRef<Path>>(dir: P) -> Self {
+        Spa {
+            dir: dir.as_ref().to_path_buf(),
+            rank: DEFAULT_RANK,
+            excluded: vec![],
+        }
+    }
+
+    /// The `dir` of the service config, `dist` when unset.
+    pub fn from_service_config(config: &ServiceConfig) -> Self {
+        Spa::new(config.dir.as_deref().unwrap_or(DEFAULT_DIR))
+    }
+
+    pub fn rank(mut self, rank: isize) -> Self {
+        self.rank = rank;
+        self
+    }
+
+    /// Requests under `prefix` (relative to the mount point) get a 404 instead of the app.
+    pub fn exclude(mut self, prefix: &str) -> Self {
+        self.excluded.push(prefix.trim_matches('/').to_string());
+        self
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn routes(&self) -> Vec<Route> {
+        vec![Route::ranked(
+            self.rank,
+            Method::Get,
+            "/<path..>",
+            self.clone(),
+        )]
+    }
+
+    /// Mounts the app at `base` when Rocket ignites, failing the launch if `dir` holds no
+    /// `index.html`.
+    pub fn fairing(self, base: &'static str) -> AdHoc {
+        AdHoc::try_on_ignite("Single page app", move |rocket| async move {
+            let index = self.dir.join(INDEX_FILE);
+            if !index.is_file() {
+                tracing::error!(path = %index.display(), "the single page app has no index file");
+                return Err(rocket);
+            }
+            Ok(rocket.mount(base, self.routes()))
+        })
+    }
+
+    fn is_excluded(&self, path: &Path) -> bool {
+        self.excluded
+            .iter()
+            .any(|prefix| !prefix.is_empty() && path.starts_with(prefix))
+    }
+
+    // The file to serve for `path`, with whether it is the app's entry point
+    fn resolve(&self, path: &Path, accepts_html: bool) -> Option<(PathBuf, bool)> {
+        let file = self.dir.join(path);
+        if file.is_file() {
+            let is_index = path == Path::new(INDEX_FILE);
+            return Some((file, is_index));
+        }
+        if file.is_dir() && file.join(INDEX_FILE).is_file() {
+            return Some((file.join(INDEX_FILE), true));
+        }
+        // Missing assets are real 404s, only page paths fall back to the app
+        if path.extension().is_none() && accepts_html {
+            let index = self.dir.join(INDEX_FILE);
+            return index.is_file().then_some((index, true));
+        }
+        None
+    }
+}
+
+/// Cache-Control for a served file: entry points are revalidated on every load, fingerprinted
+/// assets such as `index-4f3a9c2b.js` are cached forever and other files for an hour.
+pub fn cache_control(path: &Path) -> &'static str {
+    let name = path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or_default();
+    if name.ends_with(".html") || name == "service-worker.js" || name == "sw.js" {
+        return REVALIDATE;
+    }
+    if is_fingerprinted(name) {
+        IMMUTABLE
+    } else {
+        SHORT_LIVED
+    }
+}
+
+// Whether a file name carries a content hash, as bundlers emit them (`main.4f3a9c2b.js`,
+// `index-B1xk9Q_2.css`)
+fn is_fingerprinted(name: &str) -> bool {
+    let parts: Vec<&str> = name.split('.').collect();
+    parts[..parts.len() - 1].iter().any(|part| {
+        looks_like_hash(part)
+            || part
+                .rsplit_once('-')
+                .is_some_and(|(_, hash)| looks_like_hash(hash))
+    })
+}
+
+fn looks_like_hash(part: &str) -> bool {
+    part.len() >= 8
+        && part.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && part.chars().any(|c| c.is_ascii_digit())
+}
+
+/// The encodings of `accept_encoding` the client accepts, skipping those with `q=0`.
+pub fn accepted_encodings(accept_encoding: &str) -> Vec<String> {
+    accept_encoding
+        .split(',')
+        .filter_map(|part| {
+            let mut params = part.split(';').map(str::trim);
+            let encoding = params.next()?.to_ascii_lowercase();
+            let rejected = params.any(|param| {
+                param
+                    .strip_prefix("q=")
+                    .and_then(|q| q.parse::<f32>().ok())
+                    .is_some_and(|q| q == 0.0)
+            });
+            (!encoding.is_empty() && !rejected).then_some(encoding)
+        })
+        .collect()
+}
+
+// The pre-compressed sibling of `file` to send instead, with its `Content-Encoding`
+fn precompressed(file: &Path, accepted: &[String]) -> Option<(PathBuf, &'static str)> {
+    ENCODINGS.iter().find_map(|(encoding, extension)| {
+        if !accepted.iter().any(|a| a == encoding || a == "*") {
+            return None;
+        }
+        let mut name = file.as_os_str().to_os_string();
+        name.push(".");
+        name.push(extension);
+        let sibling = PathBuf::from(name);
+        sibling.is_file().then_some((sibling, *encoding))
+    })
+}
+
+#[rocket::async_trait]
+impl Handler for Spa {
+    async fn handle<'r>(&self, request: &'r Request<'_>, data: Data<'r>) -> route::Outcome<'r> {
+        // Rejects `..`, hidden files and other segments escaping `dir`
+        let path: PathBuf = match request.segments(0..) {
+            Ok(path) => path,
+            Err(_) => return route::Outcome::forward(data, Status::NotFound),
+        };
+        if self.is_excluded(&path) {
+            return route::Outcome::forward(data, Status::NotFound);
+        }
+
+        let accepts_html = request.accept().is_none_or(|accept| {
+            accept
+                .media_types()
+                .any(|media| media.is_html() || media.top() == "*")
+        });
+        let (file, is_index) = match self.resolve(&path, accepts_html) {
+            Some(resolved) => resolved,
+            None => return route::Outcome::forward(data, Status::NotFound),
+        };
+
+        let accepted = request
+            .headers()
+            .get_one("Accept-Encoding")
+            .map(accepted_encodings)
+            .unwrap_or_default();
+        let (served, encoding) = match precompressed(&file, &accepted) {
+            Some((sibling, encoding)) => (sibling, Some(encoding)),
+            None => (file.clone(), None),
+        };
+
+        let body = match tokio::fs::File::open(&served).await {
+            Ok(body) => body,
+            Err(e) => {
+                tracing::warn!(path = %served.display(), error = %e, "failed to open a static file");
+                return route::Outcome::Error(Status::InternalServerError);
+            }
+        };
+        let size = body.metadata().await.ok().map(|m| m.len() as usize);
+        let content_type = file
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .and_then(ContentType::from_extension)
+            .unwrap_or(ContentType::Binary);
+        let cache = if is_index {
+            REVALIDATE
+        } else {
+            cache_control(&file)
+        };
+
+        let mut response = Response::build();
+        response
+            .status(Status::Ok)
+            .header(content_type)
+            .header(Header::new("Cache-Control", cache))
+            .header(Header::new("Vary", "Accept-Encoding"))
+            .sized_body(size, body);
+        if let Some(encoding) = encoding {
+            response.header(Header::new("Content-Encoding", encoding));
+        }
+        route::Outcome::Success(response.finalize())
+    }
+}
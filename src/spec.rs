@@ -0,0 +1,403 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::{
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{config_io::write_atomic, LANG};
+
+const SPECS_DIR: &str = ".ginger-society/specs";
+const HTTP_METHODS: [&str; 8] = [
+    "get", "put", "post", "delete", "options", "head", "patch", "trace",
+];
+
+// A downloaded OpenAPI 3 document
+#[derive(Debug, Clone, PartialEq)]
+pub struct OpenApiSpec {
+    pub source_url: String,
+    pub document: Value,
+}
+
+impl OpenApiSpec {
+    pub fn parse(source_url: &str, contents: &str) -> Result<Self, Box<dyn Error>> {
+        let document: Value = serde_json::from_str(contents)
+            .map_err(|e| format!("The spec at {} is not valid JSON: {}", source_url, e))?;
+        let problems = validate_spec(&document);
+        if !problems.is_empty() {
+            return Err(format!(
+                "The spec at {} is not a valid OpenAPI document: {}",
+                source_url,
+                problems.join("; ")
+            )
+            .into());
+        }
+        Ok(OpenApiSpec {
+            source_url: source_url.to_string(),
+            document,
+        })
+    }
+
+    pub fn openapi_version(&self) -> &str {
+        self.document["openapi"].as_str().unwrap_or_default()
+    }
+
+    pub fn title(&self) -> &str {
+        self.document["info"]["title"].as_str().unwrap_or_default()
+    }
+
+    pub fn version(&self) -> &str {
+        self.document["info"]["version"]
+            .as_str()
+            .unwrap_or_default()
+    }
+
+    pub fn paths(&self) -> Vec<&str> {
+        let mut paths: Vec<&str> = self.document["paths"]
+            .as_object()
+            .map(|paths| paths.keys().map(String::as_str).collect())
+            .unwrap_or_default();
+        paths.sort();
+        paths
+    }
+
+    /// `(METHOD, path)` of every operation in the spec.
+    pub fn operations(&self) -> Vec<(String, String)> {
+        let mut operations = vec![];
+        for path in self.paths() {
+            for method in HTTP_METHODS {
+                if self.document["paths"][path].get(method).is_some() {
+                    operations.push((method.to_uppercase(), path.to_string()));
+                }
+            }
+        }
+        operations
+    }
+
+    pub fn to_json(&self) -> Result<String, Box<dyn Error>> {
+        Ok(serde_json::to_string_pretty(&self.document)?)
+    }
+}
+
+/// Structural checks of an OpenAPI 3 document, returns the problems found.
+pub fn validate_spec(document: &Value) -> Vec<String> {
+    let mut problems = vec![];
+
+    match document.get("openapi").and_then(Value::as_str) {
+        Some(version) if version.starts_with("3.") => {}
+        Some(version) => problems.push(format!("unsupported OpenAPI version '{}'", version)),
+        None => problems.push("missing the `openapi` version field".to_string()),
+    }
+    if document["info"]["title"].as_str().is_none() {
+        problems.push("missing `info.title`".to_string());
+    }
+    if document["info"]["version"].as_str().is_none() {
+        problems.push("missing `info.version`".to_string());
+    }
+
+    match document.get("paths").and_then(Value::as_object) {
+        Some(paths) => {
+            for (path, item) in paths {
+                if !path.starts_with('/') {
+                    problems.push(format!("path '{}' does not start with '/'", path));
+                }
+                if !item.is_object() {
+                    problems.push(format!("path '{}' is not an object", path));
+                }
+            }
+        }
+        None => problems.push("missing the `paths` object".to_string()),
+    }
+
+    problems
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct CachedSpecMetadata {
+    pub url: String,
+    pub etag: Option<String>,
+    pub fetched_at: u64, // Seconds since the unix epoch
+}
+
+// Downloaded specs kept under `.ginger-society/specs`, one JSON document and one metadata file
+// per spec URL
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpecCache {
+    dir: PathBuf,
+}
+
+impl SpecCache {
+    pub fn new<P: AsRef<Path>>(dir: P) -> Self {
+        SpecCache {
+            dir: dir.as_ref().to_path_buf(),
+        }
+    }
+
+    pub fn in_project<P: AsRef<Path>>(root: P) -> Self {
+        Self::new(root.as_ref().join(SPECS_DIR))
+    }
+
+    // A readable prefix of the URL for people browsing the directory, the hash of the whole
+    // URL keeps two URLs from sharing a file
+    fn key(url: &str) -> String {
+        let readable: String = url
+            .trim_start_matches("https://")
+            .trim_start_matches("http://")
+            .chars()
+            .take(64)
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect();
+        let hash: String = Sha256::digest(url.as_bytes())[..8]
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        format!("{}-{}", readable, hash)
+    }
+
+    pub fn spec_path(&self, url: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", Self::key(url)))
+    }
+
+    fn metadata_path(&self, url: &str) -> PathBuf {
+        self.dir.join(format!("{}.toml", Self::key(url)))
+    }
+
+    pub fn metadata(&self, url: &str) -> Option<CachedSpecMetadata> {
+        let contents = fs::read_to_string(self.metadata_path(url)).ok()?;
+        toml::from_str(&contents).ok()
+    }
+
+    pub fn load(&self, url: &str) -> Result<Option<OpenApiSpec>, Box<dyn Error>> {
+        let path = self.spec_path(url);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let contents = fs::read_to_string(&path)
+            .map_err(|e| format!("Failed to read the file '{}': {}", path.display(), e))?;
+        Ok(Some(OpenApiSpec::parse(url, &contents)?))
+    }
+
+    pub fn store(&self, spec: &OpenApiSpec, etag: Option<&str>) -> Result<PathBuf, Box<dyn Error>> {
+        fs::create_dir_all(&self.dir)?;
+        let path = self.spec_path(&spec.source_url);
+        write_atomic(&path, &spec.to_json()?, false)?;
+
+        let metadata = CachedSpecMetadata {
+            url: spec.source_url.clone(),
+            etag: etag.map(str::to_string),
+            fetched_at: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+        };
+        write_atomic(
+            self.metadata_path(&spec.source_url),
+            &toml::to_string(&metadata)?,
+            false,
+        )?;
+        Ok(path)
+    }
+}
+
+/// Downloads the spec at `url`. The cached copy is reused when the server answers the
+/// `If-None-Match` request with `304 Not Modified`.
+#[cfg(feature = "client")]
+pub async fn fetch_spec(url: &str, cache: &SpecCache) -> Result<OpenApiSpec, Box<dyn Error>> {
+    let cached_etag = cache.metadata(url).and_then(|metadata| metadata.etag);
+    let mut request = reqwest::Client::new().get(url);
+    if let (Some(etag), true) = (&cached_etag, cache.spec_path(url).exists()) {
+        request = request.header(reqwest::header::IF_NONE_MATCH, etag);
+    }
+
+    let response = request.send().await?;
+    if response.status() == reqwest::StatusCode::NOT_MODIFIED {
+        if let Some(spec) = cache.load(url)? {
+            return Ok(spec);
+        }
+    }
+    if !response.status().is_success() {
+        return Err(format!("Failed to fetch the spec at {}: {}", url, response.status()).into());
+    }
+
+    let etag = response
+        .headers()
+        .get(reqwest::header::ETAG)
+        .and_then(|etag| etag.to_str().ok())
+        .map(str::to_string);
+    let spec = OpenApiSpec::parse(url, &response.text().await?)?;
+    cache.store(&spec, etag.as_deref())?;
+    Ok(spec)
+}
+
+#[cfg(feature = "client")]
+impl crate::ServiceConfig {
+    /// Fetches the spec of this service from `spec_url`.
+    pub async fn fetch_spec(&self, cache: &SpecCache) -> Result<OpenApiSpec, Box<dyn Error>> {
+        let url = self
+            .spec_url
+            .as_deref()
+            .ok_or("No spec_url is set in the service config")?;
+        fetch_spec(url, cache).await
+    }
+}
+
+// A client generator run, described rather than executed so callers decide how to run it
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct GeneratorInvocation {
+    pub program: String,
+    pub args: Vec<String>,
+    pub output_dir: PathBuf,
+}
+
+impl GeneratorInvocation {
+    pub fn command_line(&self) -> String {
+        let mut parts = vec![self.program.clone()];
+        parts.extend(self.args.iter().map(|arg| {
+            if arg.contains(' ') {
+                format!("'{}'", arg)
+            } else {
+                arg.clone()
+            }
+        }));
+        parts.join(" ")
+    }
+
+    pub fn to_command(&self) -> Command {
+        let mut command = Command::new(&self.program);
+        command.args(&self.args);
+        command
+    }
+}
+
+pub trait ClientGenerator {
+    fn invocation(
+        &self,
+        lang: &LANG,
+        spec_path: &Path,
+        output_dir: &Path,
+        package_name: &str,
+    ) -> GeneratorInvocation;
+}
+
+// Generates clients with `openapi-generator-cli`
+#[derive(Debug, Clone, PartialEq)]
+pub struct OpenApiGeneratorCli {
+    pub program: String,
+}
+
+impl Default for OpenApiGeneratorCli {
+    fn default() -> Self {
+        OpenApiGeneratorCli {
+            program: "openapi-generator-cli".to_string(),
+        }
+    }
+}
+
+impl ClientGenerator for OpenApiGeneratorCli {
+    fn invocation(
+        &self,
+        lang: &LANG,
+        spec_path: &Path,
+        output_dir: &Path,
+        package_name: &str,
+    ) -> GeneratorInvocation {
+        let (generator, properties) = match lang {
+            LANG::Rust => (
+                "rust",
+                format!("packageName={},library=reqwest", package_name),
+            ),
+            LANG::TS => (
+                "typescript-fetch",
+                format!("npmName={},supportsES6=true", package_name),
+            ),
+            LANG::Python => ("python", format!("packageName={}", package_name)),
+            LANG::Shell => ("bash", format!("scriptName={}", package_name)),
+        };
+
+        GeneratorInvocation {
+            program: self.program.clone(),
+            args: vec![
+                "generate".to_string(),
+                "-g".to_string(),
+                generator.to_string(),
+                "-i".to_string(),
+                spec_path.display().to_string(),
+                "-o".to_string(),
+                output_dir.display().to_string(),
+                format!("--additional-properties={}", properties),
+            ],
+            output_dir: output_dir.to_path_buf(),
+        }
+    }
+}
+
+// Generates gRPC stubs with `protoc` from a descriptor set, e.g. one fetched with
+// `SchemaRegistryClient::fetch_proto`. `spec_path` is the descriptor set and `files` the proto
+// files of it to generate stubs for. Each language needs its plugins installed:
+//
+// Rust: protoc-gen-prost and protoc-gen-tonic
+// TS: protoc-gen-ts_proto
+// Python: grpcio-tools, which ships its own protoc
+// Shell: nothing, a `.protoset` for grpcurl is written instead of stubs
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProtocStubs {
+    pub program: String,
+    pub files: Vec<String>,
+}
+
+impl ProtocStubs {
+    pub fn new(files: &[&str]) -> Self {
+        ProtocStubs {
+            program: "protoc".to_string(),
+            files: files.iter().map(|f| f.to_string()).collect(),
+        }
+    }
+}
+
+impl ClientGenerator for ProtocStubs {
+    fn invocation(
+        &self,
+        lang: &LANG,
+        spec_path: &Path,
+        output_dir: &Path,
+        package_name: &str,
+    ) -> GeneratorInvocation {
+        let output = output_dir.display().to_string();
+        // grpcio-tools runs its protoc as a Python module
+        let (program, mut args) = match lang {
+            LANG::Python => (
+                "python3".to_string(),
+                vec!["-m".to_string(), "grpc_tools.protoc".to_string()],
+            ),
+            LANG::Rust | LANG::TS | LANG::Shell => (self.program.clone(), vec![]),
+        };
+        args.push(format!("--descriptor_set_in={}", spec_path.display()));
+        match lang {
+            LANG::Rust => {
+                args.push(format!("--prost_out={}", output));
+                args.push(format!("--tonic_out={}", output));
+            }
+            LANG::TS => {
+                args.push(format!("--ts_proto_out={}", output));
+                args.push("--ts_proto_opt=outputServices=grpc-js".to_string());
+            }
+            LANG::Python => {
+                args.push(format!("--python_out={}", output));
+                args.push(format!("--grpc_python_out={}", output));
+            }
+            LANG::Shell => {
+                let protoset = output_dir.join(format!("{}.protoset", package_name));
+                args.push("--include_imports".to_string());
+                args.push(format!("--descriptor_set_out={}", protoset.display()));
+            }
+        }
+        args.extend(self.files.iter().cloned());
+
+        GeneratorInvocation {
+            program,
+            args,
+            output_dir: output_dir.to_path_buf(),
+        }
+    }
+}
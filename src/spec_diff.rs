@@ -0,0 +1,554 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fmt::Write;
+
+use crate::{spec::OpenApiSpec, Channel, Version};
+
+// Deep enough for any real schema, stops recursive `$ref`s from looping forever
+const MAX_SCHEMA_DEPTH: usize = 32;
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[serde(tag = "change", rename_all = "snake_case")]
+pub enum SpecChange {
+    OperationAdded {
+        method: String,
+        path: String,
+    },
+    OperationRemoved {
+        method: String,
+        path: String,
+    },
+    ParameterAdded {
+        method: String,
+        path: String,
+        name: String,
+        location: String,
+        required: bool,
+    },
+    ParameterRemoved {
+        method: String,
+        path: String,
+        name: String,
+        location: String,
+    },
+    ParameterBecameRequired {
+        method: String,
+        path: String,
+        name: String,
+        location: String,
+    },
+    RequestBodyBecameRequired {
+        method: String,
+        path: String,
+    },
+    ResponseAdded {
+        method: String,
+        path: String,
+        status: String,
+    },
+    ResponseRemoved {
+        method: String,
+        path: String,
+        status: String,
+    },
+    ResponseSchemaChanged {
+        method: String,
+        path: String,
+        status: String,
+        breaking: bool,
+        details: Vec<String>,
+    },
+}
+
+impl SpecChange {
+    /// A change is breaking when a client generated from the old spec can no longer work
+    /// against the new one.
+    pub fn is_breaking(&self) -> bool {
+        match self {
+            SpecChange::OperationAdded { .. }
+            | SpecChange::ParameterRemoved { .. }
+            | SpecChange::ResponseAdded { .. } => false,
+            SpecChange::ParameterAdded { required, .. } => *required,
+            SpecChange::ResponseSchemaChanged { breaking, .. } => *breaking,
+            SpecChange::OperationRemoved { .. }
+            | SpecChange::ParameterBecameRequired { .. }
+            | SpecChange::RequestBodyBecameRequired { .. }
+            | SpecChange::ResponseRemoved { .. } => true,
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        match self {
+            SpecChange::OperationAdded { method, path } => {
+                format!("operation `{} {}` added", method, path)
+            }
+            SpecChange::OperationRemoved { method, path } => {
+                format!("operation `{} {}` removed", method, path)
+            }
+            SpecChange::ParameterAdded {
+                method,
+                path,
+                name,
+                location,
+                required,
+            } => format!(
+                "{} parameter `{}` added to `{} {}`{}",
+                location,
+                name,
+                method,
+                path,
+                if *required { " (required)" } else { "" }
+            ),
+            SpecChange::ParameterRemoved {
+                method,
+                path,
+                name,
+                location,
+            } => format!(
+                "{} parameter `{}` removed from `{} {}`",
+                location, name, method, path
+            ),
+            SpecChange::ParameterBecameRequired {
+                method,
+                path,
+                name,
+                location,
+            } => format!(
+                "{} parameter `{}` of `{} {}` is now required",
+                location, name, method, path
+            ),
+            SpecChange::RequestBodyBecameRequired { method, path } => {
+                format!("request body of `{} {}` is now required", method, path)
+            }
+            SpecChange::ResponseAdded {
+                method,
+                path,
+                status,
+            } => format!("response {} added to `{} {}`", status, method, path),
+            SpecChange::ResponseRemoved {
+                method,
+                path,
+                status,
+            } => format!("response {} removed from `{} {}`", status, method, path),
+            SpecChange::ResponseSchemaChanged {
+                method,
+                path,
+                status,
+                details,
+                ..
+            } => format!(
+                "response {} schema of `{} {}` changed: {}",
+                status,
+                method,
+                path,
+                details.join(", ")
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Default)]
+pub struct SpecDiff {
+    pub changes: Vec<SpecChange>,
+}
+
+impl SpecDiff {
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    pub fn has_breaking_changes(&self) -> bool {
+        self.changes.iter().any(|c| c.is_breaking())
+    }
+
+    pub fn breaking_changes(&self) -> Vec<&SpecChange> {
+        self.changes.iter().filter(|c| c.is_breaking()).collect()
+    }
+
+    /// Fails when a `Final` release ships breaking API changes without a major bump (a minor
+    /// bump while still on `0.x`).
+    pub fn check_release(&self, previous: &Version, next: &Version) -> Result<(), String> {
+        if !matches!(next.channel, Channel::Final) || !self.has_breaking_changes() {
+            return Ok(());
+        }
+        let major_bump = next.major > previous.major
+            || (previous.major == 0 && next.major == 0 && next.minor > previous.minor);
+        if major_bump {
+            return Ok(());
+        }
+        Err(format!(
+            "Version {} contains breaking API changes and needs a major version bump from {}:\n{}",
+            next.formatted(),
+            previous.formatted(),
+            self.breaking_changes()
+                .iter()
+                .map(|c| format!("  - {}", c.describe()))
+                .collect::<Vec<_>>()
+                .join("\n")
+        ))
+    }
+
+    pub fn render_text(&self) -> String {
+        if self.is_empty() {
+            return "No API changes".to_string();
+        }
+        let mut out = String::new();
+        for change in &self.changes {
+            let marker = if change.is_breaking() { "!" } else { " " };
+            writeln!(out, "{} {}", marker, change.describe()).unwrap();
+        }
+        out
+    }
+
+    pub fn render_markdown(&self) -> String {
+        if self.is_empty() {
+            return "_No API changes_\n".to_string();
+        }
+        let (breaking, safe): (Vec<_>, Vec<_>) = self.changes.iter().partition(|c| c.is_breaking());
+
+        let mut out = String::new();
+        if !breaking.is_empty() {
+            out.push_str("### Breaking API changes\n\n");
+            for change in breaking {
+                writeln!(out, "- {}", change.describe()).unwrap();
+            }
+            out.push('\n');
+        }
+        if !safe.is_empty() {
+            out.push_str("### Other API changes\n\n");
+            for change in safe {
+                writeln!(out, "- {}", change.describe()).unwrap();
+            }
+            out.push('\n');
+        }
+        out
+    }
+}
+
+pub fn compare_specs(old: &OpenApiSpec, new: &OpenApiSpec) -> SpecDiff {
+    let mut changes = vec![];
+    let old_operations = old.operations();
+    let new_operations = new.operations();
+
+    for (method, path) in &old_operations {
+        if !new_operations.contains(&(method.clone(), path.clone())) {
+            changes.push(SpecChange::OperationRemoved {
+                method: method.clone(),
+                path: path.clone(),
+            });
+            continue;
+        }
+        let op = Operation {
+            method,
+            path,
+            old: &old.document,
+            new: &new.document,
+        };
+        op.diff_parameters(&mut changes);
+        op.diff_request_body(&mut changes);
+        op.diff_responses(&mut changes);
+    }
+
+    for (method, path) in &new_operations {
+        if !old_operations.contains(&(method.clone(), path.clone())) {
+            changes.push(SpecChange::OperationAdded {
+                method: method.clone(),
+                path: path.clone(),
+            });
+        }
+    }
+
+    SpecDiff { changes }
+}
+
+// An operation present in both specs
+struct Operation<'a> {
+    method: &'a str,
+    path: &'a str,
+    old: &'a Value,
+    new: &'a Value,
+}
+
+impl Operation<'_> {
+    fn item<'d>(&self, document: &'d Value) -> &'d Value {
+        &document["paths"][self.path][self.method.to_lowercase()]
+    }
+
+    /// Path level and operation level parameters keyed by `(name, in)`, the operation ones
+    /// taking precedence.
+    fn parameters<'d>(&self, document: &'d Value) -> Vec<(String, String, &'d Value)> {
+        let path_level = document["paths"][self.path]["parameters"].as_array();
+        let operation_level = self.item(document)["parameters"].as_array();
+
+        let mut parameters: Vec<(String, String, &Value)> = vec![];
+        for parameter in path_level.into_iter().chain(operation_level).flatten() {
+            let parameter = resolve(document, parameter);
+            let name = parameter["name"].as_str().unwrap_or_default().to_string();
+            let location = parameter["in"].as_str().unwrap_or_default().to_string();
+            parameters.retain(|(n, l, _)| !(*n == name && *l == location));
+            parameters.push((name, location, parameter));
+        }
+        parameters
+    }
+
+    fn diff_parameters(&self, changes: &mut Vec<SpecChange>) {
+        let old_parameters = self.parameters(self.old);
+        let new_parameters = self.parameters(self.new);
+        let method = self.method.to_string();
+        let path = self.path.to_string();
+
+        for (name, location, old_parameter) in &old_parameters {
+            match new_parameters
+                .iter()
+                .find(|(n, l, _)| n == name && l == location)
+            {
+                Some((_, _, new_parameter)) => {
+                    if !is_required(old_parameter) && is_required(new_parameter) {
+                        changes.push(SpecChange::ParameterBecameRequired {
+                            method: method.clone(),
+                            path: path.clone(),
+                            name: name.clone(),
+                            location: location.clone(),
+                        });
+                    }
+                }
+                None => changes.push(SpecChange::ParameterRemoved {
+                    method: method.clone(),
+                    path: path.clone(),
+                    name: name.clone(),
+                    location: location.clone(),
+                }),
+            }
+        }
+
+        for (name, location, new_parameter) in &new_parameters {
+            if !old_parameters
+                .iter()
+                .any(|(n, l, _)| n == name && l == location)
+            {
+                changes.push(SpecChange::ParameterAdded {
+                    method: method.clone(),
+                    path: path.clone(),
+                    name: name.clone(),
+                    location: location.clone(),
+                    required: is_required(new_parameter),
+                });
+            }
+        }
+    }
+
+    fn diff_request_body(&self, changes: &mut Vec<SpecChange>) {
+        let old_body = resolve(self.old, &self.item(self.old)["requestBody"]);
+        let new_body = resolve(self.new, &self.item(self.new)["requestBody"]);
+        if !is_required(old_body) && is_required(new_body) {
+            changes.push(SpecChange::RequestBodyBecameRequired {
+                method: self.method.to_string(),
+                path: self.path.to_string(),
+            });
+        }
+    }
+
+    fn diff_responses(&self, changes: &mut Vec<SpecChange>) {
+        let empty = serde_json::Map::new();
+        let old_responses = self.item(self.old)["responses"]
+            .as_object()
+            .unwrap_or(&empty);
+        let new_responses = self.item(self.new)["responses"]
+            .as_object()
+            .unwrap_or(&empty);
+
+        for (status, old_response) in old_responses {
+            let new_response = match new_responses.get(status) {
+                Some(new_response) => new_response,
+                None => {
+                    changes.push(SpecChange::ResponseRemoved {
+                        method: self.method.to_string(),
+                        path: self.path.to_string(),
+                        status: status.clone(),
+                    });
+                    continue;
+                }
+            };
+
+            let old_schema = response_schema(self.old, old_response);
+            let new_schema = response_schema(self.new, new_response);
+            let mut details = vec![];
+            let breaking = compare_schemas(
+                self.old,
+                old_schema,
+                self.new,
+                new_schema,
+                "body",
+                0,
+                &mut details,
+            );
+            if !details.is_empty() {
+                changes.push(SpecChange::ResponseSchemaChanged {
+                    method: self.method.to_string(),
+                    path: self.path.to_string(),
+                    status: status.clone(),
+                    breaking,
+                    details,
+                });
+            }
+        }
+
+        for status in new_responses.keys() {
+            if !old_responses.contains_key(status) {
+                changes.push(SpecChange::ResponseAdded {
+                    method: self.method.to_string(),
+                    path: self.path.to_string(),
+                    status: status.clone(),
+                });
+            }
+        }
+    }
+}
+
+fn is_required(value: &Value) -> bool {
+    value["required"].as_bool().unwrap_or(false)
+}
+
+/// Follows local `$ref`s (`#/components/...`).
+fn resolve<'d>(document: &'d Value, value: &'d Value) -> &'d Value {
+    let mut value = value;
+    for _ in 0..MAX_SCHEMA_DEPTH {
+        let reference = match value.get("$ref").and_then(Value::as_str) {
+            Some(reference) => reference,
+            None => return value,
+        };
+        let pointer = reference.trim_start_matches('#');
+        value = match document.pointer(pointer) {
+            Some(target) => target,
+            None => return value,
+        };
+    }
+    value
+}
+
+fn response_schema<'d>(document: &'d Value, response: &'d Value) -> &'d Value {
+    let response = resolve(document, response);
+    let content = &response["content"];
+    let media = content
+        .get("application/json")
+        .or_else(|| content.as_object().and_then(|c| c.values().next()))
+        .unwrap_or(&Value::Null);
+    resolve(document, &media["schema"])
+}
+
+fn schema_type(schema: &Value) -> Option<&str> {
+    schema["type"].as_str().or_else(|| {
+        if schema.get("properties").is_some() {
+            Some("object")
+        } else if schema.get("items").is_some() {
+            Some("array")
+        } else {
+            None
+        }
+    })
+}
+
+/// Records the differences between two response schemas in `details` and returns whether
+/// any of them breaks clients: removed properties, changed types and fields that became
+/// nullable. Added properties are not breaking.
+fn compare_schemas(
+    old_doc: &Value,
+    old: &Value,
+    new_doc: &Value,
+    new: &Value,
+    location: &str,
+    depth: usize,
+    details: &mut Vec<String>,
+) -> bool {
+    if depth > MAX_SCHEMA_DEPTH {
+        return false;
+    }
+    let old = resolve(old_doc, old);
+    let new = resolve(new_doc, new);
+
+    if old.is_null() && new.is_null() {
+        return false;
+    }
+    if old.is_null() != new.is_null() {
+        details.push(format!(
+            "`{}` {}",
+            location,
+            if old.is_null() {
+                "now has a schema"
+            } else {
+                "no longer has a schema"
+            }
+        ));
+        return !old.is_null();
+    }
+
+    let (old_type, new_type) = (schema_type(old), schema_type(new));
+    if old_type != new_type {
+        details.push(format!(
+            "`{}` changed type {} -> {}",
+            location,
+            old_type.unwrap_or("any"),
+            new_type.unwrap_or("any")
+        ));
+        return true;
+    }
+
+    let mut breaking = false;
+    if !old["nullable"].as_bool().unwrap_or(false) && new["nullable"].as_bool().unwrap_or(false) {
+        details.push(format!("`{}` is now nullable", location));
+        breaking = true;
+    }
+
+    match old_type {
+        Some("object") => {
+            let empty = serde_json::Map::new();
+            let old_properties = old["properties"].as_object().unwrap_or(&empty);
+            let new_properties = new["properties"].as_object().unwrap_or(&empty);
+
+            for (name, old_property) in old_properties {
+                let property_location = format!("{}.{}", location, name);
+                match new_properties.get(name) {
+                    Some(new_property) => {
+                        breaking |= compare_schemas(
+                            old_doc,
+                            old_property,
+                            new_doc,
+                            new_property,
+                            &property_location,
+                            depth + 1,
+                            details,
+                        );
+                    }
+                    None => {
+                        details.push(format!("`{}` removed", property_location));
+                        breaking = true;
+                    }
+                }
+            }
+            for name in new_properties.keys() {
+                if !old_properties.contains_key(name) {
+                    details.push(format!("`{}.{}` added", location, name));
+                }
+            }
+        }
+        Some("array") => {
+            breaking |= compare_schemas(
+                old_doc,
+                &old["items"],
+                new_doc,
+                &new["items"],
+                &format!("{}[]", location),
+                depth + 1,
+                details,
+            );
+        }
+        _ => {
+            if old.get("enum") != new.get("enum") {
+                details.push(format!("`{}` changed its allowed values", location));
+                breaking = true;
+            }
+        }
+    }
+
+    breaking
+}
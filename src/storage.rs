@@ -0,0 +1,681 @@
+use async_trait::async_trait;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    error::Error,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+use tokio::{fs, io::AsyncWriteExt};
+use url::Url;
+
+use crate::Environment;
+
+#[cfg(feature = "client")]
+pub use self::s3::S3BlobStore;
+
+pub type StorageError = Box<dyn Error + Send + Sync>;
+
+/// Where `StorageConfig` stores blobs in `Dev` when nothing is configured.
+pub const DEFAULT_LOCAL_DIR: &str = ".ginger-society/blobs";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PresignMethod {
+    Get,
+    Put,
+}
+
+// Objects stored under `/` separated keys, e.g. `snapshots/1.2.0/snapshot.toml`
+#[async_trait]
+pub trait BlobStore: Send + Sync {
+    async fn put(
+        &self,
+        key: &str,
+        data: Vec<u8>,
+        content_type: Option<&str>,
+    ) -> Result<(), StorageError>;
+
+    /// Stores the file at `path` under `key` without reading it in memory.
+    async fn put_file(
+        &self,
+        key: &str,
+        path: &Path,
+        content_type: Option<&str>,
+    ) -> Result<(), StorageError>;
+
+    /// The contents of `key`, `None` when it does not exist.
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, StorageError>;
+
+    /// Keys starting with `prefix`, sorted.
+    async fn list(&self, prefix: &str) -> Result<Vec<String>, StorageError>;
+
+    /// Removes `key`, succeeding when it does not exist.
+    async fn delete(&self, key: &str) -> Result<(), StorageError>;
+
+    /// A URL reading or writing `key` without credentials until `expires_in` has passed.
+    async fn presign(
+        &self,
+        key: &str,
+        method: PresignMethod,
+        expires_in: Duration,
+    ) -> Result<String, StorageError>;
+}
+
+fn check_key(key: &str) -> Result<(), StorageError> {
+    if key
+        .split('/')
+        .any(|segment| segment.is_empty() || segment == "." || segment == "..")
+    {
+        return Err(format!("'{}' is not a valid blob key", key).into());
+    }
+    Ok(())
+}
+
+// Blobs stored as files under `dir`, written to a `.part` file and renamed once complete
+pub struct LocalBlobStore {
+    dir: PathBuf,
+}
+
+impl LocalBlobStore {
+    pub fn new<P: AsRef<Path>>(dir: P) -> Self {
+        LocalBlobStore {
+            dir: dir.as_ref().to_path_buf(),
+        }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    fn path(&self, key: &str) -> Result<PathBuf, StorageError> {
+        check_key(key)?;
+        Ok(self.dir.join(key))
+    }
+
+    async fn part(&self, key: &str) -> Result<(PathBuf, PathBuf), StorageError> {
+        let path = self.path(key)?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        let mut part = path.clone().into_os_string();
+        part.push(".part");
+        Ok((path, part.into()))
+    }
+}
+
+#[async_trait]
+impl BlobStore for LocalBlobStore {
+    async fn put(
+        &self,
+        key: &str,
+        data: Vec<u8>,
+        _content_type: Option<&str>,
+    ) -> Result<(), StorageError> {
+        let (path, part) = self.part(key).await?;
+        let mut file = fs::File::create(&part).await?;
+        file.write_all(&data).await?;
+        file.sync_all().await?;
+        fs::rename(&part, &path).await?;
+        Ok(())
+    }
+
+    async fn put_file(
+        &self,
+        key: &str,
+        source: &Path,
+        _content_type: Option<&str>,
+    ) -> Result<(), StorageError> {
+        let (path, part) = self.part(key).await?;
+        fs::copy(source, &part)
+            .await
+            .map_err(|e| format!("Failed to store '{}': {}", source.display(), e))?;
+        fs::rename(&part, &path).await?;
+        Ok(())
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, StorageError> {
+        match fs::read(self.path(key)?).await {
+            Ok(data) => Ok(Some(data)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn list(&self, prefix: &str) -> Result<Vec<String>, StorageError> {
+        let mut keys = vec![];
+        let mut dirs = vec![(self.dir.clone(), String::new())];
+        while let Some((dir, dir_key)) = dirs.pop() {
+            let mut entries = match fs::read_dir(&dir).await {
+                Ok(entries) => entries,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e.into()),
+            };
+            while let Some(entry) = entries.next_entry().await? {
+                let name = entry.file_name().to_string_lossy().into_owned();
+                let key = format!("{}{}", dir_key, name);
+                if entry.file_type().await?.is_dir() {
+                    dirs.push((entry.path(), format!("{}/", key)));
+                } else if key.starts_with(prefix) && !name.ends_with(".part") {
+                    keys.push(key);
+                }
+            }
+        }
+        keys.sort();
+        Ok(keys)
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), StorageError> {
+        match fs::remove_file(self.path(key)?).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// A `file://` URL: local files need no signature, `method` and `expires_in` are ignored.
+    async fn presign(
+        &self,
+        key: &str,
+        _method: PresignMethod,
+        _expires_in: Duration,
+    ) -> Result<String, StorageError> {
+        let path = std::path::absolute(self.path(key)?)?;
+        Url::from_file_path(&path)
+            .map(String::from)
+            .map_err(|_| format!("'{}' cannot be turned into a URL", path.display()).into())
+    }
+}
+
+// An S3 bucket, or one of an S3-compatible server like MinIO. The credentials are read from
+// `AWS_ACCESS_KEY_ID`, `AWS_SECRET_ACCESS_KEY` and `AWS_SESSION_TOKEN`.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, JsonSchema)]
+pub struct S3Config {
+    pub endpoint: String, // e.g. `https://s3.eu-central-1.amazonaws.com` or `http://localhost:9000`
+    pub bucket: String,
+    #[serde(default = "default_region")]
+    pub region: String,
+    pub prefix: Option<String>, // Prepended to every key, e.g. `releaser/`
+    #[serde(default)]
+    pub virtual_hosted: bool, // `bucket.host/key` URLs instead of `host/bucket/key`
+}
+
+fn default_region() -> String {
+    "us-east-1".to_string()
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, JsonSchema)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum BlobStoreConfig {
+    Local { dir: String },
+    S3(S3Config),
+}
+
+impl BlobStoreConfig {
+    pub fn open(&self) -> Result<Arc<dyn BlobStore>, StorageError> {
+        match self {
+            BlobStoreConfig::Local { dir } => Ok(Arc::new(LocalBlobStore::new(dir))),
+            #[cfg(feature = "client")]
+            BlobStoreConfig::S3(config) => Ok(Arc::new(S3BlobStore::from_env(config.clone())?)),
+            #[cfg(not(feature = "client"))]
+            BlobStoreConfig::S3(config) => Err(format!(
+                "The S3 bucket '{}' cannot be used without the 'client' feature",
+                config.bucket
+            )
+            .into()),
+        }
+    }
+}
+
+// The blob store of each environment, e.g.
+//
+// [storage.dev]
+// kind = "local"
+// dir = ".ginger-society/blobs"
+//
+// [storage.prod]
+// kind = "s3"
+// endpoint = "https://s3.eu-central-1.amazonaws.com"
+// bucket = "ginger-releases"
+// region = "eu-central-1"
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Default)]
+#[serde(transparent)]
+pub struct StorageConfig(pub HashMap<Environment, BlobStoreConfig>);
+
+impl StorageConfig {
+    /// The store of `env`, falling back to the backend environment (`prod` for `prod_k8`).
+    pub fn for_environment(&self, env: &Environment) -> Option<&BlobStoreConfig> {
+        self.0.get(env).or_else(|| self.0.get(&env.as_backend()))
+    }
+
+    /// Opens the store of `env`. `Dev` falls back to `DEFAULT_LOCAL_DIR`, the other
+    /// environments have to be configured.
+    pub fn open(&self, env: &Environment) -> Result<Arc<dyn BlobStore>, StorageError> {
+        match (self.for_environment(env), env) {
+            (Some(config), _) => config.open(),
+            (None, Environment::Dev) => Ok(Arc::new(LocalBlobStore::new(DEFAULT_LOCAL_DIR))),
+            (None, env) => {
+                Err(format!("No blob store is configured for the {} environment", env).into())
+            }
+        }
+    }
+}
+
+#[cfg(feature = "client")]
+mod s3 {
+    use async_trait::async_trait;
+    use chrono::{DateTime, Utc};
+    use data_encoding::HEXLOWER;
+    use hmac::{Hmac, Mac};
+    use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+    use reqwest::{header, Body, Client, Method, Response, StatusCode};
+    use sha2::{Digest, Sha256};
+    use std::{env, path::Path, time::Duration};
+    use url::Url;
+
+    use super::{check_key, BlobStore, PresignMethod, S3Config, StorageError};
+
+    // Characters SigV4 leaves unencoded in paths and query strings
+    const UNRESERVED: &AsciiSet = &NON_ALPHANUMERIC
+        .remove(b'-')
+        .remove(b'.')
+        .remove(b'_')
+        .remove(b'~');
+    const ALGORITHM: &str = "AWS4-HMAC-SHA256";
+    const UNSIGNED_PAYLOAD: &str = "UNSIGNED-PAYLOAD";
+    const MAX_PRESIGN: Duration = Duration::from_secs(7 * 24 * 3600);
+
+    fn encode(value: &str) -> String {
+        utf8_percent_encode(value, UNRESERVED).to_string()
+    }
+
+    fn sha256_hex(data: &[u8]) -> String {
+        HEXLOWER.encode(&Sha256::digest(data))
+    }
+
+    fn hmac(key: &[u8], data: &str) -> Vec<u8> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts any key length");
+        mac.update(data.as_bytes());
+        mac.finalize().into_bytes().to_vec()
+    }
+
+    fn canonical_query(query: &[(&str, String)]) -> String {
+        let mut pairs: Vec<(String, String)> = query
+            .iter()
+            .map(|(name, value)| (encode(name), encode(value)))
+            .collect();
+        pairs.sort();
+        pairs
+            .iter()
+            .map(|(name, value)| format!("{}={}", name, value))
+            .collect::<Vec<_>>()
+            .join("&")
+    }
+
+    // The values of the `tag` elements of an S3 XML response
+    fn xml_values(xml: &str, tag: &str) -> Vec<String> {
+        let (open, close) = (format!("<{}>", tag), format!("</{}>", tag));
+        let mut values = vec![];
+        let mut rest = xml;
+        while let Some(start) = rest.find(&open) {
+            rest = &rest[start + open.len()..];
+            let Some(end) = rest.find(&close) else { break };
+            values.push(
+                rest[..end]
+                    .replace("&lt;", "<")
+                    .replace("&gt;", ">")
+                    .replace("&quot;", "\"")
+                    .replace("&apos;", "'")
+                    .replace("&amp;", "&"),
+            );
+            rest = &rest[end + close.len()..];
+        }
+        values
+    }
+
+    pub struct S3BlobStore {
+        config: S3Config,
+        access_key: String,
+        secret_key: String,
+        session_token: Option<String>,
+        origin: String,    // Scheme, host and port requests are sent to
+        host: String,      // The signed `host` header
+        base_path: String, // `/bucket` with path-style URLs, empty otherwise
+        client: Client,
+    }
+
+    impl S3BlobStore {
+        pub fn new(
+            config: S3Config,
+            access_key: &str,
+            secret_key: &str,
+            session_token: Option<&str>,
+        ) -> Result<Self, StorageError> {
+            let endpoint = Url::parse(&config.endpoint)
+                .map_err(|e| format!("Invalid S3 endpoint '{}': {}", config.endpoint, e))?;
+            let endpoint_host = endpoint
+                .host_str()
+                .ok_or_else(|| format!("The S3 endpoint '{}' has no host", config.endpoint))?;
+            let mut host = match config.virtual_hosted {
+                true => format!("{}.{}", config.bucket, endpoint_host),
+                false => endpoint_host.to_string(),
+            };
+            if let Some(port) = endpoint.port() {
+                host = format!("{}:{}", host, port);
+            }
+            let base_path = match config.virtual_hosted {
+                true => String::new(),
+                false => format!("/{}", encode(&config.bucket)),
+            };
+            Ok(S3BlobStore {
+                origin: format!("{}://{}", endpoint.scheme(), host),
+                host,
+                base_path,
+                access_key: access_key.to_string(),
+                secret_key: secret_key.to_string(),
+                session_token: session_token.map(str::to_string),
+                client: Client::new(),
+                config,
+            })
+        }
+
+        pub fn from_env(config: S3Config) -> Result<Self, StorageError> {
+            let var = |name: &str| {
+                env::var(name)
+                    .map_err(|_| format!("{} is not set", name))
+                    .map_err(StorageError::from)
+            };
+            let session_token = env::var("AWS_SESSION_TOKEN").ok();
+            Self::new(
+                config,
+                &var("AWS_ACCESS_KEY_ID")?,
+                &var("AWS_SECRET_ACCESS_KEY")?,
+                session_token.as_deref(),
+            )
+        }
+
+        fn full_key(&self, key: &str) -> String {
+            format!(
+                "{}{}",
+                self.config.prefix.as_deref().unwrap_or_default(),
+                key
+            )
+        }
+
+        fn object_path(&self, key: &str) -> Result<String, StorageError> {
+            check_key(key)?;
+            let segments: Vec<String> = self.full_key(key).split('/').map(encode).collect();
+            Ok(format!("{}/{}", self.base_path, segments.join("/")))
+        }
+
+        fn scope(&self, now: &DateTime<Utc>) -> String {
+            format!(
+                "{}/{}/s3/aws4_request",
+                now.format("%Y%m%d"),
+                self.config.region
+            )
+        }
+
+        fn signature(&self, now: &DateTime<Utc>, canonical_request: &str) -> String {
+            let string_to_sign = format!(
+                "{}\n{}\n{}\n{}",
+                ALGORITHM,
+                now.format("%Y%m%dT%H%M%SZ"),
+                self.scope(now),
+                sha256_hex(canonical_request.as_bytes())
+            );
+            let key = hmac(
+                format!("AWS4{}", self.secret_key).as_bytes(),
+                &now.format("%Y%m%d").to_string(),
+            );
+            let key = hmac(&key, &self.config.region);
+            let key = hmac(&key, "s3");
+            let key = hmac(&key, "aws4_request");
+            HEXLOWER.encode(&hmac(&key, &string_to_sign))
+        }
+
+        // Fails unless S3 answers with a success status
+        async fn send(
+            &self,
+            method: Method,
+            path: &str,
+            query: &[(&str, String)],
+            body: Option<(Body, String, u64)>,
+            content_type: Option<&str>,
+        ) -> Result<Response, StorageError> {
+            let response = self
+                .send_raw(method.clone(), path, query, body, content_type)
+                .await?;
+            if response.status().is_success() {
+                return Ok(response);
+            }
+            Err(failure(
+                &method,
+                path,
+                response.status(),
+                response.text().await?,
+            ))
+        }
+
+        async fn send_raw(
+            &self,
+            method: Method,
+            path: &str,
+            query: &[(&str, String)],
+            body: Option<(Body, String, u64)>, // The body, its SHA-256 and its length
+            content_type: Option<&str>,
+        ) -> Result<Response, StorageError> {
+            let now = Utc::now();
+            let timestamp = now.format("%Y%m%dT%H%M%SZ").to_string();
+            let query = canonical_query(query);
+            let payload_hash = match &body {
+                Some((_, hash, _)) => hash.clone(),
+                None => sha256_hex(b""),
+            };
+            let mut headers = vec![
+                ("host", self.host.clone()),
+                ("x-amz-content-sha256", payload_hash.clone()),
+                ("x-amz-date", timestamp),
+            ];
+            if let Some(token) = &self.session_token {
+                headers.push(("x-amz-security-token", token.clone()));
+            }
+            let signed_headers = headers
+                .iter()
+                .map(|(name, _)| *name)
+                .collect::<Vec<_>>()
+                .join(";");
+            let canonical_request = format!(
+                "{}\n{}\n{}\n{}\n{}\n{}",
+                method,
+                path,
+                query,
+                headers
+                    .iter()
+                    .map(|(name, value)| format!("{}:{}\n", name, value))
+                    .collect::<String>(),
+                signed_headers,
+                payload_hash
+            );
+            let authorization = format!(
+                "{} Credential={}/{}, SignedHeaders={}, Signature={}",
+                ALGORITHM,
+                self.access_key,
+                self.scope(&now),
+                signed_headers,
+                self.signature(&now, &canonical_request)
+            );
+
+            let url = match query.is_empty() {
+                true => format!("{}{}", self.origin, path),
+                false => format!("{}{}?{}", self.origin, path, query),
+            };
+            let mut request = self
+                .client
+                .request(method.clone(), &url)
+                .header(header::AUTHORIZATION, authorization);
+            for (name, value) in headers.into_iter().skip(1) {
+                request = request.header(name, value);
+            }
+            if let Some(content_type) = content_type {
+                request = request.header(header::CONTENT_TYPE, content_type);
+            }
+            if let Some((body, _, length)) = body {
+                request = request.header(header::CONTENT_LENGTH, length).body(body);
+            }
+            Ok(request.send().await?)
+        }
+
+        fn presign_at(
+            &self,
+            key: &str,
+            method: PresignMethod,
+            expires_in: Duration,
+            now: DateTime<Utc>,
+        ) -> Result<String, StorageError> {
+            if expires_in > MAX_PRESIGN {
+                return Err("Presigned URLs expire after 7 days at most".into());
+            }
+            let path = self.object_path(key)?;
+            let method = match method {
+                PresignMethod::Get => Method::GET,
+                PresignMethod::Put => Method::PUT,
+            };
+            let mut query = vec![
+                ("X-Amz-Algorithm", ALGORITHM.to_string()),
+                (
+                    "X-Amz-Credential",
+                    format!("{}/{}", self.access_key, self.scope(&now)),
+                ),
+                ("X-Amz-Date", now.format("%Y%m%dT%H%M%SZ").to_string()),
+                ("X-Amz-Expires", expires_in.as_secs().to_string()),
+                ("X-Amz-SignedHeaders", "host".to_string()),
+            ];
+            if let Some(token) = &self.session_token {
+                query.push(("X-Amz-Security-Token", token.clone()));
+            }
+            let query = canonical_query(&query);
+            let canonical_request = format!(
+                "{}\n{}\n{}\nhost:{}\n\nhost\n{}",
+                method, path, query, self.host, UNSIGNED_PAYLOAD
+            );
+            Ok(format!(
+                "{}{}?{}&X-Amz-Signature={}",
+                self.origin,
+                path,
+                query,
+                self.signature(&now, &canonical_request)
+            ))
+        }
+    }
+
+    fn failure(method: &Method, path: &str, status: StatusCode, body: String) -> StorageError {
+        let message = xml_values(&body, "Message").pop().unwrap_or(body);
+        format!("S3 {} {} failed with {}: {}", method, path, status, message).into()
+    }
+
+    #[async_trait]
+    impl BlobStore for S3BlobStore {
+        async fn put(
+            &self,
+            key: &str,
+            data: Vec<u8>,
+            content_type: Option<&str>,
+        ) -> Result<(), StorageError> {
+            let path = self.object_path(key)?;
+            let (hash, length) = (sha256_hex(&data), data.len() as u64);
+            self.send(
+                Method::PUT,
+                &path,
+                &[],
+                Some((data.into(), hash, length)),
+                content_type,
+            )
+            .await?;
+            Ok(())
+        }
+
+        async fn put_file(
+            &self,
+            key: &str,
+            source: &Path,
+            content_type: Option<&str>,
+        ) -> Result<(), StorageError> {
+            let path = self.object_path(key)?;
+            let file = tokio::fs::File::open(source)
+                .await
+                .map_err(|e| format!("Failed to store '{}': {}", source.display(), e))?;
+            let length = file.metadata().await?.len();
+            let body = Some((file.into(), UNSIGNED_PAYLOAD.to_string(), length));
+            self.send(Method::PUT, &path, &[], body, content_type)
+                .await?;
+            Ok(())
+        }
+
+        async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, StorageError> {
+            let path = self.object_path(key)?;
+            let response = self.send_raw(Method::GET, &path, &[], None, None).await?;
+            let status = response.status();
+            if status.is_success() {
+                return Ok(Some(response.bytes().await?.to_vec()));
+            }
+            let body = response.text().await?;
+            // A missing bucket is a 404 too, only a missing object means there is nothing stored
+            if status == StatusCode::NOT_FOUND
+                && xml_values(&body, "Code").pop().as_deref() == Some("NoSuchKey")
+            {
+                return Ok(None);
+            }
+            Err(failure(&Method::GET, &path, status, body))
+        }
+
+        async fn list(&self, prefix: &str) -> Result<Vec<String>, StorageError> {
+            let path = format!("{}/", self.base_path);
+            let full_prefix = self.full_key(prefix);
+            let mut keys = vec![];
+            let mut token: Option<String> = None;
+            loop {
+                let mut query = vec![
+                    ("list-type", "2".to_string()),
+                    ("prefix", full_prefix.clone()),
+                ];
+                if let Some(token) = token.take() {
+                    query.push(("continuation-token", token));
+                }
+                let response = self.send(Method::GET, &path, &query, None, None).await?;
+                let body = response.text().await?;
+                let own_prefix = self.config.prefix.as_deref().unwrap_or_default();
+                keys.extend(
+                    xml_values(&body, "Key")
+                        .into_iter()
+                        .filter_map(|key| key.strip_prefix(own_prefix).map(str::to_string)),
+                );
+                match xml_values(&body, "NextContinuationToken").pop() {
+                    Some(next)
+                        if xml_values(&body, "IsTruncated").pop().as_deref() == Some("true") =>
+                    {
+                        token = Some(next)
+                    }
+                    _ => break,
+                }
+            }
+            keys.sort();
+            Ok(keys)
+        }
+
+        async fn delete(&self, key: &str) -> Result<(), StorageError> {
+            let path = self.object_path(key)?;
+            self.send(Method::DELETE, &path, &[], None, None).await?;
+            Ok(())
+        }
+
+        async fn presign(
+            &self,
+            key: &str,
+            method: PresignMethod,
+            expires_in: Duration,
+        ) -> Result<String, StorageError> {
+            self.presign_at(key, method, expires_in, Utc::now())
+        }
+    }
+}
@@ -0,0 +1,216 @@
+use okapi::openapi3::{MediaType, Responses};
+use rocket::{
+    futures::{stream, Stream, StreamExt},
+    http::{ContentType, Header},
+    response::{self, stream::ReaderStream, Responder},
+    tokio::sync::mpsc,
+    Request, Response,
+};
+use rocket_okapi::{
+    gen::OpenApiGenerator, response::OpenApiResponderInner, util::add_content_response,
+};
+use serde::Serialize;
+use std::{error::Error, fmt, io::Cursor};
+
+use crate::export::guard_formula;
+
+// Rows a blocking producer may run ahead of the client by
+const DEFAULT_BUFFER: usize = 64;
+
+/// The items of `rows` until the first error, which is logged. Headers are sent with the first
+/// row, so a failure halfway can only end the body early.
+pub fn until_error<S, T, E>(rows: S) -> impl Stream<Item = T>
+where
+    S: Stream<Item = Result<T, E>>,
+    E: fmt::Display,
+{
+    rows.scan((), |_, row| {
+        let row = match row {
+            Ok(row) => Some(row),
+            Err(e) => {
+                tracing::warn!(error = %e, "streamed response ended early");
+                None
+            }
+        };
+        async move { row }
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StreamClosed;
+
+impl fmt::Display for StreamClosed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "The client stopped reading the stream")
+    }
+}
+
+impl Error for StreamClosed {}
+
+// Hands rows from blocking code, e.g. a Diesel cursor, to the response stream
+pub struct RowSender<T>(mpsc::Sender<T>);
+
+impl<T> RowSender<T> {
+    /// Blocks while the buffer is full, so rows are read from the database no faster than the
+    /// client downloads them. Fails once the client is gone, to stop the producer.
+    pub fn send(&self, row: T) -> Result<(), StreamClosed> {
+        self.0.blocking_send(row).map_err(|_| StreamClosed)
+    }
+}
+
+/// Runs `produce` on the blocking thread pool and streams the rows it sends, e.g.
+///
+/// ```ignore
+/// blocking_stream(move |rows| {
+///     let mut conn = pool.get()?;
+///     for event in audit_events.load_iter::<AuditRow, DefaultLoadingMode>(&mut conn)? {
+///         rows.send(event?)?;
+///     }
+///     Ok(())
+/// })
+/// ```
+pub fn blocking_stream<T, F>(produce: F) -> impl Stream<Item = T>
+where
+    T: Send + 'static,
+    F: FnOnce(&RowSender<T>) -> Result<(), Box<dyn Error + Send + Sync>> + Send + 'static,
+{
+    let (sender, receiver) = mpsc::channel(DEFAULT_BUFFER);
+    rocket::tokio::task::spawn_blocking(move || {
+        let sender = RowSender(sender);
+        if let Err(e) = produce(&sender) {
+            if !e.is::<StreamClosed>() {
+                tracing::warn!(error = %e, "streamed response ended early");
+            }
+        }
+    });
+    stream::unfold(receiver, |mut receiver| async move {
+        receiver.recv().await.map(|row| (row, receiver))
+    })
+}
+
+fn streamed<'r, S>(chunks: S, content_type: ContentType) -> Response<'r>
+where
+    S: Stream<Item = Vec<u8>> + Send + 'r,
+{
+    Response::build()
+        .header(content_type)
+        .streamed_body(ReaderStream::from(chunks.map(Cursor::new)))
+        .finalize()
+}
+
+// Streams serializable rows as newline delimited JSON, `application/x-ndjson`
+pub struct NdJson<S>(pub S);
+
+impl<'r, S, T> Responder<'r, 'r> for NdJson<S>
+where
+    S: Stream<Item = T> + Send + 'r,
+    T: Serialize,
+{
+    fn respond_to(self, _request: &'r Request<'_>) -> response::Result<'r> {
+        let lines = self.0.scan((), |_, row| {
+            let line = match serde_json::to_vec(&row) {
+                Ok(mut line) => {
+                    line.push(b'\n');
+                    Some(line)
+                }
+                Err(e) => {
+                    tracing::warn!(error = %e, "failed to serialize a streamed row");
+                    None
+                }
+            };
+            async move { line }
+        });
+        Ok(streamed(lines, ContentType::new("application", "x-ndjson")))
+    }
+}
+
+impl<S> OpenApiResponderInner for NdJson<S> {
+    fn responses(_gen: &mut OpenApiGenerator) -> rocket_okapi::Result<Responses> {
+        let mut responses = Responses::default();
+        add_content_response(
+            &mut responses,
+            200,
+            "application/x-ndjson",
+            MediaType::default(),
+        )?;
+        Ok(responses)
+    }
+}
+
+// Streams serializable rows as CSV, with a header row named after the fields of the first one.
+// Rows must be flat, nested structs and maps are not representable in CSV.
+pub struct Csv<S> {
+    rows: S,
+    file_name: Option<String>,
+}
+
+impl<S> Csv<S> {
+    pub fn new(rows: S) -> Self {
+        Csv {
+            rows,
+            file_name: None,
+        }
+    }
+
+    /// Sends the CSV as an attachment, e.g. `audit-2024-05.csv`, for browsers to download.
+    pub fn file_name(mut self, file_name: &str) -> Self {
+        self.file_name = Some(file_name.to_string());
+        self
+    }
+}
+
+// Cells are guarded against formulas as in `Export::to_csv`, so both exports read the same
+fn csv_row<T: Serialize>(row: &T, with_headers: bool) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut writer = csv::Writer::from_writer(vec![]);
+    writer.serialize(row)?;
+    let bytes = writer.into_inner().map_err(|e| e.to_string())?;
+    let mut reader = csv::Reader::from_reader(&bytes[..]);
+    let headers = reader.headers()?.clone();
+    let record = reader.records().next().ok_or("The row has no fields")??;
+
+    let mut writer = csv::Writer::from_writer(vec![]);
+    if with_headers {
+        writer.write_record(&headers)?;
+    }
+    writer.write_record(record.iter().map(guard_formula))?;
+    Ok(writer.into_inner().map_err(|e| e.to_string())?)
+}
+
+impl<'r, S, T> Responder<'r, 'r> for Csv<S>
+where
+    S: Stream<Item = T> + Send + 'r,
+    T: Serialize,
+{
+    fn respond_to(self, _request: &'r Request<'_>) -> response::Result<'r> {
+        let lines = self.rows.scan(true, |first, row| {
+            let line = match csv_row(&row, *first) {
+                Ok(line) => {
+                    *first = false;
+                    Some(line)
+                }
+                Err(e) => {
+                    tracing::warn!(error = %e, "failed to serialize a streamed row");
+                    None
+                }
+            };
+            async move { line }
+        });
+        let mut response = streamed(lines, ContentType::CSV);
+        if let Some(file_name) = self.file_name {
+            let file_name = file_name.replace('"', "");
+            response.set_header(Header::new(
+                "Content-Disposition",
+                format!("attachment; filename=\"{}\"", file_name),
+            ));
+        }
+        Ok(response)
+    }
+}
+
+impl<S> OpenApiResponderInner for Csv<S> {
+    fn responses(_gen: &mut OpenApiGenerator) -> rocket_okapi::Result<Responses> {
+        let mut responses = Responses::default();
+        add_content_response(&mut responses, 200, "text/csv", MediaType::default())?;
+        Ok(responses)
+    }
+}
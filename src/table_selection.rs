@@ -0,0 +1,86 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{schema::SchemaDocument, ConsumerDBTables};
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Default)]
+pub struct TableSelection {
+    pub tables: Vec<String>,
+    pub warnings: Vec<String>,
+}
+
+impl ConsumerDBTables {
+    /// Expands the configured names against the full list of tables.
+    ///
+    /// Entries may contain `*` and `?` wildcards, and entries starting with `!` exclude the
+    /// tables they match. Patterns are applied in order, so `["user_*", "!user_audit"]`
+    /// selects every `user_` table except `user_audit`. When only exclusions are given, the
+    /// selection starts from all tables.
+    pub fn resolve(&self, available: &[String]) -> TableSelection {
+        let mut selected = vec![false; available.len()];
+        let mut warnings = vec![];
+
+        if self.names.iter().all(|name| name.starts_with('!')) {
+            selected.iter_mut().for_each(|s| *s = true);
+        }
+
+        for name in &self.names {
+            let (pattern, include) = match name.strip_prefix('!') {
+                Some(pattern) => (pattern.trim(), false),
+                None => (name.trim(), true),
+            };
+
+            let mut matched = false;
+            for (index, table) in available.iter().enumerate() {
+                if glob_match(pattern, table) {
+                    selected[index] = include;
+                    matched = true;
+                }
+            }
+
+            if !matched {
+                warnings.push(format!("Pattern '{}' does not match any table", name));
+            }
+        }
+
+        let tables = available
+            .iter()
+            .zip(selected)
+            .filter(|(_, selected)| *selected)
+            .map(|(table, _)| table.clone())
+            .collect();
+
+        TableSelection { tables, warnings }
+    }
+
+    pub fn resolve_against(&self, schema: &SchemaDocument) -> TableSelection {
+        self.resolve(&schema.table_names())
+    }
+}
+
+// Matches `*` (any run of characters) and `?` (any single character)
+pub fn glob_match(pattern: &str, value: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let value: Vec<char> = value.chars().collect();
+
+    let (mut p, mut v) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while v < value.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == value[v]) {
+            p += 1;
+            v += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, v));
+            p += 1;
+        } else if let Some((star_p, star_v)) = backtrack {
+            // Let the last `*` swallow one more character and retry
+            p = star_p + 1;
+            v = star_v + 1;
+            backtrack = Some((star_p, star_v + 1));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '*')
+}
@@ -0,0 +1,276 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::{
+    env,
+    error::Error,
+    fs::{self, OpenOptions},
+    future::Future,
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
+    time::Instant,
+};
+use uuid::Uuid;
+
+use crate::{config_io::write_atomic, LANG};
+
+/// `0`/`false` turns reporting off whatever the stored consent, as does `DO_NOT_TRACK=1`.
+pub const TELEMETRY_ENV: &str = "GINGER_TELEMETRY";
+/// Overrides the endpoint events are sent to.
+pub const TELEMETRY_ENDPOINT_ENV: &str = "GINGER_TELEMETRY_ENDPOINT";
+// Key of the consent in the credentials profile (`~/.ginger-society/auth.json`)
+const CONSENT_KEY: &str = "TELEMETRY";
+const SPOOL_FILE: &str = "telemetry.jsonl";
+const DEFAULT_BATCH_SIZE: usize = 20;
+
+fn ginger_dir() -> Option<PathBuf> {
+    dirs::home_dir().map(|home| home.join(".ginger-society"))
+}
+
+// An empty profile when there is none yet. One that cannot be read fails, so that writing the
+// consent never replaces the API token it holds.
+fn read_profile(path: &Path) -> Result<Map<String, Value>, Box<dyn Error>> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Map::new()),
+        Err(e) => return Err(format!("Failed to read '{}': {}", path.display(), e).into()),
+    };
+    serde_json::from_str(&contents)
+        .map_err(|e| format!("Failed to parse '{}': {}", path.display(), e).into())
+}
+
+/// The choice stored in the credentials profile, `None` when the user was never asked or the
+/// profile cannot be read.
+pub fn consent() -> Option<bool> {
+    let profile = read_profile(&ginger_dir()?.join("auth.json")).ok()?;
+    profile.get(CONSENT_KEY).and_then(Value::as_bool)
+}
+
+/// Stores the choice next to the API token, keeping the rest of the profile as is. Opting out
+/// also deletes the events not sent yet.
+pub fn set_consent(enabled: bool) -> Result<(), Box<dyn Error>> {
+    let dir = ginger_dir().ok_or("Failed to locate the home directory")?;
+    fs::create_dir_all(&dir)?;
+    let path = dir.join("auth.json");
+    let mut profile = read_profile(&path)?;
+    profile.insert(CONSENT_KEY.to_string(), Value::Bool(enabled));
+    write_atomic(&path, &serde_json::to_string_pretty(&profile)?, false)?;
+    if !enabled {
+        let spool = dir.join(SPOOL_FILE);
+        for path in [sending_path(&spool), spool] {
+            match fs::remove_file(&path) {
+                Err(e) if e.kind() != ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            }
+        }
+    }
+    Ok(())
+}
+
+// Where `flush` moves the spool while sending it, so that events recorded meanwhile go to a new
+// spool instead of being deleted with the sent ones
+fn sending_path(spool: &Path) -> PathBuf {
+    let mut name = spool.file_name().unwrap_or_default().to_os_string();
+    name.push(".sending");
+    spool.with_file_name(name)
+}
+
+fn read_events(path: &Path) -> Vec<UsageEvent> {
+    fs::read_to_string(path)
+        .map(|contents| {
+            contents
+                .lines()
+                .filter_map(|line| serde_json::from_str(line).ok())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn disabled_by_env() -> bool {
+    let off = |var: &str, values: &[&str]| {
+        env::var(var)
+            .map(|value| values.contains(&value.trim().to_lowercase().as_str()))
+            .unwrap_or(false)
+    };
+    off(TELEMETRY_ENV, &["0", "false", "off", "no"]) || off("DO_NOT_TRACK", &["1", "true"])
+}
+
+/// Whether events are recorded: only after an explicit opt-in, and never when turned off by
+/// the environment.
+pub fn is_enabled() -> bool {
+    !disabled_by_env() && consent() == Some(true)
+}
+
+// What is reported about one command run. Nothing identifies the user, the project or its files.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct UsageEvent {
+    pub id: Uuid,
+    pub tool: String,    // e.g. `ginger-db`
+    pub command: String, // Subcommand path, e.g. `generate models`, never its arguments
+    pub version: String,
+    pub lang: Option<LANG>,
+    pub success: bool,
+    pub duration_ms: u64,
+    pub os: String,
+    pub occurred_at: DateTime<Utc>,
+}
+
+// Records usage events of a CLI into a local spool and sends them in batches
+pub struct TelemetryReporter {
+    tool: String,
+    version: String,
+    endpoint: String,
+    enabled: bool,
+    spool: Option<PathBuf>,
+    batch_size: usize,
+}
+
+impl TelemetryReporter {
+    pub fn new(tool: &str, version: &str, endpoint: &str) -> Self {
+        TelemetryReporter {
+            tool: tool.to_string(),
+            version: version.to_string(),
+            endpoint: env::var(TELEMETRY_ENDPOINT_ENV).unwrap_or_else(|_| endpoint.to_string()),
+            enabled: is_enabled(),
+            spool: ginger_dir().map(|dir| dir.join(SPOOL_FILE)),
+            batch_size: DEFAULT_BATCH_SIZE,
+        }
+    }
+
+    pub fn batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    pub fn spool_path<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.spool = Some(path.into());
+        self
+    }
+
+    /// Overrides the consent, e.g. with a `--no-telemetry` flag.
+    pub fn enabled(mut self, enabled: bool) -> Self {
+        self.enabled = enabled;
+        self
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Where batches are sent, `GINGER_TELEMETRY_ENDPOINT` taking precedence.
+    pub fn endpoint(&self) -> &str {
+        &self.endpoint
+    }
+
+    pub fn event(
+        &self,
+        command: &str,
+        lang: Option<LANG>,
+        success: bool,
+        duration_ms: u64,
+    ) -> UsageEvent {
+        UsageEvent {
+            id: Uuid::new_v4(),
+            tool: self.tool.clone(),
+            command: command.to_string(),
+            version: self.version.clone(),
+            lang,
+            success,
+            duration_ms,
+            os: env::consts::OS.to_string(),
+            occurred_at: Utc::now(),
+        }
+    }
+
+    /// Appends `event` to the spool, a no-op unless reporting is enabled. Telemetry never fails
+    /// the command, errors are only logged.
+    pub fn record(&self, event: &UsageEvent) {
+        if !self.enabled {
+            return;
+        }
+        let Some(spool) = &self.spool else { return };
+        let result = (|| -> Result<(), Box<dyn Error>> {
+            if let Some(parent) = spool.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            let mut file = OpenOptions::new().create(true).append(true).open(spool)?;
+            writeln!(file, "{}", serde_json::to_string(event)?)?;
+            Ok(())
+        })();
+        if let Err(e) = result {
+            tracing::debug!(error = %e, "failed to record a usage event");
+        }
+    }
+
+    /// Runs `command`, then records its name, duration and outcome.
+    pub async fn track<T, E, F>(&self, command: &str, lang: Option<LANG>, run: F) -> Result<T, E>
+    where
+        F: Future<Output = Result<T, E>>,
+    {
+        let started = Instant::now();
+        let result = run.await;
+        let duration_ms = started.elapsed().as_millis() as u64;
+        self.record(&self.event(command, lang, result.is_ok(), duration_ms));
+        result
+    }
+
+    /// The events waiting in the spool, including those of a flush that failed.
+    pub fn pending(&self) -> Vec<UsageEvent> {
+        let Some(spool) = &self.spool else {
+            return vec![];
+        };
+        let mut events = read_events(&sending_path(spool));
+        events.extend(read_events(spool));
+        events
+    }
+
+    /// Sends the spooled events in one request and empties the spool, a no-op unless reporting
+    /// is enabled. Returns how many were sent. Events recorded while sending stay spooled for
+    /// the next flush.
+    #[cfg(feature = "client")]
+    pub async fn flush(&self) -> Result<usize, Box<dyn Error + Send + Sync>> {
+        let Some(spool) = self.spool.as_ref().filter(|_| self.enabled) else {
+            return Ok(0);
+        };
+        // The events of a failed flush are sent first, the spool waits for the next one
+        let sending = sending_path(spool);
+        if !sending.exists() {
+            match fs::rename(spool, &sending) {
+                Err(e) if e.kind() == ErrorKind::NotFound => return Ok(0),
+                result => result?,
+            }
+        }
+        let events = read_events(&sending);
+        if events.is_empty() {
+            fs::remove_file(&sending)?;
+            return Ok(0);
+        }
+        let response = reqwest::Client::new()
+            .post(&self.endpoint)
+            .json(&events)
+            .timeout(std::time::Duration::from_secs(2))
+            .send()
+            .await?;
+        if !response.status().is_success() {
+            return Err(format!(
+                "The telemetry endpoint '{}' answered {}",
+                self.endpoint,
+                response.status()
+            )
+            .into());
+        }
+        fs::remove_file(&sending)?;
+        Ok(events.len())
+    }
+
+    /// Flushes once a full batch is spooled, ignoring failures so the next run retries.
+    #[cfg(feature = "client")]
+    pub async fn flush_if_due(&self) {
+        if !self.enabled || self.pending().len() < self.batch_size {
+            return;
+        }
+        if let Err(e) = self.flush().await {
+            tracing::debug!(error = %e, "failed to send usage events");
+        }
+    }
+}
@@ -0,0 +1,313 @@
+use std::fmt;
+
+use crate::{
+    claims::{Claims, ISCClaims},
+    org::{OrgError, OrgId},
+};
+
+/// ISC scope letting a service read across organizations through `UnscopedAccess`.
+pub const UNSCOPED_SCOPE: &str = "tenancy:unscoped";
+
+// The organization a request acts for. Handlers taking it can only build queries filtered on
+// its `org_id`, see `TenantScope::scoped`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TenantScope {
+    org_id: OrgId,
+    actor: String,
+}
+
+impl TenantScope {
+    pub fn new(org_id: OrgId, actor: &str) -> Self {
+        TenantScope {
+            org_id,
+            actor: actor.to_string(),
+        }
+    }
+
+    pub fn from_isc_claims(claims: &ISCClaims) -> Result<Self, OrgError> {
+        Ok(Self::new(OrgId::parse(&claims.org_id)?, &claims.sub))
+    }
+
+    /// User tokens carry the organization as an extra `org_id` (or `organization_id`) claim,
+    /// `None` when they have none.
+    pub fn from_claims(claims: &Claims) -> Option<Result<Self, OrgError>> {
+        ["org_id", "organization_id"]
+            .iter()
+            .find_map(|claim| claims.extra.get(*claim).and_then(|org| org.as_str()))
+            .map(|org| Ok(Self::new(OrgId::parse(org)?, &claims.sub)))
+    }
+
+    pub fn org_id(&self) -> &OrgId {
+        &self.org_id
+    }
+
+    pub fn actor(&self) -> &str {
+        &self.actor
+    }
+
+    /// Whether `org_id`, e.g. taken from the path, is the organization of the scope.
+    pub fn allows(&self, org_id: &str) -> bool {
+        OrgId::parse(org_id).is_ok_and(|org_id| org_id == self.org_id)
+    }
+}
+
+// Opts a handler or a job out of tenant scoping, e.g. for cross-organization reports. It is only
+// obtained explicitly: as a request guard for ISC tokens holding `tenancy:unscoped`, or with
+// `UnscopedAccess::new` naming why, which is logged.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnscopedAccess {
+    reason: String,
+}
+
+impl UnscopedAccess {
+    pub fn new(reason: &str) -> Self {
+        tracing::warn!(reason, "unscoped data access granted");
+        UnscopedAccess {
+            reason: reason.to_string(),
+        }
+    }
+
+    pub fn reason(&self) -> &str {
+        &self.reason
+    }
+}
+
+impl fmt::Display for UnscopedAccess {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "unscoped access ({})", self.reason)
+    }
+}
+
+#[cfg(feature = "rocket")]
+pub use self::guards::TenantScopeError;
+
+#[cfg(feature = "rocket")]
+mod guards {
+    use okapi::openapi3::Responses;
+    use rocket::{
+        http::Status,
+        request::{FromRequest, Outcome, Request},
+    };
+    use rocket_okapi::{
+        gen::OpenApiGenerator,
+        request::{OpenApiFromRequest, RequestHeaderInput},
+    };
+
+    use super::{TenantScope, UnscopedAccess, UNSCOPED_SCOPE};
+    use crate::{
+        claims::{Claims, ISCClaims},
+        jwt::TokenErrorKind,
+        rocket_models::ApiError,
+        rocket_utils::{authenticate, record_actor, record_auth_failure},
+        validation::record_failure,
+    };
+
+    #[derive(Debug)]
+    pub enum TenantScopeError {
+        Unauthenticated,
+        NoOrganization, // Authenticated, but the token names no organization
+        InvalidOrganization,
+        Forbidden,
+    }
+
+    fn forbidden<T>(
+        request: &Request<'_>,
+        code: &str,
+        message: &str,
+        error: TenantScopeError,
+    ) -> Outcome<T, TenantScopeError> {
+        record_failure(request, ApiError::new(403, code, message));
+        Outcome::Error((Status::Forbidden, error))
+    }
+
+    fn unauthenticated<T>(
+        request: &Request<'_>,
+        kind: TokenErrorKind,
+    ) -> Outcome<T, TenantScopeError> {
+        record_auth_failure(request, kind);
+        Outcome::Error((Status::Unauthorized, TenantScopeError::Unauthenticated))
+    }
+
+    // Service tokens are tried first, then user tokens with an organization claim. Requests
+    // without a valid token are answered with a 401, tokens naming no organization with a 403.
+    // Tokens are decoded without the claims guards, so only the token that decided the scope
+    // is recorded as the actor, or the one that failed for the 401 catcher.
+    #[rocket::async_trait]
+    impl<'r> FromRequest<'r> for TenantScope {
+        type Error = TenantScopeError;
+
+        async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+            let isc = authenticate::<ISCClaims>(request, "X-ISC-Authorization").await;
+            let scope = match isc {
+                Ok(claims) => {
+                    record_actor(request, &claims.sub, Some(&claims.org_id));
+                    TenantScope::from_isc_claims(&claims)
+                }
+                Err(isc_error) => match authenticate::<Claims>(request, "Authorization").await {
+                    Ok(claims) => {
+                        record_actor(request, &claims.sub, None);
+                        match TenantScope::from_claims(&claims) {
+                            Some(scope) => scope,
+                            None => {
+                                return forbidden(
+                                    request,
+                                    "tenant_missing",
+                                    "The token is not issued for an organization",
+                                    TenantScopeError::NoOrganization,
+                                )
+                            }
+                        }
+                    }
+                    // The user token is the usual one, unless only a service token was sent
+                    Err(TokenErrorKind::Missing) => return unauthenticated(request, isc_error),
+                    Err(user_error) => return unauthenticated(request, user_error),
+                },
+            };
+
+            match scope {
+                Ok(scope) => Outcome::Success(scope),
+                Err(e) => forbidden(
+                    request,
+                    "tenant_invalid",
+                    &e.to_string(),
+                    TenantScopeError::InvalidOrganization,
+                ),
+            }
+        }
+    }
+
+    impl<'a> OpenApiFromRequest<'a> for TenantScope {
+        fn from_request_input(
+            gen: &mut OpenApiGenerator,
+            name: String,
+            required: bool,
+        ) -> rocket_okapi::Result<RequestHeaderInput> {
+            ISCClaims::from_request_input(gen, name, required)
+        }
+
+        fn get_responses(_gen: &mut OpenApiGenerator) -> rocket_okapi::Result<Responses> {
+            Ok(Responses::default())
+        }
+    }
+
+    #[rocket::async_trait]
+    impl<'r> FromRequest<'r> for UnscopedAccess {
+        type Error = TenantScopeError;
+
+        async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+            let claims = match authenticate::<ISCClaims>(request, "X-ISC-Authorization").await {
+                Ok(claims) => claims,
+                Err(kind) => return unauthenticated(request, kind),
+            };
+            record_actor(request, &claims.sub, Some(&claims.org_id));
+            if !claims.has_scope(UNSCOPED_SCOPE) {
+                return forbidden(
+                    request,
+                    "unscoped_access_denied",
+                    &format!(
+                        "Access across organizations requires the '{}' scope",
+                        UNSCOPED_SCOPE
+                    ),
+                    TenantScopeError::Forbidden,
+                );
+            }
+            let route = request
+                .route()
+                .map(|route| route.uri.to_string())
+                .unwrap_or_else(|| request.uri().path().to_string());
+            Outcome::Success(UnscopedAccess::new(&format!(
+                "{} {} by {}",
+                request.method(),
+                route,
+                claims.sub
+            )))
+        }
+    }
+
+    impl<'a> OpenApiFromRequest<'a> for UnscopedAccess {
+        fn from_request_input(
+            gen: &mut OpenApiGenerator,
+            name: String,
+            required: bool,
+        ) -> rocket_okapi::Result<RequestHeaderInput> {
+            ISCClaims::from_request_input(gen, name, required)
+        }
+
+        fn get_responses(_gen: &mut OpenApiGenerator) -> rocket_okapi::Result<Responses> {
+            Ok(Responses::default())
+        }
+    }
+}
+
+#[cfg(feature = "diesel")]
+pub use self::queries::TenantTable;
+
+#[cfg(feature = "diesel")]
+mod queries {
+    use diesel::{
+        dsl::{Eq, Filter},
+        expression::AsExpression,
+        query_dsl::methods::FilterDsl,
+        sql_types::{SingleValue, SqlType},
+        Column, Expression, ExpressionMethods, Table,
+    };
+
+    use super::{TenantScope, UnscopedAccess};
+
+    // A table whose rows belong to an organization, e.g.
+    // `impl TenantTable for projects::table { type OrgColumn = projects::org_id; }`
+    pub trait TenantTable: Table {
+        type OrgColumn: Column<Table = Self> + Default;
+    }
+
+    impl TenantScope {
+        /// `table` filtered on the organization of the scope, the starting point of selects,
+        /// updates and deletes: `diesel::delete(scope.scoped(projects::table))`.
+        pub fn scoped<T>(&self, table: T) -> Filter<T, Eq<T::OrgColumn, String>>
+        where
+            T: TenantTable + FilterDsl<Eq<T::OrgColumn, String>>,
+            <T::OrgColumn as Expression>::SqlType: SqlType + SingleValue,
+            String: AsExpression<<T::OrgColumn as Expression>::SqlType>,
+        {
+            table.filter(T::OrgColumn::default().eq(self.org_id.to_string()))
+        }
+    }
+
+    impl UnscopedAccess {
+        /// `table` with every organization's rows.
+        pub fn unscoped<T: TenantTable>(&self, table: T) -> T {
+            tracing::debug!(reason = %self.reason, "unscoped query");
+            table
+        }
+    }
+}
+
+#[cfg(feature = "mongodb")]
+mod collections {
+    use serde::{de::DeserializeOwned, Serialize};
+
+    use super::{TenantScope, UnscopedAccess};
+    use crate::documentdb::{MongoPool, ScopedCollection};
+
+    impl TenantScope {
+        /// The documents of `name` belonging to the organization of the scope.
+        pub fn collection<T>(&self, pool: &MongoPool, name: &str) -> ScopedCollection<T>
+        where
+            T: Serialize + DeserializeOwned + Send + Sync,
+        {
+            ScopedCollection::new(pool.collection(name), self.org_id.as_str())
+        }
+    }
+
+    impl UnscopedAccess {
+        /// The documents of `name` of every organization.
+        pub fn collection<T: Send + Sync>(
+            &self,
+            pool: &MongoPool,
+            name: &str,
+        ) -> mongodb::Collection<T> {
+            tracing::debug!(reason = %self.reason, collection = name, "unscoped collection");
+            pool.collection(name)
+        }
+    }
+}
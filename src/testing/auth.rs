@@ -0,0 +1,18 @@
+use serde::Serialize;
+
+use crate::jwt::JwtConfig;
+
+/// Signs `claims` (`Claims`, `APIClaims`, `ISCClaims` or any other serializable claims) as a
+/// HS256 token accepted by guards configured with the same `secret`.
+pub fn mint_token<T: Serialize>(claims: &T, secret: &str) -> String {
+    JwtConfig::new(secret)
+        .encode(claims)
+        .expect("Failed to sign the test token")
+}
+
+/// A Rocket whose auth guards validate tokens signed with `secret`, with the default catchers
+/// registered. Mount the routes under test and hand it to `rocket::local` clients.
+#[cfg(feature = "rocket")]
+pub fn local_rocket_with_auth(secret: &str) -> rocket::Rocket<rocket::Build> {
+    crate::rocket_errors::register_default_catchers(rocket::build().manage(JwtConfig::new(secret)))
+}
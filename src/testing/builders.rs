@@ -0,0 +1,441 @@
+use serde_json::Value;
+use std::{collections::HashMap, time::Duration};
+use url::Url;
+
+use crate::{
+    claims::{APIClaims, Claims, ISCClaims},
+    Channel, DatabaseConfig, DbEngine, DbType, Environment, FileType, GingerDBConfig, OutputType,
+    Reference, ReleaserConfig, ReleaserSettings, ServiceConfig, ServiceRef, Version, LANG,
+};
+
+use super::mint_token;
+
+/// Secret the claims builders sign their tokens with unless told otherwise.
+pub const TEST_JWT_SECRET: &str = "ginger-test-secret";
+pub const TEST_ORG_ID: &str = "test-org";
+
+const TEST_TOKEN_VALIDITY: Duration = Duration::from_secs(60 * 60);
+
+fn parse_test_url(url: &str) -> Url {
+    Url::parse(url).unwrap_or_else(|e| panic!("Invalid test URL '{}': {}", url, e))
+}
+
+pub struct ServiceConfigBuilder {
+    config: ServiceConfig,
+}
+
+impl Default for ServiceConfigBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ServiceConfigBuilder {
+    pub fn new() -> Self {
+        ServiceConfigBuilder {
+            config: ServiceConfig {
+                services: None,
+                portals_refs: None,
+                ws_refs: None,
+                lang: LANG::Rust,
+                organization_id: TEST_ORG_ID.to_string(),
+                dir: Some(".".to_string()),
+                refs_file: None,
+                spec_url: None,
+                urls: None,
+                urls_ws: None,
+                override_name: None,
+                service_type: None,
+                portal_config: None,
+            },
+        }
+    }
+
+    pub fn lang(mut self, lang: LANG) -> Self {
+        self.config.lang = lang;
+        self
+    }
+
+    pub fn organization_id(mut self, organization_id: &str) -> Self {
+        self.config.organization_id = organization_id.to_string();
+        self
+    }
+
+    pub fn name(mut self, name: &str) -> Self {
+        self.config.override_name = Some(name.to_string());
+        self
+    }
+
+    pub fn spec_url(mut self, url: &str) -> Self {
+        self.config.spec_url = Some(url.to_string());
+        self
+    }
+
+    /// Sets the service's own URL for `env`.
+    pub fn url(mut self, env: Environment, url: &str) -> Self {
+        self.config
+            .urls
+            .get_or_insert_with(HashMap::new)
+            .insert(env.to_string(), url.to_string());
+        self
+    }
+
+    /// Adds a dependency reachable at `url` in `env`. Panics on invalid URLs.
+    pub fn service(mut self, name: &str, env: Environment, url: &str) -> Self {
+        self.service_ref(name)
+            .env_urls
+            .insert(env, parse_test_url(url));
+        self
+    }
+
+    pub fn service_schema_url(mut self, name: &str, url: &str) -> Self {
+        self.service_ref(name).schema_url = Some(parse_test_url(url));
+        self
+    }
+
+    pub fn service_version_pin(mut self, name: &str, version: &str) -> Self {
+        self.service_ref(name).version_pin = Some(version.to_string());
+        self
+    }
+
+    fn service_ref(&mut self, name: &str) -> &mut ServiceRef {
+        self.config
+            .services
+            .get_or_insert_with(HashMap::new)
+            .entry(name.to_string())
+            .or_insert_with(|| ServiceRef::new(name))
+    }
+
+    pub fn build(self) -> ServiceConfig {
+        self.config
+    }
+}
+
+pub struct GingerDBConfigBuilder {
+    config: GingerDBConfig,
+}
+
+impl Default for GingerDBConfigBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl GingerDBConfigBuilder {
+    pub fn new() -> Self {
+        GingerDBConfigBuilder {
+            config: GingerDBConfig {
+                branch: "main".to_string(),
+                organization_id: TEST_ORG_ID.to_string(),
+                database: vec![],
+            },
+        }
+    }
+
+    pub fn branch(mut self, branch: &str) -> Self {
+        self.config.branch = branch.to_string();
+        self
+    }
+
+    pub fn organization_id(mut self, organization_id: &str) -> Self {
+        self.config.organization_id = organization_id.to_string();
+        self
+    }
+
+    /// Adds an enabled database with the default engine of `db_type`.
+    pub fn database(mut self, db_type: DbType, name: &str, port: u16) -> Self {
+        self.config.database.push(DatabaseConfig {
+            db_type,
+            description: format!("{} test database", name),
+            enable: true,
+            id: None,
+            name: name.to_string(),
+            port: port.to_string(),
+            studio_port: None,
+            engine: None,
+            links: vec![],
+        });
+        self
+    }
+
+    pub fn rdbms(self, name: &str, port: u16) -> Self {
+        self.database(DbType::Rdbms, name, port)
+    }
+
+    pub fn with_database(mut self, database: DatabaseConfig) -> Self {
+        self.config.database.push(database);
+        self
+    }
+
+    /// Applies `f` to the last added database, e.g. to set an engine or disable it.
+    pub fn configure_last<F: FnOnce(&mut DatabaseConfig)>(mut self, f: F) -> Self {
+        if let Some(database) = self.config.database.last_mut() {
+            f(database);
+        }
+        self
+    }
+
+    pub fn engine(self, engine: DbEngine) -> Self {
+        self.configure_last(|database| database.engine = Some(engine))
+    }
+
+    pub fn disabled(self) -> Self {
+        self.configure_last(|database| database.enable = false)
+    }
+
+    pub fn build(self) -> GingerDBConfig {
+        self.config
+    }
+}
+
+pub struct ReleaserConfigBuilder {
+    config: ReleaserConfig,
+}
+
+impl Default for ReleaserConfigBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ReleaserConfigBuilder {
+    pub fn new() -> Self {
+        ReleaserConfigBuilder {
+            config: ReleaserConfig {
+                settings: ReleaserSettings {
+                    git_url_prefix: None,
+                    take_snapshots: false,
+                },
+                version: Version::from_str("0.1.0"),
+                references: vec![],
+            },
+        }
+    }
+
+    /// Sets the version from its formatted form, e.g. `1.2.3` or `1.2.3-nightly.4`.
+    pub fn version(mut self, version: &str) -> Self {
+        self.config.version = Version::from_str(version);
+        self
+    }
+
+    pub fn channel(mut self, channel: Channel) -> Self {
+        self.config.version.channel = channel;
+        self
+    }
+
+    pub fn git_url_prefix(mut self, prefix: &str) -> Self {
+        self.config.settings.git_url_prefix = Some(prefix.to_string());
+        self
+    }
+
+    pub fn take_snapshots(mut self, take_snapshots: bool) -> Self {
+        self.config.settings.take_snapshots = take_snapshots;
+        self
+    }
+
+    pub fn reference(mut self, file_name: &str, variable: &str, output_type: OutputType) -> Self {
+        let extension = std::path::Path::new(file_name)
+            .extension()
+            .and_then(|ext| ext.to_str());
+        self.config.references.push(Reference {
+            file_name: file_name.to_string(),
+            output_type,
+            variable: variable.to_string(),
+            file_type: FileType::from_extension(extension),
+        });
+        self
+    }
+
+    pub fn build(self) -> ReleaserConfig {
+        self.config
+    }
+}
+
+pub struct ClaimsBuilder {
+    claims: Claims,
+}
+
+impl Default for ClaimsBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ClaimsBuilder {
+    /// An access token of `test-user@example.com` valid for an hour.
+    pub fn new() -> Self {
+        ClaimsBuilder {
+            claims: Claims::new("test-user@example.com", "1", "access", TEST_TOKEN_VALIDITY),
+        }
+    }
+
+    pub fn subject(mut self, sub: &str) -> Self {
+        self.claims.sub = sub.to_string();
+        self
+    }
+
+    pub fn user_id(mut self, user_id: &str) -> Self {
+        self.claims.user_id = user_id.to_string();
+        self
+    }
+
+    pub fn refresh(mut self) -> Self {
+        self.claims.token_type = "refresh".to_string();
+        self
+    }
+
+    pub fn client_id(mut self, client_id: &str) -> Self {
+        self.claims = self.claims.with_client_id(client_id);
+        self
+    }
+
+    pub fn valid_for(mut self, valid_for: Duration) -> Self {
+        self.claims.exp = Claims::new("", "", "", valid_for).exp;
+        self
+    }
+
+    /// Sets `exp` in the past so the token is rejected as expired.
+    pub fn expired(mut self) -> Self {
+        self.claims.exp = 1;
+        self
+    }
+
+    pub fn extra(mut self, key: &str, value: Value) -> Self {
+        self.claims = self.claims.with_extra(key, value);
+        self
+    }
+
+    pub fn build(self) -> Claims {
+        self.claims
+    }
+
+    pub fn token(&self) -> String {
+        self.token_signed_with(TEST_JWT_SECRET)
+    }
+
+    pub fn token_signed_with(&self, secret: &str) -> String {
+        mint_token(&self.claims, secret)
+    }
+}
+
+pub struct APIClaimsBuilder {
+    claims: APIClaims,
+}
+
+impl Default for APIClaimsBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl APIClaimsBuilder {
+    /// A token of the `test-api-client` in group 1, without scopes, valid for an hour.
+    pub fn new() -> Self {
+        APIClaimsBuilder {
+            claims: APIClaims::new("test-api-client", 1, TEST_TOKEN_VALIDITY),
+        }
+    }
+
+    pub fn subject(mut self, sub: &str) -> Self {
+        self.claims.sub = sub.to_string();
+        self
+    }
+
+    pub fn group_id(mut self, group_id: i64) -> Self {
+        self.claims.group_id = group_id;
+        self
+    }
+
+    pub fn scopes(mut self, scopes: &[&str]) -> Self {
+        self.claims = self.claims.with_scopes(scopes);
+        self
+    }
+
+    pub fn valid_for(mut self, valid_for: Duration) -> Self {
+        self.claims.exp = APIClaims::new("", 0, valid_for).exp;
+        self
+    }
+
+    pub fn expired(mut self) -> Self {
+        self.claims.exp = 1;
+        self
+    }
+
+    pub fn extra(mut self, key: &str, value: Value) -> Self {
+        self.claims = self.claims.with_extra(key, value);
+        self
+    }
+
+    pub fn build(self) -> APIClaims {
+        self.claims
+    }
+
+    pub fn token(&self) -> String {
+        self.token_signed_with(TEST_JWT_SECRET)
+    }
+
+    pub fn token_signed_with(&self, secret: &str) -> String {
+        mint_token(&self.claims, secret)
+    }
+}
+
+pub struct ISCClaimsBuilder {
+    claims: ISCClaims,
+}
+
+impl Default for ISCClaimsBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ISCClaimsBuilder {
+    /// A token of the `test-service` in the test org, without scopes, valid for an hour.
+    pub fn new() -> Self {
+        ISCClaimsBuilder {
+            claims: ISCClaims::new("test-service", TEST_ORG_ID, TEST_TOKEN_VALIDITY),
+        }
+    }
+
+    pub fn subject(mut self, sub: &str) -> Self {
+        self.claims.sub = sub.to_string();
+        self
+    }
+
+    pub fn org_id(mut self, org_id: &str) -> Self {
+        self.claims.org_id = org_id.to_string();
+        self
+    }
+
+    pub fn scopes(mut self, scopes: &[&str]) -> Self {
+        self.claims = self.claims.with_scopes(scopes);
+        self
+    }
+
+    pub fn valid_for(mut self, valid_for: Duration) -> Self {
+        self.claims.exp = ISCClaims::new("", "", valid_for).exp;
+        self
+    }
+
+    pub fn expired(mut self) -> Self {
+        self.claims.exp = 1;
+        self
+    }
+
+    pub fn extra(mut self, key: &str, value: Value) -> Self {
+        self.claims = self.claims.with_extra(key, value);
+        self
+    }
+
+    pub fn build(self) -> ISCClaims {
+        self.claims
+    }
+
+    pub fn token(&self) -> String {
+        self.token_signed_with(TEST_JWT_SECRET)
+    }
+
+    pub fn token_signed_with(&self, secret: &str) -> String {
+        mint_token(&self.claims, secret)
+    }
+}
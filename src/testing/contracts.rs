@@ -0,0 +1,432 @@
+use schemars::{schema_for, JsonSchema};
+use serde_json::Value;
+use std::fmt;
+
+#[cfg(feature = "client")]
+use crate::{
+    spec::{fetch_spec, SpecCache},
+    ServiceConfig,
+};
+
+// Deep enough for any real schema, stops recursive `$ref`s from looping forever
+const MAX_SCHEMA_DEPTH: usize = 32;
+
+// A call this service makes to one of its dependencies, with the types it sends and expects
+// back. The types are described with their `schemars` JSON Schema.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Contract {
+    pub service: String,
+    pub method: String,
+    pub path: String,
+    pub request: Option<Value>,
+    pub response: Option<Value>,
+    pub status: Option<u16>,
+}
+
+impl Contract {
+    /// `path` is the path template as written in the dependency's spec, e.g. `/users/{id}`.
+    pub fn new(service: &str, method: &str, path: &str) -> Self {
+        Contract {
+            service: service.to_string(),
+            method: method.to_uppercase(),
+            path: path.to_string(),
+            request: None,
+            response: None,
+            status: None,
+        }
+    }
+
+    pub fn request<T: JsonSchema>(mut self) -> Self {
+        self.request = serde_json::to_value(schema_for!(T)).ok();
+        self
+    }
+
+    pub fn response<T: JsonSchema>(mut self) -> Self {
+        self.response = serde_json::to_value(schema_for!(T)).ok();
+        self
+    }
+
+    /// Response status the `response` type is checked against, the first 2xx by default.
+    pub fn status(mut self, status: u16) -> Self {
+        self.status = Some(status);
+        self
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Contracts {
+    pub contracts: Vec<Contract>,
+}
+
+impl Contracts {
+    pub fn new() -> Self {
+        Contracts { contracts: vec![] }
+    }
+
+    pub fn expect(mut self, contract: Contract) -> Self {
+        self.contracts.push(contract);
+        self
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ContractViolation {
+    pub service: String,
+    pub method: String,
+    pub path: String,
+    pub message: String,
+}
+
+impl fmt::Display for ContractViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {}: {}",
+            self.service, self.method, self.path, self.message
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ContractReport {
+    pub checked: usize,
+    pub violations: Vec<ContractViolation>,
+}
+
+impl ContractReport {
+    pub fn is_ok(&self) -> bool {
+        self.violations.is_empty()
+    }
+
+    /// Panics with every violation, for use in tests.
+    pub fn assert_ok(&self) {
+        if !self.is_ok() {
+            panic!("{}", self);
+        }
+    }
+}
+
+impl fmt::Display for ContractReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_ok() {
+            return write!(f, "{} contracts verified", self.checked);
+        }
+        writeln!(
+            f,
+            "{} of {} contracts are violated:",
+            self.violations.len(),
+            self.checked
+        )?;
+        for violation in &self.violations {
+            writeln!(f, "  - {}", violation)?;
+        }
+        Ok(())
+    }
+}
+
+/// Fetches the spec of every dependency used by `contracts` (from the `schema_url` of its
+/// `ServiceRef`) and checks that the registered request and response types still match it.
+#[cfg(feature = "client")]
+pub async fn verify_contracts(config: &ServiceConfig, contracts: &Contracts) -> ContractReport {
+    verify_contracts_with_cache(config, contracts, &SpecCache::in_project(".")).await
+}
+
+#[cfg(feature = "client")]
+pub async fn verify_contracts_with_cache(
+    config: &ServiceConfig,
+    contracts: &Contracts,
+    cache: &SpecCache,
+) -> ContractReport {
+    let mut report = ContractReport::default();
+    let mut specs: std::collections::HashMap<String, Result<Value, String>> = Default::default();
+
+    for contract in &contracts.contracts {
+        report.checked += 1;
+        let violation = |message: String| ContractViolation {
+            service: contract.service.clone(),
+            method: contract.method.clone(),
+            path: contract.path.clone(),
+            message,
+        };
+
+        if !specs.contains_key(&contract.service) {
+            let spec = match config.service(&contract.service) {
+                None => Err("the service is not declared in the service config".to_string()),
+                Some(service) => match &service.schema_url {
+                    None => Err("the service has no schema_url".to_string()),
+                    Some(url) => fetch_spec(url.as_str(), cache)
+                        .await
+                        .map(|spec| spec.document)
+                        .map_err(|e| e.to_string()),
+                },
+            };
+            specs.insert(contract.service.clone(), spec);
+        }
+
+        match &specs[&contract.service] {
+            Ok(document) => {
+                for message in check_contract(document, contract) {
+                    report.violations.push(violation(message));
+                }
+            }
+            Err(e) => report.violations.push(violation(e.clone())),
+        }
+    }
+
+    report
+}
+
+/// Checks a contract against an OpenAPI document and returns the problems found.
+pub fn check_contract(document: &Value, contract: &Contract) -> Vec<String> {
+    let mut problems = vec![];
+    let operation = match document["paths"][&contract.path].get(contract.method.to_lowercase()) {
+        Some(operation) => operation,
+        None => return vec!["the operation no longer exists".to_string()],
+    };
+
+    if let Some(request) = &contract.request {
+        let body = resolve(document, &operation["requestBody"]);
+        let schema = json_schema_of(document, body);
+        if schema.is_null() {
+            problems.push("the operation no longer takes a request body".to_string());
+        } else {
+            // Everything the dependency requires has to be sent
+            check_request(
+                request,
+                request,
+                document,
+                schema,
+                "request",
+                0,
+                &mut problems,
+            );
+        }
+    }
+
+    if let Some(response) = &contract.response {
+        let responses = &operation["responses"];
+        let status = match contract.status {
+            Some(status) => Some(status.to_string()),
+            None => responses.as_object().and_then(|responses| {
+                let mut statuses: Vec<&String> = responses.keys().collect();
+                statuses.sort();
+                statuses
+                    .into_iter()
+                    .find(|status| status.starts_with('2'))
+                    .cloned()
+            }),
+        };
+        match status.as_ref().and_then(|status| responses.get(status)) {
+            Some(spec_response) => {
+                let schema = json_schema_of(document, resolve(document, spec_response));
+                // Everything this service reads has to be returned
+                check_response(
+                    response,
+                    response,
+                    document,
+                    schema,
+                    "response",
+                    0,
+                    &mut problems,
+                );
+            }
+            None => problems.push(format!(
+                "the operation has no {} response",
+                status.unwrap_or_else(|| "2xx".to_string())
+            )),
+        }
+    }
+
+    problems
+}
+
+fn resolve<'d>(document: &'d Value, value: &'d Value) -> &'d Value {
+    let mut value = value;
+    for _ in 0..MAX_SCHEMA_DEPTH {
+        match value.get("$ref").and_then(Value::as_str) {
+            Some(reference) => {
+                value = match document.pointer(reference.trim_start_matches('#')) {
+                    Some(target) => target,
+                    None => return value,
+                }
+            }
+            None => return value,
+        }
+    }
+    value
+}
+
+fn json_schema_of<'d>(document: &'d Value, body: &'d Value) -> &'d Value {
+    let content = &body["content"];
+    let media = content
+        .get("application/json")
+        .or_else(|| content.as_object().and_then(|c| c.values().next()))
+        .unwrap_or(&Value::Null);
+    resolve(document, &media["schema"])
+}
+
+/// The JSON type of a schema, ignoring `null` in type lists (`Option<T>` in schemars).
+fn schema_type(schema: &Value) -> Option<&str> {
+    match &schema["type"] {
+        Value::String(t) => Some(t),
+        Value::Array(types) => types
+            .iter()
+            .filter_map(Value::as_str)
+            .find(|t| *t != "null"),
+        _ => {
+            if schema.get("properties").is_some() {
+                Some("object")
+            } else if schema.get("items").is_some() {
+                Some("array")
+            } else {
+                None
+            }
+        }
+    }
+}
+
+fn types_compatible(ours: Option<&str>, theirs: Option<&str>) -> bool {
+    match (ours, theirs) {
+        (Some(ours), Some(theirs)) => {
+            ours == theirs
+                || (ours == "number" && theirs == "integer")
+                || (ours == "integer" && theirs == "number")
+        }
+        _ => true,
+    }
+}
+
+fn required(schema: &Value) -> Vec<&str> {
+    schema["required"]
+        .as_array()
+        .map(|names| names.iter().filter_map(Value::as_str).collect())
+        .unwrap_or_default()
+}
+
+fn check_response(
+    our_doc: &Value,
+    ours: &Value,
+    spec_doc: &Value,
+    theirs: &Value,
+    location: &str,
+    depth: usize,
+    problems: &mut Vec<String>,
+) {
+    if depth > MAX_SCHEMA_DEPTH {
+        return;
+    }
+    let ours = resolve(our_doc, ours);
+    let theirs = resolve(spec_doc, theirs);
+    if theirs.is_null() {
+        problems.push(format!("`{}` is no longer described by the spec", location));
+        return;
+    }
+
+    let (our_type, their_type) = (schema_type(ours), schema_type(theirs));
+    if !types_compatible(our_type, their_type) {
+        problems.push(format!(
+            "`{}` is expected to be {} but the spec says {}",
+            location,
+            our_type.unwrap_or("any"),
+            their_type.unwrap_or("any")
+        ));
+        return;
+    }
+
+    match our_type {
+        Some("object") => {
+            let our_required = required(ours);
+            for (name, our_property) in ours["properties"].as_object().into_iter().flatten() {
+                let property_location = format!("{}.{}", location, name);
+                match theirs["properties"].get(name) {
+                    Some(their_property) => check_response(
+                        our_doc,
+                        our_property,
+                        spec_doc,
+                        their_property,
+                        &property_location,
+                        depth + 1,
+                        problems,
+                    ),
+                    None if our_required.contains(&name.as_str()) => problems.push(format!(
+                        "`{}` is required but no longer returned",
+                        property_location
+                    )),
+                    None => {}
+                }
+            }
+        }
+        Some("array") => check_response(
+            our_doc,
+            &ours["items"],
+            spec_doc,
+            &theirs["items"],
+            &format!("{}[]", location),
+            depth + 1,
+            problems,
+        ),
+        _ => {}
+    }
+}
+
+fn check_request(
+    our_doc: &Value,
+    ours: &Value,
+    spec_doc: &Value,
+    theirs: &Value,
+    location: &str,
+    depth: usize,
+    problems: &mut Vec<String>,
+) {
+    if depth > MAX_SCHEMA_DEPTH {
+        return;
+    }
+    let ours = resolve(our_doc, ours);
+    let theirs = resolve(spec_doc, theirs);
+
+    let (our_type, their_type) = (schema_type(ours), schema_type(theirs));
+    if !types_compatible(our_type, their_type) {
+        problems.push(format!(
+            "`{}` is sent as {} but the spec expects {}",
+            location,
+            our_type.unwrap_or("any"),
+            their_type.unwrap_or("any")
+        ));
+        return;
+    }
+
+    match their_type {
+        Some("object") => {
+            let their_required = required(theirs);
+            for (name, their_property) in theirs["properties"].as_object().into_iter().flatten() {
+                let property_location = format!("{}.{}", location, name);
+                match ours["properties"].get(name) {
+                    Some(our_property) => check_request(
+                        our_doc,
+                        our_property,
+                        spec_doc,
+                        their_property,
+                        &property_location,
+                        depth + 1,
+                        problems,
+                    ),
+                    None if their_required.contains(&name.as_str()) => problems.push(format!(
+                        "`{}` is required by the spec but not sent",
+                        property_location
+                    )),
+                    None => {}
+                }
+            }
+        }
+        Some("array") => check_request(
+            our_doc,
+            &ours["items"],
+            spec_doc,
+            &theirs["items"],
+            &format!("{}[]", location),
+            depth + 1,
+            problems,
+        ),
+        _ => {}
+    }
+}
@@ -0,0 +1,7 @@
+mod auth;
+mod builders;
+mod contracts;
+
+pub use auth::*;
+pub use builders::*;
+pub use contracts::*;
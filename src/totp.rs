@@ -0,0 +1,313 @@
+use data_encoding::BASE32_NOPAD;
+use hmac::{Hmac, Mac};
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use rand::{seq::SliceRandom, Rng};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use std::{
+    error::Error,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Header carrying the current code for step-up authentication on sensitive endpoints.
+pub const TOTP_HEADER: &str = "X-TOTP-Code";
+// 160 bits, as recommended by RFC 4226
+const SECRET_LENGTH: usize = 20;
+// Recovery codes leave out characters that are easily confused, e.g. `0` and `o`
+const RECOVERY_ALPHABET: &[u8] = b"abcdefghjkmnpqrstuvwxyz23456789";
+
+// Authenticator apps only agree on HMAC-SHA1, which is still sound as a MAC
+fn hmac_sha1(key: &[u8], message: &[u8]) -> [u8; 20] {
+    let mut mac = Hmac::<Sha1>::new_from_slice(key).expect("HMAC accepts any key length");
+    mac.update(message);
+    mac.finalize().into_bytes().into()
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+// RFC 6238 time based one-time passwords, as shown by authenticator apps. The secret is stored
+// base32 encoded (`to_base32`), and should be encrypted at rest like any credential.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Totp {
+    secret: Vec<u8>,
+    digits: u32,
+    period: u64,
+    skew: u64, // Steps accepted before and after the current one, for clock drift
+}
+
+impl Totp {
+    pub fn new(secret: Vec<u8>) -> Self {
+        Totp {
+            secret,
+            digits: 6,
+            period: 30,
+            skew: 1,
+        }
+    }
+
+    /// A new random secret, to enroll a user.
+    pub fn generate() -> Self {
+        let mut secret = vec![0u8; SECRET_LENGTH];
+        rand::thread_rng().fill(&mut secret[..]);
+        Self::new(secret)
+    }
+
+    pub fn from_base32(secret: &str) -> Result<Self, Box<dyn Error>> {
+        let normalized: String = secret
+            .chars()
+            .filter(|c| !c.is_whitespace() && *c != '=')
+            .collect::<String>()
+            .to_uppercase();
+        let secret = BASE32_NOPAD
+            .decode(normalized.as_bytes())
+            .map_err(|e| format!("Invalid TOTP secret: {}", e))?;
+        Ok(Self::new(secret))
+    }
+
+    pub fn to_base32(&self) -> String {
+        BASE32_NOPAD.encode(&self.secret)
+    }
+
+    pub fn digits(mut self, digits: u32) -> Self {
+        self.digits = digits.clamp(6, 8);
+        self
+    }
+
+    pub fn period(mut self, period: Duration) -> Self {
+        self.period = period.as_secs().max(1);
+        self
+    }
+
+    pub fn skew(mut self, steps: u64) -> Self {
+        self.skew = steps;
+        self
+    }
+
+    pub fn step_at(&self, unix_time: u64) -> u64 {
+        unix_time / self.period
+    }
+
+    fn code_for_step(&self, step: u64) -> String {
+        let hash = hmac_sha1(&self.secret, &step.to_be_bytes());
+        let offset = (hash[19] & 0x0f) as usize;
+        let binary = u32::from_be_bytes([
+            hash[offset] & 0x7f,
+            hash[offset + 1],
+            hash[offset + 2],
+            hash[offset + 3],
+        ]);
+        format!(
+            "{:0width$}",
+            binary % 10u32.pow(self.digits),
+            width = self.digits as usize
+        )
+    }
+
+    pub fn code_at(&self, unix_time: u64) -> String {
+        self.code_for_step(self.step_at(unix_time))
+    }
+
+    pub fn current_code(&self) -> String {
+        self.code_at(unix_time())
+    }
+
+    /// The time step `code` is valid for at `unix_time`, within the skew. Steps up to
+    /// `last_step`, the one of the previously accepted code, are refused so a code cannot be
+    /// replayed.
+    pub fn verify_at(&self, code: &str, unix_time: u64, last_step: Option<u64>) -> Option<u64> {
+        let code: String = code.chars().filter(|c| !c.is_whitespace()).collect();
+        if code.len() != self.digits as usize || !code.chars().all(|c| c.is_ascii_digit()) {
+            return None;
+        }
+        let current = self.step_at(unix_time);
+        let mut matched = None;
+        // Every step of the window is computed, so the timing does not tell which one matched
+        for step in current.saturating_sub(self.skew)..=current.saturating_add(self.skew) {
+            if constant_time_eq(self.code_for_step(step).as_bytes(), code.as_bytes())
+                && last_step.is_none_or(|last| step > last)
+            {
+                matched = Some(step);
+            }
+        }
+        matched
+    }
+
+    pub fn verify(&self, code: &str, last_step: Option<u64>) -> Option<u64> {
+        self.verify_at(code, unix_time(), last_step)
+    }
+
+    /// `otpauth://` URI to show as a QR code, e.g. `provisioning_uri("Ginger Society",
+    /// "ada@example.com")`.
+    pub fn provisioning_uri(&self, issuer: &str, account: &str) -> String {
+        let issuer = utf8_percent_encode(issuer, NON_ALPHANUMERIC).to_string();
+        let account = utf8_percent_encode(account, NON_ALPHANUMERIC).to_string();
+        format!(
+            "otpauth://totp/{}:{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+            issuer,
+            account,
+            self.to_base32(),
+            issuer,
+            self.digits,
+            self.period
+        )
+    }
+}
+
+fn normalize_recovery_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .collect::<String>()
+        .to_lowercase()
+}
+
+fn hash_recovery_code(code: &str) -> String {
+    Sha256::digest(normalize_recovery_code(code).as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+// Single use codes letting a user in without their authenticator. Only their SHA-256 hashes are
+// stored, the codes themselves are shown once at enrollment.
+#[derive(Debug, Clone, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct RecoveryCodes {
+    pub hashes: Vec<String>,
+}
+
+impl RecoveryCodes {
+    /// `count` new codes formatted like `k7mq-x2vd-9hpt`, and their hashes to store.
+    pub fn generate(count: usize) -> (Vec<String>, RecoveryCodes) {
+        let mut rng = rand::thread_rng();
+        let codes: Vec<String> = (0..count)
+            .map(|_| {
+                (0..3)
+                    .map(|_| {
+                        (0..4)
+                            .map(|_| *RECOVERY_ALPHABET.choose(&mut rng).unwrap() as char)
+                            .collect::<String>()
+                    })
+                    .collect::<Vec<_>>()
+                    .join("-")
+            })
+            .collect();
+        let hashes = codes.iter().map(|code| hash_recovery_code(code)).collect();
+        (codes, RecoveryCodes { hashes })
+    }
+
+    /// Uses up `code`, ignoring case and separators. Store the codes again after a success.
+    pub fn redeem(&mut self, code: &str) -> bool {
+        let hash = hash_recovery_code(code);
+        match self
+            .hashes
+            .iter()
+            .position(|stored| constant_time_eq(stored.as_bytes(), hash.as_bytes()))
+        {
+            Some(index) => {
+                self.hashes.remove(index);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.hashes.len()
+    }
+}
+
+#[cfg(feature = "rocket")]
+pub use self::guards::{StepUpCode, StepUpError};
+
+#[cfg(feature = "rocket")]
+mod guards {
+    use okapi::openapi3::{Object, Parameter, ParameterValue};
+    use rocket::{
+        http::Status,
+        request::{FromRequest, Outcome, Request},
+    };
+    use rocket_okapi::{
+        gen::OpenApiGenerator,
+        request::{OpenApiFromRequest, RequestHeaderInput},
+    };
+
+    use super::TOTP_HEADER;
+    use crate::{rocket_models::ApiError, validation::record_failure};
+
+    #[derive(Debug)]
+    pub enum StepUpError {
+        Missing,
+        Malformed,
+    }
+
+    // The code of the `X-TOTP-Code` header, for handlers to check against the secret of the
+    // authenticated user before a sensitive operation. Requests without one get a 401
+    // `totp_required`, so clients know to prompt for it.
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct StepUpCode(pub String);
+
+    #[rocket::async_trait]
+    impl<'r> FromRequest<'r> for StepUpCode {
+        type Error = StepUpError;
+
+        async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+            let code = match request.headers().get_one(TOTP_HEADER) {
+                Some(code) => code.trim(),
+                None => {
+                    record_failure(
+                        request,
+                        ApiError::new(
+                            401,
+                            "totp_required",
+                            "This operation requires a one-time code",
+                        ),
+                    );
+                    return Outcome::Error((Status::Unauthorized, StepUpError::Missing));
+                }
+            };
+            if !(6..=8).contains(&code.len()) || !code.chars().all(|c| c.is_ascii_digit()) {
+                record_failure(
+                    request,
+                    ApiError::bad_request("The one-time code must be 6 to 8 digits"),
+                );
+                return Outcome::Error((Status::BadRequest, StepUpError::Malformed));
+            }
+            Outcome::Success(StepUpCode(code.to_string()))
+        }
+    }
+
+    impl<'a> OpenApiFromRequest<'a> for StepUpCode {
+        fn from_request_input(
+            gen: &mut OpenApiGenerator,
+            _name: String,
+            required: bool,
+        ) -> rocket_okapi::Result<RequestHeaderInput> {
+            let schema = gen.json_schema::<String>();
+            Ok(RequestHeaderInput::Parameter(Parameter {
+                name: TOTP_HEADER.to_owned(),
+                location: "header".to_owned(),
+                description: Some("Current code of the user's authenticator app".to_owned()),
+                required,
+                deprecated: false,
+                allow_empty_value: false,
+                value: ParameterValue::Schema {
+                    style: None,
+                    explode: None,
+                    allow_reserved: false,
+                    schema,
+                    example: None,
+                    examples: None,
+                },
+                extensions: Object::default(),
+            }))
+        }
+    }
+}
@@ -0,0 +1,207 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::{schema::FieldSchema, LANG, ORM};
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct MappedType {
+    pub column_type: String,   // What the ORM declares the column with
+    pub language_type: String, // What the generated code holds the value in
+}
+
+impl MappedType {
+    pub fn new(column_type: &str, language_type: &str) -> Self {
+        MappedType {
+            column_type: column_type.to_string(),
+            language_type: language_type.to_string(),
+        }
+    }
+}
+
+// (schema type, column type, language type) per ORM
+const DIESEL: [(&str, &str, &str); 16] = [
+    ("uuid", "Uuid", "uuid::Uuid"),
+    ("string", "Varchar", "String"),
+    ("text", "Text", "String"),
+    ("integer", "Integer", "i32"),
+    ("bigint", "BigInt", "i64"),
+    ("smallint", "SmallInt", "i16"),
+    ("float", "Float", "f32"),
+    ("double", "Double", "f64"),
+    ("decimal", "Numeric", "bigdecimal::BigDecimal"),
+    ("boolean", "Bool", "bool"),
+    ("date", "Date", "chrono::NaiveDate"),
+    ("datetime", "Timestamptz", "chrono::DateTime<chrono::Utc>"),
+    ("timestamp", "Timestamp", "chrono::NaiveDateTime"),
+    ("time", "Time", "chrono::NaiveTime"),
+    ("json", "Jsonb", "serde_json::Value"),
+    ("binary", "Binary", "Vec<u8>"),
+];
+
+const SQLALCHEMY: [(&str, &str, &str); 16] = [
+    ("uuid", "UUID(as_uuid=True)", "uuid.UUID"),
+    ("string", "String", "str"),
+    ("text", "Text", "str"),
+    ("integer", "Integer", "int"),
+    ("bigint", "BigInteger", "int"),
+    ("smallint", "SmallInteger", "int"),
+    ("float", "Float", "float"),
+    ("double", "Double", "float"),
+    ("decimal", "Numeric", "decimal.Decimal"),
+    ("boolean", "Boolean", "bool"),
+    ("date", "Date", "datetime.date"),
+    ("datetime", "DateTime(timezone=True)", "datetime.datetime"),
+    ("timestamp", "DateTime", "datetime.datetime"),
+    ("time", "Time", "datetime.time"),
+    ("json", "JSON", "dict"),
+    ("binary", "LargeBinary", "bytes"),
+];
+
+const DJANGO: [(&str, &str, &str); 16] = [
+    ("uuid", "models.UUIDField", "uuid.UUID"),
+    ("string", "models.CharField", "str"),
+    ("text", "models.TextField", "str"),
+    ("integer", "models.IntegerField", "int"),
+    ("bigint", "models.BigIntegerField", "int"),
+    ("smallint", "models.SmallIntegerField", "int"),
+    ("float", "models.FloatField", "float"),
+    ("double", "models.FloatField", "float"),
+    ("decimal", "models.DecimalField", "decimal.Decimal"),
+    ("boolean", "models.BooleanField", "bool"),
+    ("date", "models.DateField", "datetime.date"),
+    ("datetime", "models.DateTimeField", "datetime.datetime"),
+    ("timestamp", "models.DateTimeField", "datetime.datetime"),
+    ("time", "models.TimeField", "datetime.time"),
+    ("json", "models.JSONField", "dict"),
+    ("binary", "models.BinaryField", "bytes"),
+];
+
+// TypeORM hands out `bigint` and `numeric` columns as strings to avoid losing precision
+const TYPEORM: [(&str, &str, &str); 16] = [
+    ("uuid", "uuid", "string"),
+    ("string", "varchar", "string"),
+    ("text", "text", "string"),
+    ("integer", "int", "number"),
+    ("bigint", "bigint", "string"),
+    ("smallint", "smallint", "number"),
+    ("float", "real", "number"),
+    ("double", "double precision", "number"),
+    ("decimal", "numeric", "string"),
+    ("boolean", "boolean", "boolean"),
+    ("date", "date", "string"),
+    ("datetime", "timestamptz", "Date"),
+    ("timestamp", "timestamp", "Date"),
+    ("time", "time", "string"),
+    ("json", "jsonb", "Record<string, unknown>"),
+    ("binary", "bytea", "Buffer"),
+];
+
+impl ORM {
+    /// The language the ORM generates code for.
+    pub fn lang(&self) -> LANG {
+        match self {
+            ORM::Diesel => LANG::Rust,
+            ORM::TypeORM => LANG::TS,
+            ORM::SQLAlchemy | ORM::DjangoORM => LANG::Python,
+        }
+    }
+
+    fn type_table(&self) -> &'static [(&'static str, &'static str, &'static str)] {
+        match self {
+            ORM::Diesel => &DIESEL,
+            ORM::SQLAlchemy => &SQLALCHEMY,
+            ORM::DjangoORM => &DJANGO,
+            ORM::TypeORM => &TYPEORM,
+        }
+    }
+}
+
+/// The canonical name of a schema type, accepting the usual SQL spellings (`int4`,
+/// `timestamp`, `varchar`, ...).
+pub fn canonical_schema_type(schema_type: &str) -> String {
+    let lowered = schema_type.trim().to_lowercase();
+    // `varchar(255)` and `numeric(10, 2)` map like their base type
+    let base = lowered.split('(').next().unwrap_or_default().trim();
+    let canonical = match base {
+        "int" | "int4" | "integer" | "serial" => "integer",
+        "int8" | "bigint" | "bigserial" | "long" => "bigint",
+        "int2" | "smallint" => "smallint",
+        "float" | "float4" | "real" => "float",
+        "double" | "float8" | "double precision" => "double",
+        "decimal" | "numeric" => "decimal",
+        "bool" | "boolean" => "boolean",
+        "varchar" | "char" | "string" | "character varying" => "string",
+        "text" => "text",
+        "timestamptz" | "timestamp with time zone" | "datetime" => "datetime",
+        "timestamp" | "timestamp without time zone" => "timestamp",
+        "json" | "jsonb" => "json",
+        "bytea" | "blob" | "binary" => "binary",
+        other => other,
+    };
+    canonical.to_string()
+}
+
+/// Wraps a language type so it can hold no value.
+pub fn nullable_type(lang: &LANG, language_type: &str) -> String {
+    match lang {
+        LANG::Rust => format!("Option<{}>", language_type),
+        LANG::TS => format!("{} | null", language_type),
+        LANG::Python => format!("Optional[{}]", language_type),
+        LANG::Shell => language_type.to_string(),
+    }
+}
+
+// The mappings every generator uses, with overrides for project specific types
+#[derive(Debug, Clone, Default)]
+pub struct TypeMap {
+    overrides: HashMap<(LANG, ORM, String), MappedType>,
+}
+
+impl TypeMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replaces the canonical mapping of `schema_type`, or adds one for a type it lacks.
+    pub fn with_override(
+        mut self,
+        lang: LANG,
+        orm: ORM,
+        schema_type: &str,
+        mapped: MappedType,
+    ) -> Self {
+        self.overrides
+            .insert((lang, orm, canonical_schema_type(schema_type)), mapped);
+        self
+    }
+
+    /// The mapping of `schema_type`, `None` for unknown types or an ORM of another language.
+    pub fn lookup(&self, lang: LANG, orm: ORM, schema_type: &str) -> Option<MappedType> {
+        let schema_type = canonical_schema_type(schema_type);
+        if let Some(mapped) = self.overrides.get(&(lang, orm, schema_type.clone())) {
+            return Some(mapped.clone());
+        }
+        if orm.lang() != lang {
+            return None;
+        }
+        orm.type_table()
+            .iter()
+            .find(|(name, _, _)| *name == schema_type)
+            .map(|(_, column_type, language_type)| MappedType::new(column_type, language_type))
+    }
+
+    /// The language type of `field`, wrapped as nullable when the field is.
+    pub fn field_type(&self, lang: LANG, orm: ORM, field: &FieldSchema) -> Option<String> {
+        let mapped = self.lookup(lang, orm, &field.field_type)?;
+        if field.nullable {
+            Some(nullable_type(&lang, &mapped.language_type))
+        } else {
+            Some(mapped.language_type)
+        }
+    }
+
+    /// The schema types the canonical table maps for `orm`.
+    pub fn schema_types(orm: ORM) -> Vec<&'static str> {
+        orm.type_table().iter().map(|(name, _, _)| *name).collect()
+    }
+}
@@ -0,0 +1,206 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    env,
+    error::Error,
+    fmt, fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use crate::{config_io::write_atomic, publish::VersionRegistry, Version};
+
+/// Set to any value to turn the check off, e.g. in CI.
+pub const NO_UPDATE_CHECK_ENV: &str = "GINGER_NO_UPDATE_CHECK";
+const CACHE_FILE: &str = "update-check.json";
+const DEFAULT_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+// The check runs before the command's own work, it must never hold it up for long
+const CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+struct CachedCheck {
+    checked_at: DateTime<Utc>,
+    latest: Option<Version>,
+}
+
+// Last known latest versions per tool, shared by every ginger tool of the machine
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Default)]
+struct UpdateCache {
+    #[serde(default)]
+    tools: BTreeMap<String, CachedCheck>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct UpdateInfo {
+    pub name: String,
+    pub current: Version,
+    pub latest: Version,
+}
+
+impl fmt::Display for UpdateInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "A new version of {} is available: {} -> {}",
+            self.name,
+            self.current.formatted(),
+            self.latest.formatted()
+        )
+    }
+}
+
+fn default_cache_path() -> Option<PathBuf> {
+    dirs::home_dir().map(|home| home.join(".ginger-society").join(CACHE_FILE))
+}
+
+// Compares the running tool's version with the latest one published in its channel, asking the
+// registry at most once per interval
+pub struct UpdateChecker {
+    name: String,
+    current: Version,
+    registry: Arc<dyn VersionRegistry>,
+    cache_path: Option<PathBuf>,
+    interval: Duration,
+    upgrade_command: Option<String>,
+}
+
+impl UpdateChecker {
+    pub fn new(name: &str, current: Version, registry: Arc<dyn VersionRegistry>) -> Self {
+        UpdateChecker {
+            name: name.to_string(),
+            current,
+            registry,
+            cache_path: default_cache_path(),
+            interval: DEFAULT_INTERVAL,
+            upgrade_command: None,
+        }
+    }
+
+    /// A checker of a crate, the current version being the one it was built with, e.g.
+    /// `UpdateChecker::for_crate(env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"))`.
+    #[cfg(feature = "client")]
+    pub fn for_crate(name: &str, version: &str) -> Result<Self, Box<dyn Error>> {
+        use crate::registry::{parse_published_version, Registry, RegistryClient};
+
+        let current = parse_published_version(version)
+            .ok_or_else(|| format!("'{}' is not a version of a ginger release", version))?;
+        Ok(Self::new(
+            name,
+            current,
+            Arc::new(RegistryClient::new(Registry::CratesIo)),
+        ))
+    }
+
+    pub fn cache_path<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.cache_path = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Asks the registry on every check.
+    pub fn without_cache(mut self) -> Self {
+        self.cache_path = None;
+        self
+    }
+
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// The command shown to update, e.g. `cargo install ginger-db`.
+    pub fn upgrade_command(mut self, command: &str) -> Self {
+        self.upgrade_command = Some(command.to_string());
+        self
+    }
+
+    // e.g. `ginger-db@nightly`, tools installed from several channels are cached separately
+    fn cache_key(&self) -> String {
+        format!("{}@{}", self.name, self.current.channel)
+    }
+
+    fn read_cache(&self) -> UpdateCache {
+        self.cache_path
+            .as_ref()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+            .unwrap_or_default()
+    }
+
+    fn write_cache(&self, latest: Option<Version>) -> Result<(), Box<dyn Error>> {
+        let path = match &self.cache_path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut cache = self.read_cache();
+        cache.tools.insert(
+            self.cache_key(),
+            CachedCheck {
+                checked_at: Utc::now(),
+                latest,
+            },
+        );
+        write_atomic(path, &serde_json::to_string_pretty(&cache)?, false)
+    }
+
+    /// The latest version published in the channel of the current version, from the cache when
+    /// it was checked within the interval.
+    pub async fn latest(&self) -> Result<Option<Version>, Box<dyn Error + Send + Sync>> {
+        if let Some(cached) = self.read_cache().tools.get(&self.cache_key()) {
+            let age = (Utc::now() - cached.checked_at)
+                .to_std()
+                .unwrap_or_default();
+            if age < self.interval {
+                return Ok(cached.latest);
+            }
+        }
+
+        let latest = self
+            .registry
+            .published_versions(&self.name)
+            .await?
+            .into_iter()
+            .filter(|version| version.channel == self.current.channel)
+            .max();
+        if let Err(e) = self.write_cache(latest) {
+            tracing::debug!(error = %e, "failed to cache the update check");
+        }
+        Ok(latest)
+    }
+
+    /// `Some` when a newer version is published in the current channel.
+    pub async fn check(&self) -> Result<Option<UpdateInfo>, Box<dyn Error + Send + Sync>> {
+        Ok(self
+            .latest()
+            .await?
+            .filter(|latest| *latest > self.current)
+            .map(|latest| UpdateInfo {
+                name: self.name.clone(),
+                current: self.current,
+                latest,
+            }))
+    }
+
+    /// Prints a notice on stderr when the tool is outdated. Failures and slow registries are
+    /// ignored, and nothing is checked when `GINGER_NO_UPDATE_CHECK` is set.
+    pub async fn notify_if_outdated(&self) {
+        if env::var_os(NO_UPDATE_CHECK_ENV).is_some() {
+            return;
+        }
+        match tokio::time::timeout(CHECK_TIMEOUT, self.check()).await {
+            Ok(Ok(Some(update))) => {
+                eprintln!("{}", update);
+                if let Some(command) = &self.upgrade_command {
+                    eprintln!("Update with `{}`", command);
+                }
+            }
+            Ok(Ok(None)) => {}
+            Ok(Err(e)) => tracing::debug!(error = %e, "failed to check for updates"),
+            Err(_) => tracing::debug!("the update check timed out"),
+        }
+    }
+}
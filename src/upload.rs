@@ -0,0 +1,504 @@
+use okapi::openapi3::{MediaType, RequestBody};
+use rocket::{
+    data::{Data, FromData, Outcome, ToByteUnit},
+    http::Status,
+    request::Request,
+};
+use rocket_okapi::{gen::OpenApiGenerator, request::OpenApiFromData};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    error::Error,
+    marker::PhantomData,
+    ops::Deref,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use tokio::{fs, io::AsyncWriteExt};
+use uuid::Uuid;
+
+use crate::{rocket_models::ApiError, storage::BlobStore, validation::record_failure};
+
+pub type UploadError = Box<dyn Error + Send + Sync>;
+
+// Text fields next to the file are small by nature
+const MAX_TEXT_FIELD: usize = 64 * 1024;
+
+// Where uploaded files end up. Chunks are written as they arrive so files never sit in memory.
+#[rocket::async_trait]
+pub trait UploadStorage: Send + Sync {
+    async fn create(&self, key: &str) -> Result<Box<dyn UploadSink>, UploadError>;
+}
+
+#[rocket::async_trait]
+pub trait UploadSink: Send {
+    async fn write(&mut self, chunk: &[u8]) -> Result<(), UploadError>;
+
+    /// Makes the upload visible, returns where it is stored.
+    async fn finish(self: Box<Self>) -> Result<String, UploadError>;
+
+    /// Drops what was written so far, e.g. when the file turns out too large.
+    async fn abort(self: Box<Self>);
+}
+
+// Stores uploads as files under `dir`, written to a `.part` file and renamed once complete
+pub struct LocalUploadStorage {
+    dir: PathBuf,
+}
+
+impl LocalUploadStorage {
+    pub fn new<P: AsRef<Path>>(dir: P) -> Self {
+        LocalUploadStorage {
+            dir: dir.as_ref().to_path_buf(),
+        }
+    }
+}
+
+struct LocalSink {
+    part: PathBuf,
+    path: PathBuf,
+    file: fs::File,
+}
+
+#[rocket::async_trait]
+impl UploadStorage for LocalUploadStorage {
+    async fn create(&self, key: &str) -> Result<Box<dyn UploadSink>, UploadError> {
+        if key
+            .split('/')
+            .any(|segment| segment.is_empty() || segment == "..")
+        {
+            return Err(format!("'{}' is not a valid upload key", key).into());
+        }
+        let path = self.dir.join(key);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        let part = path.with_extension(match path.extension() {
+            Some(ext) => format!("{}.part", ext.to_string_lossy()),
+            None => "part".to_string(),
+        });
+        let file = fs::File::create(&part)
+            .await
+            .map_err(|e| format!("Failed to create the upload '{}': {}", part.display(), e))?;
+        Ok(Box::new(LocalSink { part, path, file }))
+    }
+}
+
+#[rocket::async_trait]
+impl UploadSink for LocalSink {
+    async fn write(&mut self, chunk: &[u8]) -> Result<(), UploadError> {
+        Ok(self.file.write_all(chunk).await?)
+    }
+
+    async fn finish(mut self: Box<Self>) -> Result<String, UploadError> {
+        self.file.sync_all().await?;
+        fs::rename(&self.part, &self.path).await?;
+        Ok(self.path.display().to_string())
+    }
+
+    async fn abort(self: Box<Self>) {
+        let _ = fs::remove_file(&self.part).await;
+    }
+}
+
+// Stores uploads in a blob store, e.g. an S3 bucket. Chunks go to a temporary file first, then
+// the complete file is streamed to the store under its key.
+pub struct BlobUploadStorage {
+    store: Arc<dyn BlobStore>,
+    tmp_dir: PathBuf,
+}
+
+impl BlobUploadStorage {
+    pub fn new(store: Arc<dyn BlobStore>) -> Self {
+        BlobUploadStorage {
+            store,
+            tmp_dir: std::env::temp_dir().join("ginger-uploads"),
+        }
+    }
+
+    /// Where uploads are staged, the system's temporary directory by default.
+    pub fn tmp_dir<P: AsRef<Path>>(mut self, dir: P) -> Self {
+        self.tmp_dir = dir.as_ref().to_path_buf();
+        self
+    }
+}
+
+struct BlobSink {
+    store: Arc<dyn BlobStore>,
+    key: String,
+    part: PathBuf,
+    file: fs::File,
+}
+
+#[rocket::async_trait]
+impl UploadStorage for BlobUploadStorage {
+    async fn create(&self, key: &str) -> Result<Box<dyn UploadSink>, UploadError> {
+        fs::create_dir_all(&self.tmp_dir).await?;
+        let part = self.tmp_dir.join(format!("{}.part", Uuid::new_v4()));
+        let file = fs::File::create(&part)
+            .await
+            .map_err(|e| format!("Failed to create the upload '{}': {}", part.display(), e))?;
+        Ok(Box::new(BlobSink {
+            store: self.store.clone(),
+            key: key.to_string(),
+            part,
+            file,
+        }))
+    }
+}
+
+#[rocket::async_trait]
+impl UploadSink for BlobSink {
+    async fn write(&mut self, chunk: &[u8]) -> Result<(), UploadError> {
+        Ok(self.file.write_all(chunk).await?)
+    }
+
+    /// Returns the key of the upload in the store.
+    async fn finish(mut self: Box<Self>) -> Result<String, UploadError> {
+        self.file.flush().await?;
+        let stored = self.store.put_file(&self.key, &self.part, None).await;
+        let _ = fs::remove_file(&self.part).await;
+        stored?;
+        Ok(self.key)
+    }
+
+    async fn abort(self: Box<Self>) {
+        let _ = fs::remove_file(&self.part).await;
+    }
+}
+
+// Rocket managed state holding the storage the `FileUpload` guard writes to
+#[derive(Clone)]
+pub struct UploadStore(Arc<dyn UploadStorage>);
+
+impl UploadStore {
+    pub fn new<S: UploadStorage + 'static>(storage: S) -> Self {
+        UploadStore(Arc::new(storage))
+    }
+}
+
+// Limits of one kind of upload, e.g.
+//
+// struct Avatar;
+// impl UploadPolicy for Avatar {
+//     const MAX_SIZE: u64 = 2 * 1024 * 1024;
+//     const ALLOWED_TYPES: &'static [&'static str] = &["image/png", "image/jpeg"];
+// }
+//
+// and `FileUpload<Avatar>` as the data guard.
+pub trait UploadPolicy: Send + Sync + 'static {
+    const MAX_SIZE: u64;
+    /// MIME types accepted, `image/*` style wildcards included. Empty accepts anything.
+    const ALLOWED_TYPES: &'static [&'static str];
+    /// Name of the multipart field carrying the file.
+    const FIELD: &'static str = "file";
+
+    /// Key of the stored file, a random name keeping the extension by default.
+    fn storage_key(file_name: Option<&str>) -> String {
+        let extension = file_name
+            .and_then(|name| Path::new(name).extension())
+            .map(|ext| ext.to_string_lossy().to_lowercase())
+            .filter(|ext| ext.chars().all(|c| c.is_ascii_alphanumeric()));
+        match extension {
+            Some(ext) => format!("{}.{}", Uuid::new_v4(), ext),
+            None => Uuid::new_v4().to_string(),
+        }
+    }
+}
+
+// 10 MiB of anything
+pub struct DefaultUpload;
+
+impl UploadPolicy for DefaultUpload {
+    const MAX_SIZE: u64 = 10 * 1024 * 1024;
+    const ALLOWED_TYPES: &'static [&'static str] = &[];
+}
+
+/// Whether `content_type` is one of `allowed`, where `type/*` accepts a whole family.
+pub fn content_type_allowed(content_type: &str, allowed: &[&str]) -> bool {
+    let essence = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_lowercase();
+    allowed.is_empty()
+        || allowed
+            .iter()
+            .any(|pattern| match pattern.strip_suffix("/*") {
+                Some(family) => essence.split('/').next() == Some(family),
+                None => essence == pattern.to_lowercase(),
+            })
+}
+
+const SIGNATURES: [(&[u8], &str); 7] = [
+    (b"\x89PNG\r\n\x1a\n", "image/png"),
+    (b"\xff\xd8\xff", "image/jpeg"),
+    (b"GIF87a", "image/gif"),
+    (b"GIF89a", "image/gif"),
+    (b"%PDF-", "application/pdf"),
+    (b"PK\x03\x04", "application/zip"),
+    (b"\x1f\x8b", "application/gzip"),
+];
+
+// The longest signature, `RIFF....WEBP`
+const SNIFF_LEN: usize = 12;
+
+/// The type announced by the leading bytes of common binary formats, `None` when they match
+/// none of them. Uploads declared as one of these types must carry its signature, so a renamed
+/// executable is not accepted as an image.
+pub fn sniff_content_type(bytes: &[u8]) -> Option<&'static str> {
+    if bytes.len() >= SNIFF_LEN && &bytes[..4] == b"RIFF" && &bytes[8..12] == b"WEBP" {
+        return Some("image/webp");
+    }
+    SIGNATURES
+        .iter()
+        .find(|(signature, _)| bytes.starts_with(signature))
+        .map(|(_, content_type)| *content_type)
+}
+
+fn has_signature(content_type: &str) -> bool {
+    content_type.eq_ignore_ascii_case("image/webp")
+        || SIGNATURES
+            .iter()
+            .any(|(_, known)| content_type.eq_ignore_ascii_case(known))
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct UploadedFile {
+    pub file_name: Option<String>, // As sent by the client, never use it as a path
+    pub content_type: String,
+    pub size: u64,
+    pub sha256: String,
+    pub stored_path: String,
+}
+
+// The stored file of a `multipart/form-data` request, with the text fields sent along
+pub struct FileUpload<P: UploadPolicy = DefaultUpload> {
+    pub file: UploadedFile,
+    pub fields: HashMap<String, String>,
+    _policy: PhantomData<fn() -> P>,
+}
+
+impl<P: UploadPolicy> Deref for FileUpload<P> {
+    type Target = UploadedFile;
+
+    fn deref(&self) -> &UploadedFile {
+        &self.file
+    }
+}
+
+async fn receive<P: UploadPolicy>(
+    request: &Request<'_>,
+    data: Data<'_>,
+) -> Result<FileUpload<P>, ApiError> {
+    let content_type = request
+        .content_type()
+        .filter(|ct| ct.is_form_data())
+        .ok_or_else(|| {
+            ApiError::new(
+                415,
+                "unsupported_media_type",
+                "Uploads must be sent as multipart/form-data",
+            )
+        })?;
+    let boundary = multer::parse_boundary(content_type.to_string())
+        .map_err(|e| ApiError::bad_request(&format!("Invalid multipart boundary: {}", e)))?;
+    let storage = request
+        .rocket()
+        .state::<UploadStore>()
+        .ok_or_else(|| ApiError::internal("No upload storage is configured"))?
+        .0
+        .clone();
+
+    // Room for the multipart framing and the text fields on top of the file
+    let limit = (P::MAX_SIZE + 1024 * 1024).bytes();
+    let mut multipart = multer::Multipart::with_reader(data.open(limit), boundary);
+    let mut fields = HashMap::new();
+    let mut upload: Option<PendingUpload> = None;
+    // The file is only kept once the whole form has been read
+    let parsed = read_form::<P>(&mut multipart, &storage, &mut fields, &mut upload).await;
+    let upload = match (parsed, upload) {
+        (Ok(()), Some(upload)) => upload,
+        (Ok(()), None) => {
+            return Err(ApiError::bad_request(&format!(
+                "The upload has no '{}' field",
+                P::FIELD
+            )))
+        }
+        (Err(e), upload) => {
+            if let Some(upload) = upload {
+                upload.sink.abort().await;
+            }
+            return Err(e);
+        }
+    };
+    let stored_path = upload
+        .sink
+        .finish()
+        .await
+        .map_err(|e| ApiError::internal(&format!("Failed to store the upload: {}", e)))?;
+    Ok(FileUpload {
+        file: UploadedFile {
+            file_name: upload.file_name,
+            content_type: upload.content_type,
+            size: upload.size,
+            sha256: upload.sha256,
+            stored_path,
+        },
+        fields,
+        _policy: PhantomData,
+    })
+}
+
+// The file field, written to its sink but not finished yet
+struct PendingUpload {
+    file_name: Option<String>,
+    content_type: String,
+    size: u64,
+    sha256: String,
+    sink: Box<dyn UploadSink>,
+}
+
+fn malformed(e: multer::Error) -> ApiError {
+    ApiError::bad_request(&format!("Malformed upload: {}", e))
+}
+
+async fn read_form<P: UploadPolicy>(
+    multipart: &mut multer::Multipart<'_>,
+    storage: &Arc<dyn UploadStorage>,
+    fields: &mut HashMap<String, String>,
+    upload: &mut Option<PendingUpload>,
+) -> Result<(), ApiError> {
+    while let Some(mut field) = multipart.next_field().await.map_err(malformed)? {
+        let name = field.name().unwrap_or_default().to_string();
+        if name != P::FIELD || upload.is_some() {
+            let mut text = Vec::new();
+            while let Some(chunk) = field.chunk().await.map_err(malformed)? {
+                if text.len() + chunk.len() > MAX_TEXT_FIELD {
+                    return Err(ApiError::bad_request(&format!(
+                        "The field '{}' is too large",
+                        name
+                    )));
+                }
+                text.extend_from_slice(&chunk);
+            }
+            fields.insert(name, String::from_utf8_lossy(&text).into_owned());
+            continue;
+        }
+
+        let file_name = field.file_name().map(str::to_string);
+        let declared = field
+            .content_type()
+            .map(|mime| mime.essence_str().to_string())
+            .unwrap_or_else(|| "application/octet-stream".to_string());
+        if !content_type_allowed(&declared, P::ALLOWED_TYPES) {
+            return Err(ApiError::new(
+                415,
+                "unsupported_media_type",
+                &format!(
+                    "Files of type '{}' are not accepted, expected {}",
+                    declared,
+                    P::ALLOWED_TYPES.join(", ")
+                ),
+            ));
+        }
+
+        let mut sink = storage
+            .create(&P::storage_key(file_name.as_deref()))
+            .await
+            .map_err(|e| ApiError::internal(&format!("Failed to store the upload: {}", e)))?;
+        let mut hasher = Sha256::new();
+        let mut size = 0u64;
+        let result: Result<(), ApiError> = async {
+            // Chunks can be shorter than a signature, the head is checked once it is complete
+            let mut head = Vec::with_capacity(SNIFF_LEN);
+            let mut checked = false;
+            while let Some(chunk) = field.chunk().await.map_err(malformed)? {
+                if !checked {
+                    let missing = SNIFF_LEN - head.len();
+                    head.extend_from_slice(&chunk[..missing.min(chunk.len())]);
+                    if head.len() == SNIFF_LEN {
+                        checked = true;
+                        check_signature::<P>(&declared, &head)?;
+                    }
+                }
+                size += chunk.len() as u64;
+                if size > P::MAX_SIZE {
+                    return Err(ApiError::new(
+                        413,
+                        "payload_too_large",
+                        &format!("Files may be at most {} bytes", P::MAX_SIZE),
+                    ));
+                }
+                hasher.update(&chunk);
+                sink.write(&chunk).await.map_err(|e| {
+                    ApiError::internal(&format!("Failed to store the upload: {}", e))
+                })?;
+            }
+            if !checked {
+                check_signature::<P>(&declared, &head)?;
+            }
+            Ok(())
+        }
+        .await;
+        if let Err(e) = result {
+            sink.abort().await;
+            return Err(e);
+        }
+
+        *upload = Some(PendingUpload {
+            file_name,
+            content_type: declared,
+            size,
+            sha256: hasher
+                .finalize()
+                .iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect(),
+            sink,
+        });
+    }
+    Ok(())
+}
+
+// A file declared as a type with a known signature must start with it, and a file announcing
+// another type is only accepted if that type is allowed too
+fn check_signature<P: UploadPolicy>(declared: &str, head: &[u8]) -> Result<(), ApiError> {
+    let mismatch = |message: String| Err(ApiError::new(415, "unsupported_media_type", &message));
+    match sniff_content_type(head) {
+        Some(actual) if actual != declared && !content_type_allowed(actual, P::ALLOWED_TYPES) => {
+            mismatch(format!("The file is a '{}', not a '{}'", actual, declared))
+        }
+        None if has_signature(declared) => mismatch(format!("The file is not a '{}'", declared)),
+        _ => Ok(()),
+    }
+}
+
+#[rocket::async_trait]
+impl<'r, P: UploadPolicy> FromData<'r> for FileUpload<P> {
+    type Error = ApiError;
+
+    async fn from_data(request: &'r Request<'_>, data: Data<'r>) -> Outcome<'r, Self> {
+        match receive::<P>(request, data).await {
+            Ok(upload) => Outcome::Success(upload),
+            Err(error) => {
+                let status = Status::from_code(error.status).unwrap_or(Status::BadRequest);
+                Outcome::Error((status, record_failure(request, error)))
+            }
+        }
+    }
+}
+
+impl<'r, P: UploadPolicy> OpenApiFromData<'r> for FileUpload<P> {
+    fn request_body(_gen: &mut OpenApiGenerator) -> rocket_okapi::Result<RequestBody> {
+        let mut body = RequestBody {
+            required: true,
+            ..Default::default()
+        };
+        body.content
+            .insert("multipart/form-data".to_owned(), MediaType::default());
+        Ok(body)
+    }
+}
@@ -7,10 +7,9 @@ use std::process::exit;
 
 pub fn split_slug(slug: &str) -> Option<(String, String)> {
     // Attempt to split the slug into two parts based on the '/'
-    match slug.split_once('/') {
-        Some((org_id, name)) => Some((org_id.to_string(), name.to_string())),
-        None => None, // Return None if the slug does not contain a '/'
-    }
+    // Return None if the slug does not contain a '/'
+    slug.split_once('/')
+        .map(|(org_id, name)| (org_id.to_string(), name.to_string()))
 }
 
 pub fn get_token_from_file_storage() -> String {
@@ -36,7 +35,7 @@ pub fn get_token_from_file_storage() -> String {
         }
     };
     let mut contents = String::new();
-    if let Err(_) = file.read_to_string(&mut contents) {
+    if file.read_to_string(&mut contents).is_err() {
         println!("Failed to read the auth.json file. Exiting.");
         exit(1);
     }
@@ -60,6 +59,21 @@ pub fn get_token_from_file_storage() -> String {
     token
 }
 
+/// The token stored by the login, like `get_token_from_file_storage` but returning an error
+/// instead of exiting.
+pub fn read_stored_token() -> Result<String, Box<dyn std::error::Error>> {
+    let home_dir = dirs::home_dir().ok_or("Failed to locate home directory")?;
+    let auth_file_path = home_dir.join(".ginger-society").join("auth.json");
+    let contents = std::fs::read_to_string(&auth_file_path)
+        .map_err(|e| format!("Failed to read {}: {}", auth_file_path.display(), e))?;
+    let json: Value = serde_json::from_str(&contents)
+        .map_err(|e| format!("Failed to parse {}: {}", auth_file_path.display(), e))?;
+    json.get("API_TOKEN")
+        .and_then(|v| v.as_str())
+        .map(|t| t.to_string())
+        .ok_or_else(|| format!("API_TOKEN not found in {}", auth_file_path.display()).into())
+}
+
 pub fn get_package_json_info() -> Option<(String, String, String, String, Vec<String>)> {
     let mut file = File::open("package.json").expect("Failed to open package.json");
     let mut content = String::new();
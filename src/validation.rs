@@ -0,0 +1,462 @@
+use schemars::schema::{InstanceType, RootSchema, Schema, SchemaObject, SingleOrVec};
+use serde_json::Value;
+
+use crate::rocket_models::{ApiError, FieldError};
+
+// Problems found in a request body, reported together as one 422 `ApiError`
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ValidationErrors {
+    pub errors: Vec<FieldError>,
+}
+
+impl ValidationErrors {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.errors.is_empty()
+    }
+
+    pub fn add(&mut self, field: &str, message: &str) {
+        self.errors.push(FieldError {
+            field: field.to_string(),
+            message: message.to_string(),
+        });
+    }
+
+    /// Records `message` for `field` unless `valid`.
+    pub fn check(&mut self, field: &str, valid: bool, message: &str) -> &mut Self {
+        if !valid {
+            self.add(field, message);
+        }
+        self
+    }
+
+    pub fn not_blank(&mut self, field: &str, value: &str) -> &mut Self {
+        self.check(field, !value.trim().is_empty(), "must not be blank")
+    }
+
+    /// Length in characters between `min` and `max`, both inclusive.
+    pub fn length(&mut self, field: &str, value: &str, min: usize, max: usize) -> &mut Self {
+        let length = value.chars().count();
+        self.check(
+            field,
+            (min..=max).contains(&length),
+            &format!("must be between {} and {} characters long", min, max),
+        )
+    }
+
+    pub fn range<N: PartialOrd + std::fmt::Display>(
+        &mut self,
+        field: &str,
+        value: N,
+        min: N,
+        max: N,
+    ) -> &mut Self {
+        let message = format!("must be between {} and {}", min, max);
+        self.check(field, value >= min && value <= max, &message)
+    }
+
+    /// A plausible address: one `@`, a non-empty local part and a dotted domain.
+    pub fn email(&mut self, field: &str, value: &str) -> &mut Self {
+        let valid = match value.split_once('@') {
+            Some((local, domain)) => {
+                !local.is_empty()
+                    && !domain.contains('@')
+                    && domain.contains('.')
+                    && !domain.starts_with('.')
+                    && !domain.ends_with('.')
+                    && !value.contains(char::is_whitespace)
+            }
+            None => false,
+        };
+        self.check(field, valid, "must be a valid email address")
+    }
+
+    pub fn into_result(self) -> Result<(), ApiError> {
+        if self.is_empty() {
+            Ok(())
+        } else {
+            Err(self.into())
+        }
+    }
+}
+
+impl From<ValidationErrors> for ApiError {
+    fn from(errors: ValidationErrors) -> Self {
+        ApiError::validation(errors.errors)
+    }
+}
+
+// Rules that cannot be expressed in the JSON schema of a payload, e.g. fields depending on each
+// other. `impl Validate for Payload {}` keeps the schema constraints only.
+pub trait Validate {
+    fn validate(&self, _errors: &mut ValidationErrors) {}
+}
+
+fn join(path: &str, key: &str) -> String {
+    if path.is_empty() {
+        key.to_string()
+    } else {
+        format!("{}.{}", path, key)
+    }
+}
+
+fn field_of(path: &str) -> String {
+    if path.is_empty() {
+        "body".to_string()
+    } else {
+        path.to_string()
+    }
+}
+
+fn type_matches(instance_type: &InstanceType, value: &Value) -> bool {
+    match instance_type {
+        InstanceType::Null => value.is_null(),
+        InstanceType::Boolean => value.is_boolean(),
+        InstanceType::Object => value.is_object(),
+        InstanceType::Array => value.is_array(),
+        InstanceType::Number => value.is_number(),
+        InstanceType::String => value.is_string(),
+        InstanceType::Integer => value.is_i64() || value.is_u64(),
+    }
+}
+
+fn type_name(instance_type: &InstanceType) -> &'static str {
+    match instance_type {
+        InstanceType::Null => "null",
+        InstanceType::Boolean => "a boolean",
+        InstanceType::Object => "an object",
+        InstanceType::Array => "an array",
+        InstanceType::Number => "a number",
+        InstanceType::String => "a string",
+        InstanceType::Integer => "an integer",
+    }
+}
+
+// Walks a value along the schemars schema of its type. Covers what derived schemas carry:
+// types, required properties, enums, length, range and item count constraints, nested objects,
+// arrays and `$ref`s. `pattern` is not checked.
+struct SchemaValidator<'a> {
+    root: &'a RootSchema,
+    errors: Vec<FieldError>,
+}
+
+impl<'a> SchemaValidator<'a> {
+    fn error(&mut self, path: &str, message: String) {
+        self.errors.push(FieldError {
+            field: field_of(path),
+            message,
+        });
+    }
+
+    fn resolve(&self, schema: &'a SchemaObject) -> Option<&'a SchemaObject> {
+        match &schema.reference {
+            None => Some(schema),
+            Some(reference) => {
+                let name = reference.rsplit('/').next().unwrap_or(reference);
+                match self.root.definitions.get(name) {
+                    Some(Schema::Object(object)) => self.resolve(object),
+                    _ => None,
+                }
+            }
+        }
+    }
+
+    fn check(&mut self, schema: &'a Schema, value: &Value, path: &str) {
+        let object = match schema {
+            Schema::Bool(true) => return,
+            Schema::Bool(false) => {
+                self.error(path, "is not allowed".to_string());
+                return;
+            }
+            Schema::Object(object) => object,
+        };
+        let object = match self.resolve(object) {
+            Some(object) => object,
+            None => return,
+        };
+
+        if let Some(instance_type) = &object.instance_type {
+            let types: Vec<&InstanceType> = match instance_type {
+                SingleOrVec::Single(single) => vec![single],
+                SingleOrVec::Vec(types) => types.iter().collect(),
+            };
+            if !types.iter().any(|t| type_matches(t, value)) {
+                let expected: Vec<&str> = types
+                    .iter()
+                    .filter(|t| **t != &InstanceType::Null)
+                    .map(|t| type_name(t))
+                    .collect();
+                let message = if value.is_null() {
+                    "is required".to_string()
+                } else {
+                    format!("must be {}", expected.join(" or "))
+                };
+                self.error(path, message);
+                return;
+            }
+        }
+
+        if let Some(values) = &object.enum_values {
+            if !values.contains(value) {
+                let allowed: Vec<String> = values.iter().map(|v| v.to_string()).collect();
+                self.error(path, format!("must be one of {}", allowed.join(", ")));
+                return;
+            }
+        }
+
+        if let (Some(string), Some(text)) = (&object.string, value.as_str()) {
+            let length = text.chars().count() as u32;
+            if let Some(min) = string.min_length.filter(|min| length < *min) {
+                self.error(path, format!("must be at least {} characters long", min));
+            }
+            if let Some(max) = string.max_length.filter(|max| length > *max) {
+                self.error(path, format!("must be at most {} characters long", max));
+            }
+        }
+
+        if let (Some(number), Some(n)) = (&object.number, value.as_f64()) {
+            if let Some(min) = number.minimum.filter(|min| n < *min) {
+                self.error(path, format!("must be at least {}", min));
+            }
+            if let Some(max) = number.maximum.filter(|max| n > *max) {
+                self.error(path, format!("must be at most {}", max));
+            }
+            if let Some(min) = number.exclusive_minimum.filter(|min| n <= *min) {
+                self.error(path, format!("must be greater than {}", min));
+            }
+            if let Some(max) = number.exclusive_maximum.filter(|max| n >= *max) {
+                self.error(path, format!("must be less than {}", max));
+            }
+        }
+
+        if let (Some(array), Some(items)) = (&object.array, value.as_array()) {
+            let count = items.len() as u32;
+            if let Some(min) = array.min_items.filter(|min| count < *min) {
+                self.error(path, format!("must have at least {} items", min));
+            }
+            if let Some(max) = array.max_items.filter(|max| count > *max) {
+                self.error(path, format!("must have at most {} items", max));
+            }
+            match &array.items {
+                Some(SingleOrVec::Single(item_schema)) => {
+                    for (index, item) in items.iter().enumerate() {
+                        self.check(item_schema, item, &format!("{}[{}]", path, index));
+                    }
+                }
+                Some(SingleOrVec::Vec(item_schemas)) => {
+                    for (index, (item_schema, item)) in item_schemas.iter().zip(items).enumerate() {
+                        self.check(item_schema, item, &format!("{}[{}]", path, index));
+                    }
+                }
+                None => {}
+            }
+        }
+
+        if let (Some(validation), Some(fields)) = (&object.object, value.as_object()) {
+            for required in &validation.required {
+                if !fields.contains_key(required) {
+                    self.error(&join(path, required), "is required".to_string());
+                }
+            }
+            for (key, field) in fields {
+                match validation.properties.get(key) {
+                    Some(property) => self.check(property, field, &join(path, key)),
+                    None => {
+                        if let Some(additional) = &validation.additional_properties {
+                            self.check(additional, field, &join(path, key));
+                        }
+                    }
+                }
+            }
+        }
+
+        if let Some(subschemas) = &object.subschemas {
+            if let Some(all_of) = &subschemas.all_of {
+                for schema in all_of {
+                    self.check(schema, value, path);
+                }
+            }
+            for alternatives in [&subschemas.any_of, &subschemas.one_of]
+                .into_iter()
+                .flatten()
+            {
+                let matching = alternatives.iter().find(|schema| {
+                    let mut nested = SchemaValidator {
+                        root: self.root,
+                        errors: vec![],
+                    };
+                    nested.check(schema, value, path);
+                    nested.errors.is_empty()
+                });
+                if matching.is_none() {
+                    self.error(path, "does not match any of the allowed shapes".to_string());
+                }
+            }
+        }
+    }
+}
+
+/// Checks `value` against the constraints of `schema`, e.g. `schemars::schema_for!(Payload)`.
+/// Constraints come from the `#[schemars(length(...), range(...))]` or `#[validate(...)]`
+/// attributes of the payload type.
+pub fn schema_errors(schema: &RootSchema, value: &Value) -> Vec<FieldError> {
+    let mut validator = SchemaValidator {
+        root: schema,
+        errors: vec![],
+    };
+    let root = Schema::Object(schema.schema.clone());
+    validator.check(&root, value, "");
+    validator.errors
+}
+
+#[cfg(feature = "rocket")]
+pub(crate) use self::guard::record_failure;
+#[cfg(feature = "rocket")]
+pub use self::guard::{validation_failure, Validated};
+
+#[cfg(feature = "rocket")]
+mod guard {
+    use okapi::openapi3::RequestBody;
+    use rocket::{
+        data::{Data, FromData, Outcome, ToByteUnit},
+        http::Status,
+        request::Request,
+        serde::json::Json,
+    };
+    use rocket_okapi::{gen::OpenApiGenerator, request::OpenApiFromData};
+    use schemars::{gen::SchemaSettings, schema::RootSchema, JsonSchema};
+    use serde::de::DeserializeOwned;
+    use serde_json::Value;
+    use std::{
+        any::TypeId,
+        collections::HashMap,
+        ops::Deref,
+        sync::{Arc, Mutex, OnceLock},
+    };
+
+    use super::{schema_errors, Validate, ValidationErrors};
+    use crate::rocket_models::{ApiError, FieldError};
+
+    struct ValidationFailure(Option<ApiError>);
+
+    pub(crate) fn record_failure(request: &Request<'_>, error: ApiError) -> ApiError {
+        request.local_cache(|| ValidationFailure(Some(error.clone())));
+        error
+    }
+
+    /// Error recorded by the body guards (`Validated`, `FileUpload`) for rejecting the current
+    /// request, if any. The default catchers answer with it.
+    pub fn validation_failure(request: &Request<'_>) -> Option<ApiError> {
+        request.local_cache(|| ValidationFailure(None)).0.clone()
+    }
+
+    // A JSON body checked against the constraints of its schema and the `Validate` rules of `T`.
+    // Malformed JSON is rejected with a 400, invalid fields with a 422 listing every problem.
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct Validated<T>(pub T);
+
+    impl<T> Validated<T> {
+        pub fn into_inner(self) -> T {
+            self.0
+        }
+    }
+
+    impl<T> Deref for Validated<T> {
+        type Target = T;
+
+        fn deref(&self) -> &T {
+            &self.0
+        }
+    }
+
+    // Schemas are generated once per payload type
+    fn schema_of<T: JsonSchema + 'static>() -> Arc<RootSchema> {
+        static SCHEMAS: OnceLock<Mutex<HashMap<TypeId, Arc<RootSchema>>>> = OnceLock::new();
+        SCHEMAS
+            .get_or_init(Default::default)
+            .lock()
+            .unwrap()
+            .entry(TypeId::of::<T>())
+            .or_insert_with(|| {
+                Arc::new(
+                    SchemaSettings::draft07()
+                        .into_generator()
+                        .into_root_schema_for::<T>(),
+                )
+            })
+            .clone()
+    }
+
+    fn validate<T: DeserializeOwned + JsonSchema + Validate + 'static>(
+        body: &str,
+    ) -> Result<T, ApiError> {
+        let value: Value = serde_json::from_str(body).map_err(|e| {
+            ApiError::bad_request(&format!("The request body is not valid JSON: {}", e))
+        })?;
+
+        let errors = schema_errors(&schema_of::<T>(), &value);
+        if !errors.is_empty() {
+            return Err(ApiError::validation(errors));
+        }
+
+        let payload: T = serde_json::from_value(value).map_err(|e| {
+            ApiError::validation(vec![FieldError {
+                field: "body".to_string(),
+                message: e.to_string(),
+            }])
+        })?;
+        let mut errors = ValidationErrors::new();
+        payload.validate(&mut errors);
+        errors.into_result()?;
+        Ok(payload)
+    }
+
+    #[rocket::async_trait]
+    impl<'r, T> FromData<'r> for Validated<T>
+    where
+        T: DeserializeOwned + JsonSchema + Validate + Send + 'static,
+    {
+        type Error = ApiError;
+
+        async fn from_data(request: &'r Request<'_>, data: Data<'r>) -> Outcome<'r, Self> {
+            let limit = request.limits().get("json").unwrap_or(1.mebibytes());
+            let body = match data.open(limit).into_string().await {
+                Ok(body) if body.is_complete() => body.into_inner(),
+                Ok(_) => {
+                    let error =
+                        ApiError::new(413, "payload_too_large", "The request body is too large");
+                    return Outcome::Error((
+                        Status::PayloadTooLarge,
+                        record_failure(request, error),
+                    ));
+                }
+                Err(e) => {
+                    let error =
+                        ApiError::bad_request(&format!("Failed to read the request body: {}", e));
+                    return Outcome::Error((Status::BadRequest, record_failure(request, error)));
+                }
+            };
+
+            match validate::<T>(&body) {
+                Ok(payload) => Outcome::Success(Validated(payload)),
+                Err(error) => {
+                    let status =
+                        Status::from_code(error.status).unwrap_or(Status::UnprocessableEntity);
+                    Outcome::Error((status, record_failure(request, error)))
+                }
+            }
+        }
+    }
+
+    impl<'r, T> OpenApiFromData<'r> for Validated<T>
+    where
+        T: DeserializeOwned + JsonSchema + Validate + Send + 'static,
+    {
+        fn request_body(gen: &mut OpenApiGenerator) -> rocket_okapi::Result<RequestBody> {
+            Json::<T>::request_body(gen)
+        }
+    }
+}
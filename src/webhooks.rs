@@ -0,0 +1,488 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::{error::Error, sync::Mutex, time::Duration};
+use uuid::Uuid;
+
+pub const WEBHOOK_ID_HEADER: &str = "Webhook-Id";
+pub const WEBHOOK_TIMESTAMP_HEADER: &str = "Webhook-Timestamp";
+pub const WEBHOOK_SIGNATURE_HEADER: &str = "Webhook-Signature";
+pub const WEBHOOK_EVENT_HEADER: &str = "Webhook-Event";
+const SIGNATURE_VERSION: &str = "v1";
+/// How far the timestamp of a received webhook may be from the receiver's clock.
+pub const DEFAULT_TOLERANCE: Duration = Duration::from_secs(5 * 60);
+
+fn mac(secret: &str, id: &str, timestamp: i64, body: &[u8]) -> Hmac<Sha256> {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(format!("{}.{}.", id, timestamp).as_bytes());
+    mac.update(body);
+    mac
+}
+
+/// The `Webhook-Signature` value for a delivery: `v1=` followed by the hex HMAC-SHA256 of
+/// `{id}.{timestamp}.{body}` keyed with `secret`.
+pub fn sign(secret: &str, id: &str, timestamp: i64, body: &[u8]) -> String {
+    let signature: String = mac(secret, id, timestamp, body)
+        .finalize()
+        .into_bytes()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    format!("{}={}", SIGNATURE_VERSION, signature)
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// Checks the signature headers of a received webhook. `signatures` may hold several
+/// space separated `v1=` values and any of `secrets` may match, so both sides can rotate secrets
+/// without downtime.
+pub fn verify(
+    secrets: &[String],
+    id: &str,
+    timestamp: &str,
+    signatures: &str,
+    body: &[u8],
+    tolerance: Duration,
+) -> Result<(), Box<dyn Error>> {
+    let timestamp: i64 = timestamp
+        .trim()
+        .parse()
+        .map_err(|_| format!("The webhook timestamp '{}' is not a Unix time", timestamp))?;
+    let skew = Utc::now().timestamp().abs_diff(timestamp);
+    if skew > tolerance.as_secs() {
+        return Err(format!(
+            "The webhook timestamp is {}s away from the current time, {}s are tolerated",
+            skew,
+            tolerance.as_secs()
+        )
+        .into());
+    }
+
+    let candidates: Vec<Vec<u8>> = signatures
+        .split_whitespace()
+        .filter_map(|signature| signature.strip_prefix("v1="))
+        .filter_map(decode_hex)
+        .collect();
+    let matches = secrets.iter().any(|secret| {
+        candidates.iter().any(|candidate| {
+            mac(secret, id, timestamp, body)
+                .verify_slice(candidate)
+                .is_ok()
+        })
+    });
+    if matches {
+        Ok(())
+    } else {
+        Err("The webhook signature does not match".into())
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct DeliveryAttempt {
+    pub webhook_id: Uuid,
+    pub url: String,
+    pub attempt: u32, // Starting at 1
+    pub attempted_at: DateTime<Utc>,
+    pub status: Option<u16>, // None when no response was received
+    pub error: Option<String>,
+    pub duration_ms: u64,
+}
+
+impl DeliveryAttempt {
+    pub fn succeeded(&self) -> bool {
+        self.error.is_none()
+            && self
+                .status
+                .is_some_and(|status| (200..300).contains(&status))
+    }
+}
+
+// Where the sender records every delivery attempt, e.g. to show them to the webhook's owner
+#[async_trait]
+pub trait DeliveryLog: Send + Sync {
+    async fn record(&self, attempt: &DeliveryAttempt);
+}
+
+#[derive(Default)]
+pub struct MemoryDeliveryLog {
+    attempts: Mutex<Vec<DeliveryAttempt>>,
+}
+
+impl MemoryDeliveryLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn attempts(&self) -> Vec<DeliveryAttempt> {
+        self.attempts.lock().unwrap().clone()
+    }
+}
+
+#[async_trait]
+impl DeliveryLog for MemoryDeliveryLog {
+    async fn record(&self, attempt: &DeliveryAttempt) {
+        self.attempts.lock().unwrap().push(attempt.clone());
+    }
+}
+
+// Exponential backoff between delivery attempts
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 5,
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(60),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// How long to wait after the failed attempt number `attempt` (starting at 1).
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+        self.initial_backoff
+            .saturating_mul(factor)
+            .min(self.max_backoff)
+    }
+}
+
+#[cfg(feature = "client")]
+pub use self::sender::WebhookSender;
+
+#[cfg(feature = "client")]
+mod sender {
+    use chrono::Utc;
+    use serde::Serialize;
+    use std::{sync::Arc, time::Duration};
+
+    use super::{
+        sign, DeliveryAttempt, DeliveryLog, RetryPolicy, WEBHOOK_EVENT_HEADER, WEBHOOK_ID_HEADER,
+        WEBHOOK_SIGNATURE_HEADER, WEBHOOK_TIMESTAMP_HEADER,
+    };
+    use crate::events::{EventEnvelope, PublishError};
+
+    // POSTs signed event envelopes to subscriber URLs. Timeouts, connection errors, 408, 429 and
+    // 5xx responses are retried, other responses end the delivery.
+    #[derive(Clone)]
+    pub struct WebhookSender {
+        secret: String,
+        http: reqwest::Client,
+        retry: RetryPolicy,
+        log: Option<Arc<dyn DeliveryLog>>,
+    }
+
+    impl WebhookSender {
+        pub fn new(secret: &str) -> Self {
+            WebhookSender {
+                secret: secret.to_string(),
+                http: reqwest::Client::new(),
+                retry: RetryPolicy::default(),
+                log: None,
+            }
+        }
+
+        pub fn retry(mut self, retry: RetryPolicy) -> Self {
+            self.retry = retry;
+            self
+        }
+
+        /// Time limit of each attempt.
+        pub fn timeout(mut self, timeout: Duration) -> Self {
+            self.http = reqwest::Client::builder()
+                .timeout(timeout)
+                .build()
+                .unwrap_or_default();
+            self
+        }
+
+        pub fn log(mut self, log: Arc<dyn DeliveryLog>) -> Self {
+            self.log = Some(log);
+            self
+        }
+
+        /// Delivers `envelope` to `url`, retrying per the policy. Returns the successful attempt;
+        /// every attempt is also recorded in the delivery log.
+        pub async fn send<T: Serialize>(
+            &self,
+            url: &str,
+            envelope: &EventEnvelope<T>,
+        ) -> Result<DeliveryAttempt, PublishError> {
+            let body = serde_json::to_vec(envelope)?;
+            let id = envelope.id.to_string();
+            let max_attempts = self.retry.max_attempts.max(1);
+
+            for attempt in 1..=max_attempts {
+                let (result, retryable) = self.attempt(url, envelope, &id, &body, attempt).await;
+                if let Some(log) = &self.log {
+                    log.record(&result).await;
+                }
+                if result.succeeded() {
+                    return Ok(result);
+                }
+                let reason = match (&result.error, result.status) {
+                    (Some(error), _) => error.clone(),
+                    (None, Some(status)) => format!("the receiver answered {}", status),
+                    (None, None) => "no response".to_string(),
+                };
+                if !retryable || attempt == max_attempts {
+                    return Err(format!(
+                        "Failed to deliver the webhook {} to '{}' after {} attempt(s): {}",
+                        id, url, attempt, reason
+                    )
+                    .into());
+                }
+                tracing::debug!(webhook_id = %id, url, attempt, reason, "retrying a webhook");
+                tokio::time::sleep(self.retry.delay(attempt)).await;
+            }
+            unreachable!("the last attempt always returns")
+        }
+
+        async fn attempt<T>(
+            &self,
+            url: &str,
+            envelope: &EventEnvelope<T>,
+            id: &str,
+            body: &[u8],
+            attempt: u32,
+        ) -> (DeliveryAttempt, bool) {
+            // Signed again on each attempt so retries are not rejected as stale
+            let attempted_at = Utc::now();
+            let timestamp = attempted_at.timestamp();
+            let started = std::time::Instant::now();
+            let response = self
+                .http
+                .post(url)
+                .header("Content-Type", "application/json")
+                .header(WEBHOOK_ID_HEADER, id)
+                .header(WEBHOOK_TIMESTAMP_HEADER, timestamp.to_string())
+                .header(WEBHOOK_EVENT_HEADER, &envelope.topic)
+                .header(
+                    WEBHOOK_SIGNATURE_HEADER,
+                    sign(&self.secret, id, timestamp, body),
+                )
+                .body(body.to_vec())
+                .send()
+                .await;
+
+            let mut result = DeliveryAttempt {
+                webhook_id: envelope.id,
+                url: url.to_string(),
+                attempt,
+                attempted_at,
+                status: None,
+                error: None,
+                duration_ms: started.elapsed().as_millis() as u64,
+            };
+            let retryable = match response {
+                Ok(response) => {
+                    let status = response.status();
+                    result.status = Some(status.as_u16());
+                    status.is_server_error() || matches!(status.as_u16(), 408 | 429)
+                }
+                Err(e) => {
+                    result.error = Some(e.to_string());
+                    true
+                }
+            };
+            (result, retryable)
+        }
+    }
+}
+
+#[cfg(feature = "rocket")]
+pub use self::guard::{VerifiedWebhook, WebhookSecrets};
+
+#[cfg(feature = "rocket")]
+mod guard {
+    use okapi::openapi3::RequestBody;
+    use rocket::{
+        data::{Data, FromData, Outcome, ToByteUnit},
+        http::Status,
+        request::Request,
+        serde::json::Json,
+    };
+    use rocket_okapi::{gen::OpenApiGenerator, request::OpenApiFromData};
+    use schemars::JsonSchema;
+    use serde::de::DeserializeOwned;
+    use std::{env, ops::Deref, time::Duration};
+
+    use super::{
+        verify, DEFAULT_TOLERANCE, WEBHOOK_ID_HEADER, WEBHOOK_SIGNATURE_HEADER,
+        WEBHOOK_TIMESTAMP_HEADER,
+    };
+    use crate::{events::EventEnvelope, rocket_models::ApiError, validation::record_failure};
+
+    // Rocket managed state with the secrets `VerifiedWebhook` accepts signatures from
+    #[derive(Debug, Clone)]
+    pub struct WebhookSecrets {
+        secrets: Vec<String>,
+        tolerance: Duration,
+    }
+
+    impl WebhookSecrets {
+        pub fn new(secret: &str) -> Self {
+            WebhookSecrets {
+                secrets: vec![secret.to_string()],
+                tolerance: DEFAULT_TOLERANCE,
+            }
+        }
+
+        /// Also accepts `secret`, e.g. the previous one while the sender rotates.
+        pub fn with_secret(mut self, secret: &str) -> Self {
+            self.secrets.push(secret.to_string());
+            self
+        }
+
+        pub fn tolerance(mut self, tolerance: Duration) -> Self {
+            self.tolerance = tolerance;
+            self
+        }
+
+        /// Reads a comma separated list of secrets, e.g. `WEBHOOK_SECRETS="new,old"`.
+        pub fn from_env(var: &str) -> Result<Self, String> {
+            let secrets: Vec<String> = env::var(var)
+                .unwrap_or_default()
+                .split(',')
+                .map(str::trim)
+                .filter(|secret| !secret.is_empty())
+                .map(str::to_string)
+                .collect();
+            if secrets.is_empty() {
+                return Err(format!("No webhook secret is set in {}", var));
+            }
+            Ok(WebhookSecrets {
+                secrets,
+                tolerance: DEFAULT_TOLERANCE,
+            })
+        }
+    }
+
+    // The envelope of a webhook whose signature matched one of the `WebhookSecrets`. Unsigned or
+    // stale deliveries are rejected with a 401, malformed envelopes with a 400.
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct VerifiedWebhook<T>(pub EventEnvelope<T>);
+
+    impl<T> VerifiedWebhook<T> {
+        pub fn into_inner(self) -> EventEnvelope<T> {
+            self.0
+        }
+    }
+
+    impl<T> Deref for VerifiedWebhook<T> {
+        type Target = EventEnvelope<T>;
+
+        fn deref(&self) -> &EventEnvelope<T> {
+            &self.0
+        }
+    }
+
+    fn fail<'r, T>(request: &'r Request<'_>, error: ApiError) -> Outcome<'r, T, ApiError> {
+        let status = Status::from_code(error.status).unwrap_or(Status::BadRequest);
+        Outcome::Error((status, record_failure(request, error)))
+    }
+
+    #[rocket::async_trait]
+    impl<'r, T> FromData<'r> for VerifiedWebhook<T>
+    where
+        T: DeserializeOwned + JsonSchema + Send + 'static,
+    {
+        type Error = ApiError;
+
+        async fn from_data(request: &'r Request<'_>, data: Data<'r>) -> Outcome<'r, Self> {
+            let secrets = match request.rocket().state::<WebhookSecrets>() {
+                Some(secrets) => secrets,
+                None => {
+                    tracing::error!("VerifiedWebhook is used without managed WebhookSecrets");
+                    return fail(
+                        request,
+                        ApiError::internal("Webhooks are not configured on this service"),
+                    );
+                }
+            };
+            let headers = request.headers();
+            let (id, timestamp, signature) = match (
+                headers.get_one(WEBHOOK_ID_HEADER),
+                headers.get_one(WEBHOOK_TIMESTAMP_HEADER),
+                headers.get_one(WEBHOOK_SIGNATURE_HEADER),
+            ) {
+                (Some(id), Some(timestamp), Some(signature)) => (id, timestamp, signature),
+                _ => {
+                    let error = ApiError::new(
+                        401,
+                        "webhook_unsigned",
+                        "The webhook is missing its signature headers",
+                    );
+                    return fail(request, error);
+                }
+            };
+
+            let limit = request.limits().get("json").unwrap_or(1.mebibytes());
+            let body = match data.open(limit).into_bytes().await {
+                Ok(body) if body.is_complete() => body.into_inner(),
+                Ok(_) => {
+                    let error =
+                        ApiError::new(413, "payload_too_large", "The request body is too large");
+                    return fail(request, error);
+                }
+                Err(e) => {
+                    let error =
+                        ApiError::bad_request(&format!("Failed to read the request body: {}", e));
+                    return fail(request, error);
+                }
+            };
+
+            if let Err(e) = verify(
+                &secrets.secrets,
+                id,
+                timestamp,
+                signature,
+                &body,
+                secrets.tolerance,
+            ) {
+                let error = ApiError::new(401, "webhook_signature_invalid", &e.to_string());
+                return fail(request, error);
+            }
+
+            let envelope: EventEnvelope<T> = match serde_json::from_slice(&body) {
+                Ok(envelope) => envelope,
+                Err(e) => {
+                    let error =
+                        ApiError::bad_request(&format!("The webhook payload is invalid: {}", e));
+                    return fail(request, error);
+                }
+            };
+            if envelope.id.to_string() != id {
+                let error = ApiError::bad_request("The webhook id does not match its payload");
+                return fail(request, error);
+            }
+            Outcome::Success(VerifiedWebhook(envelope))
+        }
+    }
+
+    impl<'r, T> OpenApiFromData<'r> for VerifiedWebhook<T>
+    where
+        T: DeserializeOwned + JsonSchema + Send + 'static,
+    {
+        fn request_body(gen: &mut OpenApiGenerator) -> rocket_okapi::Result<RequestBody> {
+            Json::<EventEnvelope<T>>::request_body(gen)
+        }
+    }
+}